@@ -0,0 +1,44 @@
+use std::str::FromStr;
+
+// The hardware behaviors that cost the most to emulate, which slow machines
+// can turn off to keep full speed. Most games don't notice; the ones that
+// change registers partway through a line, or time their VRAM writes to the
+// PPU's modes, may show glitches without them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AccuracyConfig {
+    // Mode 3 drawn a dot at a time through the pixel FIFO, taking longer for
+    // fine scrolling, the window and objects. Off, each line is drawn at once
+    // when a mode 3 of fixed length ends.
+    pub pixel_fifo: bool,
+    // VRAM and OAM closed to the CPU while the PPU reads them
+    pub vram_blocking: bool,
+    // The rest of the machine run through each of an instruction's memory
+    // accesses as it happens. Off, it catches up once the instruction is done.
+    pub access_timing: bool,
+}
+
+impl AccuracyConfig {
+    pub const ACCURATE: AccuracyConfig = AccuracyConfig { pixel_fifo: true, vram_blocking: true, access_timing: true };
+    // The renderer is most of the cost and raster effects rarely need it
+    pub const BALANCED: AccuracyConfig = AccuracyConfig { pixel_fifo: false, ..AccuracyConfig::ACCURATE };
+    pub const FAST: AccuracyConfig = AccuracyConfig { pixel_fifo: false, vram_blocking: false, access_timing: false };
+}
+
+impl Default for AccuracyConfig {
+    fn default() -> Self {
+        AccuracyConfig::ACCURATE
+    }
+}
+
+impl FromStr for AccuracyConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fast" => Ok(AccuracyConfig::FAST),
+            "balanced" => Ok(AccuracyConfig::BALANCED),
+            "accurate" => Ok(AccuracyConfig::ACCURATE),
+            _ => Err(format!("unknown accuracy profile '{}'", s)),
+        }
+    }
+}
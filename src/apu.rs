@@ -0,0 +1,976 @@
+use std::collections::VecDeque;
+
+use crate::error::Result;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+
+const CPU_CLOCK_HZ: u64 = 4_194_304;
+// Output rate until set_sample_rate picks another
+pub const SAMPLE_RATE: u32 = 44_100;
+
+// The frame sequencer steps at 512 Hz and clocks length, sweep and envelope
+const FRAME_SEQUENCER_PERIOD: u32 = 8192;
+
+const NR10: u16 = 0xFF10;
+const NR50: u16 = 0xFF24;
+const NR51: u16 = 0xFF25;
+const NR52: u16 = 0xFF26;
+const WAVE_RAM_START: u16 = 0xFF30;
+const WAVE_RAM_END: u16 = 0xFF3F;
+
+const DUTY_PATTERNS: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 1, 1, 1],
+    [0, 1, 1, 1, 1, 1, 1, 0],
+];
+
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+// Outputs kept per channel for the channel overlay, one every SCOPE_PERIOD
+// cycles; together they span about one period of a 400 Hz note
+pub const SCOPE_LEN: usize = 160;
+const SCOPE_PERIOD: u32 = 64;
+
+// What the channel overlay shows of a channel
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ChannelStatus {
+    // Triggered and not yet silenced by its length counter or DAC
+    pub playing: bool,
+    // 0-15: the envelope's volume now, or the wave channel's output level
+    pub volume: u8,
+    // None for the wave channel, which has no envelope
+    pub envelope: Option<EnvelopeStatus>,
+    // Of the note, or for the noise channel how often its LFSR shifts
+    pub frequency_hz: f32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EnvelopeStatus {
+    pub increase: bool,
+    // 64 Hz steps between volume changes; 0 holds the volume
+    pub period: u8,
+}
+
+struct Envelope {
+    initial_volume: u8,
+    increase: bool,
+    period: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    fn new() -> Self {
+        Envelope { initial_volume: 0, increase: false, period: 0, volume: 0, timer: 0 }
+    }
+
+    fn status(&self) -> EnvelopeStatus {
+        EnvelopeStatus { increase: self.increase, period: self.period }
+    }
+
+    fn write(&mut self, value: u8) {
+        self.initial_volume = value >> 4;
+        self.increase = value & 0x08 != 0;
+        self.period = value & 0x07;
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.initial_volume;
+        self.timer = self.period;
+    }
+
+    fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.increase && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+impl Savestate for Envelope {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.initial_volume);
+        state.write_bool(self.increase);
+        state.write_u8(self.period);
+        state.write_u8(self.volume);
+        state.write_u8(self.timer);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.initial_volume = state.read_u8()?;
+        self.increase = state.read_bool()?;
+        self.period = state.read_u8()?;
+        self.volume = state.read_u8()?;
+        self.timer = state.read_u8()?;
+        Ok(())
+    }
+}
+
+// Length counter; when enabled it silences the channel once it runs out
+struct Length {
+    max: u16,
+    counter: u16,
+    enabled: bool,
+}
+
+impl Length {
+    fn new(max: u16) -> Self {
+        Length { max, counter: 0, enabled: false }
+    }
+
+    fn load(&mut self, value: u8) {
+        self.counter = self.max - value as u16;
+    }
+
+    fn trigger(&mut self) {
+        if self.counter == 0 {
+            self.counter = self.max;
+        }
+    }
+
+    // Returns false when the counter just expired
+    fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter != 0;
+        }
+        true
+    }
+}
+
+impl Savestate for Length {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.counter);
+        state.write_bool(self.enabled);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.counter = state.read_u16()?.min(self.max);
+        self.enabled = state.read_bool()?;
+        Ok(())
+    }
+}
+
+struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    enabled: bool,
+    shadow: u16,
+}
+
+impl Sweep {
+    fn new() -> Self {
+        Sweep { period: 0, negate: false, shift: 0, timer: 0, enabled: false, shadow: 0 }
+    }
+
+    fn next_frequency(&self) -> u16 {
+        let delta = self.shadow >> self.shift;
+        if self.negate {
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+
+    fn reload_timer(&mut self) {
+        // A period of 0 is treated as 8 by the timer
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+}
+
+struct PulseChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    duty: u8,
+    duty_position: u8,
+    frequency: u16,
+    timer: u32,
+    length: Length,
+    envelope: Envelope,
+    // Only channel 1 has a frequency sweep unit
+    sweep: Option<Sweep>,
+}
+
+impl PulseChannel {
+    fn new(with_sweep: bool) -> Self {
+        PulseChannel {
+            enabled: false,
+            dac_enabled: false,
+            duty: 0,
+            duty_position: 0,
+            frequency: 0,
+            timer: 8192,
+            length: Length::new(64),
+            envelope: Envelope::new(),
+            sweep: if with_sweep { Some(Sweep::new()) } else { None },
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 4
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        let mut remaining = cycles;
+        while remaining >= self.timer {
+            remaining -= self.timer;
+            self.timer = self.period();
+            self.duty_position = (self.duty_position + 1) % 8;
+        }
+        self.timer -= remaining;
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.length.trigger();
+        self.timer = self.period();
+        self.envelope.trigger();
+
+        let frequency = self.frequency;
+        if let Some(sweep) = &mut self.sweep {
+            sweep.shadow = frequency;
+            sweep.reload_timer();
+            sweep.enabled = sweep.period != 0 || sweep.shift != 0;
+            if sweep.shift != 0 && sweep.next_frequency() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn clock_sweep(&mut self) {
+        let Some(sweep) = &mut self.sweep else {
+            return;
+        };
+
+        sweep.timer = sweep.timer.saturating_sub(1);
+        if sweep.timer != 0 {
+            return;
+        }
+        sweep.reload_timer();
+        if !sweep.enabled || sweep.period == 0 {
+            return;
+        }
+
+        let frequency = sweep.next_frequency();
+        if frequency > 2047 {
+            self.enabled = false;
+            return;
+        }
+        if sweep.shift != 0 {
+            sweep.shadow = frequency;
+            self.frequency = frequency;
+            // The overflow check runs a second time with the new frequency
+            if sweep.next_frequency() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        DUTY_PATTERNS[self.duty as usize][self.duty_position as usize] * self.envelope.volume
+    }
+}
+
+impl Savestate for PulseChannel {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_bool(self.dac_enabled);
+        state.write_u8(self.duty);
+        state.write_u8(self.duty_position);
+        state.write_u16(self.frequency);
+        state.write_u32(self.timer);
+        self.length.save_state(state);
+        self.envelope.save_state(state);
+        if let Some(sweep) = &self.sweep {
+            state.write_u8(sweep.period);
+            state.write_bool(sweep.negate);
+            state.write_u8(sweep.shift);
+            state.write_u8(sweep.timer);
+            state.write_bool(sweep.enabled);
+            state.write_u16(sweep.shadow);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.enabled = state.read_bool()?;
+        self.dac_enabled = state.read_bool()?;
+        self.duty = state.read_u8()? & 0x03;
+        self.duty_position = state.read_u8()? % 8;
+        self.frequency = state.read_u16()? & 0x07FF;
+        self.timer = state.read_u32()?.max(1);
+        self.length.load_state(state)?;
+        self.envelope.load_state(state)?;
+        if let Some(sweep) = &mut self.sweep {
+            sweep.period = state.read_u8()? & 0x07;
+            sweep.negate = state.read_bool()?;
+            sweep.shift = state.read_u8()? & 0x07;
+            sweep.timer = state.read_u8()?;
+            sweep.enabled = state.read_bool()?;
+            sweep.shadow = state.read_u16()?;
+        }
+        Ok(())
+    }
+}
+
+struct WaveChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    volume_code: u8,
+    frequency: u16,
+    timer: u32,
+    position: u8,
+    length: Length,
+    ram: [u8; 16],
+}
+
+impl WaveChannel {
+    fn new() -> Self {
+        WaveChannel {
+            enabled: false,
+            dac_enabled: false,
+            volume_code: 0,
+            frequency: 0,
+            timer: 4096,
+            position: 0,
+            length: Length::new(256),
+            ram: [0; 16],
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 2
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        let mut remaining = cycles;
+        while remaining >= self.timer {
+            remaining -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) % 32;
+        }
+        self.timer -= remaining;
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.length.trigger();
+        self.timer = self.period();
+        self.position = 0;
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        let byte = self.ram[(self.position / 2) as usize];
+        let sample = if self.position.is_multiple_of(2) { byte >> 4 } else { byte & 0x0F };
+        match self.volume_code {
+            0 => 0,
+            1 => sample,
+            2 => sample >> 1,
+            _ => sample >> 2,
+        }
+    }
+}
+
+impl Savestate for WaveChannel {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_bool(self.dac_enabled);
+        state.write_u8(self.volume_code);
+        state.write_u16(self.frequency);
+        state.write_u32(self.timer);
+        state.write_u8(self.position);
+        self.length.save_state(state);
+        state.write_bytes(&self.ram);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.enabled = state.read_bool()?;
+        self.dac_enabled = state.read_bool()?;
+        self.volume_code = state.read_u8()? & 0x03;
+        self.frequency = state.read_u16()? & 0x07FF;
+        self.timer = state.read_u32()?.max(1);
+        self.position = state.read_u8()? % 32;
+        self.length.load_state(state)?;
+        state.read_bytes(&mut self.ram)
+    }
+}
+
+struct NoiseChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    clock_shift: u8,
+    narrow: bool,
+    divisor_code: u8,
+    timer: u32,
+    lfsr: u16,
+    length: Length,
+    envelope: Envelope,
+}
+
+impl NoiseChannel {
+    fn new() -> Self {
+        NoiseChannel {
+            enabled: false,
+            dac_enabled: false,
+            clock_shift: 0,
+            narrow: false,
+            divisor_code: 0,
+            timer: 8,
+            lfsr: 0x7FFF,
+            length: Length::new(64),
+            envelope: Envelope::new(),
+        }
+    }
+
+    fn period(&self) -> u32 {
+        NOISE_DIVISORS[self.divisor_code as usize] << self.clock_shift
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        let mut remaining = cycles;
+        while remaining >= self.timer {
+            remaining -= self.timer;
+            self.timer = self.period();
+
+            let bit = (self.lfsr & 0x01) ^ ((self.lfsr >> 1) & 0x01);
+            self.lfsr = (self.lfsr >> 1) | (bit << 14);
+            // 7-bit mode also feeds the result back into bit 6
+            if self.narrow {
+                self.lfsr = (self.lfsr & !0x40) | (bit << 6);
+            }
+        }
+        self.timer -= remaining;
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.length.trigger();
+        self.timer = self.period();
+        self.lfsr = 0x7FFF;
+        self.envelope.trigger();
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled || self.lfsr & 0x01 != 0 {
+            return 0;
+        }
+        self.envelope.volume
+    }
+}
+
+impl Savestate for NoiseChannel {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_bool(self.dac_enabled);
+        state.write_u8(self.clock_shift);
+        state.write_bool(self.narrow);
+        state.write_u8(self.divisor_code);
+        state.write_u32(self.timer);
+        state.write_u16(self.lfsr);
+        self.length.save_state(state);
+        self.envelope.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.enabled = state.read_bool()?;
+        self.dac_enabled = state.read_bool()?;
+        self.clock_shift = state.read_u8()? & 0x0F;
+        self.narrow = state.read_bool()?;
+        self.divisor_code = state.read_u8()? & 0x07;
+        self.timer = state.read_u32()?.max(1);
+        self.lfsr = state.read_u16()?;
+        self.length.load_state(state)?;
+        self.envelope.load_state(state)
+    }
+}
+
+fn accumulate(sums: &mut [f32; 2], level: [f32; 2], cycles: u64) {
+    sums[0] += level[0] * cycles as f32;
+    sums[1] += level[1] * cycles as f32;
+}
+
+#[allow(clippy::upper_case_acronyms)]
+pub struct APU {
+    powered: bool,
+    // Raw NR10-NR52 values, used for reads
+    registers: [u8; 0x17],
+    pulse1: PulseChannel,
+    pulse2: PulseChannel,
+    wave: WaveChannel,
+    noise: NoiseChannel,
+    sequencer_cycles: u32,
+    sequencer_step: u8,
+    sample_rate: u32,
+    sample_clock: u64,
+    // Output summed over the sample period so far, weighted by cycles
+    accumulated: [f32; 2],
+    accumulated_cycles: u64,
+    // Interleaved left and right
+    samples: Vec<f32>,
+    // Channels left out of the mix, for listening to parts of a track
+    muted: [bool; 4],
+    solo: Option<usize>,
+    // Each channel on its own as well, ignoring mute and solo, for
+    // recording stems
+    stems_enabled: bool,
+    stem_accumulated: [[f32; 2]; 4],
+    stem_samples: [Vec<f32>; 4],
+    // Recent digital output of each channel, only kept while enabled
+    scope_enabled: bool,
+    scope_cycles: u32,
+    scope: [VecDeque<u8>; 4],
+}
+
+impl Default for APU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl APU {
+    pub fn new() -> Self {
+        APU {
+            powered: false,
+            registers: [0; 0x17],
+            pulse1: PulseChannel::new(true),
+            pulse2: PulseChannel::new(false),
+            wave: WaveChannel::new(),
+            noise: NoiseChannel::new(),
+            sequencer_cycles: 0,
+            sequencer_step: 0,
+            sample_rate: SAMPLE_RATE,
+            sample_clock: 0,
+            accumulated: [0.0; 2],
+            accumulated_cycles: 0,
+            samples: Vec::new(),
+            muted: [false; 4],
+            solo: None,
+            stems_enabled: false,
+            stem_accumulated: [[0.0; 2]; 4],
+            stem_samples: Default::default(),
+            scope_enabled: false,
+            scope_cycles: 0,
+            scope: Default::default(),
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        if !self.scope_enabled {
+            self.run(cycles);
+            return;
+        }
+        // Sampled as it goes, so a long tick still plots the wave
+        let mut remaining = cycles;
+        while remaining > 0 {
+            let step = remaining.min(SCOPE_PERIOD - self.scope_cycles);
+            self.run(step);
+            remaining -= step;
+            self.scope_cycles += step;
+            if self.scope_cycles == SCOPE_PERIOD {
+                self.scope_cycles = 0;
+                let outputs = self.outputs();
+                for (scope, output) in self.scope.iter_mut().zip(outputs) {
+                    if scope.len() == SCOPE_LEN {
+                        scope.pop_front();
+                    }
+                    scope.push_back(output);
+                }
+            }
+        }
+    }
+
+    fn run(&mut self, cycles: u32) {
+        if self.powered {
+            self.pulse1.tick(cycles);
+            self.pulse2.tick(cycles);
+            self.wave.tick(cycles);
+            self.noise.tick(cycles);
+
+            self.sequencer_cycles += cycles;
+            while self.sequencer_cycles >= FRAME_SEQUENCER_PERIOD {
+                self.sequencer_cycles -= FRAME_SEQUENCER_PERIOD;
+                self.step_frame_sequencer();
+            }
+        }
+
+        // Each output sample is the average of the mix over its period, a box
+        // filter that keeps the channels' high harmonics from aliasing
+        let levels = self.channel_levels();
+        let output = self.mix(&levels);
+        let rate = self.sample_rate as u64;
+        let mut remaining = cycles as u64;
+        while remaining > 0 {
+            let until_sample = (CPU_CLOCK_HZ - self.sample_clock).div_ceil(rate);
+            let step = remaining.min(until_sample);
+            accumulate(&mut self.accumulated, output, step);
+            if self.stems_enabled {
+                for (sums, level) in self.stem_accumulated.iter_mut().zip(levels) {
+                    accumulate(sums, level, step);
+                }
+            }
+            self.accumulated_cycles += step;
+            self.sample_clock += step * rate;
+            remaining -= step;
+            if self.sample_clock >= CPU_CLOCK_HZ {
+                self.sample_clock -= CPU_CLOCK_HZ;
+                let cycles = self.accumulated_cycles as f32;
+                self.samples.extend(std::mem::take(&mut self.accumulated).map(|sum| sum / cycles));
+                if self.stems_enabled {
+                    for (stem, sums) in self.stem_samples.iter_mut().zip(&mut self.stem_accumulated) {
+                        stem.extend(std::mem::take(sums).map(|sum| sum / cycles));
+                    }
+                }
+                self.accumulated_cycles = 0;
+            }
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // Output samples per second, up to the CPU clock; takes effect from the
+    // next sample
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        self.sample_rate = rate.clamp(1, CPU_CLOCK_HZ as u32);
+    }
+
+    fn step_frame_sequencer(&mut self) {
+        if self.sequencer_step.is_multiple_of(2) {
+            self.pulse1.enabled &= self.pulse1.length.clock();
+            self.pulse2.enabled &= self.pulse2.length.clock();
+            self.wave.enabled &= self.wave.length.clock();
+            self.noise.enabled &= self.noise.length.clock();
+        }
+        if self.sequencer_step == 2 || self.sequencer_step == 6 {
+            self.pulse1.clock_sweep();
+        }
+        if self.sequencer_step == 7 {
+            self.pulse1.envelope.clock();
+            self.pulse2.envelope.clock();
+            self.noise.envelope.clock();
+        }
+        self.sequencer_step = (self.sequencer_step + 1) % 8;
+    }
+
+    // What each channel adds to the left and right outputs: NR51 routes its
+    // DAC to either side, NR50 sets each side's volume from 1/8 to 8/8
+    fn channel_levels(&self) -> [[f32; 2]; 4] {
+        let dac = |enabled: bool, output: u8| {
+            if enabled { output as f32 / 7.5 - 1.0 } else { 0.0 }
+        };
+        let outputs = [
+            dac(self.pulse1.dac_enabled, self.pulse1.output()),
+            dac(self.pulse2.dac_enabled, self.pulse2.output()),
+            dac(self.wave.dac_enabled, self.wave.output()),
+            dac(self.noise.dac_enabled, self.noise.output()),
+        ];
+        let nr50 = self.registers[(NR50 - NR10) as usize];
+        let nr51 = self.registers[(NR51 - NR10) as usize];
+        let gain = [(nr50 >> 4) & 0x07, nr50 & 0x07].map(|volume| (volume + 1) as f32 / 8.0 / 4.0 * 0.25);
+        let mut levels = [[0.0; 2]; 4];
+        for (channel, level) in levels.iter_mut().enumerate() {
+            let routed = [nr51 & (0x10 << channel) != 0, nr51 & (0x01 << channel) != 0];
+            for side in 0..2 {
+                if routed[side] {
+                    level[side] = outputs[channel] * gain[side];
+                }
+            }
+        }
+        levels
+    }
+
+    fn mix(&self, levels: &[[f32; 2]; 4]) -> [f32; 2] {
+        let mut output = [0.0; 2];
+        for (channel, level) in levels.iter().enumerate() {
+            if self.is_audible(channel) {
+                output[0] += level[0];
+                output[1] += level[1];
+            }
+        }
+        output
+    }
+
+    fn is_audible(&self, channel: usize) -> bool {
+        match self.solo {
+            Some(solo) => solo == channel,
+            None => !self.muted[channel],
+        }
+    }
+
+    // Channels are numbered 0-3: pulse 1, pulse 2, wave and noise
+    pub fn set_muted(&mut self, channel: usize, muted: bool) {
+        self.muted[channel] = muted;
+    }
+
+    pub fn is_muted(&self, channel: usize) -> bool {
+        self.muted[channel]
+    }
+
+    // While a channel is soloed only it is heard, whatever is muted
+    pub fn set_solo(&mut self, channel: Option<usize>) {
+        self.solo = channel;
+    }
+
+    pub fn solo(&self) -> Option<usize> {
+        self.solo
+    }
+
+    pub fn set_stems_enabled(&mut self, enabled: bool) {
+        self.stems_enabled = enabled;
+        self.stem_accumulated = [[0.0; 2]; 4];
+        self.stem_samples = Default::default();
+    }
+
+    // Each channel's samples since the last call, laid out like take_samples
+    pub fn take_stems(&mut self) -> [Vec<f32>; 4] {
+        std::mem::take(&mut self.stem_samples)
+    }
+
+    // Interleaved left/right pairs at sample_rate
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    pub fn set_scope_enabled(&mut self, enabled: bool) {
+        self.scope_enabled = enabled;
+        self.scope_cycles = 0;
+        self.scope = Default::default();
+    }
+
+    // Each channel's last SCOPE_LEN outputs, 0-15 and oldest first; fewer
+    // right after the scope is enabled
+    pub fn scope(&self) -> [Vec<u8>; 4] {
+        std::array::from_fn(|channel| self.scope[channel].iter().copied().collect())
+    }
+
+    pub fn channel_status(&self) -> [ChannelStatus; 4] {
+        let clock = CPU_CLOCK_HZ as f32;
+        let pulse = |pulse: &PulseChannel| ChannelStatus {
+            playing: pulse.enabled,
+            volume: pulse.envelope.volume,
+            envelope: Some(pulse.envelope.status()),
+            // Eight steps of the duty pattern per period
+            frequency_hz: clock / (pulse.period() * 8) as f32,
+        };
+        [
+            pulse(&self.pulse1),
+            pulse(&self.pulse2),
+            ChannelStatus {
+                playing: self.wave.enabled,
+                volume: [0, 15, 7, 3][self.wave.volume_code as usize],
+                envelope: None,
+                frequency_hz: clock / (self.wave.period() * 32) as f32,
+            },
+            ChannelStatus {
+                playing: self.noise.enabled,
+                volume: self.noise.envelope.volume,
+                envelope: Some(self.noise.envelope.status()),
+                frequency_hz: clock / self.noise.period() as f32,
+            },
+        ]
+    }
+
+    // The boot chime ends with channel 1 still on but faded out by its
+    // envelope; skipped boots trigger it and then jump to that point
+    pub fn fade_out_boot_chime(&mut self) {
+        self.pulse1.envelope.volume = 0;
+    }
+
+    fn outputs(&self) -> [u8; 4] {
+        [self.pulse1.output(), self.pulse2.output(), self.wave.output(), self.noise.output()]
+    }
+
+    // CGB PCM12/PCM34: current digital output of channels 1+2 and 3+4
+    pub fn pcm12(&self) -> u8 {
+        (self.pulse2.output() << 4) | self.pulse1.output()
+    }
+
+    pub fn pcm34(&self) -> u8 {
+        (self.noise.output() << 4) | self.wave.output()
+    }
+
+    // Register contents as written; the bus adds the bits that read as 1
+    // from the io map
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            NR52 => {
+                let mut value = 0;
+                if self.powered {
+                    value |= 0x80;
+                }
+                for (bit, enabled) in [self.pulse1.enabled, self.pulse2.enabled, self.wave.enabled, self.noise.enabled]
+                    .into_iter()
+                    .enumerate()
+                {
+                    if enabled {
+                        value |= 1 << bit;
+                    }
+                }
+                value
+            }
+            NR10..=0xFF25 => self.registers[(addr - NR10) as usize],
+            WAVE_RAM_START..=WAVE_RAM_END => self.wave.ram[(addr - WAVE_RAM_START) as usize],
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            WAVE_RAM_START..=WAVE_RAM_END => {
+                self.wave.ram[(addr - WAVE_RAM_START) as usize] = value;
+                return;
+            }
+            NR52 => {
+                let powered = value & 0x80 != 0;
+                if self.powered && !powered {
+                    self.power_off();
+                } else if !self.powered && powered {
+                    self.sequencer_step = 0;
+                }
+                self.powered = powered;
+                return;
+            }
+            _ => {}
+        }
+
+        // While powered off every other register ignores writes
+        if !self.powered || !(NR10..NR52).contains(&addr) {
+            return;
+        }
+        self.registers[(addr - NR10) as usize] = value;
+
+        match addr {
+            0xFF10 => {
+                if let Some(sweep) = &mut self.pulse1.sweep {
+                    sweep.period = (value >> 4) & 0x07;
+                    sweep.negate = value & 0x08 != 0;
+                    sweep.shift = value & 0x07;
+                }
+            }
+            0xFF11 => {
+                self.pulse1.duty = value >> 6;
+                self.pulse1.length.load(value & 0x3F);
+            }
+            0xFF12 => {
+                self.pulse1.envelope.write(value);
+                self.pulse1.dac_enabled = value & 0xF8 != 0;
+                self.pulse1.enabled &= self.pulse1.dac_enabled;
+            }
+            0xFF13 => self.pulse1.frequency = (self.pulse1.frequency & 0x0700) | value as u16,
+            0xFF14 => {
+                self.pulse1.frequency = (self.pulse1.frequency & 0x00FF) | ((value as u16 & 0x07) << 8);
+                self.pulse1.length.enabled = value & 0x40 != 0;
+                if value & 0x80 != 0 {
+                    self.pulse1.trigger();
+                }
+            }
+            0xFF16 => {
+                self.pulse2.duty = value >> 6;
+                self.pulse2.length.load(value & 0x3F);
+            }
+            0xFF17 => {
+                self.pulse2.envelope.write(value);
+                self.pulse2.dac_enabled = value & 0xF8 != 0;
+                self.pulse2.enabled &= self.pulse2.dac_enabled;
+            }
+            0xFF18 => self.pulse2.frequency = (self.pulse2.frequency & 0x0700) | value as u16,
+            0xFF19 => {
+                self.pulse2.frequency = (self.pulse2.frequency & 0x00FF) | ((value as u16 & 0x07) << 8);
+                self.pulse2.length.enabled = value & 0x40 != 0;
+                if value & 0x80 != 0 {
+                    self.pulse2.trigger();
+                }
+            }
+            0xFF1A => {
+                self.wave.dac_enabled = value & 0x80 != 0;
+                self.wave.enabled &= self.wave.dac_enabled;
+            }
+            0xFF1B => self.wave.length.load(value),
+            0xFF1C => self.wave.volume_code = (value >> 5) & 0x03,
+            0xFF1D => self.wave.frequency = (self.wave.frequency & 0x0700) | value as u16,
+            0xFF1E => {
+                self.wave.frequency = (self.wave.frequency & 0x00FF) | ((value as u16 & 0x07) << 8);
+                self.wave.length.enabled = value & 0x40 != 0;
+                if value & 0x80 != 0 {
+                    self.wave.trigger();
+                }
+            }
+            0xFF20 => self.noise.length.load(value & 0x3F),
+            0xFF21 => {
+                self.noise.envelope.write(value);
+                self.noise.dac_enabled = value & 0xF8 != 0;
+                self.noise.enabled &= self.noise.dac_enabled;
+            }
+            0xFF22 => {
+                self.noise.clock_shift = value >> 4;
+                self.noise.narrow = value & 0x08 != 0;
+                self.noise.divisor_code = value & 0x07;
+            }
+            0xFF23 => {
+                self.noise.length.enabled = value & 0x40 != 0;
+                if value & 0x80 != 0 {
+                    self.noise.trigger();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Powering off clears every register; wave RAM keeps its contents
+    fn power_off(&mut self) {
+        let wave_ram = self.wave.ram;
+        self.registers = [0; 0x17];
+        self.pulse1 = PulseChannel::new(true);
+        self.pulse2 = PulseChannel::new(false);
+        self.wave = WaveChannel::new();
+        self.wave.ram = wave_ram;
+        self.noise = NoiseChannel::new();
+    }
+}
+
+// Samples waiting for the frontend are not part of the state
+impl Savestate for APU {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.powered);
+        state.write_bytes(&self.registers);
+        self.pulse1.save_state(state);
+        self.pulse2.save_state(state);
+        self.wave.save_state(state);
+        self.noise.save_state(state);
+        state.write_u32(self.sequencer_cycles);
+        state.write_u8(self.sequencer_step);
+        state.write_u64(self.sample_clock);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.powered = state.read_bool()?;
+        state.read_bytes(&mut self.registers)?;
+        self.pulse1.load_state(state)?;
+        self.pulse2.load_state(state)?;
+        self.wave.load_state(state)?;
+        self.noise.load_state(state)?;
+        self.sequencer_cycles = state.read_u32()? % FRAME_SEQUENCER_PERIOD;
+        self.sequencer_step = state.read_u8()? % 8;
+        self.sample_clock = state.read_u64()? % CPU_CLOCK_HZ;
+        self.accumulated = [0.0; 2];
+        self.stem_accumulated = [[0.0; 2]; 4];
+        self.accumulated_cycles = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+// ROMs packed in .gz files or .zip archives, unpacked in memory. Files are
+// told apart by their first bytes rather than their names, and anything that
+// isn't either is taken as a plain ROM.
+
+use crate::inflate::inflate;
+use crate::png::crc32;
+
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+const ZIP_LOCAL_HEADER: [u8; 4] = *b"PK\x03\x04";
+const ZIP_CENTRAL_HEADER: [u8; 4] = *b"PK\x01\x02";
+const ZIP_END_OF_DIRECTORY: [u8; 4] = *b"PK\x05\x06";
+// The end record is 22 bytes plus a comment of up to 65535
+const ZIP_END_SEARCH: usize = 22 + 0xFFFF;
+// Zip entries picked as the ROM, by file name
+const ROM_EXTENSIONS: [&str; 3] = [".gb", ".gbc", ".cgb"];
+
+// Gzip header flags
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+// The ROM inside data if it's a .gz or .zip, or data itself otherwise
+pub fn unpack(data: Vec<u8>) -> Result<Vec<u8>, String> {
+    if data.starts_with(&GZIP_MAGIC) {
+        gunzip(&data)
+    } else if data.starts_with(&ZIP_LOCAL_HEADER) || data.starts_with(&ZIP_END_OF_DIRECTORY) {
+        unzip_rom(&data)
+    } else {
+        Ok(data)
+    }
+}
+
+// A single gzip member (RFC 1952)
+pub fn gunzip(data: &[u8]) -> Result<Vec<u8>, String> {
+    let truncated = || "gzip file is truncated".to_string();
+    if data.get(2) != Some(&8) {
+        return Err("gzip file doesn't use deflate".to_string());
+    }
+    let flags = *data.get(3).ok_or_else(truncated)?;
+    let mut at = 10;
+    if flags & FEXTRA != 0 {
+        at += 2 + read_u16(data, at).ok_or_else(truncated)? as usize;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flag & flags != 0 {
+            let end = data.get(at..).and_then(|rest| rest.iter().position(|&b| b == 0)).ok_or_else(truncated)?;
+            at += end + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        at += 2;
+    }
+    if data.len() < at + 8 {
+        return Err(truncated());
+    }
+
+    let out = inflate(&data[at..data.len() - 8])?;
+    let (crc, size) = (read_u32(data, data.len() - 8), read_u32(data, data.len() - 4));
+    if crc != Some(crc32(&out)) || size != Some(out.len() as u32) {
+        return Err("gzip file is corrupt (checksum mismatch)".to_string());
+    }
+    Ok(out)
+}
+
+// The first entry in the archive's directory with a ROM's extension
+pub fn unzip_rom(data: &[u8]) -> Result<Vec<u8>, String> {
+    let search_from = data.len().saturating_sub(ZIP_END_SEARCH);
+    let end = data[search_from..]
+        .windows(4)
+        .rposition(|window| window == ZIP_END_OF_DIRECTORY)
+        .map(|at| search_from + at)
+        .ok_or("zip archive has no central directory")?;
+    let corrupt = || "zip archive is corrupt".to_string();
+    let entries = read_u16(data, end + 10).ok_or_else(corrupt)?;
+    let mut at = read_u32(data, end + 16).ok_or_else(corrupt)? as usize;
+
+    let mut names = Vec::new();
+    for _ in 0..entries {
+        if data.get(at..at + 4) != Some(&ZIP_CENTRAL_HEADER[..]) {
+            return Err(corrupt());
+        }
+        let field = |offset: usize| read_u16(data, at + offset).map(usize::from).ok_or_else(corrupt);
+        let (name_len, extra_len, comment_len) = (field(28)?, field(30)?, field(32)?);
+        let name_bytes = data.get(at + 46..at + 46 + name_len).ok_or_else(corrupt)?;
+        let name = String::from_utf8_lossy(name_bytes).into_owned();
+        if is_rom_name(&name) {
+            let method = field(10)?;
+            let crc = read_u32(data, at + 16).ok_or_else(corrupt)?;
+            let compressed = read_u32(data, at + 20).ok_or_else(corrupt)? as usize;
+            let local = read_u32(data, at + 42).ok_or_else(corrupt)? as usize;
+            return read_zip_entry(data, &name, method, crc, compressed, local);
+        }
+        names.push(name);
+        at += 46 + name_len + extra_len + comment_len;
+    }
+    if names.is_empty() {
+        Err("zip archive is empty".to_string())
+    } else {
+        Err(format!("zip archive has no .gb or .gbc file, only {}", names.join(", ")))
+    }
+}
+
+fn read_zip_entry(data: &[u8], name: &str, method: usize, crc: u32, compressed: usize, local: usize) -> Result<Vec<u8>, String> {
+    let corrupt = || format!("'{}' in the zip archive is corrupt", name);
+    if data.get(local..local + 4) != Some(&ZIP_LOCAL_HEADER[..]) {
+        return Err(corrupt());
+    }
+    // The local header's name and extra field can differ from the directory's
+    let name_len = read_u16(data, local + 26).ok_or_else(corrupt)? as usize;
+    let extra_len = read_u16(data, local + 28).ok_or_else(corrupt)? as usize;
+    let start = local + 30 + name_len + extra_len;
+    let packed = data.get(start..start + compressed).ok_or_else(corrupt)?;
+    let out = match method {
+        0 => packed.to_vec(),
+        8 => inflate(packed).map_err(|e| format!("'{}' in the zip archive: {}", name, e))?,
+        _ => return Err(format!("'{}' in the zip archive uses unsupported compression method {}", name, method)),
+    };
+    if crc32(&out) != crc {
+        return Err(format!("'{}' in the zip archive is corrupt (checksum mismatch)", name));
+    }
+    Ok(out)
+}
+
+// Leaves out the resource forks macOS adds to archives it makes
+fn is_rom_name(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    !name.starts_with("__macosx/") && ROM_EXTENSIONS.iter().any(|extension| name.ends_with(extension))
+}
+
+fn read_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn read_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
@@ -0,0 +1,72 @@
+use std::str::FromStr;
+
+// Samples faded in and out around a skipped gap, so the cut doesn't click
+const SKIP_FADE_SAMPLES: usize = 32;
+
+// What the audio does while the emulator runs faster than real time. Either
+// way only a real-time amount of audio is queued, which keeps the queue from
+// growing without bound and lets it keep pacing the emulation.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FastForwardAudio {
+    // Squeezes all the audio into real time, raising the pitch
+    Resample,
+    // Plays the most recent stretch at normal pitch and drops the rest
+    Skip,
+}
+
+impl FromStr for FastForwardAudio {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "resample" => Ok(FastForwardAudio::Resample),
+            "skip" => Ok(FastForwardAudio::Skip),
+            _ => Err(format!("unknown fast-forward audio mode '{}'", s)),
+        }
+    }
+}
+
+// Reduces audio produced at `speed` times real time to the real-time length.
+// Samples are interleaved left/right pairs, which stay together.
+pub fn fit_to_speed(samples: &[f32], speed: u32, mode: FastForwardAudio) -> Vec<f32> {
+    let speed = speed.max(1) as usize;
+    if speed == 1 {
+        return samples.to_vec();
+    }
+
+    match mode {
+        // Averaging each group doubles as a crude low-pass filter
+        FastForwardAudio::Resample => samples
+            .chunks(speed * 2)
+            .flat_map(|group| {
+                let pairs = (group.len() / 2).max(1) as f32;
+                let sum = |side: usize| group.iter().skip(side).step_by(2).sum::<f32>() / pairs;
+                [sum(0), sum(1)]
+            })
+            .collect(),
+        FastForwardAudio::Skip => {
+            let pairs = samples.len() / 2;
+            let mut kept = samples[(pairs - pairs / speed) * 2..pairs * 2].to_vec();
+            let len = kept.len() / 2;
+            let fade = SKIP_FADE_SAMPLES.min(len / 2);
+            for i in 0..fade {
+                let gain = i as f32 / fade as f32;
+                for side in 0..2 {
+                    kept[i * 2 + side] *= gain;
+                    kept[(len - 1 - i) * 2 + side] *= gain;
+                }
+            }
+            kept
+        }
+    }
+}
+
+// Spreads audio produced at 1/`divisor` of real time over the real-time
+// length, holding each left/right pair. The pitch drops with the speed.
+pub fn stretch(samples: &[f32], divisor: u32) -> Vec<f32> {
+    samples
+        .chunks_exact(2)
+        .flat_map(|pair| std::iter::repeat_n(pair, divisor.max(1) as usize).flatten())
+        .copied()
+        .collect()
+}
@@ -0,0 +1,97 @@
+use crate::joypad::Button;
+
+// Stick deflection, out of 32767, below which it doesn't count as a direction
+pub const DEFAULT_DEAD_ZONE: i16 = 8000;
+
+// Which host keys press which buttons. Keys are SDL key names ("Z", "Return",
+// "Left Shift"), so this can be read and checked without a window open.
+pub struct Bindings {
+    pub keys: Vec<(String, Button)>,
+    pub dead_zone: i16,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let keys = [
+            ("Right", Button::Right),
+            ("Left", Button::Left),
+            ("Up", Button::Up),
+            ("Down", Button::Down),
+            ("Z", Button::A),
+            ("X", Button::B),
+            ("Return", Button::Start),
+            ("Backspace", Button::Select),
+        ];
+        Bindings {
+            keys: keys.into_iter().map(|(key, button)| (key.to_string(), button)).collect(),
+            dead_zone: DEFAULT_DEAD_ZONE,
+        }
+    }
+}
+
+impl Bindings {
+    // Reads a TOML file like
+    //
+    //   [keys]
+    //   a = "Z"
+    //   start = ["Return", "Space"]
+    //
+    //   [gamepad]
+    //   dead_zone = 8000
+    //
+    // Buttons that aren't listed keep their default keys.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let table: toml::Table = text.parse().map_err(|e: toml::de::Error| e.message().to_string())?;
+        let mut bindings = Bindings::default();
+
+        for (section, value) in &table {
+            let entries = value.as_table().ok_or_else(|| format!("[{}] must be a table", section))?;
+            match section.as_str() {
+                "keys" => {
+                    for (name, keys) in entries {
+                        let button: Button = name.parse()?;
+                        let keys = match keys {
+                            toml::Value::String(key) => vec![key.clone()],
+                            toml::Value::Array(keys) => keys
+                                .iter()
+                                .map(|key| key.as_str().map(str::to_string))
+                                .collect::<Option<Vec<_>>>()
+                                .ok_or_else(|| format!("keys for '{}' must be strings", name))?,
+                            _ => return Err(format!("keys for '{}' must be a string or a list", name)),
+                        };
+                        bindings.keys.retain(|&(_, bound)| bound != button);
+                        bindings.keys.extend(keys.into_iter().map(|key| (key, button)));
+                    }
+                }
+                "gamepad" => {
+                    for (name, value) in entries {
+                        match (name.as_str(), value.as_integer()) {
+                            ("dead_zone", Some(zone)) if (0..=i16::MAX as i64).contains(&zone) => {
+                                bindings.dead_zone = zone as i16;
+                            }
+                            _ => return Err(format!("invalid gamepad setting '{}'", name)),
+                        }
+                    }
+                }
+                _ => return Err(format!("unknown section [{}]", section)),
+            }
+        }
+        Ok(bindings)
+    }
+
+    // Directions an analog stick position presses, as Button masks
+    pub fn stick_directions(&self, x: i16, y: i16) -> u8 {
+        let mut buttons = 0;
+        if x < -self.dead_zone {
+            buttons |= Button::Left.mask();
+        } else if x > self.dead_zone {
+            buttons |= Button::Right.mask();
+        }
+        if y < -self.dead_zone {
+            buttons |= Button::Up.mask();
+        } else if y > self.dead_zone {
+            buttons |= Button::Down.mask();
+        }
+        buttons
+    }
+}
@@ -1,3 +1,5 @@
+use crate::model::HardwareModel;
+
 const BOOT_ROM: [u8; 256] = [
     0x31, 0xFE, 0xFF, 0xAF, 0x21, 0xFF, 0x9F, 0x32, 0xCB, 0x7C, 0x20, 0xFB, 0x21, 0x26, 0xFF, 0x0E,
     0x11, 0x3E, 0x80, 0x32, 0xE2, 0x0C, 0x3E, 0xF3, 0xE2, 0x32, 0x3E, 0x77, 0x77, 0x3E, 0xFC, 0xE0,
@@ -17,7 +19,45 @@ const BOOT_ROM: [u8; 256] = [
     0xF5, 0x06, 0x19, 0x78, 0x86, 0x23, 0x05, 0x20, 0xFB, 0x86, 0x20, 0xFE, 0x3E, 0x01, 0xE0, 0x50,
 ];
 
+pub const DMG_BOOT_ROM_SIZE: usize = 0x100;
+// Mapped at 0000-00FF and 0200-08FF, around the cartridge header
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
 pub fn get_boot_rom() -> &'static [u8; 256] {
     &BOOT_ROM
 }
 
+// I/O registers the boot ROM leaves behind that differ from their power-on
+// values, written in this order. NR52 comes first since the APU ignores
+// writes while powered off, and NR14 retriggers channel 1 so NR52 reads
+// 0xF1 as it does once the boot chime has faded out.
+pub const POST_BOOT_IO: [(u16, u8); 21] = [
+    (0xFF26, 0x80), (0xFF10, 0x80), (0xFF11, 0xBF), (0xFF12, 0xF3), (0xFF13, 0xFF),
+    (0xFF14, 0xBF), (0xFF16, 0x3F), (0xFF18, 0xFF), (0xFF19, 0xBF), (0xFF1A, 0x7F),
+    (0xFF1B, 0xFF), (0xFF1C, 0x9F), (0xFF1D, 0xFF), (0xFF1E, 0xBF), (0xFF20, 0xFF),
+    (0xFF23, 0xBF), (0xFF24, 0x77), (0xFF25, 0xF3), (0xFF40, 0x91), (0xFF47, 0xFC),
+    (0xFF0F, 0xE1),
+];
+
+// Internal divider counter at 0x0100; DIV reads its upper byte. The CGB
+// boot ROM's run time depends on the header, so its value is only typical.
+pub fn post_boot_divider(model: HardwareModel) -> u16 {
+    match model {
+        HardwareModel::Dmg | HardwareModel::Mgb => 0xABCC,
+        HardwareModel::Cgb | HardwareModel::Agb => 0x1EA0,
+    }
+}
+
+// A, F, B, C, D, E, H and L as the boot ROM leaves them when it hands over
+// to the cartridge at 0x0100
+pub fn post_boot_registers(model: HardwareModel, cgb_mode: bool) -> [u8; 8] {
+    match (model, cgb_mode) {
+        (HardwareModel::Dmg, _) => [0x01, 0xB0, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D],
+        (HardwareModel::Mgb, _) => [0xFF, 0xB0, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D],
+        (HardwareModel::Cgb, true) => [0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D],
+        (HardwareModel::Cgb, false) => [0x11, 0x80, 0x00, 0x00, 0x00, 0x08, 0x00, 0x7C],
+        (HardwareModel::Agb, true) => [0x11, 0x00, 0x01, 0x00, 0xFF, 0x56, 0x00, 0x0D],
+        (HardwareModel::Agb, false) => [0x11, 0x00, 0x01, 0x00, 0x00, 0x08, 0x00, 0x7C],
+    }
+}
+
@@ -0,0 +1,66 @@
+use std::str::FromStr;
+
+use crate::interrupts::InterruptType;
+
+// Breaks on something the hardware does rather than on a code address
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum EventBreakpoint {
+    // The PPU entering VBlank
+    VBlank,
+    // The CPU jumping to an interrupt handler
+    Interrupt(InterruptType),
+    // A write that makes (register & mask) become value, e.g. LCDC bit 7 cleared
+    IoWrite { addr: u16, mask: u8, value: u8 },
+}
+
+impl EventBreakpoint {
+    pub fn matches_io_write(&self, addr: u16, old: u8, new: u8) -> bool {
+        match *self {
+            EventBreakpoint::IoWrite { addr: watched, mask, value } => {
+                addr == watched && new & mask == value && old & mask != value
+            }
+            _ => false,
+        }
+    }
+}
+
+// Accepts "vblank", "irq:<vblank|stat|timer|serial|joypad>" and
+// "io:<addr>:<mask>:<value>" with the numbers in hex
+impl FromStr for EventBreakpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        match parts.as_slice() {
+            ["vblank"] => Ok(EventBreakpoint::VBlank),
+            ["irq", name] => {
+                let interrupt = match *name {
+                    "vblank" => InterruptType::VBlank,
+                    "stat" => InterruptType::LcdStat,
+                    "timer" => InterruptType::Timer,
+                    "serial" => InterruptType::Serial,
+                    "joypad" => InterruptType::Joypad,
+                    _ => return Err(format!("unknown interrupt '{}'", name)),
+                };
+                Ok(EventBreakpoint::Interrupt(interrupt))
+            }
+            ["io", addr, mask, value] => {
+                let hex = |text: &str| {
+                    u16::from_str_radix(text.trim_start_matches("0x"), 16)
+                        .map_err(|_| format!("invalid hex value '{}'", text))
+                };
+                let addr = hex(addr)?;
+                if !(0xFF00..=0xFFFF).contains(&addr) {
+                    return Err(format!("0x{:04X} is not an I/O register", addr));
+                }
+                let mask = hex(mask)? as u8;
+                Ok(EventBreakpoint::IoWrite {
+                    addr,
+                    mask,
+                    value: hex(value)? as u8 & mask,
+                })
+            }
+            _ => Err(format!("unknown breakpoint '{}'", s)),
+        }
+    }
+}
@@ -1,15 +1,88 @@
 use crate::memory::Memory;
 use crate::ppu::PPU;
+use crate::apu::APU;
 use crate::timer::Timer;
 use crate::joypad::Joypad;
 use crate::interrupts::Interrupts;
+use crate::io::{self, Owner};
+use crate::model::HardwareModel;
+use crate::serial::Serial;
+use crate::infrared::InfraredPort;
+use crate::hdma::{self, Hdma};
+use crate::debug_port::{DebugPort, DebugPortMode};
+use crate::debugger::Debugger;
+use crate::perf::{Subsystem, SubsystemTimer};
+#[cfg(feature = "cached-interpreter")]
+use crate::decode_cache::Instruction;
+use crate::error::Result;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+
+// OAM DMA moves one byte per machine cycle
+const DMA_CYCLES_PER_BYTE: u32 = 4;
+const OAM_SIZE: u16 = 0xA0;
+
+// An OAM DMA transfer in flight
+#[derive(Copy, Clone)]
+struct OamDma {
+    source: u16,
+    index: u16,
+    cycles: u32,
+}
 
 pub struct MemoryBus {
     pub memory: Memory,
     pub ppu: PPU,
+    pub apu: APU,
     pub timer: Timer,
     pub joypad: Joypad,
+    pub serial: Serial,
+    pub infrared: InfraredPort,
     pub interrupts: Interrupts,
+    pub model: HardwareModel,
+    pub cgb_mode: bool,
+    // KEY1: the CPU, timer, serial port and OAM DMA run at twice the clock in
+    // double speed, the PPU and APU don't. A STOP with the switch armed toggles it.
+    pub double_speed: bool,
+    pub speed_switch_armed: bool,
+    // Set by STOP until a selected button is pressed. The CPU and the timer
+    // wait; the PPU and APU keep running so frames still reach the frontend
+    // that delivers the press.
+    pub stopped: bool,
+    // Values of the io::Owner::Latch registers, and the last write to DMA,
+    // by address from FF00
+    io_latches: [u8; 0x80],
+    dma: Option<OamDma>,
+    hdma: Hdma,
+    // CPU cycles the CPU still has to sit out while VRAM DMA copies
+    hdma_stall: u32,
+    // CPU cycles the CPU's own accesses have advanced the machine by since
+    // the last take_access_cycles
+    access_cycles: u32,
+    // Whether tick_access runs the machine, see AccuracyConfig::access_timing
+    access_timing: bool,
+    // What tick saw happen, for GameBoy::step's hooks and breakpoints
+    hblank_line: Option<u8>,
+    vblank_started: bool,
+    // I/O register writes as (address, old value, new value), only collected
+    // while something is watching them
+    pub record_io_writes: bool,
+    // Time spent in the PPU and APU, while GameBoy::set_profiling has it on
+    pub profile: SubsystemTimer,
+    pub io_writes: Vec<(u16, u8, u8)>,
+    // Addresses GameBoy's write hooks watch, sorted, and the writes to them
+    // as (address, value) since the instruction began
+    pub write_watches: Vec<u16>,
+    pub watched_writes: Vec<(u16, u8)>,
+    // Homebrew printf channel, lines wait in debug_lines until collected
+    pub debug_port: Option<DebugPort>,
+    pub debug_lines: Vec<String>,
+    pub debugger: Debugger,
+}
+
+impl Default for MemoryBus {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MemoryBus {
@@ -17,45 +90,328 @@ impl MemoryBus {
         MemoryBus {
             memory: Memory::new(),
             ppu: PPU::new(),
+            apu: APU::new(),
             timer: Timer::new(),
             joypad: Joypad::new(),
+            serial: Serial::new(),
+            infrared: InfraredPort::new(),
             interrupts: Interrupts::new(),
+            model: HardwareModel::Dmg,
+            cgb_mode: false,
+            double_speed: false,
+            speed_switch_armed: false,
+            stopped: false,
+            io_latches: [0; 0x80],
+            dma: None,
+            hdma: Hdma::new(),
+            hdma_stall: 0,
+            access_cycles: 0,
+            access_timing: true,
+            hblank_line: None,
+            vblank_started: false,
+            record_io_writes: false,
+            profile: SubsystemTimer::default(),
+            io_writes: Vec::new(),
+            write_watches: Vec::new(),
+            watched_writes: Vec::new(),
+            debug_port: None,
+            debug_lines: Vec::new(),
+            debugger: Debugger::new(),
         }
     }
 
     pub fn read_byte(&self, addr: u16) -> u8 {
+        let value = self.read_unwatched(addr);
+        if self.debugger.is_watching() {
+            self.debugger.note_access(addr, value, false);
+        }
+        value
+    }
+
+    // A read that doesn't trip watchpoints, for tools looking at memory
+    pub fn read_unwatched(&self, addr: u16) -> u8 {
         match addr {
-            0x8000..=0x9FFF | 0xFE00..=0xFE9F | 0xFF40..=0xFF4B => self.ppu.read(addr),
-            0xFF04..=0xFF07 => self.timer.read(addr),
-            0xFF00 => self.joypad.read(),
-            0xFF0F => self.interrupts.interrupt_flag,
-            0xFFFF => self.interrupts.interrupt_enable,
+            0x8000..=0x9FFF | 0xFE00..=0xFE9F => self.ppu.read(addr),
+            0xFF00..=0xFF7F | 0xFFFF => self.read_io(addr),
             _ => self.memory.read_byte(addr),
         }
     }
 
+    fn read_io(&self, addr: u16) -> u8 {
+        let Some(register) = io::register(addr).filter(|register| register.is_present(self.model.is_cgb(), self.cgb_mode)) else {
+            return 0xFF;
+        };
+        let value = match register.owner {
+            Owner::Joypad => self.joypad.read(),
+            Owner::Serial => self.serial.read(addr),
+            Owner::Timer => self.timer.read(addr),
+            Owner::InterruptFlag => self.interrupts.interrupt_flag,
+            Owner::InterruptEnable => self.interrupts.interrupt_enable,
+            Owner::Apu => self.apu.read(addr),
+            Owner::Ppu => self.ppu.read(addr),
+            Owner::Key1 => ((self.double_speed as u8) << 7) | self.speed_switch_armed as u8,
+            Owner::Memory => self.memory.read_byte(addr),
+            Owner::Hdma => self.hdma.read(addr),
+            Owner::Pcm if addr == 0xFF76 => self.apu.pcm12(),
+            Owner::Pcm => self.apu.pcm34(),
+            Owner::Infrared => self.infrared.read(),
+            Owner::OamDma | Owner::Latch => self.io_latches[(addr - 0xFF00) as usize],
+        };
+        value | register.unused
+    }
+
     pub fn write_byte(&mut self, addr: u16, value: u8) {
+        if self.debugger.is_watching() {
+            self.debugger.note_access(addr, value, true);
+        }
+        if self.record_io_writes && ((0xFF00..0xFF80).contains(&addr) || addr == 0xFFFF) {
+            let old = self.read_unwatched(addr);
+            self.io_writes.push((addr, old, value));
+        }
+        if self.write_watches.binary_search(&addr).is_ok() {
+            self.watched_writes.push((addr, value));
+        }
+
+        if let Some(port) = &mut self.debug_port {
+            let byte = match port.mode {
+                DebugPortMode::Address(watched) if addr == watched => Some(value),
+                // A transfer started with the internal clock sends SB
+                DebugPortMode::Serial if addr == 0xFF02 && value & 0x81 == 0x81 => {
+                    Some(self.serial.read(0xFF01))
+                }
+                _ => None,
+            };
+            if let Some(byte) = byte {
+                self.debug_lines.extend(port.push(byte));
+            }
+        }
+
         match addr {
-            0xFF46 => self.dma_transfer(value),
-            0x8000..=0x9FFF | 0xFE00..=0xFE9F | 0xFF40..=0xFF4B => self.ppu.write(addr, value),
-            0xFF04..=0xFF07 => self.timer.write(addr, value),
-            0xFF00 => self.joypad.write(value),
-            0xFF0F => self.interrupts.interrupt_flag = value,
-            0xFFFF => self.interrupts.interrupt_enable = value,
+            0x8000..=0x9FFF | 0xFE00..=0xFE9F => self.ppu.write(addr, value),
+            0xFF00..=0xFF7F | 0xFFFF => self.write_io(addr, value),
             _ => self.memory.write_byte(addr, value),
         }
     }
 
-    fn dma_transfer(&mut self, value: u8) {
-        let source = (value as u16) << 8;
-        for i in 0..0xA0 {
-            let byte = self.read_byte(source + i);
-            self.ppu.write(0xFE00 + i, byte);
+    fn write_io(&mut self, addr: u16, value: u8) {
+        let Some(register) = io::register(addr).filter(|register| register.is_present(self.model.is_cgb(), self.cgb_mode)) else {
+            return;
+        };
+        match register.owner {
+            Owner::Joypad => self.joypad.write(value, &mut self.interrupts),
+            Owner::Serial => self.serial.write(addr, value),
+            Owner::Timer => self.timer.write(addr, value),
+            Owner::InterruptFlag => self.interrupts.interrupt_flag = value,
+            Owner::InterruptEnable => self.interrupts.interrupt_enable = value,
+            Owner::Apu => self.apu.write(addr, value),
+            Owner::Ppu => self.ppu.write(addr, value),
+            Owner::OamDma => {
+                self.io_latches[(addr - 0xFF00) as usize] = value;
+                self.start_dma(value);
+            }
+            Owner::Key1 => self.speed_switch_armed = value & 0x01 != 0,
+            Owner::Memory => self.memory.write_byte(addr, value),
+            Owner::Hdma => {
+                if let Some(blocks) = self.hdma.write(addr, value) {
+                    self.copy_hdma_blocks(blocks);
+                }
+            }
+            Owner::Pcm => {}
+            Owner::Infrared => self.infrared.write(value),
+            Owner::Latch => self.io_latches[(addr - 0xFF00) as usize] = value & !register.unused,
         }
     }
 
-    pub fn load_rom(&mut self, rom: &[u8]) {
-        self.memory.load_rom(rom);
+    // Called by STOP; returns false when no switch was armed and STOP should
+    // stop the CPU instead
+    pub fn try_speed_switch(&mut self) -> bool {
+        if !self.cgb_mode || !self.speed_switch_armed {
+            return false;
+        }
+        self.double_speed = !self.double_speed;
+        self.speed_switch_armed = false;
+        true
+    }
+
+    // Converts CPU cycles into cycles of the normal-speed clock that drives
+    // the PPU and APU
+    pub fn base_cycles(&self, cycles: u32) -> u32 {
+        if self.double_speed { cycles / 2 } else { cycles }
+    }
+
+    // Advances everything but the CPU by `cycles` CPU cycles
+    pub fn tick(&mut self, cycles: u32, cpu_halted: bool) {
+        let base_cycles = self.base_cycles(cycles);
+        self.tick_dma(cycles);
+        if !self.stopped {
+            self.timer.tick(cycles, &mut self.interrupts);
+        }
+        self.profile.time(Subsystem::Apu, || self.apu.tick(base_cycles));
+        let was_in_vblank = self.ppu.is_in_vblank();
+        self.profile.time(Subsystem::Ppu, || self.ppu.tick(base_cycles, &mut self.interrupts));
+        self.vblank_started |= !was_in_vblank && self.ppu.is_in_vblank();
+        if let Some(ly) = self.ppu.take_hblank_line() {
+            self.hblank_dma(cpu_halted);
+            self.hblank_line = Some(ly);
+        }
+        self.serial.tick(cycles, &mut self.interrupts);
+        self.memory.tick(base_cycles);
+    }
+
+    // Read watchpoints see instruction fetches, so they turn the cache off
+    #[cfg(feature = "cached-interpreter")]
+    pub fn cached_instruction(&mut self, addr: u16) -> Option<Instruction> {
+        if self.debugger.is_watching() {
+            return None;
+        }
+        self.memory.cached_instruction(addr)
+    }
+
+    // The machine cycle of a CPU read or write. Without access timing
+    // GameBoy::step runs them all with the rest of the instruction.
+    pub fn tick_access(&mut self) {
+        if self.access_timing {
+            self.tick(4, false);
+            self.access_cycles = self.access_cycles.saturating_add(4);
+        }
+    }
+
+    pub fn set_access_timing(&mut self, enabled: bool) {
+        self.access_timing = enabled;
+    }
+
+    pub fn take_access_cycles(&mut self) -> u32 {
+        std::mem::take(&mut self.access_cycles)
+    }
+
+    // The line whose HBlank started during the last ticks, if any
+    pub fn take_hblank_line(&mut self) -> Option<u8> {
+        self.hblank_line.take()
+    }
+
+    pub fn take_vblank_started(&mut self) -> bool {
+        std::mem::take(&mut self.vblank_started)
+    }
+
+    // Writing FF46 (re)starts a transfer from value * 0x100
+    fn start_dma(&mut self, value: u8) {
+        self.dma = Some(OamDma {
+            source: (value as u16) << 8,
+            index: 0,
+            cycles: 0,
+        });
+    }
+
+    pub fn tick_dma(&mut self, cycles: u32) {
+        let Some(mut dma) = self.dma else {
+            return;
+        };
+
+        dma.cycles += cycles;
+        while dma.cycles >= DMA_CYCLES_PER_BYTE && dma.index < OAM_SIZE {
+            dma.cycles -= DMA_CYCLES_PER_BYTE;
+            let byte = self.dma_source_byte(dma.source + dma.index);
+            self.ppu.oam[dma.index as usize] = byte;
+            dma.index += 1;
+        }
+
+        self.dma = if dma.index < OAM_SIZE { Some(dma) } else { None };
+    }
+
+    // The DMA engine has its own view of the bus: cartridge ROM and RAM go through
+    // the MBC with the current banks, VRAM through the PPU, and anything from 0xE000
+    // up lands on work RAM instead of echo RAM, OAM or the I/O registers
+    fn dma_source_byte(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x7FFF | 0xA000..=0xDFFF => self.memory.read_byte(addr),
+            0x8000..=0x9FFF => self.ppu.read_vram(addr),
+            0xE000..=0xFFFF => self.memory.read_byte(addr - 0x2000),
+        }
+    }
+
+    // Called as a visible line enters HBlank. A halted CPU holds the transfer
+    // back; the block is copied at the first HBlank after it wakes.
+    pub fn hblank_dma(&mut self, cpu_halted: bool) {
+        if self.hdma.is_hblank_active() && !cpu_halted {
+            self.copy_hdma_blocks(1);
+        }
+    }
+
+    fn copy_hdma_blocks(&mut self, blocks: u16) {
+        for _ in 0..blocks {
+            let (source, dest) = self.hdma.next_block();
+            for offset in 0..hdma::BLOCK_SIZE {
+                let byte = self.dma_source_byte(source.wrapping_add(offset));
+                self.ppu.write_vram(dest + offset, byte);
+            }
+        }
+        self.hdma_stall += Hdma::stall_cycles(blocks, self.double_speed);
+    }
+
+    // CPU cycles the CPU is stopped for by VRAM DMA copied since the last call
+    pub fn take_hdma_stall(&mut self) -> u32 {
+        std::mem::take(&mut self.hdma_stall)
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<()> {
+        self.memory.load_rom(rom)
     }
 }
 
+// Saves every component behind the bus. The hardware model and link cable
+// are configuration rather than machine state and are left as they are.
+impl Savestate for MemoryBus {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.memory.save_state(state);
+        self.ppu.save_state(state);
+        self.apu.save_state(state);
+        self.timer.save_state(state);
+        self.joypad.save_state(state);
+        self.serial.save_state(state);
+        self.infrared.save_state(state);
+        self.interrupts.save_state(state);
+        state.write_bool(self.cgb_mode);
+        state.write_bool(self.double_speed);
+        state.write_bool(self.speed_switch_armed);
+        state.write_bool(self.stopped);
+        state.write_bytes(&self.io_latches);
+
+        // A partially copied OAM DMA resumes from the next byte on load
+        state.write_bool(self.dma.is_some());
+        let dma = self.dma.unwrap_or(OamDma { source: 0, index: 0, cycles: 0 });
+        state.write_u16(dma.source);
+        state.write_u16(dma.index);
+        state.write_u32(dma.cycles);
+
+        self.hdma.save_state(state);
+        state.write_u32(self.hdma_stall);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.memory.load_state(state)?;
+        self.ppu.load_state(state)?;
+        self.apu.load_state(state)?;
+        self.timer.load_state(state)?;
+        self.joypad.load_state(state)?;
+        self.serial.load_state(state)?;
+        self.infrared.load_state(state)?;
+        self.interrupts.load_state(state)?;
+        self.cgb_mode = state.read_bool()?;
+        self.double_speed = state.read_bool()?;
+        self.speed_switch_armed = state.read_bool()?;
+        self.stopped = state.read_bool()?;
+        state.read_bytes(&mut self.io_latches)?;
+
+        let active = state.read_bool()?;
+        let dma = OamDma {
+            source: state.read_u16()?,
+            index: state.read_u16()?.min(OAM_SIZE),
+            cycles: state.read_u32()?,
+        };
+        self.dma = if active { Some(dma) } else { None };
+
+        self.hdma.load_state(state)?;
+        self.hdma_stall = state.read_u32()?;
+        Ok(())
+    }
+}
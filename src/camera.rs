@@ -0,0 +1,182 @@
+// The Game Boy Camera's sensor, as its cartridge maps it: a start register,
+// exposure and edge settings, and a 4x4 dithering matrix that turns each
+// captured gray level into one of four colors. Finished pictures land in
+// cartridge RAM bank 0 as 16x14 tiles, where the Camera ROM copies them from.
+// The picture comes from a CameraSource: a test pattern, a still image, or
+// whatever a frontend supplies.
+
+use crate::error::Result;
+use crate::png::Image;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+
+pub const CAMERA_WIDTH: usize = 128;
+pub const CAMERA_HEIGHT: usize = 112;
+
+// Registers A000-A035, mirrored through the rest of the 128-byte page
+const REGISTER_COUNT: usize = 0x36;
+const REGISTER_MASK: u16 = 0x7F;
+// Register 0 bit 0 starts a capture and stays set until it's done
+const CAPTURE_BIT: u8 = 0x01;
+const EXPOSURE_HIGH: usize = 2;
+const EXPOSURE_LOW: usize = 3;
+const MATRIX_START: usize = 6;
+// Where in RAM bank 0 the picture goes
+pub const PICTURE_START: usize = 0x100;
+const PICTURE_SIZE: usize = CAMERA_WIDTH * CAMERA_HEIGHT / 4;
+// A capture takes a fixed readout time plus the exposure, which counts in
+// units of 16 cycles of the sensor's 1MHz clock
+const CAPTURE_CYCLES: u32 = 32446 * 4;
+const CYCLES_PER_EXPOSURE_STEP: u32 = 64;
+
+// Pictures for the sensor to take, 128x112 gray levels from 0 (black) to 255
+pub trait CameraSource: Send {
+    fn capture(&mut self) -> Vec<u8>;
+}
+
+// Gray bars with a circle over them, so there's something to see in the
+// Camera ROM without a picture of one's own
+pub struct TestPattern;
+
+impl CameraSource for TestPattern {
+    fn capture(&mut self) -> Vec<u8> {
+        let (cx, cy) = (CAMERA_WIDTH as i32 / 2, CAMERA_HEIGHT as i32 / 2);
+        let mut pixels = Vec::with_capacity(CAMERA_WIDTH * CAMERA_HEIGHT);
+        for y in 0..CAMERA_HEIGHT as i32 {
+            for x in 0..CAMERA_WIDTH as i32 {
+                let bar = (x * 8 / CAMERA_WIDTH as i32) as u8;
+                let level = 255 - bar * 36;
+                let distance = (x - cx).pow(2) + (y - cy).pow(2);
+                let in_circle = distance < 40 * 40;
+                pixels.push(if in_circle { 255 - level } else { level });
+            }
+        }
+        pixels
+    }
+}
+
+// The same picture every time, from an image of any size
+pub struct StillImage {
+    pixels: Vec<u8>,
+}
+
+impl StillImage {
+    // Stretched to the sensor's size and turned gray
+    pub fn new(image: &Image) -> Self {
+        let mut pixels = Vec::with_capacity(CAMERA_WIDTH * CAMERA_HEIGHT);
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let (sx, sy) = (x * image.width / CAMERA_WIDTH, y * image.height / CAMERA_HEIGHT);
+                let at = (sy * image.width + sx) * 3;
+                let rgb = &image.pixels[at..at + 3];
+                pixels.push(((rgb[0] as u32 * 299 + rgb[1] as u32 * 587 + rgb[2] as u32 * 114) / 1000) as u8);
+            }
+        }
+        StillImage { pixels }
+    }
+}
+
+impl CameraSource for StillImage {
+    fn capture(&mut self) -> Vec<u8> {
+        self.pixels.clone()
+    }
+}
+
+pub struct CameraSensor {
+    registers: [u8; REGISTER_COUNT],
+    // Cycles until the capture in progress is done, 0 when idle
+    busy_cycles: u32,
+    source: Box<dyn CameraSource>,
+}
+
+impl Default for CameraSensor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CameraSensor {
+    pub fn new() -> Self {
+        CameraSensor { registers: [0; REGISTER_COUNT], busy_cycles: 0, source: Box::new(TestPattern) }
+    }
+
+    pub fn set_source(&mut self, source: Box<dyn CameraSource>) {
+        self.source = source;
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.busy_cycles > 0
+    }
+
+    // Only register 0 can be read back, the rest read 0
+    pub fn read(&self, addr: u16) -> u8 {
+        match (addr & REGISTER_MASK) as usize {
+            0 => (self.registers[0] & 0x06) | self.is_busy() as u8,
+            _ => 0x00,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        let register = (addr & REGISTER_MASK) as usize;
+        if register >= REGISTER_COUNT {
+            return;
+        }
+        self.registers[register] = value;
+        if register == 0 {
+            if value & CAPTURE_BIT != 0 && !self.is_busy() {
+                let exposure = u16::from_be_bytes([self.registers[EXPOSURE_HIGH], self.registers[EXPOSURE_LOW]]);
+                self.busy_cycles = CAPTURE_CYCLES + exposure as u32 * CYCLES_PER_EXPOSURE_STEP;
+            } else if value & CAPTURE_BIT == 0 {
+                // Stops a capture early, leaving the last picture in place
+                self.busy_cycles = 0;
+            }
+        }
+    }
+
+    // Develops the picture into ram once the capture time is up
+    pub fn tick(&mut self, cycles: u32, ram: &mut [u8]) {
+        if !self.is_busy() {
+            return;
+        }
+        self.busy_cycles = self.busy_cycles.saturating_sub(cycles);
+        if !self.is_busy() {
+            let pixels = self.source.capture();
+            let picture = self.dither(&pixels);
+            if let Some(target) = ram.get_mut(PICTURE_START..PICTURE_START + PICTURE_SIZE) {
+                target.copy_from_slice(&picture);
+            }
+        }
+    }
+
+    // Each pixel is compared against the three thresholds at its place in
+    // the 4x4 matrix: darker than the first is black, lighter than all three
+    // is white. The result is laid out as 2bpp tiles.
+    fn dither(&self, pixels: &[u8]) -> Vec<u8> {
+        let mut tiles = vec![0; PICTURE_SIZE];
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let level = pixels.get(y * CAMERA_WIDTH + x).copied().unwrap_or(0);
+                let at = MATRIX_START + ((y % 4) * 4 + x % 4) * 3;
+                let thresholds = &self.registers[at..at + 3];
+                let color = 3 - thresholds.iter().filter(|&&threshold| level >= threshold).count() as u8;
+                let row = ((y / 8) * (CAMERA_WIDTH / 8) + x / 8) * 16 + (y % 8) * 2;
+                let bit = 7 - (x % 8);
+                tiles[row] |= (color & 1) << bit;
+                tiles[row + 1] |= (color >> 1) << bit;
+            }
+        }
+        tiles
+    }
+}
+
+impl Savestate for CameraSensor {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.registers);
+        state.write_u32(self.busy_cycles);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_bytes(&mut self.registers)?;
+        self.busy_cycles = state.read_u32()?;
+        Ok(())
+    }
+}
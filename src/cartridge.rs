@@ -0,0 +1,154 @@
+use std::fs;
+use std::path::Path;
+
+use crate::archive;
+use crate::error::{EmulatorError, Result};
+use crate::model::HardwareModel;
+
+const TITLE_START: usize = 0x134;
+const CGB_FLAG: usize = 0x143;
+const CARTRIDGE_TYPE: usize = 0x147;
+const ROM_SIZE: usize = 0x148;
+const RAM_SIZE: usize = 0x149;
+const HEADER_END: usize = 0x150;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum CgbFlag {
+    DmgOnly,
+    Enhanced,
+    CgbOnly,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum CompatDecision {
+    // Plain DMG cartridge
+    Dmg,
+    // CGB-enhanced or CGB-only cartridge running in CGB mode
+    Cgb,
+    // CGB-enhanced cartridge running its DMG code path
+    EnhancedAsDmg,
+    // CGB-only cartridge forced into DMG-compat mode on request
+    CgbOnlyForcedDmg,
+    // CGB-only cartridge on DMG hardware; likely to show a blank screen
+    CgbOnlyUnsupported,
+}
+
+impl CompatDecision {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            CompatDecision::Dmg => "DMG",
+            CompatDecision::Cgb => "CGB",
+            CompatDecision::EnhancedAsDmg => "DMG (CGB-enhanced cartridge running its DMG code path)",
+            CompatDecision::CgbOnlyForcedDmg => "DMG-compat (forced for CGB-only cartridge)",
+            CompatDecision::CgbOnlyUnsupported => "CGB-only cartridge on DMG hardware",
+        }
+    }
+
+    pub fn is_warning(&self) -> bool {
+        matches!(self, CompatDecision::CgbOnlyForcedDmg | CompatDecision::CgbOnlyUnsupported)
+    }
+}
+
+// Reads a ROM, unpacking it first if it's in a .gz file or .zip archive
+pub fn read_rom_file(path: &Path) -> Result<Vec<u8>> {
+    let data = fs::read(path).map_err(|source| EmulatorError::RomRead {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let rom = archive::unpack(data).map_err(|message| EmulatorError::RomArchive {
+        path: path.to_path_buf(),
+        message,
+    })?;
+    if rom.len() < HEADER_END {
+        return Err(EmulatorError::RomTooSmall(rom.len()));
+    }
+    Ok(rom)
+}
+
+pub struct CartridgeHeader {
+    pub title: String,
+    pub cgb_flag: CgbFlag,
+    pub cartridge_type: u8,
+    pub rom_size_kb: usize,
+    pub ram_size_code: u8,
+}
+
+impl CartridgeHeader {
+    pub fn parse(rom: &[u8]) -> Option<Self> {
+        if rom.len() < HEADER_END {
+            return None;
+        }
+
+        let cgb_flag = match rom[CGB_FLAG] {
+            0xC0 => CgbFlag::CgbOnly,
+            0x80 => CgbFlag::Enhanced,
+            _ => CgbFlag::DmgOnly,
+        };
+
+        // On CGB cartridges the last title byte is taken over by the CGB flag
+        let title_end = if cgb_flag == CgbFlag::DmgOnly { 0x144 } else { CGB_FLAG };
+        let title = String::from_utf8_lossy(&rom[TITLE_START..title_end])
+            .trim_end_matches('\0')
+            .to_string();
+
+        Some(CartridgeHeader {
+            title,
+            cgb_flag,
+            cartridge_type: rom[CARTRIDGE_TYPE],
+            rom_size_kb: 32usize << rom[ROM_SIZE].min(8),
+            ram_size_code: rom[RAM_SIZE],
+        })
+    }
+
+    pub fn cartridge_type_name(&self) -> &'static str {
+        match self.cartridge_type {
+            0x00 => "ROM ONLY",
+            0x01 => "MBC1",
+            0x02 => "MBC1+RAM",
+            0x03 => "MBC1+RAM+BATTERY",
+            0x08 => "ROM+RAM",
+            0x09 => "ROM+RAM+BATTERY",
+            0x0F => "MBC3+TIMER+BATTERY",
+            0x10 => "MBC3+TIMER+RAM+BATTERY",
+            0x11 => "MBC3",
+            0x12 => "MBC3+RAM",
+            0x13 => "MBC3+RAM+BATTERY",
+            0x19 => "MBC5",
+            0x1A => "MBC5+RAM",
+            0x1B => "MBC5+RAM+BATTERY",
+            0x1C => "MBC5+RUMBLE",
+            0x1D => "MBC5+RUMBLE+RAM",
+            0x1E => "MBC5+RUMBLE+RAM+BATTERY",
+            0xFC => "POCKET CAMERA",
+            0xFE => "HuC3",
+            0xFF => "HuC1+RAM+BATTERY",
+            _ => "Unknown",
+        }
+    }
+
+    pub fn has_battery(&self) -> bool {
+        matches!(self.cartridge_type, 0x03 | 0x09 | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0xFC | 0xFE | 0xFF)
+    }
+
+    pub fn has_rtc(&self) -> bool {
+        matches!(self.cartridge_type, 0x0F | 0x10 | 0xFE)
+    }
+
+    pub fn compat_decision(&self, force_dmg: bool, cgb_hardware: bool) -> CompatDecision {
+        match self.cgb_flag {
+            CgbFlag::DmgOnly => CompatDecision::Dmg,
+            CgbFlag::CgbOnly if force_dmg => CompatDecision::CgbOnlyForcedDmg,
+            CgbFlag::CgbOnly if !cgb_hardware => CompatDecision::CgbOnlyUnsupported,
+            CgbFlag::Enhanced if force_dmg || !cgb_hardware => CompatDecision::EnhancedAsDmg,
+            CgbFlag::Enhanced | CgbFlag::CgbOnly => CompatDecision::Cgb,
+        }
+    }
+
+    // The hardware a cartridge was made for, used when no model is requested
+    pub fn preferred_model(&self) -> HardwareModel {
+        match self.cgb_flag {
+            CgbFlag::DmgOnly => HardwareModel::Dmg,
+            CgbFlag::Enhanced | CgbFlag::CgbOnly => HardwareModel::Cgb,
+        }
+    }
+}
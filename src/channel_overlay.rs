@@ -0,0 +1,70 @@
+// The audio channel overlay: the screen split into a strip per channel, each
+// plotting the channel's recent output left to right with its frequency and
+// volume envelope above, so chiptune authors and anyone debugging the APU
+// can see what every part of a track is doing.
+
+use crate::apu::ChannelStatus;
+use crate::overlay::{OverlayText, LINE_HEIGHT};
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const NAMES: [&str; 4] = ["PULSE 1", "PULSE 2", "WAVE", "NOISE"];
+const COLORS: [[u8; 3]; 4] = [[0xFF, 0x60, 0x60], [0xFF, 0xC0, 0x40], [0x60, 0xC0, 0xFF], [0xA0, 0xFF, 0x80]];
+// Labels of channels that aren't playing
+const SILENT_COLOR: [u8; 3] = [0x80, 0x80, 0x80];
+
+pub const STRIP_HEIGHT: usize = SCREEN_HEIGHT / 4;
+// Under the label; output 15 is plotted at its top and 0 at its bottom
+const PLOT_TOP: usize = LINE_HEIGHT as usize + 2;
+const PLOT_HEIGHT: usize = STRIP_HEIGHT - PLOT_TOP - 2;
+
+// Over an RGB24 frame, which is darkened so the plots stand out. Each scope
+// is drawn with its newest output at the right edge.
+pub fn draw(pixels: &mut [u8], status: &[ChannelStatus; 4], scope: &[Vec<u8>; 4]) {
+    for value in pixels.iter_mut() {
+        *value /= 2;
+    }
+    for (channel, (status, scope)) in status.iter().zip(scope).enumerate() {
+        let top = channel * STRIP_HEIGHT;
+        let color = if status.playing { COLORS[channel] } else { SILENT_COLOR };
+        OverlayText { x: 2, y: top as i32 + 1, text: label(channel, status), color }.draw(pixels);
+
+        let y_of = |output: u8| top + PLOT_TOP + (15 - output.min(15) as usize) * (PLOT_HEIGHT - 1) / 15;
+        let start = SCREEN_WIDTH.saturating_sub(scope.len());
+        let mut previous = None;
+        for (x, &output) in (start..SCREEN_WIDTH).zip(scope.iter().skip(scope.len().saturating_sub(SCREEN_WIDTH))) {
+            let y = y_of(output);
+            // Joined up, so a square wave shows its edges
+            let (from, to) = match previous {
+                Some(last) if last < y => (last, y),
+                Some(last) => (y, last),
+                None => (y, y),
+            };
+            for y in from..=to {
+                put_pixel(pixels, x, y, COLORS[channel]);
+            }
+            previous = Some(y);
+        }
+    }
+}
+
+// "PULSE 1  440HZ  VOL 15 -3": the envelope's direction and period follow
+// the volume when it's moving
+pub fn label(channel: usize, status: &ChannelStatus) -> String {
+    if !status.playing {
+        return format!("{}  OFF", NAMES[channel]);
+    }
+    let envelope = match status.envelope {
+        Some(envelope) if envelope.period > 0 => {
+            format!(" {}{}", if envelope.increase { '+' } else { '-' }, envelope.period)
+        }
+        _ => String::new(),
+    };
+    format!("{}  {:.0}HZ  VOL {}{}", NAMES[channel], status.frequency_hz, status.volume, envelope)
+}
+
+fn put_pixel(pixels: &mut [u8], x: usize, y: usize, color: [u8; 3]) {
+    let at = (y * SCREEN_WIDTH + x) * 3;
+    if let Some(pixel) = pixels.get_mut(at..at + 3) {
+        pixel.copy_from_slice(&color);
+    }
+}
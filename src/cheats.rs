@@ -0,0 +1,108 @@
+use std::str::FromStr;
+
+// Replaces a ROM byte as it is read. With a compare value the byte is only
+// replaced where the ROM holds that value, which keeps the patch off other
+// banks mapped at the same address.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GeniePatch {
+    pub addr: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+}
+
+// Writes a RAM byte once per frame. `bank` picks the CGB work RAM bank for
+// D000-DFFF, whatever SVBK says.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SharkWrite {
+    pub addr: u16,
+    pub value: u8,
+    pub bank: Option<u8>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CheatCode {
+    Genie(GeniePatch),
+    Shark(SharkWrite),
+}
+
+impl FromStr for CheatCode {
+    type Err = String;
+
+    // Game Genie codes are ABC-DEF or ABC-DEF-GHI, GameShark codes ABCDEFGH
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid cheat code '{}'", s);
+        let digits: Vec<u8> = s
+            .chars()
+            .filter(|&c| c != '-')
+            .map(|c| c.to_digit(16).map(|digit| digit as u8))
+            .collect::<Option<_>>()
+            .ok_or_else(invalid)?;
+        let byte = |high: usize, low: usize| digits[high] << 4 | digits[low];
+
+        match (digits.len(), s.contains('-')) {
+            // AB is the new value, FCDE the address with F inverted and GI
+            // the compare value, XORed with BA and rotated; H is a checksum
+            (6 | 9, _) => {
+                let addr = ((digits[5] ^ 0x0F) as u16) << 12 | (digits[2] as u16) << 8 | (digits[3] as u16) << 4 | digits[4] as u16;
+                if addr >= 0x8000 {
+                    return Err(invalid());
+                }
+                let compare = (digits.len() == 9).then(|| (byte(6, 8) ^ 0xBA).rotate_left(2));
+                Ok(CheatCode::Genie(GeniePatch { addr, value: byte(0, 1), compare }))
+            }
+            // AB is the type, CD the value and GHEF the address. Types 90-97
+            // write that work RAM bank.
+            (8, false) => {
+                let addr = u16::from_be_bytes([byte(6, 7), byte(4, 5)]);
+                if !(0xA000..=0xDFFF).contains(&addr) {
+                    return Err(invalid());
+                }
+                let bank = (digits[0] == 9).then_some(digits[1] & 0x07);
+                Ok(CheatCode::Shark(SharkWrite { addr, value: byte(2, 3), bank }))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+// A code as loaded, with the text it was written as
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cheat {
+    pub code: CheatCode,
+    pub text: String,
+    pub name: String,
+    pub enabled: bool,
+}
+
+impl Cheat {
+    pub fn parse(text: &str, name: &str) -> Result<Self, String> {
+        Ok(Cheat { code: text.parse()?, text: text.to_string(), name: name.to_string(), enabled: true })
+    }
+
+    // One line for listings: "2 on  01FF2CD0 Infinite health"
+    pub fn describe(&self, index: usize) -> String {
+        format!("{} {:<3} {} {}", index, if self.enabled { "on" } else { "off" }, self.text, self.name).trim_end().to_string()
+    }
+}
+
+// A cheat file has one code per line, optionally followed by a name.
+// Blank lines and lines starting with # are skipped.
+pub fn parse_file(text: &str) -> Result<Vec<Cheat>, String> {
+    text.lines()
+        .enumerate()
+        .map(|(number, line)| (number, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| {
+            let (code, name) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            Cheat::parse(code, name.trim()).map_err(|message| format!("line {}: {}", number + 1, message))
+        })
+        .collect()
+}
+
+// The byte the CPU sees at a ROM address with the patches applied
+pub fn patch_rom(patches: &[GeniePatch], addr: u16, value: u8) -> u8 {
+    patches
+        .iter()
+        .find(|patch| patch.addr == addr && patch.compare.is_none_or(|compare| compare == value))
+        .map_or(value, |patch| patch.value)
+}
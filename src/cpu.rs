@@ -1,13 +1,36 @@
 use crate::bus::MemoryBus;
+#[cfg(feature = "cached-interpreter")]
+use crate::decode_cache::Instruction;
+use crate::error::Result;
+use crate::interrupts::InterruptType;
+use crate::savestate::{Savestate, StateReader, StateWriter};
 
 const INITIAL_PC: u16 = 0x0100;
 const INITIAL_SP: u16 = 0xFFFE;
+// The CPU waits 2050 machine cycles for the clock to settle after STOP
+// switches speed
+const SPEED_SWITCH_CYCLES: u32 = 2050 * 4;
 
 const ZERO_FLAG: u8 = 0b1000_0000;
 const SUBTRACT_FLAG: u8 = 0b0100_0000;
 const HALF_CARRY_FLAG: u8 = 0b0010_0000;
 const CARRY_FLAG: u8 = 0b0001_0000;
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RegisterSnapshot {
+  pub a: u8,
+  pub f: u8,
+  pub b: u8,
+  pub c: u8,
+  pub d: u8,
+  pub e: u8,
+  pub h: u8,
+  pub l: u8,
+  pub sp: u16,
+  pub pc: u16,
+}
+
+#[allow(clippy::upper_case_acronyms)]
 pub struct CPU {
   pub a: u8,
   pub b: u8,
@@ -22,6 +45,27 @@ pub struct CPU {
   pub bus: MemoryBus,
   pub cycles: u64,
   pub halted: bool,
+  // HALT with IME clear and an interrupt already pending doesn't halt, and
+  // the byte after it is fetched twice
+  halt_bug: bool,
+  // EI sets IME only after the instruction that follows it
+  ime_pending: bool,
+  // Set when a step dispatches an interrupt, for event breakpoints
+  dispatched_interrupt: Option<InterruptType>,
+  // Set when a step runs an opcode that doesn't exist, for reporting the lock-up
+  invalid_opcode: Option<u8>,
+  // The instruction being run, from the decode cache, and how much of it
+  // has been fetched
+  #[cfg(feature = "cached-interpreter")]
+  prefetched: Instruction,
+  #[cfg(feature = "cached-interpreter")]
+  prefetch_index: u8,
+}
+
+impl Default for CPU {
+  fn default() -> Self {
+    Self::new()
+  }
 }
 
 impl CPU {
@@ -40,6 +84,14 @@ impl CPU {
       bus: MemoryBus::new(),
       cycles: 0,
       halted: false,
+      halt_bug: false,
+      ime_pending: false,
+      dispatched_interrupt: None,
+      invalid_opcode: None,
+      #[cfg(feature = "cached-interpreter")]
+      prefetched: Instruction::default(),
+      #[cfg(feature = "cached-interpreter")]
+      prefetch_index: 0,
     }
   }
 
@@ -128,6 +180,22 @@ impl CPU {
     }
   }
 
+  pub fn snapshot(&self) -> RegisterSnapshot {
+    RegisterSnapshot {
+      a: self.a,
+      f: self.f,
+      b: self.b,
+      c: self.c,
+      d: self.d,
+      e: self.e,
+      h: self.h,
+      l: self.l,
+      sp: self.sp,
+      pc: self.pc,
+    }
+  }
+
+  #[allow(dead_code)]
   pub fn show_cpu_state(&self) {
     println!("A: {:02X}, B: {:02X}, C: {:02X}", self.a, self.b, self.c);
     println!("D: {:02X}, E: {:02X}, H: {:02X}, L: {:02X}, F: {:02X}", self.d, self.e, self.h, self.l, self.f);
@@ -164,21 +232,23 @@ impl CPU {
 
   pub fn add_to_a(&mut self, value: u8) {
     let (result, carry) = self.a.overflowing_add(value);
+    let half_carry = (self.a & 0x0F) + (value & 0x0F) > 0x0F;
     self.a = result;
 
     self.set_zero_flag(self.a == 0);
     self.set_carry_flag(carry);
-    self.set_half_carry_flag((self.a & 0x0F) + (value & 0x0F) > 0x0F);
+    self.set_half_carry_flag(half_carry);
     self.set_subtract_flag(false);
   }
 
   pub fn sub_from_a(&mut self, value: u8) {
     let (result, carry) = self.a.overflowing_sub(value);
+    let half_carry = (self.a & 0x0F) < (value & 0x0F);
     self.a = result;
 
     self.set_zero_flag(self.a == 0);
     self.set_carry_flag(carry);
-    self.set_half_carry_flag((self.a & 0x0F) + (value & 0x0F) > 0x0F);
+    self.set_half_carry_flag(half_carry);
     self.set_subtract_flag(true);
   }
 
@@ -222,7 +292,7 @@ impl CPU {
       }
 
       // 8-bit loads: LD r, r'
-      0x7F => { self.a = self.a; 4 } // LD A, A
+      0x7F => 4, // LD A, A
       0x78 => { self.a = self.b; 4 } // LD A, B
       0x79 => { self.a = self.c; 4 } // LD A, C
       0x7A => { self.a = self.d; 4 } // LD A, D
@@ -231,7 +301,7 @@ impl CPU {
       0x7D => { self.a = self.l; 4 } // LD A, L
 
       0x47 => { self.b = self.a; 4 } // LD B, A
-      0x40 => { self.b = self.b; 4 } // LD B, B
+      0x40 => 4, // LD B, B
       0x41 => { self.b = self.c; 4 } // LD B, C
       0x42 => { self.b = self.d; 4 } // LD B, D
       0x43 => { self.b = self.e; 4 } // LD B, E
@@ -240,7 +310,7 @@ impl CPU {
 
       0x4F => { self.c = self.a; 4 } // LD C, A
       0x48 => { self.c = self.b; 4 } // LD C, B
-      0x49 => { self.c = self.c; 4 } // LD C, C
+      0x49 => 4, // LD C, C
       0x4A => { self.c = self.d; 4 } // LD C, D
       0x4B => { self.c = self.e; 4 } // LD C, E
       0x4C => { self.c = self.h; 4 } // LD C, H
@@ -249,7 +319,7 @@ impl CPU {
       0x57 => { self.d = self.a; 4 } // LD D, A
       0x50 => { self.d = self.b; 4 } // LD D, B
       0x51 => { self.d = self.c; 4 } // LD D, C
-      0x52 => { self.d = self.d; 4 } // LD D, D
+      0x52 => 4, // LD D, D
       0x53 => { self.d = self.e; 4 } // LD D, E
       0x54 => { self.d = self.h; 4 } // LD D, H
       0x55 => { self.d = self.l; 4 } // LD D, L
@@ -258,7 +328,7 @@ impl CPU {
       0x58 => { self.e = self.b; 4 } // LD E, B
       0x59 => { self.e = self.c; 4 } // LD E, C
       0x5A => { self.e = self.d; 4 } // LD E, D
-      0x5B => { self.e = self.e; 4 } // LD E, E
+      0x5B => 4, // LD E, E
       0x5C => { self.e = self.h; 4 } // LD E, H
       0x5D => { self.e = self.l; 4 } // LD E, L
 
@@ -267,7 +337,7 @@ impl CPU {
       0x61 => { self.h = self.c; 4 } // LD H, C
       0x62 => { self.h = self.d; 4 } // LD H, D
       0x63 => { self.h = self.e; 4 } // LD H, E
-      0x64 => { self.h = self.h; 4 } // LD H, H
+      0x64 => 4, // LD H, H
       0x65 => { self.h = self.l; 4 } // LD H, L
 
       0x6F => { self.l = self.a; 4 } // LD L, A
@@ -276,87 +346,87 @@ impl CPU {
       0x6A => { self.l = self.d; 4 } // LD L, D
       0x6B => { self.l = self.e; 4 } // LD L, E
       0x6C => { self.l = self.h; 4 } // LD L, H
-      0x6D => { self.l = self.l; 4 } // LD L, L
+      0x6D => 4, // LD L, L
 
       // LD r, (HL)
-      0x7E => { self.a = self.bus.read_byte(self.get_hl()); 8 }
-      0x46 => { self.b = self.bus.read_byte(self.get_hl()); 8 }
-      0x4E => { self.c = self.bus.read_byte(self.get_hl()); 8 }
-      0x56 => { self.d = self.bus.read_byte(self.get_hl()); 8 }
-      0x5E => { self.e = self.bus.read_byte(self.get_hl()); 8 }
-      0x66 => { self.h = self.bus.read_byte(self.get_hl()); 8 }
-      0x6E => { self.l = self.bus.read_byte(self.get_hl()); 8 }
+      0x7E => { self.a = self.read(self.get_hl()); 8 }
+      0x46 => { self.b = self.read(self.get_hl()); 8 }
+      0x4E => { self.c = self.read(self.get_hl()); 8 }
+      0x56 => { self.d = self.read(self.get_hl()); 8 }
+      0x5E => { self.e = self.read(self.get_hl()); 8 }
+      0x66 => { self.h = self.read(self.get_hl()); 8 }
+      0x6E => { self.l = self.read(self.get_hl()); 8 }
 
       // LD (HL), r
-      0x77 => { self.bus.write_byte(self.get_hl(), self.a); 8 }
-      0x70 => { self.bus.write_byte(self.get_hl(), self.b); 8 }
-      0x71 => { self.bus.write_byte(self.get_hl(), self.c); 8 }
-      0x72 => { self.bus.write_byte(self.get_hl(), self.d); 8 }
-      0x73 => { self.bus.write_byte(self.get_hl(), self.e); 8 }
-      0x74 => { self.bus.write_byte(self.get_hl(), self.h); 8 }
-      0x75 => { self.bus.write_byte(self.get_hl(), self.l); 8 }
+      0x77 => { self.write(self.get_hl(), self.a); 8 }
+      0x70 => { self.write(self.get_hl(), self.b); 8 }
+      0x71 => { self.write(self.get_hl(), self.c); 8 }
+      0x72 => { self.write(self.get_hl(), self.d); 8 }
+      0x73 => { self.write(self.get_hl(), self.e); 8 }
+      0x74 => { self.write(self.get_hl(), self.h); 8 }
+      0x75 => { self.write(self.get_hl(), self.l); 8 }
       0x36 => {
         let value = self.fetch_byte();
-        self.bus.write_byte(self.get_hl(), value);
+        self.write(self.get_hl(), value);
         12
       }
 
       // LD A, (BC/DE/nn)
-      0x0A => { self.a = self.bus.read_byte(self.get_bc()); 8 }
-      0x1A => { self.a = self.bus.read_byte(self.get_de()); 8 }
+      0x0A => { self.a = self.read(self.get_bc()); 8 }
+      0x1A => { self.a = self.read(self.get_de()); 8 }
       0xFA => {
         let addr = self.fetch_word();
-        self.a = self.bus.read_byte(addr);
+        self.a = self.read(addr);
         16
       }
 
       // LD (BC/DE/nn), A
-      0x02 => { self.bus.write_byte(self.get_bc(), self.a); 8 }
-      0x12 => { self.bus.write_byte(self.get_de(), self.a); 8 }
+      0x02 => { self.write(self.get_bc(), self.a); 8 }
+      0x12 => { self.write(self.get_de(), self.a); 8 }
       0xEA => {
         let addr = self.fetch_word();
-        self.bus.write_byte(addr, self.a);
+        self.write(addr, self.a);
         16
       }
 
       // LD A, (C) - Load A from address 0xFF00 + C
       0xF2 => {
         let addr = 0xFF00 + (self.c as u16);
-        self.a = self.bus.read_byte(addr);
+        self.a = self.read(addr);
         8
       }
 
       // LD (C), A - Store A at address 0xFF00 + C
       0xE2 => {
         let addr = 0xFF00 + (self.c as u16);
-        self.bus.write_byte(addr, self.a);
+        self.write(addr, self.a);
         8
       }
 
       // LDD A, (HL) - Load A from (HL) and decrement HL
       0x3A => {
-        self.a = self.bus.read_byte(self.get_hl());
+        self.a = self.read(self.get_hl());
         self.set_hl(self.get_hl().wrapping_sub(1));
         8
       }
 
       // LDD (HL), A - Store A at (HL) and decrement HL
       0x32 => {
-        self.bus.write_byte(self.get_hl(), self.a);
+        self.write(self.get_hl(), self.a);
         self.set_hl(self.get_hl().wrapping_sub(1));
         8
       }
 
       // LDI A, (HL) - Load A from (HL) and increment HL
       0x2A => {
-        self.a = self.bus.read_byte(self.get_hl());
+        self.a = self.read(self.get_hl());
         self.set_hl(self.get_hl().wrapping_add(1));
         8
       }
 
       // LDI (HL), A - Store A at (HL) and increment HL
       0x22 => {
-        self.bus.write_byte(self.get_hl(), self.a);
+        self.write(self.get_hl(), self.a);
         self.set_hl(self.get_hl().wrapping_add(1));
         8
       }
@@ -365,7 +435,7 @@ impl CPU {
       0xE0 => {
         let offset = self.fetch_byte();
         let addr = 0xFF00 + (offset as u16);
-        self.bus.write_byte(addr, self.a);
+        self.write(addr, self.a);
         12
       }
 
@@ -373,7 +443,7 @@ impl CPU {
       0xF0 => {
         let offset = self.fetch_byte();
         let addr = 0xFF00 + (offset as u16);
-        self.a = self.bus.read_byte(addr);
+        self.a = self.read(addr);
         12
       }
 
@@ -408,8 +478,8 @@ impl CPU {
       0x08 => {
         let addr = self.fetch_word();
         let sp = self.sp;
-        self.bus.write_byte(addr, (sp & 0xFF) as u8);
-        self.bus.write_byte(addr + 1, (sp >> 8) as u8);
+        self.write(addr, (sp & 0xFF) as u8);
+        self.write(addr.wrapping_add(1), (sp >> 8) as u8);
         20
       }
 
@@ -434,7 +504,7 @@ impl CPU {
       0x84 => { self.add_to_a(self.h); 4 }
       0x85 => { self.add_to_a(self.l); 4 }
       0x86 => {
-        let value = self.bus.read_byte(self.get_hl());
+        let value = self.read(self.get_hl());
         self.add_to_a(value);
         8
       }
@@ -453,7 +523,7 @@ impl CPU {
       0x8C => { let h = self.h; self.adc(h); 4 }
       0x8D => { let l = self.l; self.adc(l); 4 }
       0x8E => {
-        let value = self.bus.read_byte(self.get_hl());
+        let value = self.read(self.get_hl());
         self.adc(value);
         8
       }
@@ -472,7 +542,7 @@ impl CPU {
       0x94 => { self.sub_from_a(self.h); 4 }
       0x95 => { self.sub_from_a(self.l); 4 }
       0x96 => {
-        let value = self.bus.read_byte(self.get_hl());
+        let value = self.read(self.get_hl());
         self.sub_from_a(value);
         8
       }
@@ -491,7 +561,7 @@ impl CPU {
       0x9C => { let h = self.h; self.sbc(h); 4 }
       0x9D => { let l = self.l; self.sbc(l); 4 }
       0x9E => {
-        let value = self.bus.read_byte(self.get_hl());
+        let value = self.read(self.get_hl());
         self.sbc(value);
         8
       }
@@ -510,7 +580,7 @@ impl CPU {
       0xA4 => { self.and(self.h); 4 }
       0xA5 => { self.and(self.l); 4 }
       0xA6 => {
-        let value = self.bus.read_byte(self.get_hl());
+        let value = self.read(self.get_hl());
         self.and(value);
         8
       }
@@ -529,7 +599,7 @@ impl CPU {
       0xB4 => { self.or(self.h); 4 }
       0xB5 => { self.or(self.l); 4 }
       0xB6 => {
-        let value = self.bus.read_byte(self.get_hl());
+        let value = self.read(self.get_hl());
         self.or(value);
         8
       }
@@ -548,7 +618,7 @@ impl CPU {
       0xAC => { self.xor(self.h); 4 }
       0xAD => { self.xor(self.l); 4 }
       0xAE => {
-        let value = self.bus.read_byte(self.get_hl());
+        let value = self.read(self.get_hl());
         self.xor(value);
         8
       }
@@ -567,7 +637,7 @@ impl CPU {
       0xBC => { self.cp(self.h); 4 }
       0xBD => { self.cp(self.l); 4 }
       0xBE => {
-        let value = self.bus.read_byte(self.get_hl());
+        let value = self.read(self.get_hl());
         self.cp(value);
         8
       }
@@ -587,9 +657,9 @@ impl CPU {
       0x2C => { self.l = self.inc(self.l); 4 }
       0x34 => {
         let addr = self.get_hl();
-        let value = self.bus.read_byte(addr);
+        let value = self.read(addr);
         let result = self.inc(value);
-        self.bus.write_byte(addr, result);
+        self.write(addr, result);
         12
       }
 
@@ -603,9 +673,9 @@ impl CPU {
       0x2D => { self.l = self.dec(self.l); 4 }
       0x35 => {
         let addr = self.get_hl();
-        let value = self.bus.read_byte(addr);
+        let value = self.read(addr);
         let result = self.dec(value);
-        self.bus.write_byte(addr, result);
+        self.write(addr, result);
         12
       }
 
@@ -629,6 +699,19 @@ impl CPU {
         16
       }
 
+      // LD HL, SP+n - flags are computed like ADD SP, n
+      0xF8 => {
+        let value = self.fetch_byte() as i8 as i16 as u16;
+        let sp = self.sp;
+        self.set_hl(sp.wrapping_add(value));
+
+        self.set_zero_flag(false);
+        self.set_subtract_flag(false);
+        self.set_half_carry_flag((sp & 0x0F) + (value & 0x0F) > 0x0F);
+        self.set_carry_flag((sp & 0xFF) + (value & 0xFF) > 0xFF);
+        12
+      }
+
       // INC rr
       0x03 => { let bc = self.get_bc(); self.set_bc(bc.wrapping_add(1)); 8 }
       0x13 => { let de = self.get_de(); self.set_de(de.wrapping_add(1)); 8 }
@@ -816,8 +899,9 @@ impl CPU {
           8
         }
       }
-      0xD9 => {
+      0xD9 => { // RETI
         self.pc = self.pop();
+        self.bus.interrupts.ime = true;
         16
       }
 
@@ -840,18 +924,21 @@ impl CPU {
       // Misc
       0x00 => 4, // NOP
       0x76 => { // HALT
-        self.halted = true;
-        4
-      }
-      0x10 => { // STOP
-        self.fetch_byte(); // STOP is 2 bytes
-        self.halted = true;
+        if !self.bus.interrupts.ime && self.bus.interrupts.requested() != 0 {
+          self.halt_bug = true;
+        } else {
+          self.halted = true;
+        }
         4
       }
-      0xF3 => {
+      0x10 => self.stop(),
+      0xF3 => { // DI
+        self.bus.interrupts.ime = false;
+        self.ime_pending = false;
         4
       }
-      0xFB => {
+      0xFB => { // EI
+        self.ime_pending = true;
         4
       }
 
@@ -891,16 +978,108 @@ impl CPU {
         self.execute_cb_instruction(cb_opcode)
       }
 
-      _ => {
-        println!("Unimplemented opcode: {:02X} at PC: {:04X}", opcode, self.pc - 1);
+      // Unused opcodes lock up the CPU until the next reset; re-executing
+      // the same byte forever has the same visible effect
+      0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => {
+        self.pc = self.pc.wrapping_sub(1);
+        self.invalid_opcode = Some(opcode);
         4
       }
     }
   }
 
+  // Where an interrupt dispatched now returns to. After EI, HALT with an
+  // interrupt pending, that is the HALT itself, which then runs again.
+  fn interrupt_return_address(&mut self) -> u16 {
+    if std::mem::take(&mut self.halt_bug) {
+      self.pc.wrapping_sub(1)
+    } else {
+      self.pc
+    }
+  }
+
+  // Each access takes a machine cycle, during which the rest of the machine
+  // runs; GameBoy::step runs it through whatever the instruction has left
+  fn read(&mut self, addr: u16) -> u8 {
+    self.bus.tick_access();
+    self.bus.read_byte(addr)
+  }
+
+  fn write(&mut self, addr: u16, value: u8) {
+    self.bus.tick_access();
+    self.bus.write_byte(addr, value);
+  }
+
+  // A machine cycle with no access, run as it happens rather than caught up
+  // on after the instruction, for when what follows depends on the timing
+  fn idle(&mut self) {
+    self.bus.tick_access();
+  }
+
+  // Five machine cycles: two internal ones, the two pushes of PC, and the
+  // jump. The interrupt is only chosen between the pushes, so if pushing the
+  // high byte of PC lands on IE it can change the target, or cancel the
+  // dispatch and jump to 0000 instead, leaving IF as it was.
+  fn dispatch_interrupt(&mut self) -> u32 {
+    self.bus.interrupts.ime = false;
+    let return_address = self.interrupt_return_address();
+    self.idle();
+    self.idle();
+    self.sp = self.sp.wrapping_sub(1);
+    self.write(self.sp, (return_address >> 8) as u8);
+    let interrupt = self.bus.interrupts.acknowledge();
+    self.sp = self.sp.wrapping_sub(1);
+    self.write(self.sp, return_address as u8);
+    self.pc = interrupt.map_or(0x0000, InterruptType::vector);
+    self.idle();
+    self.dispatched_interrupt = interrupt;
+    20
+  }
+
+  // STOP, following Pan Docs' table of cases. With a selected button held
+  // it doesn't stop at all, and halts unless an interrupt is pending.
+  // Otherwise it resets DIV, then either switches speed if KEY1 armed a
+  // switch, or stops the CPU and timer until a selected button is pressed.
+  // It skips the byte after it unless an interrupt is pending.
+  fn stop(&mut self) -> u32 {
+    let pending = self.bus.interrupts.requested() != 0;
+    if !pending {
+      self.fetch_byte();
+    }
+    if self.bus.joypad.read() & 0x0F != 0x0F {
+      self.halted = !pending;
+      return 4;
+    }
+    self.bus.timer.write(0xFF04, 0);
+    if self.bus.try_speed_switch() {
+      return 4 + SPEED_SWITCH_CYCLES;
+    }
+    self.bus.stopped = true;
+    4
+  }
+
+  // The interrupt the last step jumped to, if it dispatched one
+  pub fn take_dispatched_interrupt(&mut self) -> Option<InterruptType> {
+    self.dispatched_interrupt.take()
+  }
+
+  // The opcode the last step locked up on, if it ran one that doesn't exist
+  pub fn take_invalid_opcode(&mut self) -> Option<u8> {
+    self.invalid_opcode.take()
+  }
+
   pub fn fetch_byte(&mut self) -> u8 {
-    let byte = self.bus.read_byte(self.pc);
-    self.pc += 1;
+    #[cfg(feature = "cached-interpreter")]
+    if let Some(byte) = self.prefetched.byte(self.prefetch_index) {
+      self.prefetch_index += 1;
+      self.idle();
+      self.pc = self.pc.wrapping_add(1);
+      return byte;
+    }
+    let byte = self.read(self.pc);
+    if !std::mem::take(&mut self.halt_bug) {
+      self.pc = self.pc.wrapping_add(1);
+    }
     byte
   }
 
@@ -912,15 +1091,15 @@ impl CPU {
 
   pub fn push(&mut self, value: u16) {
     self.sp = self.sp.wrapping_sub(1);
-    self.bus.write_byte(self.sp, (value >> 8) as u8);
+    self.write(self.sp, (value >> 8) as u8);
     self.sp = self.sp.wrapping_sub(1);
-    self.bus.write_byte(self.sp, (value & 0xFF) as u8);
+    self.write(self.sp, (value & 0xFF) as u8);
   }
 
   pub fn pop(&mut self) -> u16 {
-    let low = self.bus.read_byte(self.sp) as u16;
+    let low = self.read(self.sp) as u16;
     self.sp = self.sp.wrapping_add(1);
-    let high = self.bus.read_byte(self.sp) as u16;
+    let high = self.read(self.sp) as u16;
     self.sp = self.sp.wrapping_add(1);
     (high << 8) | low
   }
@@ -1102,9 +1281,9 @@ impl CPU {
       0x05 => { self.l = self.rlc(self.l); 8 }
       0x06 => {
         let addr = self.get_hl();
-        let value = self.bus.read_byte(addr);
+        let value = self.read(addr);
         let result = self.rlc(value);
-        self.bus.write_byte(addr, result);
+        self.write(addr, result);
         16
       }
 
@@ -1118,9 +1297,9 @@ impl CPU {
       0x0D => { self.l = self.rrc(self.l); 8 }
       0x0E => {
         let addr = self.get_hl();
-        let value = self.bus.read_byte(addr);
+        let value = self.read(addr);
         let result = self.rrc(value);
-        self.bus.write_byte(addr, result);
+        self.write(addr, result);
         16
       }
 
@@ -1134,9 +1313,9 @@ impl CPU {
       0x15 => { self.l = self.rl(self.l); 8 }
       0x16 => {
         let addr = self.get_hl();
-        let value = self.bus.read_byte(addr);
+        let value = self.read(addr);
         let result = self.rl(value);
-        self.bus.write_byte(addr, result);
+        self.write(addr, result);
         16
       }
 
@@ -1150,9 +1329,9 @@ impl CPU {
       0x1D => { self.l = self.rr(self.l); 8 }
       0x1E => {
         let addr = self.get_hl();
-        let value = self.bus.read_byte(addr);
+        let value = self.read(addr);
         let result = self.rr(value);
-        self.bus.write_byte(addr, result);
+        self.write(addr, result);
         16
       }
 
@@ -1166,9 +1345,9 @@ impl CPU {
       0x25 => { self.l = self.sla(self.l); 8 }
       0x26 => {
         let addr = self.get_hl();
-        let value = self.bus.read_byte(addr);
+        let value = self.read(addr);
         let result = self.sla(value);
-        self.bus.write_byte(addr, result);
+        self.write(addr, result);
         16
       }
 
@@ -1182,9 +1361,9 @@ impl CPU {
       0x2D => { self.l = self.sra(self.l); 8 }
       0x2E => {
         let addr = self.get_hl();
-        let value = self.bus.read_byte(addr);
+        let value = self.read(addr);
         let result = self.sra(value);
-        self.bus.write_byte(addr, result);
+        self.write(addr, result);
         16
       }
 
@@ -1198,9 +1377,9 @@ impl CPU {
       0x35 => { self.l = self.swap(self.l); 8 }
       0x36 => {
         let addr = self.get_hl();
-        let value = self.bus.read_byte(addr);
+        let value = self.read(addr);
         let result = self.swap(value);
-        self.bus.write_byte(addr, result);
+        self.write(addr, result);
         16
       }
 
@@ -1214,9 +1393,9 @@ impl CPU {
       0x3D => { self.l = self.srl(self.l); 8 }
       0x3E => {
         let addr = self.get_hl();
-        let value = self.bus.read_byte(addr);
+        let value = self.read(addr);
         let result = self.srl(value);
-        self.bus.write_byte(addr, result);
+        self.write(addr, result);
         16
       }
 
@@ -1231,7 +1410,7 @@ impl CPU {
           3 => self.e,
           4 => self.h,
           5 => self.l,
-          6 => self.bus.read_byte(self.get_hl()),
+          6 => self.read(self.get_hl()),
           7 => self.a,
           _ => unreachable!(),
         };
@@ -1252,9 +1431,9 @@ impl CPU {
           5 => self.l = self.res(bit, self.l),
           6 => {
             let addr = self.get_hl();
-            let value = self.bus.read_byte(addr);
+            let value = self.read(addr);
             let result = self.res(bit, value);
-            self.bus.write_byte(addr, result);
+            self.write(addr, result);
           }
           7 => self.a = self.res(bit, self.a),
           _ => unreachable!(),
@@ -1275,9 +1454,9 @@ impl CPU {
           5 => self.l = self.set_bit(bit, self.l),
           6 => {
             let addr = self.get_hl();
-            let value = self.bus.read_byte(addr);
+            let value = self.read(addr);
             let result = self.set_bit(bit, value);
-            self.bus.write_byte(addr, result);
+            self.write(addr, result);
           }
           7 => self.a = self.set_bit(bit, self.a),
           _ => unreachable!(),
@@ -1375,7 +1554,7 @@ impl CPU {
   }
 
   fn swap(&mut self, value: u8) -> u8 {
-    let result = (value >> 4) | (value << 4);
+    let result = value.rotate_left(4);
     
     self.set_zero_flag(result == 0);
     self.set_subtract_flag(false);
@@ -1402,17 +1581,57 @@ impl CPU {
 
   // Execute one instruction and return cycles elapsed
   pub fn step(&mut self) -> u32 {
+    // Only a selected input line going low ends STOP; interrupts don't
+    if self.bus.stopped {
+      if self.bus.joypad.read() & 0x0F == 0x0F {
+        return 4;
+      }
+      self.bus.stopped = false;
+    }
+    // A requested interrupt ends HALT even with IME clear; the CPU then
+    // carries on without jumping to the handler
     if self.halted {
-      return 4;
+      if self.bus.interrupts.requested() == 0 {
+        return 4;
+      }
+      self.halted = false;
+    }
+    if self.bus.interrupts.has_pending_interrupt() {
+      let cycles = self.dispatch_interrupt();
+      self.cycles += cycles as u64;
+      return cycles;
     }
 
+    let enable_ime = self.ime_pending;
+    #[cfg(feature = "cached-interpreter")]
+    self.prefetch();
     let opcode = self.fetch_byte();
     let cycles = self.execute_instruction(opcode);
+    #[cfg(feature = "cached-interpreter")]
+    {
+      self.prefetched = Instruction::default();
+    }
+    // Unless the instruction was a DI cancelling it
+    if enable_ime && self.ime_pending {
+      self.ime_pending = false;
+      self.bus.interrupts.ime = true;
+    }
     self.cycles += cycles as u64;
     cycles
   }
 
+  // Takes the instruction at PC from the decode cache, for fetch_byte to use
+  // instead of reading it over the bus. The HALT bug fetches a byte twice,
+  // so it's left to the interpreter.
+  #[cfg(feature = "cached-interpreter")]
+  fn prefetch(&mut self) {
+    let cached = if self.halt_bug { None } else { self.bus.cached_instruction(self.pc) };
+    self.prefetched = cached.unwrap_or_default();
+    self.prefetch_index = 0;
+  }
+
   // Run for a specific number of cycles
+  #[allow(dead_code)]
   pub fn run_cycles(&mut self, target_cycles: u32) {
     let start_cycles = self.cycles;
     while (self.cycles - start_cycles) < target_cycles as u64 {
@@ -1421,8 +1640,9 @@ impl CPU {
   }
 
   // Load a ROM into memory
-  pub fn load_rom(&mut self, rom: &[u8]) {
-    self.bus.load_rom(rom);
+  #[allow(dead_code)]
+  pub fn load_rom(&mut self, rom: &[u8]) -> Result<()> {
+    self.bus.load_rom(rom)
   }
 
   // Reset CPU to initial state
@@ -1439,5 +1659,40 @@ impl CPU {
     self.sp = 0x0000;
     self.cycles = 0;
     self.halted = false;
+    self.halt_bug = false;
+    self.ime_pending = false;
+    self.dispatched_interrupt = None;
+    self.invalid_opcode = None;
+  }
+}
+
+impl Savestate for CPU {
+  fn save_state(&self, state: &mut StateWriter) {
+    for register in [self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l] {
+      state.write_u8(register);
+    }
+    state.write_u16(self.sp);
+    state.write_u16(self.pc);
+    state.write_u64(self.cycles);
+    state.write_bool(self.halted);
+    state.write_bool(self.halt_bug);
+    state.write_bool(self.ime_pending);
+    self.bus.save_state(state);
   }
-}
\ No newline at end of file
+
+  fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+    for register in [
+      &mut self.a, &mut self.f, &mut self.b, &mut self.c,
+      &mut self.d, &mut self.e, &mut self.h, &mut self.l,
+    ] {
+      *register = state.read_u8()?;
+    }
+    self.sp = state.read_u16()?;
+    self.pc = state.read_u16()?;
+    self.cycles = state.read_u64()?;
+    self.halted = state.read_bool()?;
+    self.halt_bug = state.read_bool()?;
+    self.ime_pending = state.read_bool()?;
+    self.bus.load_state(state)
+  }
+}
@@ -0,0 +1,57 @@
+use std::str::FromStr;
+
+// Longer lines are flushed anyway, in case a program never sends a newline
+const MAX_LINE_LEN: usize = 256;
+
+// Where homebrew sends its printf output
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DebugPortMode {
+    // Bytes the program transfers out of the serial port as clock master
+    Serial,
+    // Bytes written to this address, which still reach it as usual
+    Address(u16),
+}
+
+// Accepts "serial" and "addr:<hex address>"
+impl FromStr for DebugPortMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "serial" => Ok(DebugPortMode::Serial),
+            Some(("addr", addr)) => u16::from_str_radix(addr.trim_start_matches("0x"), 16)
+                .map(DebugPortMode::Address)
+                .map_err(|_| format!("invalid hex address '{}'", addr)),
+            _ => Err(format!("unknown debug port '{}'", s)),
+        }
+    }
+}
+
+// Collects bytes into UTF-8 lines
+pub struct DebugPort {
+    pub mode: DebugPortMode,
+    line: Vec<u8>,
+}
+
+impl DebugPort {
+    pub fn new(mode: DebugPortMode) -> Self {
+        DebugPort { mode, line: Vec::new() }
+    }
+
+    // Returns a line once it is complete; invalid UTF-8 is replaced, not dropped
+    pub fn push(&mut self, byte: u8) -> Option<String> {
+        match byte {
+            b'\n' => {}
+            b'\r' => return None,
+            _ => {
+                self.line.push(byte);
+                if self.line.len() < MAX_LINE_LEN {
+                    return None;
+                }
+            }
+        }
+        let line = String::from_utf8_lossy(&self.line).into_owned();
+        self.line.clear();
+        Some(line)
+    }
+}
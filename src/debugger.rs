@@ -0,0 +1,582 @@
+use std::cell::Cell;
+use std::io::{BufRead, Write};
+
+use crate::gameboy::GameBoy;
+use crate::history;
+use crate::memory::Region;
+use crate::ramsearch::{self, Comparison};
+
+// Largest block the x command dumps
+const MAX_DUMP_LEN: usize = 0x400;
+// Matches the find command lists before giving just a count
+const MAX_FIND_RESULTS: usize = 16;
+// Instructions the u command lists by default, and at most
+const DISASSEMBLY_LINES: u16 = 8;
+const MAX_DISASSEMBLY_LINES: u16 = 0x100;
+
+// A memory address the debugger stops on when the CPU reads or writes it
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Watchpoint {
+    pub addr: u16,
+    pub on_read: bool,
+    pub on_write: bool,
+}
+
+// Why the debugger stopped the machine. It stops between instructions, with
+// PC on the next one to run.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DebugStop {
+    Breakpoint,
+    Watch { addr: u16, value: u8, write: bool },
+    Step,
+}
+
+#[derive(Copy, Clone)]
+enum Stepping {
+    Into,
+    // Runs until the CALL or RST at the start returns to `pc`
+    Over { pc: u16, sp: u16 },
+}
+
+// Code breakpoints, watchpoints and single-stepping. It lives on the bus so
+// the CPU's memory accesses can be watched; GameBoy::step checks it after
+// every instruction.
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: Vec<u16>,
+    watchpoints: Vec<Watchpoint>,
+    stepping: Option<Stepping>,
+    // First watched access of the current instruction
+    watch_hit: Cell<Option<DebugStop>>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_breakpoint(&mut self, pc: u16) {
+        if !self.breakpoints.contains(&pc) {
+            self.breakpoints.push(pc);
+        }
+    }
+
+    // Returns false when there was no breakpoint at pc
+    pub fn remove_breakpoint(&mut self, pc: u16) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|&breakpoint| breakpoint != pc);
+        self.breakpoints.len() != len
+    }
+
+    pub fn breakpoints(&self) -> &[u16] {
+        &self.breakpoints
+    }
+
+    // Replaces any watchpoint already on the address
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.remove_watchpoint(watchpoint.addr);
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: u16) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|watchpoint| watchpoint.addr != addr);
+        self.watchpoints.len() != len
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    // Stops after the next instruction
+    pub fn step_into(&mut self) {
+        self.stepping = Some(Stepping::Into);
+    }
+
+    // Like step_into, except that a CALL or RST at pc runs until it returns
+    pub fn step_over(&mut self, pc: u16, sp: u16, opcode: u8) {
+        let length = match opcode {
+            0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC => Some(3),
+            _ if opcode & 0xC7 == 0xC7 => Some(1),
+            _ => None,
+        };
+        self.stepping = Some(match length {
+            Some(length) => Stepping::Over { pc: pc.wrapping_add(length), sp },
+            None => Stepping::Into,
+        });
+    }
+
+    pub fn is_watching(&self) -> bool {
+        !self.watchpoints.is_empty()
+    }
+
+    // Called by the bus for CPU accesses while watchpoints are set
+    pub fn note_access(&self, addr: u16, value: u8, write: bool) {
+        let watched = self.watchpoints.iter().any(|watchpoint| {
+            watchpoint.addr == addr && if write { watchpoint.on_write } else { watchpoint.on_read }
+        });
+        if watched && self.watch_hit.get().is_none() {
+            self.watch_hit.set(Some(DebugStop::Watch { addr, value, write }));
+        }
+    }
+
+    // Forgets accesses made outside of an instruction, like a frontend
+    // reading memory between frames
+    pub fn begin_instruction(&self) {
+        self.watch_hit.set(None);
+    }
+
+    // Whether to stop now that PC and SP are as given after an instruction
+    pub fn end_instruction(&mut self, pc: u16, sp: u16) -> Option<DebugStop> {
+        let stop = self.watch_hit.take().or_else(|| match self.stepping {
+            Some(Stepping::Into) => Some(DebugStop::Step),
+            // A recursive call passes the same address deeper in the stack
+            Some(Stepping::Over { pc: target, sp: start_sp }) if pc == target && sp >= start_sp => {
+                Some(DebugStop::Step)
+            }
+            _ if self.breakpoints.contains(&pc) => Some(DebugStop::Breakpoint),
+            _ => None,
+        });
+        if stop.is_some() {
+            self.stepping = None;
+        }
+        stop
+    }
+}
+
+// How an interactive session ended
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ConsoleExit {
+    Resume,
+    Quit,
+}
+
+// Reads commands until one resumes the machine; end of input quits
+pub fn console(gb: &mut GameBoy, input: &mut dyn BufRead, output: &mut dyn Write) -> ConsoleExit {
+    let _ = writeln!(output, "{}", dump_registers(gb));
+    let _ = writeln!(output, "{}", disassembly(gb, gb.registers().pc, 1));
+    loop {
+        let _ = write!(output, "(debug) ");
+        let _ = output.flush();
+        let mut line = String::new();
+        match input.read_line(&mut line) {
+            Ok(0) | Err(_) => return ConsoleExit::Quit,
+            Ok(_) => {}
+        }
+        match execute(gb, line.trim()) {
+            Ok(Command::Resume) => return ConsoleExit::Resume,
+            Ok(Command::Quit) => return ConsoleExit::Quit,
+            Ok(Command::Reply(reply)) if reply.is_empty() => {}
+            Ok(Command::Reply(reply)) => {
+                let _ = writeln!(output, "{}", reply);
+            }
+            Err(error) => {
+                let _ = writeln!(output, "error: {}", error);
+            }
+        }
+    }
+}
+
+enum Command {
+    Reply(String),
+    Resume,
+    Quit,
+}
+
+const HELP: &str = "\
+break <addr> / delete <addr>      set or clear a breakpoint
+watch <addr> [r|w|rw] / unwatch <addr>
+                                  stop when the CPU reads or writes addr (default rw)
+list                              breakpoints and watchpoints
+step (s) / next (n)               run one instruction, next runs CALL and RST to their return
+continue (c)                      run until a breakpoint or watchpoint
+regs (r)                          registers and flags
+x <addr> [len]                    hex dump of memory
+set <addr> <byte>...              write bytes to memory
+find [region] <byte>...           search the address space, or every bank of a region
+u [addr] [count]                  disassemble from addr, PC by default
+history [count]                   the last instructions run, all those kept by default
+cheats / cheat <n> on|off         list cheat codes, or turn one on or off
+search [inc|dec|same|diff|<byte>] cheat search over work RAM: on its own starts
+                                  over, otherwise keeps the bytes that went up,
+                                  down, stayed, changed or equal byte since the last
+results                           the bytes the search has left
+pin <addr> / unpin <addr>         show a work RAM byte over the screen
+shark <addr> [byte]               GameShark code holding addr at byte, or its value
+oam                               the 40 objects, marking those dropped last frame
+quit (q)                          close the emulator
+Addresses are hex, with or without 0x. x and set go through the bus like the
+CPU does, so a write to ROM reaches the MBC, unless the address names a bank:
+rom:<bank>:<addr>, vram:, wram: or sram: reach that bank whatever is mapped,
+and set on rom: patches the ROM itself.";
+
+fn execute(gb: &mut GameBoy, line: &str) -> Result<Command, String> {
+    let mut words = line.split_whitespace();
+    let command = words.next().unwrap_or("");
+    let args: Vec<&str> = words.collect();
+
+    let reply = match (command, args.as_slice()) {
+        ("", []) => String::new(),
+        ("help" | "h" | "?", []) => HELP.to_string(),
+        ("break" | "b", [addr]) => {
+            let addr = parse_hex(addr)?;
+            gb.debugger().add_breakpoint(addr);
+            format!("breakpoint at {:04X}", addr)
+        }
+        ("delete" | "d", [addr]) => {
+            let addr = parse_hex(addr)?;
+            if !gb.debugger().remove_breakpoint(addr) {
+                return Err(format!("no breakpoint at {:04X}", addr));
+            }
+            String::new()
+        }
+        ("watch" | "w", [addr, rest @ ..]) if rest.len() <= 1 => {
+            let addr = parse_hex(addr)?;
+            let (on_read, on_write) = match rest.first().copied().unwrap_or("rw") {
+                "r" => (true, false),
+                "w" => (false, true),
+                "rw" => (true, true),
+                other => return Err(format!("unknown access '{}'", other)),
+            };
+            gb.debugger().add_watchpoint(Watchpoint { addr, on_read, on_write });
+            format!("watchpoint at {:04X}", addr)
+        }
+        ("unwatch", [addr]) => {
+            let addr = parse_hex(addr)?;
+            if !gb.debugger().remove_watchpoint(addr) {
+                return Err(format!("no watchpoint at {:04X}", addr));
+            }
+            String::new()
+        }
+        ("list" | "l", []) => {
+            let debugger = gb.debugger();
+            let mut lines: Vec<String> = debugger.breakpoints().iter().map(|pc| format!("break {:04X}", pc)).collect();
+            lines.extend(debugger.watchpoints().iter().map(|watchpoint| {
+                let access = match (watchpoint.on_read, watchpoint.on_write) {
+                    (true, false) => "r",
+                    (false, true) => "w",
+                    _ => "rw",
+                };
+                format!("watch {:04X} {}", watchpoint.addr, access)
+            }));
+            lines.join("\n")
+        }
+        ("step" | "s", []) => {
+            gb.debugger().step_into();
+            return Ok(Command::Resume);
+        }
+        ("next" | "n", []) => {
+            let registers = gb.registers();
+            let opcode = gb.peek(registers.pc);
+            gb.debugger().step_over(registers.pc, registers.sp, opcode);
+            return Ok(Command::Resume);
+        }
+        ("continue" | "c", []) => return Ok(Command::Resume),
+        ("regs" | "r", []) => dump_registers(gb),
+        ("x", [addr, rest @ ..]) if rest.len() <= 1 => {
+            let location = Location::parse(addr)?;
+            let len = rest.first().map(|len| parse_hex(len)).transpose()?.unwrap_or(0x10) as usize;
+            if len == 0 || len > MAX_DUMP_LEN {
+                return Err("length out of range".to_string());
+            }
+            hex_dump(location, &location.read(gb, len)?)
+        }
+        ("set", [addr, values @ ..]) if !values.is_empty() => {
+            let location = Location::parse(addr)?;
+            let values = values.iter().map(|value| parse_byte(value)).collect::<Result<Vec<_>, _>>()?;
+            location.write(gb, &values)?;
+            String::new()
+        }
+        ("find", [first, rest @ ..]) => {
+            let (region, values) = match first.parse::<Region>() {
+                Ok(region) => (Some(region), rest),
+                Err(_) => (None, args.as_slice()),
+            };
+            if values.is_empty() {
+                return Err("nothing to find".to_string());
+            }
+            let pattern = values.iter().map(|value| parse_byte(value)).collect::<Result<Vec<_>, _>>()?;
+            let found = find(gb, region, &pattern);
+            if found.is_empty() {
+                return Err("not found".to_string());
+            }
+            let mut lines: Vec<String> = found.iter().take(MAX_FIND_RESULTS).map(Location::to_string).collect();
+            if found.len() > MAX_FIND_RESULTS {
+                lines.push(format!("and {} more", found.len() - MAX_FIND_RESULTS));
+            }
+            lines.join("\n")
+        }
+        ("u" | "disasm", rest) if rest.len() <= 2 => {
+            let addr = match rest.first() {
+                Some(addr) => parse_hex(addr)?,
+                None => gb.registers().pc,
+            };
+            let count = rest.get(1).map(|count| parse_hex(count)).transpose()?.unwrap_or(DISASSEMBLY_LINES);
+            if count == 0 || count > MAX_DISASSEMBLY_LINES {
+                return Err("count out of range".to_string());
+            }
+            disassembly(gb, addr, count)
+        }
+        ("history", rest) if rest.len() <= 1 => {
+            let entries = gb.history();
+            if entries.is_empty() {
+                return Err("no history kept, run with --history=N".to_string());
+            }
+            let count = rest.first().map(|count| parse_hex(count)).transpose()?.map_or(entries.len(), usize::from);
+            history::dump(&entries[entries.len().saturating_sub(count)..])
+        }
+        ("cheats", []) => gb.cheats().iter().enumerate().map(|(index, cheat)| cheat.describe(index)).collect::<Vec<_>>().join("\n"),
+        ("oam", []) => oam_listing(gb).join("\n"),
+        ("cheat", [index, state @ ("on" | "off")]) => {
+            let index = index.parse().map_err(|_| format!("invalid cheat number '{}'", index))?;
+            if !gb.set_cheat_enabled(index, *state == "on") {
+                return Err(format!("no cheat {}", index));
+            }
+            String::new()
+        }
+        ("search", []) => {
+            gb.start_ram_search();
+            format!("search started with {} candidates", gb.ram_search().candidates().len())
+        }
+        ("search", [comparison]) => {
+            let comparison: Comparison = comparison.parse()?;
+            format!("candidates left: {}", gb.filter_ram_search(comparison))
+        }
+        ("results", []) => {
+            if !gb.ram_search().is_started() {
+                return Err("no search, start one with search".to_string());
+            }
+            let wram = gb.searchable_wram();
+            let candidates = gb.ram_search().candidates();
+            let mut lines: Vec<String> = candidates
+                .iter()
+                .take(MAX_FIND_RESULTS)
+                .map(|&(offset, _)| {
+                    let (bank, addr) = ramsearch::wram_location(offset);
+                    format!("{} {:02X}", Location::Bank { region: Region::Wram, bank, addr }, wram[offset])
+                })
+                .collect();
+            if candidates.len() > MAX_FIND_RESULTS {
+                lines.push(format!("and {} more", candidates.len() - MAX_FIND_RESULTS));
+            }
+            lines.join("\n")
+        }
+        ("pin", [addr]) => {
+            let offset = wram_offset(gb, addr)?;
+            gb.ram_search_mut().pin(offset);
+            String::new()
+        }
+        ("unpin", [addr]) => {
+            let offset = wram_offset(gb, addr)?;
+            if !gb.ram_search_mut().unpin(offset) {
+                return Err(format!("{} isn't pinned", addr));
+            }
+            String::new()
+        }
+        ("shark", [addr, rest @ ..]) if rest.len() <= 1 => {
+            let offset = wram_offset(gb, addr)?;
+            let value = match rest.first() {
+                Some(value) => parse_byte(value)?,
+                None => gb.searchable_wram()[offset],
+            };
+            ramsearch::shark_code(offset, value, gb.is_cgb_mode())
+        }
+        ("quit" | "q", []) => return Ok(Command::Quit),
+        _ => return Err(format!("bad command '{}', try help", line)),
+    };
+    Ok(Command::Reply(reply))
+}
+
+pub fn dump_registers(gb: &GameBoy) -> String {
+    let r = gb.registers();
+    let flag = |bit: u8, name: char| if r.f & bit != 0 { name } else { '-' };
+    format!(
+        "AF: {:02X}{:02X} BC: {:02X}{:02X} DE: {:02X}{:02X} HL: {:02X}{:02X} SP: {:04X} PC: {:04X} [{}{}{}{}]",
+        r.a, r.f, r.b, r.c, r.d, r.e, r.h, r.l, r.sp, r.pc,
+        flag(0x80, 'Z'), flag(0x40, 'N'), flag(0x20, 'H'), flag(0x10, 'C'),
+    )
+}
+
+// One line per OAM entry, with the lines of the last frame that left it out
+pub fn oam_listing(gb: &GameBoy) -> Vec<String> {
+    let cgb_mode = gb.is_cgb_mode();
+    gb.oam_entries()
+        .iter()
+        .map(|entry| {
+            let lines: Vec<String> = gb
+                .sprite_overflow()
+                .iter()
+                .filter(|overflow| overflow.dropped.contains(&entry.index))
+                .map(|overflow| overflow.line.to_string())
+                .collect();
+            match lines.as_slice() {
+                [] => entry.describe(cgb_mode),
+                _ => format!("{} dropped on line {}", entry.describe(cgb_mode), lines.join(",")),
+            }
+        })
+        .collect()
+}
+
+// `count` instructions from addr, with bytes, marking the one at PC
+fn disassembly(gb: &GameBoy, mut addr: u16, count: u16) -> String {
+    let pc = gb.registers().pc;
+    let mut lines = Vec::new();
+    for _ in 0..count {
+        let (text, len) = gb.disassemble(addr);
+        let bytes: Vec<String> = (0..len).map(|offset| format!("{:02X}", gb.peek(addr.wrapping_add(offset)))).collect();
+        let marker = if addr == pc { "=>" } else { "  " };
+        lines.push(format!("{} {:04X}: {:<9} {}", marker, addr, bytes.join(" "), text));
+        addr = addr.wrapping_add(len);
+    }
+    lines.join("\n")
+}
+
+// A bus address in C000-FDFF as currently mapped, or wram:<bank>:<addr>
+fn wram_offset(gb: &GameBoy, text: &str) -> Result<usize, String> {
+    match Location::parse(text)? {
+        Location::Bus(addr @ 0xC000..=0xFDFF) => Ok(gb.cpu.bus.memory.wram_offset(addr)),
+        Location::Bank { region: Region::Wram, bank, addr } => {
+            let offset = bank * 0x1000 + (addr as usize & 0x0FFF);
+            if offset >= gb.searchable_wram().len() {
+                return Err(format!("no wram bank {:X}", bank));
+            }
+            Ok(offset)
+        }
+        _ => Err(format!("{} isn't in work RAM", text)),
+    }
+}
+
+fn parse_hex(text: &str) -> Result<u16, String> {
+    u16::from_str_radix(text.trim_start_matches("0x"), 16).map_err(|_| format!("invalid hex value '{}'", text))
+}
+
+fn parse_byte(text: &str) -> Result<u8, String> {
+    u8::from_str_radix(text.trim_start_matches("0x"), 16).map_err(|_| format!("invalid byte '{}'", text))
+}
+
+// Where x, set and find work: the address space as the CPU sees it, or one
+// bank of a region whether it is mapped or not
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Location {
+    Bus(u16),
+    Bank { region: Region, bank: usize, addr: u16 },
+}
+
+impl Location {
+    // "<addr>" or "<region>:<bank>:<addr>", in hex
+    fn parse(text: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = text.split(':').collect();
+        match parts.as_slice() {
+            [addr] => Ok(Location::Bus(parse_hex(addr)?)),
+            [region, bank, addr] => {
+                let region: Region = region.parse()?;
+                let (bank, addr) = (parse_hex(bank)? as usize, parse_hex(addr)?);
+                if !region.window().0.contains(&addr) {
+                    return Err(format!("{:04X} is outside {}", addr, region.name()));
+                }
+                Ok(Location::Bank { region, bank, addr })
+            }
+            _ => Err(format!("invalid address '{}'", text)),
+        }
+    }
+
+    fn advance(self, by: usize) -> Self {
+        match self {
+            Location::Bus(addr) => Location::Bus(addr.wrapping_add(by as u16)),
+            Location::Bank { region, bank, addr } => Location::Bank { region, bank, addr: addr.wrapping_add(by as u16) },
+        }
+    }
+
+    // Where `len` bytes from a banked address start in the region's
+    // storage, which is `stored` bytes long. They must stay in the bank.
+    fn span(region: Region, bank: usize, addr: u16, len: usize, stored: usize) -> Result<usize, String> {
+        let (window, size) = region.window();
+        let offset = (addr - window.start()) as usize % size;
+        if offset + len > size {
+            return Err("length runs past the end of the bank".to_string());
+        }
+        // A 2 KB SRAM is a bank of its own, shorter than the window
+        if bank * size + offset + len > stored {
+            return Err(format!("no {} bank {:X}", region.name(), bank));
+        }
+        Ok(bank * size + offset)
+    }
+
+    fn read(self, gb: &GameBoy, len: usize) -> Result<Vec<u8>, String> {
+        match self {
+            Location::Bus(addr) => {
+                if addr as usize + len > 0x10000 {
+                    return Err("length out of range".to_string());
+                }
+                Ok((0..len).map(|offset| gb.peek(addr + offset as u16)).collect())
+            }
+            Location::Bank { region, bank, addr } => {
+                let stored = gb.region(region);
+                let start = Self::span(region, bank, addr, len, stored.len())?;
+                Ok(stored[start..start + len].to_vec())
+            }
+        }
+    }
+
+    fn write(self, gb: &mut GameBoy, values: &[u8]) -> Result<(), String> {
+        match self {
+            Location::Bus(addr) => {
+                if addr as usize + values.len() > 0x10000 {
+                    return Err("length out of range".to_string());
+                }
+                for (offset, &value) in values.iter().enumerate() {
+                    gb.write_byte(addr + offset as u16, value);
+                }
+            }
+            Location::Bank { region, bank, addr } => {
+                let stored = gb.region_mut(region);
+                let start = Self::span(region, bank, addr, values.len(), stored.len())?;
+                stored[start..start + values.len()].copy_from_slice(values);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Location::Bus(addr) => write!(f, "{:04X}", addr),
+            Location::Bank { region, bank, addr } => write!(f, "{}:{:X}:{:04X}", region.name(), bank, addr),
+        }
+    }
+}
+
+// Sixteen bytes a line, followed by the printable ones as text
+fn hex_dump(start: Location, bytes: &[u8]) -> String {
+    bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(row, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02X}", byte)).collect();
+            let text: String = chunk.iter().map(|&byte| if byte == b' ' || byte.is_ascii_graphic() { byte as char } else { '.' }).collect();
+            format!("{}: {:<47}  {}", start.advance(row * 16), hex.join(" "), text)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Every place the pattern starts, in the address space or in each bank of
+// the region. Matches don't cross from one bank to the next.
+fn find(gb: &GameBoy, region: Option<Region>, pattern: &[u8]) -> Vec<Location> {
+    let matches = |bytes: &[u8]| -> Vec<usize> {
+        bytes.windows(pattern.len()).enumerate().filter(|(_, window)| *window == pattern).map(|(at, _)| at).collect()
+    };
+    let Some(region) = region else {
+        let memory: Vec<u8> = (0..=0xFFFF).map(|addr| gb.peek(addr)).collect();
+        return matches(&memory).into_iter().map(|addr| Location::Bus(addr as u16)).collect();
+    };
+    let (_, size) = region.window();
+    gb.region(region)
+        .chunks(size)
+        .enumerate()
+        .flat_map(|(bank, stored)| {
+            matches(stored).into_iter().map(move |offset| Location::Bank { region, bank, addr: region.address(bank, offset) })
+        })
+        .collect()
+}
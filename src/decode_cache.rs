@@ -0,0 +1,109 @@
+// The cached interpreter's store of decoded instructions: each one's bytes
+// are read from memory once and kept by where they sit in the cartridge ROM
+// (bank and offset, so switching banks doesn't mix them up) or in work RAM.
+// The CPU then takes them from here instead of reading them over the bus,
+// still spending a machine cycle on each byte so timing doesn't change.
+// Writes to work RAM drop what they overwrite; code running anywhere else
+// (HRAM, VRAM, cartridge RAM) is left to the plain interpreter, as it's
+// usually short routines next to I/O, like OAM DMA waits.
+
+// Entries are allocated a page at a time as code is first run
+const PAGE_SIZE: usize = 0x1000;
+const ROM_BANK_SIZE: usize = 0x4000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Instruction {
+    pub bytes: [u8; 3],
+    pub len: u8,
+}
+
+impl Instruction {
+    // None past the end, and for the empty instruction standing for no cache hit
+    pub fn byte(&self, index: u8) -> Option<u8> {
+        (index < self.len).then(|| self.bytes[index as usize])
+    }
+}
+
+#[derive(Default)]
+pub struct DecodeCache {
+    // Keyed by ROM offset, then by work RAM offset past the end of the ROM
+    pages: Vec<Option<Box<[Option<Instruction>; PAGE_SIZE]>>>,
+    rom_len: usize,
+    rom_banks: usize,
+}
+
+impl DecodeCache {
+    pub fn new(rom_len: usize, wram_len: usize) -> Self {
+        DecodeCache {
+            pages: vec![None; (rom_len + wram_len).div_ceil(PAGE_SIZE)],
+            rom_len,
+            rom_banks: (rom_len / ROM_BANK_SIZE).max(1),
+        }
+    }
+
+    // Banks wrap around the ROM size as they do for the MBCs
+    pub fn rom_key(&self, bank: usize, addr: u16) -> Option<usize> {
+        let offset = (bank % self.rom_banks) * ROM_BANK_SIZE + (addr as usize % ROM_BANK_SIZE);
+        (offset < self.rom_len).then_some(offset)
+    }
+
+    pub fn wram_key(&self, offset: usize) -> usize {
+        self.rom_len + offset
+    }
+
+    // Decodes on a miss with read, given the offset of each byte
+    pub fn get(&mut self, key: usize, read: impl Fn(usize) -> u8) -> Option<Instruction> {
+        let page = self.pages.get_mut(key / PAGE_SIZE)?.get_or_insert_with(|| Box::new([None; PAGE_SIZE]));
+        if let Some(instruction) = page[key % PAGE_SIZE] {
+            return Some(instruction);
+        }
+        let opcode = read(0);
+        let len = instruction_length(opcode)?;
+        let mut bytes = [opcode, 0, 0];
+        for (offset, byte) in bytes.iter_mut().enumerate().take(len as usize).skip(1) {
+            *byte = read(offset);
+        }
+        let instruction = Instruction { bytes, len };
+        page[key % PAGE_SIZE] = Some(instruction);
+        Some(instruction)
+    }
+
+    // After a write to work RAM, dropping the instructions it was part of
+    pub fn invalidate_wram(&mut self, offset: usize) {
+        let key = self.wram_key(offset);
+        for key in key.saturating_sub(2)..=key {
+            if let Some(Some(page)) = self.pages.get_mut(key / PAGE_SIZE) {
+                page[key % PAGE_SIZE] = None;
+            }
+        }
+    }
+
+    // After the ROM was patched or replaced wholesale
+    pub fn clear_rom(&mut self) {
+        let rom_pages = self.rom_len / PAGE_SIZE;
+        self.pages[..rom_pages].fill(None);
+        // A page shared with work RAM goes too
+        if let Some(page) = self.pages.get_mut(rom_pages) {
+            *page = None;
+        }
+    }
+
+    pub fn clear_wram(&mut self) {
+        let first = self.rom_len / PAGE_SIZE;
+        self.pages[first..].fill(None);
+    }
+}
+
+// None for STOP, which only reads its second byte when no interrupt is
+// pending, and for the opcodes that don't exist
+fn instruction_length(opcode: u8) -> Option<u8> {
+    match opcode {
+        0x10 | 0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => None,
+        0x01 | 0x11 | 0x21 | 0x31 | 0x08 | 0xEA | 0xFA => Some(3),
+        0xC2 | 0xC3 | 0xCA | 0xD2 | 0xDA | 0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC => Some(3),
+        0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => Some(2),
+        0x18 | 0x20 | 0x28 | 0x30 | 0x38 | 0xE0 | 0xF0 | 0xE8 | 0xF8 | 0xCB => Some(2),
+        0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => Some(2),
+        _ => Some(1),
+    }
+}
@@ -0,0 +1,141 @@
+// Checks for the mistakes that make homebrew go off the rails without a
+// crash to show for it: jumping into memory that doesn't hold code, pushing
+// outside RAM and popping past the top of the stack. Each is reported once
+// when it starts, along with the last few PCs run to show how it got there.
+// SP pointing into ROM isn't a problem by itself, as LD SP,HL and POP is a
+// quick way to read a table. An opcode locking up the CPU is reported
+// whether these are on or not, as EmulatorEvent::CpuLocked.
+
+use crate::cpu::CPU;
+
+pub const RECENT_PCS: usize = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DevWarningKind {
+    // PC is in memory that isn't ROM or RAM
+    BadJump,
+    // Pushed outside RAM
+    StackOverflow,
+    // Popped past the top of RAM
+    StackUnderflow,
+}
+
+#[derive(Debug, Clone)]
+pub struct DevWarning {
+    pub kind: DevWarningKind,
+    pub pc: u16,
+    pub sp: u16,
+    // The ROM bank mapped when PC is in ROM
+    pub bank: Option<usize>,
+    // Oldest first; the last one is the instruction that caused it
+    pub recent: Vec<u16>,
+}
+
+impl DevWarning {
+    pub fn describe(&self) -> String {
+        let at = match self.bank {
+            Some(bank) => format!("0x{:04X} (bank {})", self.pc, bank),
+            None => format!("0x{:04X}", self.pc),
+        };
+        match self.kind {
+            DevWarningKind::BadJump => format!("executing {} at {}", region_name(self.pc), at),
+            DevWarningKind::StackOverflow => {
+                format!("stack overflow, pushed to SP 0x{:04X} in {}, PC {}", self.sp, region_name(self.sp), at)
+            }
+            DevWarningKind::StackUnderflow => {
+                format!("stack underflow, popped to SP 0x{:04X} in {}, PC {}", self.sp, region_name(self.sp), at)
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct DevWarnings {
+    recent: [u16; RECENT_PCS],
+    // Instructions recorded, for where the next goes in recent
+    count: usize,
+    last_sp: Option<u16>,
+    // Set while a problem already reported lasts, so it's reported once
+    pc_flagged: bool,
+    sp_flagged: bool,
+}
+
+impl DevWarnings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Called with the PC of each instruction before it runs
+    pub fn record(&mut self, pc: u16) {
+        self.recent[self.count % RECENT_PCS] = pc;
+        self.count += 1;
+    }
+
+    // Called after each instruction; returns the problems it started
+    pub fn check(&mut self, cpu: &CPU) -> Vec<DevWarning> {
+        let mut warnings = Vec::new();
+
+        let bad_pc = !is_code(cpu.pc);
+        if bad_pc && !self.pc_flagged {
+            warnings.push(self.warning(DevWarningKind::BadJump, cpu));
+        }
+        self.pc_flagged = bad_pc;
+
+        let sp = cpu.sp;
+        let last_sp = self.last_sp.replace(sp).unwrap_or(sp);
+        if is_stack(sp) {
+            self.sp_flagged = false;
+        } else if !self.sp_flagged {
+            // Pushes and pops move SP by two, calls, returns and interrupts too
+            let kind = match sp.wrapping_sub(last_sp) as i16 {
+                -4..=-1 => Some(DevWarningKind::StackOverflow),
+                1..=4 if is_stack(last_sp) => Some(DevWarningKind::StackUnderflow),
+                _ => None,
+            };
+            if let Some(kind) = kind {
+                warnings.push(self.warning(kind, cpu));
+                self.sp_flagged = true;
+            }
+        }
+
+        warnings
+    }
+
+    fn warning(&self, kind: DevWarningKind, cpu: &CPU) -> DevWarning {
+        let len = self.count.min(RECENT_PCS);
+        let recent = (self.count - len..self.count).map(|i| self.recent[i % RECENT_PCS]).collect();
+        DevWarning {
+            kind,
+            pc: cpu.pc,
+            sp: cpu.sp,
+            bank: (cpu.pc < 0x8000).then(|| cpu.bus.memory.rom_bank(cpu.pc)),
+            recent,
+        }
+    }
+}
+
+// HRAM is where OAM DMA routines run from, so it counts
+fn is_code(addr: u16) -> bool {
+    matches!(addr, 0x0000..=0x7FFF | 0xA000..=0xDFFF | 0xFF80..=0xFFFE)
+}
+
+// SP points at the last byte pushed, so the byte after RAM is fine for an
+// empty stack, as with the common LD SP,E000
+fn is_stack(sp: u16) -> bool {
+    matches!(sp, 0xA000..=0xE000 | 0xFF80..=0xFFFF)
+}
+
+pub fn region_name(addr: u16) -> &'static str {
+    match addr {
+        0x0000..=0x7FFF => "ROM",
+        0x8000..=0x9FFF => "VRAM",
+        0xA000..=0xBFFF => "cartridge RAM",
+        0xC000..=0xDFFF => "work RAM",
+        0xE000..=0xFDFF => "echo RAM",
+        0xFE00..=0xFE9F => "OAM",
+        0xFEA0..=0xFEFF => "unused memory",
+        0xFF00..=0xFF7F => "the I/O registers",
+        0xFF80..=0xFFFE => "HRAM",
+        0xFFFF => "the IE register",
+    }
+}
@@ -0,0 +1,101 @@
+use crate::bus::MemoryBus;
+
+const REGISTERS: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const PAIRS: [&str; 4] = ["BC", "DE", "HL", "SP"];
+// PUSH and POP use AF where the others use SP
+const STACK_PAIRS: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const CONDITIONS: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU: [&str; 8] = ["ADD A,", "ADC A,", "SUB ", "SBC A,", "AND ", "XOR ", "OR ", "CP "];
+const ROTATES: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+const ACCUMULATOR_OPS: [&str; 8] = ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"];
+
+// Decodes the instruction at addr into its mnemonic and length in bytes.
+// Immediates are shown in hex with a $ prefix, relative jumps as the address
+// they land on. Opcodes the SM83 doesn't have come out as DB.
+pub fn disassemble(addr: u16, bus: &MemoryBus) -> (String, u16) {
+    decode(|offset| bus.read_unwatched(addr.wrapping_add(offset)), addr)
+}
+
+// The decoder itself, reading the byte `offset` bytes after addr through `read`
+pub fn decode(read: impl Fn(u16) -> u8, addr: u16) -> (String, u16) {
+    let opcode = read(0);
+    let d8 = || read(1);
+    let d16 = || u16::from_le_bytes([read(1), read(2)]);
+    let e8 = || read(1) as i8;
+    let jump_target = || addr.wrapping_add(2).wrapping_add_signed(e8() as i16);
+
+    let (x, y, z) = (opcode >> 6, ((opcode >> 3) & 7) as usize, (opcode & 7) as usize);
+    let (p, q) = (y >> 1, y & 1);
+
+    match (x, z) {
+        (0, 0) => match y {
+            0 => ("NOP".to_string(), 1),
+            1 => (format!("LD (${:04X}),SP", d16()), 3),
+            2 => ("STOP".to_string(), 2),
+            3 => (format!("JR ${:04X}", jump_target()), 2),
+            _ => (format!("JR {},${:04X}", CONDITIONS[y - 4], jump_target()), 2),
+        },
+        (0, 1) if q == 0 => (format!("LD {},${:04X}", PAIRS[p], d16()), 3),
+        (0, 1) => (format!("ADD HL,{}", PAIRS[p]), 1),
+        (0, 2) => {
+            let pointer = ["(BC)", "(DE)", "(HL+)", "(HL-)"][p];
+            let text = if q == 0 { format!("LD {},A", pointer) } else { format!("LD A,{}", pointer) };
+            (text, 1)
+        }
+        (0, 3) => (format!("{} {}", if q == 0 { "INC" } else { "DEC" }, PAIRS[p]), 1),
+        (0, 4) => (format!("INC {}", REGISTERS[y]), 1),
+        (0, 5) => (format!("DEC {}", REGISTERS[y]), 1),
+        (0, 6) => (format!("LD {},${:02X}", REGISTERS[y], d8()), 2),
+        (0, _) => (ACCUMULATOR_OPS[y].to_string(), 1),
+        (1, _) if opcode == 0x76 => ("HALT".to_string(), 1),
+        (1, _) => (format!("LD {},{}", REGISTERS[y], REGISTERS[z]), 1),
+        (2, _) => (format!("{}{}", ALU[y], REGISTERS[z]), 1),
+        (_, 0) => match y {
+            0..=3 => (format!("RET {}", CONDITIONS[y]), 1),
+            4 => (format!("LDH ($FF{:02X}),A", d8()), 2),
+            5 => (format!("ADD SP,{}", signed(e8())), 2),
+            6 => (format!("LDH A,($FF{:02X})", d8()), 2),
+            _ => (format!("LD HL,SP{}", signed_offset(e8())), 2),
+        },
+        (_, 1) if q == 0 => (format!("POP {}", STACK_PAIRS[p]), 1),
+        (_, 1) => (["RET", "RETI", "JP HL", "LD SP,HL"][p].to_string(), 1),
+        (_, 2) => match y {
+            0..=3 => (format!("JP {},${:04X}", CONDITIONS[y], d16()), 3),
+            4 => ("LD (C),A".to_string(), 1),
+            5 => (format!("LD (${:04X}),A", d16()), 3),
+            6 => ("LD A,(C)".to_string(), 1),
+            _ => (format!("LD A,(${:04X})", d16()), 3),
+        },
+        (_, 3) => match y {
+            0 => (format!("JP ${:04X}", d16()), 3),
+            1 => (decode_cb(read(1)), 2),
+            6 => ("DI".to_string(), 1),
+            7 => ("EI".to_string(), 1),
+            _ => (format!("DB ${:02X}", opcode), 1),
+        },
+        (_, 4) if y < 4 => (format!("CALL {},${:04X}", CONDITIONS[y], d16()), 3),
+        (_, 5) if q == 0 => (format!("PUSH {}", STACK_PAIRS[p]), 1),
+        (_, 5) if p == 0 => (format!("CALL ${:04X}", d16()), 3),
+        (_, 6) => (format!("{}${:02X}", ALU[y], d8()), 2),
+        (_, 7) => (format!("RST ${:02X}", y * 8), 1),
+        _ => (format!("DB ${:02X}", opcode), 1),
+    }
+}
+
+fn decode_cb(opcode: u8) -> String {
+    let (y, z) = (((opcode >> 3) & 7) as usize, REGISTERS[(opcode & 7) as usize]);
+    match opcode >> 6 {
+        0 => format!("{} {}", ROTATES[y], z),
+        1 => format!("BIT {},{}", y, z),
+        2 => format!("RES {},{}", y, z),
+        _ => format!("SET {},{}", y, z),
+    }
+}
+
+fn signed(value: i8) -> String {
+    if value < 0 { format!("-${:02X}", value.unsigned_abs()) } else { format!("${:02X}", value) }
+}
+
+fn signed_offset(value: i8) -> String {
+    if value < 0 { signed(value) } else { format!("+{}", signed(value)) }
+}
@@ -1,101 +1,1243 @@
+use sdl2::audio::AudioSpecDesired;
+use sdl2::controller::{Axis, Button as PadButton, GameController};
+use sdl2::{GameControllerSubsystem, VideoSubsystem};
 use sdl2::pixels::PixelFormatEnum;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use crate::ppu::{SCREEN_WIDTH, SCREEN_HEIGHT};
-use crate::gameboy::GameBoy;
-
-pub fn run_with_display(mut gb: GameBoy) -> Result<(), String> {
-    let sdl_context = sdl2::init()?;
-    let video_subsystem = sdl_context.video()?;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{FullscreenType, Window, WindowContext};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use rust_gbc::ppu::{SpriteOverflow, SCREEN_WIDTH, SCREEN_HEIGHT};
+use rust_gbc::audio_sink::{self, FastForwardAudio};
+use rust_gbc::gameboy::{Frame, GameBoy};
+use rust_gbc::png;
+use rust_gbc::scanout::{self, DmgPalette, Filter, FrameBlend, PaletteList, Scanout};
+use rust_gbc::debugger::{self, ConsoleExit, DebugStop};
+use rust_gbc::events::{self, EmulatorEvent};
+use rust_gbc::pacing::{FramePacer, Speed};
+use rust_gbc::rewind::RewindBuffer;
+use rust_gbc::vram_viewer::{self, ViewerPage, VIEWER_HEIGHT, VIEWER_WIDTH};
+use rust_gbc::launcher::{RomBrowser, VISIBLE_ROMS};
+use rust_gbc::session::LinkSession;
+use rust_gbc::threaded::{CoreThread, FrameOutput, MAX_FRAMES_AHEAD};
+use rust_gbc::input::InputLayer;
+use rust_gbc::bindings::Bindings;
+use rust_gbc::joypad::Button;
+use rust_gbc::remote::RemoteServer;
+use rust_gbc::netplay::Netplay;
+use rust_gbc::headless::FrameDriver;
+use rust_gbc::script::Script;
+use rust_gbc::overlay::OverlayText;
+use rust_gbc::channel_overlay;
+use rust_gbc::error::{EmulatorError, Result};
+
+pub const MIN_SCALE: u32 = 1;
+pub const MAX_SCALE: u32 = 6;
+
+// With --perf the speed and timings follow it once a second
+const WINDOW_TITLE: &str = "Game Boy Color Emulator";
+
+// Once this much audio is waiting the loop sleeps until it drains, which
+// keeps latency low if the host's audio clock runs slower than the pacer
+const MAX_QUEUED_AUDIO: Duration = Duration::from_millis(200);
+// A left/right pair of f32 samples
+const AUDIO_FRAME_BYTES: u32 = 8;
+
+// How long each rumble update runs for. The loop renews it every frame, so
+// the motor stops soon after the game or the emulator does.
+const RUMBLE_DURATION_MS: u32 = 100;
+
+// The VRAM viewer window's pixel size
+const VIEWER_SCALE: u32 = 2;
+
+// Color the sprite overflow overlay blends into lines that dropped objects
+const OVERFLOW_TINT: [u8; 3] = [0xFF, 0x00, 0x00];
+// Pinned RAM search watches, in the top left corner
+const WATCH_COLOR: [u8; 3] = [0xFF, 0xFF, 0x00];
+
+// Window settings chosen on the command line
+pub struct DisplayOptions {
+    pub scale: u32,
+    pub fast_forward_audio: FastForwardAudio,
+    // How fast holding Space runs
+    pub fast_forward_speed: Speed,
+    // Alt+P moves through them, starting at the one selected
+    pub palettes: PaletteList,
+    // Alt+F steps through the filters from this one
+    pub filter: Filter,
+    // Start fullscreen; Alt+Enter switches
+    pub fullscreen: bool,
+    // Percent of each frame left in the next, Alt+G turns it on and off
+    pub frame_blend: u8,
+    // For the game started with; another opened gets its own
+    pub paths: RomPaths,
+    // Where Ctrl+O looks for ROMs
+    pub rom_dir: PathBuf,
+    pub screenshot_scale: u32,
+    pub bindings: Bindings,
+    // Asked of the audio device, which may pick another
+    pub sample_rate: u32,
+    // How far back holding F3 can go; 0 turns rewinding off
+    pub rewind_seconds: u32,
+}
+
+// Files named after the game that's running
+#[derive(Debug, Clone)]
+pub struct RomPaths {
+    // Where F5 and F8 save and load the state
+    pub state: PathBuf,
+    // Print Screen saves <base>-<time>.png
+    pub screenshot_base: PathBuf,
+}
+
+// Replaces the GameBoy with one running the ROM at the path, leaving it as
+// it was on an error
+pub type RomOpener<'a> = dyn FnMut(&mut GameBoy, &Path) -> Result<RomPaths> + 'a;
+
+pub fn run_with_display(
+    gb: &mut GameBoy,
+    options: &DisplayOptions,
+    mut remote: Option<RemoteServer>,
+    mut netplay: Option<Netplay>,
+    mut script: Option<Script>,
+    open_rom: &mut RomOpener,
+) -> Result<()> {
+    let scale = options.scale;
+    let sdl_context = sdl2::init().map_err(frontend("start SDL"))?;
+    let video_subsystem = sdl_context.video().map_err(frontend("start SDL video"))?;
     
     let window = video_subsystem
-        .window("Game Boy Color Emulator", (SCREEN_WIDTH * 4) as u32, (SCREEN_HEIGHT * 4) as u32)
+        .window(WINDOW_TITLE, SCREEN_WIDTH as u32 * scale, SCREEN_HEIGHT as u32 * scale)
         .position_centered()
+        .resizable()
         .build()
-        .map_err(|e| e.to_string())?;
-
-    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
-    canvas.set_scale(4.0, 4.0).map_err(|e| e.to_string())?;
+        .map_err(frontend("open the window"))?;
 
+    let canvas = window.into_canvas().build().map_err(frontend("create the renderer"))?;
     let texture_creator = canvas.texture_creator();
-    let mut texture = texture_creator
-        .create_texture_streaming(
-            PixelFormatEnum::RGB24,
-            SCREEN_WIDTH as u32,
-            SCREEN_HEIGHT as u32,
-        )
-        .map_err(|e| e.to_string())?;
+    let mut scanout = SdlScanout::new(canvas, &texture_creator, 1, scale)?;
+    let mut view = ViewSettings::apply(options, &mut scanout)?;
+
+    let audio_subsystem = sdl_context.audio().map_err(frontend("start SDL audio"))?;
+    let audio_spec = AudioSpecDesired {
+        freq: Some(options.sample_rate as i32),
+        channels: Some(2),
+        samples: Some(1024),
+    };
+    let audio_queue = audio_subsystem
+        .open_queue::<f32, _>(None, &audio_spec)
+        .map_err(frontend("open the audio device"))?;
+    audio_queue.resume();
+    let sample_rate = audio_queue.spec().freq as u32;
+    gb.set_sample_rate(sample_rate);
+    let max_queued_audio_bytes = (sample_rate as f64 * MAX_QUEUED_AUDIO.as_secs_f64()) as u32 * AUDIO_FRAME_BYTES;
 
-    let mut event_pump = sdl_context.event_pump()?;
+    let mut event_pump = sdl_context.event_pump().map_err(frontend("read input events"))?;
+    let keys = key_map(&options.bindings)?;
+    let mut gamepads = Gamepads::new(sdl_context.game_controller().map_err(frontend("start SDL game controllers"))?);
 
     let mut frame_count = 0;
-    let mut last_pc = gb.cpu.pc;
-    let mut stuck_count = 0;
+    let mut input = InputLayer::new();
     
     println!("\nEmulator running! Press ESC to quit.\n");
     
-    println!("Debug - LCDC: 0x{:02X}", gb.cpu.bus.ppu.read(0xFF40));
-    println!("Debug - LY: 0x{:02X}", gb.cpu.bus.ppu.read(0xFF44));
-    println!("Debug - PC: 0x{:04X}", gb.cpu.pc);
+    println!("Debug - LCDC: 0x{:02X}", gb.read_byte(0xFF40));
+    println!("Debug - LY: 0x{:02X}", gb.read_byte(0xFF44));
+    println!("Debug - PC: 0x{:04X}", gb.registers().pc);
     
     for i in 0..10 {
         let addr = 0x8000 + i * 100;
-        println!("VRAM[0x{:04X}]: {:02X}", addr, gb.cpu.bus.ppu.read(addr));
+        println!("VRAM[0x{:04X}]: {:02X}", addr, gb.read_byte(addr));
     }
 
+    let mut paused = false;
+    let mut fast_forward = false;
+    let mut slow_motion = Speed::Times(1);
+    let mut rewind = RewindBuffer::with_seconds(options.rewind_seconds);
+    let mut rewinding = false;
+    let mut rewind_frames = 0;
+    let mut show_sprite_overflow = false;
+    let mut show_channels = false;
+    let mut reported_overflow = Vec::new();
+    let mut viewer: Option<ViewerWindow> = None;
+    let mut browser: Option<RomBrowser> = None;
+    let mut paths = options.paths.clone();
+    let mut pacer = FramePacer::new();
+
     'running: loop {
-        gb.run_frame();
-        
-        if gb.cpu.pc == last_pc {
-            stuck_count += 1;
-        } else {
-            stuck_count = 0;
-            last_pc = gb.cpu.pc;
+        pacer.set_speed(if fast_forward { options.fast_forward_speed } else { slow_motion });
+        let frames = if paused || rewinding || browser.is_some() { 0 } else { pacer.frames_due() };
+        let mut upload = false;
+        if let Some(server) = remote.as_mut() {
+            server.poll(gb);
+        }
+        let remote_buttons = remote.as_ref().map_or(0, RemoteServer::held_buttons);
+        if frames == 0 {
+            poll_netplay(&mut netplay, gb);
+        }
+        for _ in 0..frames {
+            if netplay.is_some() {
+                // The peer being late drops the frame for both
+                if !run_netplay_frame(&mut netplay, gb, input.next_frame() | remote_buttons) {
+                    break;
+                }
+                upload = true;
+                continue;
+            }
+            let script_buttons = script.as_ref().map_or(0, Script::buttons);
+            gb.set_inputs(input.next_frame() | remote_buttons | script_buttons);
+            match gb.run_frame() {
+                Some(frame) => upload |= frame.dirty,
+                // Show the lines drawn up to the breakpoint
+                None => {
+                    upload = true;
+                    break;
+                }
+            }
+            // A failing script stops, the game goes on
+            if let Some(Err(e)) = script.as_mut().map(|script| script.after_frame(gb)) {
+                eprintln!("{}", e);
+                script = None;
+                upload = true;
+            }
+            rewind.record(gb);
+        }
+        // Going back one snapshot per interval runs time backwards at normal speed
+        if rewinding {
+            if rewind_frames == 0 && rewind.step_back(gb) {
+                upload = true;
+            }
+            rewind_frames = (rewind_frames + 1) % rewind.interval();
+        }
+
+        let samples = gb.take_audio().samples;
+        let samples = match pacer.speed() {
+            Speed::Fraction(divisor) => audio_sink::stretch(&samples, divisor),
+            _ => audio_sink::fit_to_speed(&samples, frames, options.fast_forward_audio),
+        };
+        // Unthrottled runs outrun the audio device, so what doesn't fit is dropped
+        let unthrottled = pacer.speed() == Speed::Unthrottled;
+        while !unthrottled && audio_queue.size() > max_queued_audio_bytes {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        if audio_queue.size() <= max_queued_audio_bytes {
+            audio_queue.queue_audio(&samples).map_err(frontend("queue audio"))?;
+        }
+
+        if let Some(strength) = gb.take_rumble() {
+            gamepads.rumble(strength);
+        }
+
+        let timed = gb.take_events();
+        let debug_stop = timed.iter().any(|timed| matches!(timed.event, EmulatorEvent::DebugStop { .. }));
+        let stepped = timed.iter().any(|timed| matches!(timed.event, EmulatorEvent::DebugStop { stop: DebugStop::Step, .. }));
+        if events::report(timed) {
+            // Not after every step in the console, which has a command for it
+            if !stepped {
+                events::report_history(&gb.history());
+            }
+            // The window stays frozen while the console waits for commands
+            if debug_stop {
+                if debugger::console(gb, &mut std::io::stdin().lock(), &mut std::io::stdout()) == ConsoleExit::Quit {
+                    break 'running;
+                }
+                pacer.reset();
+            } else {
+                paused = true;
+                println!("Paused at breakpoint, press F6 to continue");
+            }
         }
         
         if frame_count == 0 {
             println!("\nAfter first frame:");
-            println!("LCDC: 0x{:02X}", gb.cpu.bus.ppu.read(0xFF40));
-            println!("LY: 0x{:02X}", gb.cpu.bus.ppu.read(0xFF44));
+            println!("LCDC: 0x{:02X}", gb.read_byte(0xFF40));
+            println!("LY: 0x{:02X}", gb.read_byte(0xFF44));
             println!("SCX: 0x{:02X}, SCY: 0x{:02X}", 
-                     gb.cpu.bus.ppu.read(0xFF43),
-                     gb.cpu.bus.ppu.read(0xFF42));
+                     gb.read_byte(0xFF43),
+                     gb.read_byte(0xFF42));
             
             let mut non_white = 0;
-            for i in 0..gb.cpu.bus.ppu.framebuffer.len() {
-                if gb.cpu.bus.ppu.framebuffer[i] != 0xFF {
+            for i in 0..gb.framebuffer().len() {
+                if gb.framebuffer()[i] != 0xFF {
                     non_white += 1;
                 }
             }
-            println!("Non-white pixels: {}/{}", non_white, gb.cpu.bus.ppu.framebuffer.len());
+            println!("Non-white pixels: {}/{}", non_white, gb.framebuffer().len());
         }
         
-        texture.update(None, &gb.cpu.bus.ppu.framebuffer, SCREEN_WIDTH * 3).unwrap();
-        canvas.copy(&texture, None, None).unwrap();
-        canvas.present();
+        let script_overlay = script.as_ref().is_some_and(Script::has_overlay);
+        let watches = !gb.ram_search().pinned().is_empty();
+        if show_sprite_overflow || show_channels || script_overlay || watches || browser.is_some() {
+            let mut pixels = gb.frame().pixels.to_vec();
+            if show_sprite_overflow {
+                let overflow = gb.sprite_overflow();
+                if overflow != reported_overflow.as_slice() {
+                    report_sprite_overflow(overflow);
+                    reported_overflow = overflow.to_vec();
+                }
+                scanout::highlight_lines(&mut pixels, overflow.iter().map(|o| o.line as usize), OVERFLOW_TINT);
+            }
+            if show_channels {
+                channel_overlay::draw(&mut pixels, &gb.channel_status(), &gb.channel_scope());
+            }
+            if let Some(script) = &script {
+                script.draw_overlay(&mut pixels);
+            }
+            if watches {
+                let text = gb.ram_search().watch_text(gb.searchable_wram());
+                OverlayText { x: 2, y: 2, text, color: WATCH_COLOR }.draw(&mut pixels);
+            }
+            if let Some(browser) = &browser {
+                browser.draw(&mut pixels);
+            }
+            scanout.present(&Frame { pixels: &pixels, dirty: true, ..gb.frame() })?;
+        } else {
+            scanout.present(&Frame { dirty: upload, ..gb.frame() })?;
+        }
+        if let Some(viewer) = &mut viewer {
+            viewer.present(gb)?;
+        }
         
+        let mut close_viewer = false;
+        let mut to_open: Option<PathBuf> = None;
         for event in event_pump.poll_iter() {
+            // The viewer takes its own keys, the rest still reach the game
+            if let Some(viewer) = viewer.as_mut().filter(|viewer| event.get_window_id() == Some(viewer.id())) {
+                match event {
+                    Event::Window { win_event: WindowEvent::Close, .. }
+                    | Event::KeyDown { keycode: Some(Keycode::Escape | Keycode::F1), repeat: false, .. } => {
+                        close_viewer = true;
+                        continue;
+                    }
+                    Event::KeyDown { keycode: Some(Keycode::Tab), repeat: false, .. } => {
+                        viewer.next_page()?;
+                        continue;
+                    }
+                    _ => {}
+                }
+            }
+            // The browser takes the keys while it's open, releases still reach the game
+            if let (Some(list), Event::KeyDown { keycode: Some(keycode), keymod, .. }) = (browser.as_mut(), &event) {
+                match *keycode {
+                    Keycode::Up => list.move_selection(-1),
+                    Keycode::Down => list.move_selection(1),
+                    Keycode::PageUp => list.move_selection(-(VISIBLE_ROMS as isize)),
+                    Keycode::PageDown => list.move_selection(VISIBLE_ROMS as isize),
+                    Keycode::Return => {
+                        to_open = list.selected().map(Path::to_path_buf);
+                        browser = None;
+                    }
+                    Keycode::Escape => browser = None,
+                    Keycode::O if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => browser = None,
+                    _ => {}
+                }
+                // Redraw without the list
+                if browser.is_none() {
+                    scanout.stale = true;
+                }
+                continue;
+            }
             match event {
+                Event::DropFile { filename, .. } => match open_blocked(gb, &netplay) {
+                    Some(reason) => println!("{}", reason),
+                    None => to_open = Some(PathBuf::from(filename)),
+                },
+                Event::KeyDown { keycode: Some(Keycode::O), keymod, repeat: false, .. }
+                    if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) =>
+                {
+                    if let Some(reason) = open_blocked(gb, &netplay) {
+                        println!("{}", reason);
+                        continue;
+                    }
+                    match RomBrowser::open(&options.rom_dir) {
+                        Ok(list) => {
+                            println!("{} ROMs in {}, Up/Down to choose, Enter to open, Esc to close", list.roms().len(), list.dir().display());
+                            input.release_keys();
+                            browser = Some(list);
+                        }
+                        Err(e) => eprintln!("Error: failed to list ROMs in '{}': {}", options.rom_dir.display(), e),
+                    }
+                }
                 Event::Quit { .. }
+                | Event::Window { win_event: WindowEvent::Close, .. }
                 | Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
                 } => break 'running,
-                _ => {}
+                Event::KeyDown { keycode: Some(Keycode::F1), repeat: false, .. } => {
+                    if viewer.is_some() {
+                        close_viewer = true;
+                    } else {
+                        viewer = Some(ViewerWindow::open(&video_subsystem)?);
+                    }
+                }
+                Event::KeyDown { keycode: Some(Keycode::F6), repeat: false, .. } => {
+                    paused = !paused;
+                    println!("{}", if paused { "Paused, press F6 to continue" } else { "Continuing" });
+                }
+                Event::KeyDown { keycode: Some(Keycode::F3), repeat: false, .. } => {
+                    if gb.is_movie_active() {
+                        println!("Can't rewind during a movie");
+                    } else if netplay.is_some() {
+                        println!("Can't rewind during netplay");
+                    } else if options.rewind_seconds == 0 {
+                        println!("Rewind is off, turn it on with --rewind=N");
+                    } else {
+                        rewinding = true;
+                        rewind_frames = 0;
+                    }
+                }
+                Event::KeyUp { keycode: Some(Keycode::F3), .. } => rewinding = false,
+                Event::KeyDown { keycode: Some(Keycode::F4), repeat: false, .. } => {
+                    slow_motion = match slow_motion {
+                        Speed::Times(1) => Speed::Fraction(2),
+                        Speed::Fraction(2) => Speed::Fraction(4),
+                        _ => Speed::Times(1),
+                    };
+                    println!("Speed {}", slow_motion);
+                }
+                // Breaks into the debugger console after the next instruction
+                Event::KeyDown { keycode: Some(Keycode::F7), repeat: false, .. } => {
+                    gb.debugger().step_into();
+                    paused = false;
+                }
+                Event::KeyDown { keycode: Some(Keycode::F2), repeat: false, .. } => {
+                    show_sprite_overflow = !show_sprite_overflow;
+                    reported_overflow.clear();
+                    // Redraw without the tint when turned off
+                    scanout.stale = true;
+                    println!("Sprite overflow overlay {}", if show_sprite_overflow { "on" } else { "off" });
+                }
+                Event::KeyDown { keycode: Some(Keycode::F5), repeat: false, .. } => save_state_file(gb, &paths.state),
+                Event::KeyDown { keycode: Some(Keycode::PrintScreen), repeat: false, .. } => {
+                    let image = gb.screenshot(options.screenshot_scale, view.palettes.current().colors.as_ref());
+                    write_screenshot(&paths.screenshot_base, &image);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F8), repeat: false, .. } if gb.is_movie_active() => {
+                    println!("Can't load a state during a movie");
+                }
+                Event::KeyDown { keycode: Some(Keycode::F8), repeat: false, .. } if netplay.is_some() => {
+                    println!("Can't load a state during netplay");
+                }
+                Event::KeyDown { keycode: Some(Keycode::F8), repeat: false, .. } => load_state_file(gb, &paths.state),
+                Event::KeyDown { keycode: Some(Keycode::Space), .. } => fast_forward = true,
+                Event::KeyUp { keycode: Some(Keycode::Space), .. } => fast_forward = false,
+                Event::KeyDown { keycode: Some(Keycode::A), keymod, repeat: false, .. }
+                    if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) =>
+                {
+                    show_channels = !show_channels;
+                    gb.set_channel_scope(show_channels);
+                    scanout.stale = true;
+                    println!("Audio channel overlay {}", if show_channels { "on" } else { "off" });
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    keymod,
+                    repeat,
+                    ..
+                } if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => view.handle_key(&mut scanout, keycode, repeat, options)?,
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    keymod,
+                    repeat: false,
+                    ..
+                } => {
+                    if let Some(&button) = keys.get(&keycode) {
+                        input.set_held(button, true);
+                    }
+                    if let Some(slot) = macro_slot_for_key(keycode) {
+                        if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                            input.toggle_recording(slot);
+                            let state = if input.is_recording() { "Recording" } else { "Stopped recording" };
+                            println!("{} macro {}", state, slot + 1);
+                        } else {
+                            input.play_macro(slot);
+                        }
+                    }
+                    if let Some(channel) = channel_for_key(keycode) {
+                        let solo = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+                        report_channels(gb, channel, solo);
+                    }
+                }
+                Event::KeyUp { keycode: Some(keycode), .. } => {
+                    if let Some(&button) = keys.get(&keycode) {
+                        input.set_held(button, false);
+                    }
+                }
+                other => gamepads.handle(other, &mut input, &options.bindings),
+            }
+        }
+
+        if close_viewer {
+            viewer = None;
+        }
+
+        if let Some(path) = to_open {
+            match open_rom(gb, &path) {
+                Ok(opened) => {
+                    println!("Opened {}", path.display());
+                    paths = opened;
+                    gb.set_sample_rate(sample_rate);
+                    gb.set_channel_scope(show_channels);
+                    rewind.clear();
+                    rewinding = false;
+                    reported_overflow.clear();
+                    input.release_keys();
+                    // It was written for the game it was started with
+                    if script.take().is_some() {
+                        println!("Stopped the script");
+                    }
+                    paused = false;
+                    pacer.reset();
+                    scanout.stale = true;
+                }
+                Err(e) => eprintln!("Error: failed to open '{}': {}", path.display(), e),
             }
         }
 
+        pacer.wait();
+
         frame_count += 1;
         if frame_count % 60 == 0 {
+            if let Some(stats) = gb.perf_stats() {
+                scanout.set_title(&format!("{} - {}", WINDOW_TITLE, stats.summary()))?;
+            }
+            let frame = gb.frame();
             println!("Frames: {}, Cycles: {}, PC: 0x{:04X}", 
-                     frame_count, gb.cpu.cycles, gb.cpu.pc);
+                     frame.index + 1, frame.cycles, gb.registers().pc);
         }
     }
 
     println!("\nEmulator stopped after {} frames", frame_count);
-    println!("Total cycles: {}", gb.cpu.cycles);
+    println!("Total cycles: {}", gb.cycles());
     
     Ok(())
 }
 
+// Both screens share one window: side by side, or only the focused one.
+// Tab switches which instance receives input, F1 switches the view.
+pub fn run_session_with_display(session: &mut LinkSession, options: &DisplayOptions) -> Result<()> {
+    let scale = options.scale;
+    let sdl_context = sdl2::init().map_err(frontend("start SDL"))?;
+    let video_subsystem = sdl_context.video().map_err(frontend("start SDL video"))?;
+
+    let window = video_subsystem
+        .window("Game Boy Color Emulator - Link", SCREEN_WIDTH as u32 * scale * 2, SCREEN_HEIGHT as u32 * scale)
+        .position_centered()
+        .resizable()
+        .build()
+        .map_err(frontend("open the window"))?;
+
+    let canvas = window.into_canvas().build().map_err(frontend("create the renderer"))?;
+    let texture_creator = canvas.texture_creator();
+    let mut scanout = SdlScanout::new(canvas, &texture_creator, 2, scale)?;
+    let mut view = ViewSettings::apply(options, &mut scanout)?;
+
+    let mut event_pump = sdl_context.event_pump().map_err(frontend("read input events"))?;
+    let keys = key_map(&options.bindings)?;
+    let mut inputs = [InputLayer::new(), InputLayer::new()];
+    let mut side_by_side = true;
+    let mut fast_forward = false;
+    let mut pacer = FramePacer::new();
+
+    println!("\nLink session running! Tab switches focus, F1 switches view, ESC quits.\n");
+
+    'running: loop {
+        for (gb, input) in session.games_mut().iter_mut().zip(&mut inputs) {
+            gb.set_inputs(input.next_frame());
+        }
+        session.run_frame();
+
+        for (index, gb) in session.games_mut().iter_mut().enumerate() {
+            events::report(gb.take_events());
+            // The link window has no sound
+            gb.take_audio();
+            scanout.update_screen(index, &gb.frame())?;
+        }
+        scanout.solo = if side_by_side { None } else { Some(session.focus()) };
+        scanout.draw()?;
+
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => break 'running,
+                Event::DropFile { .. } => println!("Can't open another ROM in a link session"),
+                Event::KeyDown { keycode: Some(Keycode::O), keymod, repeat: false, .. }
+                    if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) =>
+                {
+                    println!("Can't open another ROM in a link session");
+                }
+                Event::KeyDown { keycode: Some(Keycode::Tab), .. } => {
+                    inputs[session.focus()].release_keys();
+                    session.toggle_focus();
+                    println!("Input focus: instance {}", session.focus() + 1);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F1), .. } => {
+                    side_by_side = !side_by_side;
+                    scanout.solo = if side_by_side { None } else { Some(session.focus()) };
+                    scanout.resize(scanout.scale)?;
+                }
+                Event::KeyDown { keycode: Some(Keycode::Space), .. } => fast_forward = true,
+                Event::KeyUp { keycode: Some(Keycode::Space), .. } => fast_forward = false,
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    keymod,
+                    repeat,
+                    ..
+                } if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => view.handle_key(&mut scanout, keycode, repeat, options)?,
+                // Keys go to the instance that has focus
+                Event::KeyDown { keycode: Some(keycode), repeat: false, .. } => {
+                    if let Some(&button) = keys.get(&keycode) {
+                        inputs[session.focus()].set_held(button, true);
+                    }
+                }
+                Event::KeyUp { keycode: Some(keycode), .. } => {
+                    if let Some(&button) = keys.get(&keycode) {
+                        inputs[session.focus()].set_held(button, false);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if fast_forward {
+            pacer.reset();
+        } else {
+            pacer.wait();
+        }
+    }
+
+    Ok(())
+}
+
+// The game runs on the core thread while this one shows the frame before
+// and queues its sound, so buttons take a frame longer to reach it than in
+// run_with_display. Past the view keys only F5, F6, F8, Print Screen and
+// fast forward are handled; the rest needs the machine on this thread.
+pub fn run_threaded_with_display(core: &mut CoreThread, options: &DisplayOptions) -> Result<()> {
+    let scale = options.scale;
+    let sdl_context = sdl2::init().map_err(frontend("start SDL"))?;
+    let video_subsystem = sdl_context.video().map_err(frontend("start SDL video"))?;
+
+    let window = video_subsystem
+        .window(WINDOW_TITLE, SCREEN_WIDTH as u32 * scale, SCREEN_HEIGHT as u32 * scale)
+        .position_centered()
+        .resizable()
+        .build()
+        .map_err(frontend("open the window"))?;
+
+    let canvas = window.into_canvas().build().map_err(frontend("create the renderer"))?;
+    let texture_creator = canvas.texture_creator();
+    let mut scanout = SdlScanout::new(canvas, &texture_creator, 1, scale)?;
+    let mut view = ViewSettings::apply(options, &mut scanout)?;
+
+    let audio_subsystem = sdl_context.audio().map_err(frontend("start SDL audio"))?;
+    let audio_spec = AudioSpecDesired {
+        freq: Some(options.sample_rate as i32),
+        channels: Some(2),
+        samples: Some(1024),
+    };
+    let audio_queue = audio_subsystem
+        .open_queue::<f32, _>(None, &audio_spec)
+        .map_err(frontend("open the audio device"))?;
+    audio_queue.resume();
+    let sample_rate = audio_queue.spec().freq as u32;
+    core.with(move |gb| gb.set_sample_rate(sample_rate))?;
+    let max_queued_audio_bytes = (sample_rate as f64 * MAX_QUEUED_AUDIO.as_secs_f64()) as u32 * AUDIO_FRAME_BYTES;
+
+    let mut event_pump = sdl_context.event_pump().map_err(frontend("read input events"))?;
+    let keys = key_map(&options.bindings)?;
+    let mut gamepads = Gamepads::new(sdl_context.game_controller().map_err(frontend("start SDL game controllers"))?);
+    let mut input = InputLayer::new();
+    let mut shown: Option<FrameOutput> = None;
+    let mut paused = false;
+    let mut fast_forward = false;
+    let mut pacer = FramePacer::new();
+
+    println!("\nEmulator running on its own thread! Press ESC to quit.\n");
+
+    'running: loop {
+        pacer.set_speed(if fast_forward { options.fast_forward_speed } else { Speed::Times(1) });
+        let frames = if paused { 0 } else { pacer.frames_due() };
+        let mut samples = Vec::new();
+        let mut upload = false;
+        for _ in 0..frames {
+            // One frame stays in flight, emulated while the one before is shown
+            core.start_frame(input.next_frame())?;
+            if core.pending_frames() < MAX_FRAMES_AHEAD {
+                continue;
+            }
+            let Some(mut frame) = core.finish_frame()? else { break };
+            samples.extend_from_slice(&frame.samples);
+            if let Some(strength) = frame.rumble {
+                gamepads.rumble(strength);
+            }
+            if events::report(std::mem::take(&mut frame.events)) {
+                events::report_history(&frame.history);
+                paused = true;
+                println!("Paused at breakpoint, press F6 to continue");
+            }
+            if frame.index % 60 == 59 {
+                if let Some(stats) = frame.perf {
+                    scanout.set_title(&format!("{} - {}", WINDOW_TITLE, stats.summary()))?;
+                }
+            }
+            upload |= frame.dirty || !frame.complete;
+            let stopped = !frame.complete;
+            shown = Some(frame);
+            if stopped {
+                break;
+            }
+        }
+
+        let samples = audio_sink::fit_to_speed(&samples, frames, options.fast_forward_audio);
+        let unthrottled = pacer.speed() == Speed::Unthrottled;
+        while !unthrottled && audio_queue.size() > max_queued_audio_bytes {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        if audio_queue.size() <= max_queued_audio_bytes {
+            audio_queue.queue_audio(&samples).map_err(frontend("queue audio"))?;
+        }
+
+        if let Some(frame) = &shown {
+            scanout.present(&Frame { dirty: upload, ..frame.frame() })?;
+        }
+
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => break 'running,
+                Event::KeyDown { keycode: Some(Keycode::F6), repeat: false, .. } => {
+                    paused = !paused;
+                    println!("{}", if paused { "Paused, press F6 to continue" } else { "Continuing" });
+                }
+                Event::DropFile { .. } => println!("Can't open another ROM with --threaded"),
+                Event::KeyDown { keycode: Some(Keycode::O), keymod, repeat: false, .. }
+                    if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) =>
+                {
+                    println!("Can't open another ROM with --threaded");
+                }
+                Event::KeyDown { keycode: Some(Keycode::F5), repeat: false, .. } => {
+                    let path = options.paths.state.clone();
+                    core.with(move |gb| save_state_file(gb, &path))?;
+                }
+                Event::KeyDown { keycode: Some(Keycode::F8), repeat: false, .. } => {
+                    let path = options.paths.state.clone();
+                    core.with(move |gb| load_state_file(gb, &path))?;
+                }
+                Event::KeyDown { keycode: Some(Keycode::PrintScreen), repeat: false, .. } => {
+                    let (scale, palette) = (options.screenshot_scale, view.palettes.current().colors);
+                    let image = core.with(move |gb| gb.screenshot(scale, palette.as_ref()))?;
+                    write_screenshot(&options.paths.screenshot_base, &image);
+                }
+                Event::KeyDown { keycode: Some(Keycode::Space), .. } => fast_forward = true,
+                Event::KeyUp { keycode: Some(Keycode::Space), .. } => fast_forward = false,
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    keymod,
+                    repeat,
+                    ..
+                } if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => view.handle_key(&mut scanout, keycode, repeat, options)?,
+                Event::KeyDown { keycode: Some(keycode), repeat: false, .. } => {
+                    if let Some(&button) = keys.get(&keycode) {
+                        input.set_held(button, true);
+                    }
+                }
+                Event::KeyUp { keycode: Some(keycode), .. } => {
+                    if let Some(&button) = keys.get(&keycode) {
+                        input.set_held(button, false);
+                    }
+                }
+                other => gamepads.handle(other, &mut input, &options.bindings),
+            }
+        }
+
+        pacer.wait();
+    }
+
+    if let Some(frame) = &shown {
+        println!("\nEmulator stopped after {} frames", frame.index + 1);
+        println!("Total cycles: {}", frame.cycles);
+    }
+    Ok(())
+}
+
+// How the screen is shown, which the Alt keys change while running
+struct ViewSettings {
+    palettes: PaletteList,
+    filter: Filter,
+    frame_blend: u8,
+}
+
+impl ViewSettings {
+    // Starts the scanout with the settings from the command line
+    fn apply(options: &DisplayOptions, scanout: &mut SdlScanout) -> Result<ViewSettings> {
+        let view = ViewSettings { palettes: options.palettes.clone(), filter: options.filter, frame_blend: options.frame_blend };
+        scanout.set_palette(view.palettes.current().colors);
+        scanout.set_filter(view.filter);
+        if options.fullscreen {
+            scanout.toggle_fullscreen()?;
+        }
+        scanout.set_frame_blend(view.frame_blend);
+        Ok(view)
+    }
+
+    fn handle_key(&mut self, scanout: &mut SdlScanout, keycode: Keycode, repeat: bool, options: &DisplayOptions) -> Result<()> {
+        if let Some(scale) = scale_for_key(keycode) {
+            scanout.resize(scale)?;
+        }
+        match keycode {
+            Keycode::P if !repeat => {
+                let palette = self.palettes.cycle();
+                scanout.set_palette(palette.colors);
+                println!("Palette: {}", palette.name);
+            }
+            Keycode::F if !repeat => {
+                self.filter = self.filter.next();
+                scanout.set_filter(self.filter);
+                println!("Filter: {}", self.filter);
+            }
+            Keycode::G if !repeat => {
+                self.frame_blend = match (self.frame_blend, options.frame_blend) {
+                    (0, 0) => scanout::DEFAULT_FRAME_BLEND,
+                    (0, chosen) => chosen,
+                    _ => 0,
+                };
+                scanout.set_frame_blend(self.frame_blend);
+                match self.frame_blend {
+                    0 => println!("Frame blending off"),
+                    percent => println!("Frame blending {}%", percent),
+                }
+            }
+            Keycode::Return if !repeat => {
+                let fullscreen = scanout.toggle_fullscreen()?;
+                println!("Fullscreen {}", if fullscreen { "on" } else { "off" });
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+// Printed when the set of overflowing lines changes, not every frame
+fn report_sprite_overflow(overflow: &[SpriteOverflow]) {
+    let Some(first) = overflow.first() else {
+        println!("Sprite overflow: none");
+        return;
+    };
+    let dropped: Vec<String> = first.dropped.iter().map(|i| i.to_string()).collect();
+    println!(
+        "Sprite overflow on {} lines, first at line {}: {} objects, dropped OAM {}",
+        overflow.len(), first.line, first.requested, dropped.join(", ")
+    );
+}
+
+// Failures are only reported, a missing or stale state shouldn't end the session
+// Runs one frame of a netplay session, returning false when the other
+// player's buttons haven't arrived. A session whose peer is gone ends and
+// the game carries on alone.
+fn run_netplay_frame(netplay: &mut Option<Netplay>, gb: &mut GameBoy, buttons: u8) -> bool {
+    let Some(session) = netplay else {
+        return false;
+    };
+    let result = session.run_frame(gb, buttons);
+    if let Some(frame) = session.take_desync() {
+        println!("Netplay out of sync since frame {}, the two games no longer match", frame);
+    }
+    match result {
+        Ok(ran) => ran,
+        Err(error) => {
+            println!("Netplay ended: {}", error);
+            *netplay = None;
+            false
+        }
+    }
+}
+
+// Another game would end the movie or leave the peer behind
+fn open_blocked(gb: &GameBoy, netplay: &Option<Netplay>) -> Option<&'static str> {
+    if gb.is_movie_active() {
+        Some("Can't open another ROM during a movie")
+    } else if netplay.is_some() {
+        Some("Can't open another ROM during netplay")
+    } else {
+        None
+    }
+}
+
+fn poll_netplay(netplay: &mut Option<Netplay>, gb: &mut GameBoy) {
+    if let Some(Err(error)) = netplay.as_mut().map(|session| session.poll(gb)) {
+        println!("Netplay ended: {}", error);
+        *netplay = None;
+    }
+}
+
+fn save_state_file(gb: &GameBoy, path: &Path) {
+    match fs::write(path, gb.save_state()) {
+        Ok(()) => println!("Saved state to {}", path.display()),
+        Err(e) => eprintln!("Error: failed to save state to '{}': {}", path.display(), e),
+    }
+}
+
+fn write_screenshot(base: &Path, image: &[u8]) {
+    let path = png::screenshot_path(base, SystemTime::now());
+    match fs::write(&path, image) {
+        Ok(()) => println!("Saved screenshot to {}", path.display()),
+        Err(e) => eprintln!("Error: failed to save screenshot to '{}': {}", path.display(), e),
+    }
+}
+
+fn load_state_file(gb: &mut GameBoy, path: &Path) {
+    let result = fs::read(path)
+        .map_err(|e| e.to_string())
+        .and_then(|data| gb.load_state(&data).map_err(|e| e.to_string()));
+    match result {
+        Ok(()) => println!("Loaded state from {}", path.display()),
+        Err(e) => eprintln!("Error: failed to load state from '{}': {}", path.display(), e),
+    }
+}
+
+// Alt+1 through Alt+6 switch to the matching integer scale
+fn scale_for_key(keycode: Keycode) -> Option<u32> {
+    match keycode {
+        Keycode::Num1 => Some(1),
+        Keycode::Num2 => Some(2),
+        Keycode::Num3 => Some(3),
+        Keycode::Num4 => Some(4),
+        Keycode::Num5 => Some(5),
+        Keycode::Num6 => Some(6),
+        _ => None,
+    }
+}
+
+// 1 to 4 mute the matching sound channel, with Shift they solo it
+fn channel_for_key(keycode: Keycode) -> Option<usize> {
+    match keycode {
+        Keycode::Num1 => Some(0),
+        Keycode::Num2 => Some(1),
+        Keycode::Num3 => Some(2),
+        Keycode::Num4 => Some(3),
+        _ => None,
+    }
+}
+
+fn report_channels(gb: &mut GameBoy, channel: usize, solo: bool) {
+    if solo {
+        match gb.toggle_channel_solo(channel) {
+            Some(_) => println!("Soloing channel {}", channel + 1),
+            None => println!("Channel {} unsoloed", channel + 1),
+        }
+    } else {
+        let muted = gb.toggle_channel_muted(channel);
+        println!("Channel {} {}", channel + 1, if muted { "muted" } else { "unmuted" });
+    }
+}
+
+fn key_map(bindings: &Bindings) -> Result<HashMap<Keycode, Button>> {
+    bindings
+        .keys
+        .iter()
+        .map(|(name, button)| {
+            let keycode = Keycode::from_name(name).ok_or_else(|| EmulatorError::UnknownKey(name.clone()))?;
+            Ok((keycode, *button))
+        })
+        .collect()
+}
+
+fn button_for_pad(button: PadButton) -> Option<Button> {
+    match button {
+        PadButton::DPadRight => Some(Button::Right),
+        PadButton::DPadLeft => Some(Button::Left),
+        PadButton::DPadUp => Some(Button::Up),
+        PadButton::DPadDown => Some(Button::Down),
+        PadButton::A => Some(Button::A),
+        PadButton::B => Some(Button::B),
+        PadButton::Start => Some(Button::Start),
+        PadButton::Back => Some(Button::Select),
+        _ => None,
+    }
+}
+
+// Game controllers, opened as they are plugged in. SDL also reports the ones
+// connected at startup as added, so there is no separate scan.
+struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    // By joystick instance id, which is what the other controller events carry
+    controllers: HashMap<u32, GameController>,
+    stick: (i16, i16),
+}
+
+impl Gamepads {
+    fn new(subsystem: GameControllerSubsystem) -> Self {
+        Gamepads { subsystem, controllers: HashMap::new(), stick: (0, 0) }
+    }
 
+    fn handle(&mut self, event: Event, input: &mut InputLayer, bindings: &Bindings) {
+        match event {
+            Event::ControllerDeviceAdded { which, .. } => match self.subsystem.open(which) {
+                Ok(controller) => {
+                    println!("Controller connected: {}", controller.name());
+                    self.controllers.insert(controller.instance_id(), controller);
+                }
+                Err(e) => eprintln!("Warning: failed to open controller {}: {}", which, e),
+            },
+            Event::ControllerDeviceRemoved { which, .. } => {
+                if let Some(controller) = self.controllers.remove(&which) {
+                    println!("Controller disconnected: {}", controller.name());
+                }
+                // Don't leave buttons stuck down
+                for button in Button::ALL {
+                    input.set_pad_held(button, false);
+                }
+                self.stick = (0, 0);
+                input.set_stick(0);
+            }
+            Event::ControllerButtonDown { button, .. } => {
+                if let Some(button) = button_for_pad(button) {
+                    input.set_pad_held(button, true);
+                }
+            }
+            Event::ControllerButtonUp { button, .. } => {
+                if let Some(button) = button_for_pad(button) {
+                    input.set_pad_held(button, false);
+                }
+            }
+            Event::ControllerAxisMotion { axis, value, .. } => {
+                match axis {
+                    Axis::LeftX => self.stick.0 = value,
+                    Axis::LeftY => self.stick.1 = value,
+                    _ => return,
+                }
+                input.set_stick(bindings.stick_directions(self.stick.0, self.stick.1));
+            }
+            _ => {}
+        }
+    }
+
+    // Both motors at the same strength, from 0.0 to 1.0
+    fn rumble(&mut self, strength: f32) {
+        let level = (strength.clamp(0.0, 1.0) * u16::MAX as f32) as u16;
+        for controller in self.controllers.values_mut() {
+            // Controllers without motors just don't rumble
+            let _ = controller.set_rumble(level, level, RUMBLE_DURATION_MS);
+        }
+    }
+}
+
+// F9 through F12 play a macro slot, with Shift held they record into it
+fn macro_slot_for_key(keycode: Keycode) -> Option<usize> {
+    match keycode {
+        Keycode::F9 => Some(0),
+        Keycode::F10 => Some(1),
+        Keycode::F11 => Some(2),
+        Keycode::F12 => Some(3),
+        _ => None,
+    }
+}
+
+// The SDL window, with one or more screens side by side in a single texture.
+// The texture is drawn at the largest whole scale that fits the window.
+struct SdlScanout<'a> {
+    canvas: Canvas<Window>,
+    texture_creator: &'a TextureCreator<WindowContext>,
+    texture: Texture<'a>,
+    screens: u32,
+    scale: u32,
+    // Screen shown on its own, None shows them all
+    solo: Option<usize>,
+    palette: Option<DmgPalette>,
+    filter: Filter,
+    // Ghosting for each screen
+    blends: Vec<FrameBlend>,
+    // Uploads the next frames even if unchanged, after the palette or
+    // filter changed
+    stale: bool,
+}
+
+impl<'a> SdlScanout<'a> {
+    fn new(
+        canvas: Canvas<Window>,
+        texture_creator: &'a TextureCreator<WindowContext>,
+        screens: u32,
+        scale: u32,
+    ) -> Result<Self> {
+        let texture = Self::create_texture(texture_creator, screens, Filter::None)?;
+        Ok(SdlScanout {
+            canvas,
+            texture_creator,
+            texture,
+            screens,
+            scale,
+            solo: None,
+            palette: None,
+            filter: Filter::None,
+            blends: (0..screens).map(|_| FrameBlend::new(0)).collect(),
+            stale: true,
+        })
+    }
+
+    fn create_texture(texture_creator: &'a TextureCreator<WindowContext>, screens: u32, filter: Filter) -> Result<Texture<'a>> {
+        let factor = filter.factor() as u32;
+        texture_creator
+            .create_texture_streaming(PixelFormatEnum::RGB24, SCREEN_WIDTH as u32 * screens * factor, SCREEN_HEIGHT as u32 * factor)
+            .map_err(frontend("create the screen texture"))
+    }
+
+    fn screen_rect(&self, index: usize) -> Rect {
+        let factor = self.filter.factor();
+        Rect::new((SCREEN_WIDTH * factor * index) as i32, 0, (SCREEN_WIDTH * factor) as u32, (SCREEN_HEIGHT * factor) as u32)
+    }
+
+    fn update_screen(&mut self, index: usize, frame: &Frame) -> Result<()> {
+        if !frame.dirty && !self.stale && self.blends[index].is_settled() {
+            return Ok(());
+        }
+        let rect = self.screen_rect(index);
+        let colored = self.palette.as_ref().map(|palette| scanout::apply_palette(frame.pixels, palette));
+        let pixels = self.blends[index].apply(colored.as_deref().unwrap_or(frame.pixels));
+        let pitch = SCREEN_WIDTH * self.filter.factor() * 3;
+        match self.filter {
+            Filter::None => self.texture.update(rect, pixels, pitch),
+            filter => self.texture.update(rect, &scanout::apply_filter(pixels, filter), pitch),
+        }
+        .map_err(frontend("update the screen texture"))?;
+        if index + 1 == self.screens as usize {
+            self.stale = false;
+        }
+        Ok(())
+    }
+
+    fn set_title(&mut self, title: &str) -> Result<()> {
+        self.canvas.window_mut().set_title(title).map_err(frontend("set the window title"))
+    }
+
+    fn is_fullscreen(&self) -> bool {
+        self.canvas.window().fullscreen_state() != FullscreenType::Off
+    }
+
+    // Fullscreen at the desktop's resolution, back to the window at its
+    // scale. Returns whether it's now fullscreen.
+    fn toggle_fullscreen(&mut self) -> Result<bool> {
+        let fullscreen = !self.is_fullscreen();
+        let mode = if fullscreen { FullscreenType::Desktop } else { FullscreenType::Off };
+        self.canvas.window_mut().set_fullscreen(mode).map_err(frontend("switch fullscreen"))?;
+        if !fullscreen {
+            self.resize(self.scale)?;
+        }
+        Ok(fullscreen)
+    }
+
+    fn draw(&mut self) -> Result<()> {
+        self.canvas.clear();
+        let source = self.solo.map(|index| self.screen_rect(index));
+        let shown = if self.solo.is_some() { 1 } else { self.screens };
+        let image = (SCREEN_WIDTH as u32 * shown, SCREEN_HEIGHT as u32);
+        let (x, y, width, height) = scanout::integer_fit(image, self.canvas.output_size().map_err(frontend("read the window size"))?);
+        self.canvas.copy(&self.texture, source, Rect::new(x, y, width, height)).map_err(frontend("draw the screen"))?;
+        self.canvas.present();
+        Ok(())
+    }
+}
+
+impl Scanout for SdlScanout<'_> {
+    fn present(&mut self, frame: &Frame) -> Result<()> {
+        self.update_screen(0, frame)?;
+        self.draw()
+    }
+
+    fn set_palette(&mut self, palette: Option<DmgPalette>) {
+        self.palette = palette;
+        self.stale = true;
+    }
+
+    fn set_filter(&mut self, filter: Filter) {
+        match Self::create_texture(self.texture_creator, self.screens, filter) {
+            Ok(texture) => {
+                self.texture = texture;
+                self.filter = filter;
+                self.stale = true;
+            }
+            Err(e) => eprintln!("Error: failed to switch to the {} filter: {}", filter, e),
+        }
+    }
+
+    fn set_frame_blend(&mut self, percent: u8) {
+        self.blends = (0..self.screens).map(|_| FrameBlend::new(percent)).collect();
+    }
+
+    // In fullscreen the scale is kept for when it's left
+    fn resize(&mut self, scale: u32) -> Result<()> {
+        self.scale = scale;
+        if self.is_fullscreen() {
+            return Ok(());
+        }
+        let shown = if self.solo.is_some() { 1 } else { self.screens };
+        self.canvas
+            .window_mut()
+            .set_size(SCREEN_WIDTH as u32 * scale * shown, SCREEN_HEIGHT as u32 * scale)
+            .map_err(frontend("resize the window"))?;
+        self.canvas.set_viewport(None);
+        self.canvas.clear();
+        Ok(())
+    }
+}
+
+// The F1 window with the tile data, the background maps and the palettes,
+// redrawn every frame. Tab switches between them.
+struct ViewerWindow {
+    canvas: Canvas<Window>,
+    texture_creator: TextureCreator<WindowContext>,
+    page: ViewerPage,
+}
+
+impl ViewerWindow {
+    fn open(video: &VideoSubsystem) -> Result<Self> {
+        let page = ViewerPage::Tiles;
+        let window = video
+            .window(&viewer_title(page), VIEWER_WIDTH as u32 * VIEWER_SCALE, VIEWER_HEIGHT as u32 * VIEWER_SCALE)
+            .build()
+            .map_err(frontend("open the VRAM viewer window"))?;
+        let canvas = window.into_canvas().build().map_err(frontend("create the renderer"))?;
+        let texture_creator = canvas.texture_creator();
+        Ok(ViewerWindow { canvas, texture_creator, page })
+    }
+
+    fn id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    fn next_page(&mut self) -> Result<()> {
+        self.page = self.page.next();
+        self.canvas.window_mut().set_title(&viewer_title(self.page)).map_err(frontend("set the window title"))
+    }
+
+    fn present(&mut self, gb: &GameBoy) -> Result<()> {
+        let mut texture = self
+            .texture_creator
+            .create_texture_streaming(PixelFormatEnum::RGB24, VIEWER_WIDTH as u32, VIEWER_HEIGHT as u32)
+            .map_err(frontend("create the VRAM viewer texture"))?;
+        texture.update(None, &vram_viewer::render(gb, self.page), VIEWER_WIDTH * 3).map_err(frontend("update the VRAM viewer texture"))?;
+        self.canvas.copy(&texture, None, None).map_err(frontend("draw the VRAM viewer"))?;
+        self.canvas.present();
+        Ok(())
+    }
+}
+
+fn viewer_title(page: ViewerPage) -> String {
+    format!("VRAM Viewer - {} (Tab: next)", page.title())
+}
+
+// For map_err on SDL calls, which mostly fail with a message string
+fn frontend<E: ToString>(action: &'static str) -> impl FnOnce(E) -> EmulatorError {
+    move |error| EmulatorError::Frontend { action, message: error.to_string() }
+}
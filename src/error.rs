@@ -0,0 +1,108 @@
+use std::io;
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum EmulatorError {
+    #[error("failed to read ROM '{path}': {source}")]
+    RomRead { path: PathBuf, source: io::Error },
+
+    #[error("failed to unpack ROM '{path}': {message}")]
+    RomArchive { path: PathBuf, message: String },
+
+    #[error("failed to read boot ROM '{path}': {source}")]
+    BootRomRead { path: PathBuf, source: io::Error },
+
+    #[error("boot ROM must be 256 bytes (DMG) or 2304 bytes (CGB), got {0}")]
+    BootRomSize(usize),
+
+    #[error("ROM is too small to contain a cartridge header ({0} bytes)")]
+    RomTooSmall(usize),
+
+    #[error("unsupported cartridge type 0x{0:02X}")]
+    UnsupportedCartridge(u8),
+
+    #[error("data is not a save state for this emulator")]
+    InvalidState,
+
+    #[error("save state version {0} is not supported")]
+    StateVersion(u16),
+
+    #[error("save state is truncated")]
+    StateTruncated,
+
+    #[error("movie was recorded with a different ROM")]
+    MovieRom,
+
+    #[error("failed to read movie '{path}': {source}")]
+    MovieRead { path: PathBuf, source: io::Error },
+
+    #[error("invalid movie '{path}': {message}")]
+    Movie { path: PathBuf, message: String },
+
+    #[error("failed to write movie '{path}': {source}")]
+    MovieWrite { path: PathBuf, source: io::Error },
+
+    #[error("failed to read save file '{path}': {source}")]
+    SaveRead { path: PathBuf, source: io::Error },
+
+    #[error("failed to write save file '{path}': {source}")]
+    SaveWrite { path: PathBuf, source: io::Error },
+
+    #[error("failed to write screenshot '{path}': {source}")]
+    ScreenshotWrite { path: PathBuf, source: io::Error },
+
+    #[error("failed to create trace file '{path}': {source}")]
+    TraceCreate { path: PathBuf, source: io::Error },
+
+    #[error("failed to create WAV file '{path}': {source}")]
+    WavCreate { path: PathBuf, source: io::Error },
+
+    #[error("failed to read config file '{path}': {source}")]
+    ConfigRead { path: PathBuf, source: io::Error },
+
+    #[error("invalid config file '{path}': {message}")]
+    Config { path: PathBuf, message: String },
+
+    #[error("{0}")]
+    Palette(String),
+
+    #[error("failed to read camera image '{path}': {message}")]
+    CameraImage { path: PathBuf, message: String },
+
+    #[error("failed to read cheat file '{path}': {source}")]
+    CheatRead { path: PathBuf, source: io::Error },
+
+    #[error("invalid cheat file '{path}': {message}")]
+    CheatFile { path: PathBuf, message: String },
+
+    #[error("failed to start the remote control server on port {port}: {source}")]
+    RemoteBind { port: u16, source: io::Error },
+
+    #[error("failed to open the link cable to '{addr}': {source}")]
+    LinkConnect { addr: String, source: io::Error },
+
+    #[error("failed to start netplay with '{addr}': {source}")]
+    NetplayConnect { addr: String, source: io::Error },
+
+    #[error("failed to read script '{path}': {source}")]
+    ScriptRead { path: PathBuf, source: io::Error },
+
+    #[error("script '{name}' failed: {message}")]
+    Script { name: String, message: String },
+
+    #[error("failed to start the emulation thread: {source}")]
+    CoreThreadSpawn { source: io::Error },
+
+    #[error("the emulation thread stopped")]
+    CoreThreadStopped,
+
+    #[error("unknown key '{0}' in the key bindings")]
+    UnknownKey(String),
+
+    #[error("failed to {action}: {message}")]
+    Frontend { action: &'static str, message: String },
+}
+
+pub type Result<T> = std::result::Result<T, EmulatorError>;
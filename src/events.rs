@@ -0,0 +1,118 @@
+use crate::breakpoints::EventBreakpoint;
+use crate::cartridge::CompatDecision;
+use crate::cpu::RegisterSnapshot;
+use crate::debugger::DebugStop;
+use crate::dev_warnings::DevWarning;
+use crate::history::{self, HistoryEntry};
+
+#[derive(Debug, Clone)]
+pub enum EmulatorEvent {
+    Hang(HangReport),
+    // The CPU ran an opcode that doesn't exist at pc and won't go on until
+    // reset, with the instructions before it if history is kept
+    CpuLocked { opcode: u8, pc: u16, history: Vec<HistoryEntry> },
+    // A likely bug in the program, reported with developer warnings on
+    DevWarning(DevWarning),
+    Compatibility { title: String, decision: CompatDecision },
+    Breakpoint { breakpoint: EventBreakpoint, pc: u16 },
+    // The debugger stopped before the instruction at pc
+    DebugStop { stop: DebugStop, pc: u16 },
+    // A line the program printed through the debug port
+    DebugLog(String),
+    // A serial transfer finished, as master or as an armed slave
+    SerialByte { sent: u8, received: u8 },
+    // A replayed movie's state hash didn't match the recording after this
+    // many frames; only the first mismatch is reported
+    MovieDesync { frame: u64 },
+    // A movie played its last frame and handed the buttons back
+    MovieEnded { frames: u64 },
+    // Writing the instruction trace failed, which turned it off
+    TraceStopped(String),
+    // Writing or finishing a WAV dump failed; a failed write stops the dump
+    AudioDumpFailed(String),
+    // The network link broke and the cable was unplugged
+    LinkDisconnected(String),
+    // An MBC5 rumble cartridge switched its motor on (1.0) or off (0.0)
+    Rumble { strength: f32 },
+}
+
+// An event with the value of GameBoy::cycles when it was raised
+#[derive(Debug, Clone)]
+pub struct TimedEvent {
+    pub cycle: u64,
+    pub event: EmulatorEvent,
+}
+
+#[derive(Debug, Clone)]
+pub struct HangReport {
+    pub frames: u32,
+    pub pc_low: u16,
+    pub pc_high: u16,
+    pub halted: bool,
+    pub registers: RegisterSnapshot,
+}
+
+// Prints the instructions that led somewhere, if history is kept
+pub fn report_history(history: &[HistoryEntry]) {
+    if history.is_empty() {
+        return;
+    }
+    eprintln!("  Last {} instructions:", history.len());
+    for line in history::dump(history).lines() {
+        eprintln!("  {}", line);
+    }
+}
+
+// Prints events to the console, returns true when one of them was a breakpoint
+pub fn report(events: Vec<TimedEvent>) -> bool {
+    let mut hit_breakpoint = false;
+    for TimedEvent { event, .. } in events {
+        match event {
+            EmulatorEvent::Hang(report) => {
+                let r = report.registers;
+                eprintln!("\nWarning: CPU hang detected after {} frames at PC 0x{:04X}-0x{:04X}{}",
+                          report.frames, report.pc_low, report.pc_high,
+                          if report.halted { " (halted)" } else { "" });
+                eprintln!("  AF: {:02X}{:02X} BC: {:02X}{:02X} DE: {:02X}{:02X} HL: {:02X}{:02X} SP: {:04X} PC: {:04X}",
+                          r.a, r.f, r.b, r.c, r.d, r.e, r.h, r.l, r.sp, r.pc);
+            }
+            EmulatorEvent::CpuLocked { opcode, pc, history } => {
+                eprintln!("\nWarning: invalid opcode 0x{:02X} at 0x{:04X} locked up the CPU", opcode, pc);
+                report_history(&history);
+            }
+            EmulatorEvent::DevWarning(warning) => {
+                eprintln!("\nWarning: {}", warning.describe());
+                let recent: Vec<String> = warning.recent.iter().map(|pc| format!("{:04X}", pc)).collect();
+                eprintln!("  Recent PCs: {}", recent.join(" "));
+            }
+            EmulatorEvent::Compatibility { title, decision } => {
+                eprintln!("\nWarning: '{}' runs as {}", title, decision.describe());
+            }
+            EmulatorEvent::DebugLog(line) => println!("[debug] {}", line),
+            EmulatorEvent::Breakpoint { breakpoint, pc } => {
+                println!("\nBreakpoint {:?} hit at PC 0x{:04X}", breakpoint, pc);
+                hit_breakpoint = true;
+            }
+            EmulatorEvent::DebugStop { stop, pc } => {
+                match stop {
+                    DebugStop::Breakpoint => println!("\nStopped at breakpoint 0x{:04X}", pc),
+                    DebugStop::Watch { addr, value, write } => println!(
+                        "\nStopped at PC 0x{:04X} after {} 0x{:04X} (0x{:02X})",
+                        pc, if write { "a write to" } else { "a read from" }, addr, value,
+                    ),
+                    DebugStop::Step => {}
+                }
+                hit_breakpoint = true;
+            }
+            EmulatorEvent::SerialByte { .. } | EmulatorEvent::Rumble { .. } => {}
+            EmulatorEvent::MovieDesync { frame } => {
+                eprintln!("\nWarning: movie playback desynced by frame {}", frame);
+            }
+            EmulatorEvent::MovieEnded { frames } => println!("\nMovie ended after {} frames", frames),
+            EmulatorEvent::TraceStopped(error) => eprintln!("\nTrace stopped: {}", error),
+            EmulatorEvent::AudioDumpFailed(error) => eprintln!("\nWAV recording stopped: {}", error),
+            EmulatorEvent::LinkDisconnected(error) => eprintln!("\nLink cable disconnected: {}", error),
+        }
+    }
+    hit_breakpoint
+}
@@ -1,50 +1,1061 @@
-use crate::cpu::CPU;
+use crate::accuracy::AccuracyConfig;
+use crate::apu::ChannelStatus;
+use crate::bootrom;
+use crate::breakpoints::EventBreakpoint;
+use crate::cheats::{Cheat, CheatCode};
+use crate::debug_port::{DebugPort, DebugPortMode};
+use crate::debugger::Debugger;
+use crate::dev_warnings::DevWarnings;
+use crate::disasm;
+use crate::cartridge::{CartridgeHeader, CompatDecision};
+use crate::bus::MemoryBus;
+use crate::camera::CameraSource;
+use crate::cpu::{RegisterSnapshot, CPU};
+use crate::error::{EmulatorError, Result};
+use crate::events::{EmulatorEvent, TimedEvent};
+use crate::history::{ExecutionHistory, HistoryEntry};
+use crate::hooks::{Break, HookId, Hooks};
+use crate::joypad::Button;
+use crate::netlink::NetLink;
+use crate::infrared::IrTransport;
+use crate::model::HardwareModel;
+use crate::memory::Region;
+use crate::movie::{self, Movie};
+use crate::perf::{PerfCounters, PerfStats};
+use crate::png;
+use crate::ramsearch::{Comparison, RamSearch};
+use crate::ppu::{DecodedPalettes, OamEntry, ScrollLatch, SpriteOverflow, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::power_on::{self, PowerOnMemory, PowerOnPattern};
+use crate::rtc::{ClockSource, RtcMode};
+use crate::savefile::{self, SaveFormat};
+use crate::savestate::{state_hash, Savestate, StateReader, StateWriter};
+use crate::scanout::{self, DmgPalette};
+use crate::trace::Tracer;
+use crate::wav::AudioDump;
+use crate::watchdog::{Watchdog, DEFAULT_HANG_FRAMES};
+
+// Called as each visible line enters HBlank (mode 0) with its LY. Register
+// writes made here land before the next line is drawn, like an HBlank handler.
+pub type HBlankHook = Box<dyn FnMut(u8, &mut MemoryBus)>;
+
+// A completed frame as handed to the frontend
+pub struct Frame<'a> {
+    // Frames completed before this one since the machine was created
+    pub index: u64,
+    // Emulated time at the end of the frame, in normal-speed clock cycles
+    pub cycles: u64,
+    // RGB24, SCREEN_WIDTH x SCREEN_HEIGHT
+    pub pixels: &'a [u8],
+    // False when every pixel is the same as in the previous frame
+    pub dirty: bool,
+}
+
+// Samples produced since the last take_audio call
+pub struct AudioChunk {
+    // Value of GameBoy::cycles when the chunk began; the first sample falls
+    // within one sample period after it
+    pub cycle: u64,
+    // Interleaved left/right pairs at the APU's sample rate
+    pub samples: Vec<f32>,
+}
+
+// A movie being recorded or played back
+struct MovieSession {
+    movie: Movie,
+    recording: bool,
+    // frame_index when it started
+    start_frame: u64,
+    desynced: bool,
+}
 
 pub struct GameBoy {
+    // The hardware itself, for debuggers and tests; frontends should only
+    // need the methods below
     pub cpu: CPU,
+    watchdog: Watchdog,
+    dev_warnings: Option<DevWarnings>,
+    history: Option<ExecutionHistory>,
+    // Where the CPU last locked up on an invalid opcode, so it's reported once
+    locked_at: Option<u16>,
+    events: Vec<TimedEvent>,
+    force_dmg_compat: bool,
+    skip_boot: bool,
+    compat: CompatDecision,
+    power_on: PowerOnPattern,
+    seed: u64,
+    breakpoints: Vec<EventBreakpoint>,
+    break_hit: bool,
+    hblank_hook: Option<HBlankHook>,
+    hooks: Hooks,
+    // Filled in by run_frame while profiling is on
+    perf: Option<PerfCounters>,
+    tracer: Option<Tracer>,
+    audio_dump: Option<AudioDump>,
+    cheats: Vec<Cheat>,
+    ram_search: RamSearch,
+    link: Option<NetLink>,
+    infrared: Option<Box<dyn IrTransport>>,
+    movie: Option<MovieSession>,
+    rom_hash: u64,
+    has_battery: bool,
+    has_rtc: bool,
+    frame_index: u64,
+    cycles: u64,
+    frame_cycles: u64,
+    frame_dirty: bool,
+    audio_cycle: u64,
+    serial_capture: Option<Vec<u8>>,
+}
+
+impl Default for GameBoy {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl GameBoy {
     pub fn new() -> Self {
         GameBoy {
             cpu: CPU::new(),
+            watchdog: Watchdog::new(DEFAULT_HANG_FRAMES),
+            dev_warnings: None,
+            history: None,
+            locked_at: None,
+            events: Vec::new(),
+            force_dmg_compat: false,
+            compat: CompatDecision::Dmg,
+            power_on: PowerOnPattern::Zeros,
+            seed: 0,
+            breakpoints: Vec::new(),
+            break_hit: false,
+            hblank_hook: None,
+            hooks: Hooks::new(),
+            perf: None,
+            tracer: None,
+            audio_dump: None,
+            cheats: Vec::new(),
+            ram_search: RamSearch::new(),
+            link: None,
+            infrared: None,
+            movie: None,
+            rom_hash: 0,
+            has_battery: false,
+            has_rtc: false,
+            frame_index: 0,
+            cycles: 0,
+            frame_cycles: 0,
+            frame_dirty: true,
+            audio_cycle: 0,
+            serial_capture: None,
+            skip_boot: false,
+        }
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<()> {
+        let header = CartridgeHeader::parse(rom).ok_or(EmulatorError::RomTooSmall(rom.len()))?;
+        self.cpu.bus.load_rom(rom)?;
+        self.rom_hash = state_hash(rom);
+
+        self.has_battery = header.has_battery();
+        self.has_rtc = header.has_rtc();
+        self.compat = header.compat_decision(self.force_dmg_compat, self.cpu.bus.model.is_cgb());
+        self.cpu.bus.cgb_mode = self.compat == CompatDecision::Cgb;
+        self.cpu.bus.ppu.set_cgb_mode(self.cpu.bus.cgb_mode);
+        self.cpu.bus.memory.set_cgb_mode(self.cpu.bus.cgb_mode);
+        self.cpu.bus.serial.set_cgb_mode(self.cpu.bus.cgb_mode);
+        if self.compat.is_warning() {
+            self.emit(EmulatorEvent::Compatibility {
+                title: header.title,
+                decision: self.compat,
+            });
+        }
+        Ok(())
+    }
+
+    // A DMG or CGB boot ROM dump to run instead of the built-in DMG one
+    pub fn set_boot_rom(&mut self, boot_rom: &[u8]) -> Result<()> {
+        if ![bootrom::DMG_BOOT_ROM_SIZE, bootrom::CGB_BOOT_ROM_SIZE].contains(&boot_rom.len()) {
+            return Err(EmulatorError::BootRomSize(boot_rom.len()));
+        }
+        self.cpu.bus.memory.set_boot_rom(boot_rom.to_vec());
+        Ok(())
+    }
+
+    // Starts reset machines at 0x0100 in the state the boot ROM leaves
+    // behind. CGB models always do unless a CGB boot ROM was set, since the
+    // built-in one is for the DMG.
+    pub fn set_skip_boot(&mut self, skip: bool) {
+        self.skip_boot = skip;
+    }
+
+    // Must be set before load_rom so CGB mode can be decided from the header
+    pub fn set_model(&mut self, model: HardwareModel) {
+        self.cpu.bus.model = model;
+        self.cpu.bus.ppu.set_agb_lcd(model == HardwareModel::Agb);
+    }
+
+    pub fn model(&self) -> HardwareModel {
+        self.cpu.bus.model
+    }
+
+    // Identifies the loaded ROM, for checking movies and netplay peers
+    // are about the same game
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    // On by default; turning it off lets the CPU into VRAM and OAM while the
+    // PPU is drawing
+    pub fn set_access_restrictions(&mut self, enabled: bool) {
+        self.cpu.bus.ppu.set_access_restrictions(enabled);
+    }
+
+    // Defaults to AccuracyConfig::ACCURATE
+    pub fn set_accuracy(&mut self, accuracy: AccuracyConfig) {
+        self.cpu.bus.ppu.set_pixel_fifo(accuracy.pixel_fifo);
+        self.cpu.bus.ppu.set_access_restrictions(accuracy.vram_blocking);
+        self.cpu.bus.set_access_timing(accuracy.access_timing);
+    }
+
+    // Must be set before load_rom for the decision to take effect
+    pub fn set_force_dmg_compat(&mut self, force: bool) {
+        self.force_dmg_compat = force;
+    }
+
+    pub fn compat_decision(&self) -> CompatDecision {
+        self.compat
+    }
+
+    pub fn set_rtc_mode(&mut self, mode: RtcMode) {
+        self.cpu.bus.memory.set_rtc_mode(mode);
+    }
+
+    // Time source for the cartridge clock in host clock mode; has no effect
+    // on cartridges without one. Must be set after load_rom.
+    pub fn set_clock_source(&mut self, clock: Box<dyn ClockSource>) {
+        if let Some(rtc) = self.cpu.bus.memory.rtc_mut() {
+            rtc.set_clock(clock);
+        }
+    }
+
+    // Where the Game Boy Camera gets its pictures, a test pattern unless set;
+    // has no effect on other cartridges. Must be set after load_rom.
+    pub fn set_camera_source(&mut self, source: Box<dyn CameraSource>) {
+        if let Some(camera) = self.cpu.bus.memory.camera_mut() {
+            camera.set_source(source);
+        }
+    }
+
+    // Number of frames the CPU may spin in place with interrupts disabled
+    // before a hang is reported; 0 disables the watchdog
+    pub fn set_hang_threshold(&mut self, frames: u32) {
+        self.watchdog.set_threshold(frames);
+    }
+
+    // Reports jumps outside ROM and RAM, invalid opcodes and a stack that
+    // leaves RAM as events; see dev_warnings. Off by default.
+    pub fn set_dev_warnings(&mut self, enabled: bool) {
+        self.dev_warnings = enabled.then(DevWarnings::new);
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        let bus = &mut self.cpu.bus;
+        bus.joypad.set_button(button, pressed, &mut bus.interrupts);
+    }
+
+    // Sets every button at once from a packed byte laid out as in Button::mask
+    pub fn set_inputs(&mut self, buttons: u8) {
+        for button in Button::ALL {
+            self.set_button(button, buttons & button.mask() != 0);
+        }
+    }
+
+    // Memory as the CPU sees it, including the I/O registers
+    pub fn read_byte(&self, addr: u16) -> u8 {
+        self.cpu.bus.read_byte(addr)
+    }
+
+    pub fn write_byte(&mut self, addr: u16, value: u8) {
+        self.cpu.bus.write_byte(addr, value);
+    }
+
+    // Like read_byte, without tripping watchpoints
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.cpu.bus.read_unwatched(addr)
+    }
+
+    // The instruction at addr and its length
+    pub fn disassemble(&self, addr: u16) -> (String, u16) {
+        disasm::disassemble(addr, &self.cpu.bus)
+    }
+
+    pub fn registers(&self) -> RegisterSnapshot {
+        self.cpu.snapshot()
+    }
+
+    // RGB24, SCREEN_WIDTH x SCREEN_HEIGHT, including lines drawn since the
+    // last completed frame
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.cpu.bus.ppu.framebuffer
+    }
+
+    // SCX and SCY as each line of the last frame was drawn
+    pub fn scroll_table(&self) -> &[ScrollLatch; SCREEN_HEIGHT] {
+        self.cpu.bus.ppu.scroll_table()
+    }
+
+    // Lines of the last frame that had more than ten sprites
+    pub fn sprite_overflow(&self) -> &[SpriteOverflow] {
+        self.cpu.bus.ppu.sprite_overflow()
+    }
+
+    // Both VRAM banks, bank 1 starting at 0x2000
+    pub fn vram(&self) -> &[u8; 0x4000] {
+        &self.cpu.bus.ppu.vram
+    }
+
+    // Every bank of a region back to back, as stored rather than as mapped
+    pub fn region(&self, region: Region) -> &[u8] {
+        let bus = &self.cpu.bus;
+        match region {
+            Region::Rom => bus.memory.rom(),
+            Region::Vram => &bus.ppu.vram[..],
+            Region::Wram => bus.memory.wram(),
+            Region::Sram => bus.memory.cartridge_ram(),
         }
     }
 
-    pub fn load_rom(&mut self, rom: &[u8]) {
-        self.cpu.bus.load_rom(rom);
+    pub fn region_mut(&mut self, region: Region) -> &mut [u8] {
+        let bus = &mut self.cpu.bus;
+        match region {
+            Region::Rom => bus.memory.rom_mut(),
+            Region::Vram => &mut bus.ppu.vram[..],
+            Region::Wram => bus.memory.wram_mut(),
+            Region::Sram => bus.memory.cartridge_ram_mut(),
+        }
+    }
+
+    pub fn palettes(&self) -> DecodedPalettes {
+        self.cpu.bus.ppu.palettes()
+    }
+
+    pub fn oam_entries(&self) -> [OamEntry; 40] {
+        self.cpu.bus.ppu.oam_entries()
+    }
+
+    // Whether objects are 8x16 rather than 8x8
+    pub fn has_tall_objects(&self) -> bool {
+        self.cpu.bus.ppu.is_obj_tall()
+    }
+
+    // Whether the PPU uses the CGB tile attributes and color palettes
+    pub fn is_cgb_mode(&self) -> bool {
+        self.cpu.bus.ppu.is_cgb_mode()
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: EventBreakpoint) {
+        if let EventBreakpoint::IoWrite { .. } = breakpoint {
+            self.cpu.bus.record_io_writes = true;
+        }
+        self.breakpoints.push(breakpoint);
+    }
+
+    // Code breakpoints, watchpoints and stepping; stops end run_frame early
+    // with an EmulatorEvent::DebugStop
+    pub fn debugger(&mut self) -> &mut Debugger {
+        &mut self.cpu.bus.debugger
+    }
+
+    pub fn set_debug_port(&mut self, mode: Option<DebugPortMode>) {
+        self.cpu.bus.debug_port = mode.map(DebugPort::new);
+    }
+
+    // Keeps every byte sent out of the serial port for serial_output, as
+    // test ROMs report their results there
+    pub fn set_serial_capture(&mut self, enabled: bool) {
+        self.serial_capture = enabled.then(Vec::new);
+    }
+
+    pub fn serial_output(&self) -> String {
+        String::from_utf8_lossy(self.serial_capture.as_deref().unwrap_or_default()).into_owned()
+    }
+
+    pub fn set_hblank_hook(&mut self, hook: Option<HBlankHook>) {
+        self.hblank_hook = hook;
+    }
+
+    // Callbacks for programs embedding the core; see hooks::Hooks. Each
+    // returns an id that remove_hook takes.
+    pub fn on_frame(&mut self, hook: impl FnMut(&Frame) + 'static) -> HookId {
+        self.hooks.add_frame(Box::new(hook))
+    }
+
+    pub fn on_vblank(&mut self, hook: impl FnMut(&mut MemoryBus) + 'static) -> HookId {
+        self.hooks.add_vblank(Box::new(hook))
+    }
+
+    pub fn on_serial(&mut self, hook: impl FnMut(u8, u8) + 'static) -> HookId {
+        self.hooks.add_serial(Box::new(hook))
+    }
+
+    // Called after each instruction that writes to addr, once per write
+    pub fn on_write(&mut self, addr: u16, hook: impl FnMut(u16, u8) + 'static) -> HookId {
+        let id = self.hooks.add_write(addr, Box::new(hook));
+        self.cpu.bus.write_watches = self.hooks.watched_addresses();
+        id
+    }
+
+    // Called for event breakpoints and debugger stops alike, before the
+    // frame stops running
+    pub fn on_break(&mut self, hook: impl FnMut(Break, u16) + 'static) -> HookId {
+        self.hooks.add_break(Box::new(hook))
+    }
+
+    // Returns false when no hook had the id
+    pub fn remove_hook(&mut self, id: HookId) -> bool {
+        let removed = self.hooks.remove(id);
+        self.cpu.bus.write_watches = self.hooks.watched_addresses();
+        removed
+    }
+
+    // Logs every instruction the CPU runs; see trace::Tracer
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    // Trace output is buffered, call this before reading it back
+    pub fn flush_trace(&mut self) {
+        if let Some(tracer) = &mut self.tracer {
+            let _ = tracer.flush();
+        }
+    }
+
+    // Keeps the last `length` instructions run, for history() and the
+    // report of a lock-up; 0 turns it off
+    pub fn set_history_length(&mut self, length: usize) {
+        self.history = (length > 0).then(|| ExecutionHistory::new(length));
+    }
+
+    // Oldest first; empty unless set_history_length turned it on
+    pub fn history(&self) -> Vec<HistoryEntry> {
+        self.history.as_ref().map(ExecutionHistory::entries).unwrap_or_default()
+    }
+
+    fn record_history(&mut self) {
+        let Some(history) = &mut self.history else {
+            return;
+        };
+        if self.cpu.halted || self.cpu.bus.stopped {
+            return;
+        }
+        let pc = self.cpu.pc;
+        history.record(HistoryEntry {
+            registers: self.cpu.snapshot(),
+            bank: (pc < 0x8000).then(|| self.cpu.bus.memory.rom_bank(pc)),
+            bytes: [0, 1, 2].map(|offset| self.cpu.bus.read_unwatched(pc.wrapping_add(offset))),
+        });
+    }
+
+    fn trace_instruction(&mut self) {
+        let Some(tracer) = &mut self.tracer else {
+            return;
+        };
+        if self.cpu.halted || self.cpu.bus.stopped {
+            return;
+        }
+        let pc = self.cpu.pc;
+        let pcmem = [0, 1, 2, 3].map(|offset| self.cpu.bus.read_unwatched(pc.wrapping_add(offset)));
+        // A full disk or closed pipe ends the trace instead of the emulation
+        if let Err(error) = tracer.log(&self.cpu.snapshot(), pcmem) {
+            self.tracer = None;
+            self.emit(EmulatorEvent::TraceStopped(error.to_string()));
+        }
+    }
+
+    // Plugs the serial port into another emulator over the network, or
+    // unplugs it; see netlink::NetLink
+    pub fn set_link(&mut self, link: Option<NetLink>) {
+        self.cpu.bus.serial.set_connected(link.is_some());
+        self.link = link;
+    }
+
+    // Points the infrared port at another Game Boy, or away from it. A
+    // network link carries infrared itself and takes over while connected.
+    pub fn set_infrared(&mut self, transport: Option<Box<dyn IrTransport>>) {
+        self.infrared = transport;
+    }
+
+    pub fn add_cheat(&mut self, cheat: Cheat) {
+        self.cheats.push(cheat);
+        self.update_rom_patches();
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    // Returns false when there is no cheat with that index
+    pub fn set_cheat_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        let Some(cheat) = self.cheats.get_mut(index) else {
+            return false;
+        };
+        cheat.enabled = enabled;
+        self.update_rom_patches();
+        true
+    }
+
+    pub fn clear_cheats(&mut self) {
+        self.cheats.clear();
+        self.update_rom_patches();
+    }
+
+    // The cheat search over work RAM and the watches pinned from it
+    pub fn ram_search(&self) -> &RamSearch {
+        &self.ram_search
+    }
+
+    pub fn ram_search_mut(&mut self) -> &mut RamSearch {
+        &mut self.ram_search
+    }
+
+    pub fn start_ram_search(&mut self) {
+        self.ram_search.start(&self.cpu.bus.memory.wram()[..self.searchable_wram_len()]);
+    }
+
+    // Returns how many candidates are left
+    pub fn filter_ram_search(&mut self, comparison: Comparison) -> usize {
+        let len = self.searchable_wram_len();
+        self.ram_search.filter(&self.cpu.bus.memory.wram()[..len], comparison)
+    }
+
+    // The work RAM a search covers: all eight banks in CGB mode, two otherwise
+    pub fn searchable_wram(&self) -> &[u8] {
+        &self.cpu.bus.memory.wram()[..self.searchable_wram_len()]
+    }
+
+    fn searchable_wram_len(&self) -> usize {
+        if self.is_cgb_mode() { self.cpu.bus.memory.wram().len() } else { 0x2000 }
+    }
+
+    fn update_rom_patches(&mut self) {
+        let patches = self
+            .cheats
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .filter_map(|cheat| match cheat.code {
+                CheatCode::Genie(patch) => Some(patch),
+                CheatCode::Shark(_) => None,
+            })
+            .collect();
+        self.cpu.bus.memory.set_rom_patches(patches);
+    }
+
+    // GameShark codes rewrite their bytes as each VBlank starts, like the
+    // real device does from its VBlank interrupt hook
+    fn apply_shark_codes(&mut self) {
+        for cheat in self.cheats.iter().filter(|cheat| cheat.enabled) {
+            let CheatCode::Shark(write) = cheat.code else {
+                continue;
+            };
+            let memory = &mut self.cpu.bus.memory;
+            match write.bank {
+                Some(bank) if write.addr >= 0xD000 => memory.write_wram_bank(bank, write.addr, write.value),
+                _ => memory.write_byte(write.addr, write.value),
+            }
+        }
+    }
+
+    fn hit_breakpoint(&mut self, breakpoint: EventBreakpoint) {
+        self.hooks.brk(Break::Event(breakpoint), self.cpu.pc);
+        self.emit(EmulatorEvent::Breakpoint { breakpoint, pc: self.cpu.pc });
+        self.break_hit = true;
+    }
+
+    fn emit(&mut self, event: EmulatorEvent) {
+        self.events.push(TimedEvent { cycle: self.cycles, event });
+    }
+
+    // Oldest first, so the cycle stamps never decrease
+    pub fn take_events(&mut self) -> Vec<TimedEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    // For MBC5 rumble cartridges, the share of the time since the last call
+    // the motor was on; None for other cartridges. EmulatorEvent::Rumble
+    // has each switch at the cycle it happened.
+    pub fn take_rumble(&mut self) -> Option<f32> {
+        self.cpu.bus.memory.take_rumble()
+    }
+
+    // Emulated time since the machine was created, in normal-speed clock
+    // cycles. Never goes backwards, not even when a state is loaded.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // Audio output rate, apu::SAMPLE_RATE unless set
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        self.cpu.bus.apu.set_sample_rate(rate);
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.cpu.bus.apu.sample_rate()
+    }
+
+    pub fn take_audio(&mut self) -> AudioChunk {
+        let cycle = std::mem::replace(&mut self.audio_cycle, self.cycles);
+        let samples = self.cpu.bus.apu.take_samples();
+        if let Some(dump) = &mut self.audio_dump {
+            let stems = self.cpu.bus.apu.take_stems();
+            if let Err(error) = dump.write(self.cpu.bus.apu.sample_rate(), &samples, &stems) {
+                self.audio_dump = None;
+                self.emit(EmulatorEvent::AudioDumpFailed(error.to_string()));
+            }
+        }
+        AudioChunk { cycle, samples }
+    }
+
+    // Copies everything take_audio returns from now on into WAV files.
+    // Replacing or clearing the dump finishes the previous one.
+    pub fn set_audio_dump(&mut self, dump: Option<AudioDump>) {
+        let stems = dump.as_ref().is_some_and(AudioDump::has_stems);
+        self.cpu.bus.apu.set_stems_enabled(stems);
+        if let Some(mut previous) = std::mem::replace(&mut self.audio_dump, dump) {
+            if let Err(error) = previous.finish() {
+                self.emit(EmulatorEvent::AudioDumpFailed(error.to_string()));
+            }
+        }
+    }
+
+    // Channels are numbered 0-3: pulse 1, pulse 2, wave and noise. Muting and
+    // soloing only change what is heard, not the emulation.
+    pub fn toggle_channel_muted(&mut self, channel: usize) -> bool {
+        let muted = !self.cpu.bus.apu.is_muted(channel);
+        self.cpu.bus.apu.set_muted(channel, muted);
+        muted
+    }
+
+    // Solos the channel, or unsolos it if it already was; returns the solo
+    // channel afterwards
+    pub fn toggle_channel_solo(&mut self, channel: usize) -> Option<usize> {
+        let solo = if self.cpu.bus.apu.solo() == Some(channel) { None } else { Some(channel) };
+        self.cpu.bus.apu.set_solo(solo);
+        solo
+    }
+
+    // Keeps each channel's recent output for channel_scope while enabled,
+    // which costs a little time every cycle
+    pub fn set_channel_scope(&mut self, enabled: bool) {
+        self.cpu.bus.apu.set_scope_enabled(enabled);
+    }
+
+    // Per channel, the last apu::SCOPE_LEN outputs from 0 to 15, oldest first
+    pub fn channel_scope(&self) -> [Vec<u8>; 4] {
+        self.cpu.bus.apu.scope()
+    }
+
+    pub fn channel_status(&self) -> [ChannelStatus; 4] {
+        self.cpu.bus.apu.channel_status()
+    }
+
+    // Applied on the next reset; the same seed always produces the same contents
+    pub fn set_power_on_pattern(&mut self, pattern: PowerOnPattern, seed: u64) {
+        self.power_on = pattern;
+        self.seed = seed;
     }
 
     pub fn reset(&mut self) {
         self.cpu.reset();
+        self.cpu.bus.stopped = false;
+        self.locked_at = None;
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+
+        let model = self.cpu.bus.model;
+        let skip_boot = self.skip_boot || (model.is_cgb() && !self.cpu.bus.memory.has_cgb_boot_rom());
+        self.cpu.bus.memory.set_boot_rom_enabled(!skip_boot);
+        if skip_boot {
+            let [a, f, b, c, d, e, h, l] = bootrom::post_boot_registers(model, self.cpu.bus.cgb_mode);
+            (self.cpu.a, self.cpu.f, self.cpu.b, self.cpu.c) = (a, f, b, c);
+            (self.cpu.d, self.cpu.e, self.cpu.h, self.cpu.l) = (d, e, h, l);
+            self.cpu.sp = 0xFFFE;
+            self.cpu.pc = 0x0100;
+
+            // Straight to the components so nothing counts as a CPU write
+            let bus = &mut self.cpu.bus;
+            for (addr, value) in bootrom::POST_BOOT_IO {
+                match addr {
+                    0xFF0F => bus.interrupts.interrupt_flag = value,
+                    0xFF10..=0xFF3F => bus.apu.write(addr, value),
+                    _ => bus.ppu.write(addr, value),
+                }
+            }
+            bus.apu.fade_out_boot_chime();
+            bus.timer.set_divider(bootrom::post_boot_divider(model));
+        }
+
+        let bus = &mut self.cpu.bus;
+        power_on::fill(
+            PowerOnMemory {
+                wram: bus.memory.wram_mut(),
+                vram: &mut bus.ppu.vram,
+                oam: &mut bus.ppu.oam,
+            },
+            self.power_on,
+            model,
+            self.seed,
+        );
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.has_battery
     }
 
+    // Contents of the battery-backed .sav file, or None when the cartridge has
+    // no battery. The RTC footer is only written for MBC3 timer cartridges.
+    pub fn battery_save(&mut self, format: SaveFormat) -> Option<Vec<u8>> {
+        if !self.has_battery {
+            return None;
+        }
+        let memory = &mut self.cpu.bus.memory;
+        let rtc = if self.has_rtc { memory.rtc_mut().map(|rtc| (rtc.registers(), rtc.now())) } else { None };
+        Some(savefile::encode(memory.cartridge_ram(), rtc, format))
+    }
+
+    // Accepts saves written in any supported format; the layout is detected
+    // from the size, and an RTC footer is applied when one is present
+    pub fn load_battery_save(&mut self, data: &[u8]) {
+        let memory = &mut self.cpu.bus.memory;
+        let ram = memory.cartridge_ram_mut();
+        let save = savefile::decode(data, ram.len(), self.has_rtc);
+        ram.copy_from_slice(&save.ram);
+        if let (Some((registers, saved_at)), Some(rtc)) = (save.rtc, memory.rtc_mut()) {
+            rtc.restore(registers, saved_at);
+        }
+    }
+
+    // The cartridge ROM is not part of the state; load it with load_rom first
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        self.cpu.save_state(&mut state);
+        state.finish()
+    }
+
+    // A fingerprint of the whole emulated machine, equal exactly when
+    // save_state would be. The core is deterministic: two machines with the
+    // same model, ROM, save data, power-on pattern and seed, given the same
+    // buttons on the same frames, hash the same after every frame on any
+    // platform. It uses no randomness of its own and no floating point that
+    // reaches the state. Outside that contract are the inputs the host
+    // supplies: the cartridge clock in RtcMode::HostClock, camera pictures,
+    // and link cable or infrared partners.
+    pub fn state_hash(&self) -> u64 {
+        state_hash(&self.save_state())
+    }
+
+    // On error the machine is left exactly as it was before the call
+    pub fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut state = StateReader::new(data)?;
+        let backup = self.save_state();
+        let result = self.cpu.load_state(&mut state).and_then(|_| state.finish());
+        if result.is_err() {
+            let mut restore = StateReader::new(&backup)?;
+            self.cpu.load_state(&mut restore)?;
+            return result;
+        }
+        self.locked_at = None;
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+        result
+    }
+
+    // Returns the elapsed time in normal-speed clock cycles, which is twice the
+    // CPU cycles executed while in CGB double speed
     pub fn step(&mut self) -> u32 {
-        let cycles = if self.cpu.bus.interrupts.has_pending_interrupt() {
-            if let Some(addr) = self.cpu.bus.interrupts.get_next_interrupt() {
-                self.cpu.halted = false;
-                self.cpu.bus.interrupts.ime = false;
-                self.cpu.push(self.cpu.pc);
-                self.cpu.pc = addr;
-                20
-            } else {
-                self.cpu.step()
-            }
+        let boot_rom_was_enabled = self.cpu.bus.memory.is_boot_rom_enabled();
+        let was_halted = self.cpu.halted;
+        self.cpu.bus.debugger.begin_instruction();
+        self.cpu.bus.watched_writes.clear();
+        self.cpu.bus.take_access_cycles();
+        // The CPU sits out VRAM DMA while the rest of the machine keeps running
+        let stall = self.cpu.bus.take_hdma_stall();
+        let cycles = if stall > 0 {
+            stall
         } else {
+            // Dispatching an interrupt isn't an instruction of its own in the
+            // trace
+            if !self.cpu.bus.interrupts.has_pending_interrupt() {
+                self.trace_instruction();
+                self.record_history();
+            }
+            if let Some(dev_warnings) = &mut self.dev_warnings {
+                if !was_halted {
+                    dev_warnings.record(self.cpu.pc);
+                }
+            }
             self.cpu.step()
         };
+        if let Some(dev_warnings) = &mut self.dev_warnings {
+            for warning in dev_warnings.check(&self.cpu) {
+                self.emit(EmulatorEvent::DevWarning(warning));
+            }
+        }
+        if let Some(opcode) = self.cpu.take_invalid_opcode() {
+            let pc = self.cpu.pc;
+            if self.locked_at != Some(pc) {
+                self.locked_at = Some(pc);
+                let history = self.history();
+                self.emit(EmulatorEvent::CpuLocked { opcode, pc, history });
+            }
+        }
+        if let Some(interrupt) = self.cpu.take_dispatched_interrupt() {
+            if self.breakpoints.contains(&EventBreakpoint::Interrupt(interrupt)) {
+                self.hit_breakpoint(EventBreakpoint::Interrupt(interrupt));
+            }
+        }
+
+        // The instruction's memory accesses already ran the machine through
+        // their machine cycles; internal cycles are caught up on here
+        let internal_cycles = cycles.saturating_sub(self.cpu.bus.take_access_cycles());
+        self.cpu.bus.tick(internal_cycles, self.cpu.halted);
+        let base_cycles = self.cpu.bus.base_cycles(cycles);
+        // Events raised below are stamped with the end of this instruction
+        self.cycles += base_cycles as u64;
+        if let Some(ly) = self.cpu.bus.take_hblank_line() {
+            if let Some(hook) = &mut self.hblank_hook {
+                hook(ly, &mut self.cpu.bus);
+            }
+        }
+        if self.cpu.bus.take_vblank_started() {
+            self.apply_shark_codes();
+            self.hooks.vblank(&mut self.cpu.bus);
+            if self.breakpoints.contains(&EventBreakpoint::VBlank) {
+                self.hit_breakpoint(EventBreakpoint::VBlank);
+            }
+        }
+        if let Some(link) = &mut self.link {
+            let bus = &mut self.cpu.bus;
+            if let Err(error) = link.service(&mut bus.serial, &mut bus.interrupts, &mut bus.infrared, base_cycles) {
+                self.set_link(None);
+                self.emit(EmulatorEvent::LinkDisconnected(error.to_string()));
+            }
+        } else if let Some(transport) = &mut self.infrared {
+            let infrared = &mut self.cpu.bus.infrared;
+            if let Some(led) = infrared.take_led_change() {
+                transport.send(led);
+            }
+            infrared.set_light(transport.light());
+        }
+        for (sent, received) in self.cpu.bus.serial.take_transfers() {
+            if let Some(capture) = &mut self.serial_capture {
+                capture.push(sent);
+            }
+            self.hooks.serial(sent, received);
+            self.emit(EmulatorEvent::SerialByte { sent, received });
+        }
+        self.watchdog.observe(self.cpu.pc, self.cpu.bus.interrupts.ime);
 
-        self.cpu.bus.timer.tick(cycles, &mut self.cpu.bus.interrupts);
-        self.cpu.bus.ppu.tick(cycles, &mut self.cpu.bus.interrupts);
+        for line in std::mem::take(&mut self.cpu.bus.debug_lines) {
+            self.emit(EmulatorEvent::DebugLog(line));
+        }
+        for on in std::mem::take(&mut self.cpu.bus.memory.motor_switches) {
+            self.emit(EmulatorEvent::Rumble { strength: if on { 1.0 } else { 0.0 } });
+        }
 
-        cycles
+        for (addr, old, new) in std::mem::take(&mut self.cpu.bus.io_writes) {
+            let hits: Vec<EventBreakpoint> = self.breakpoints.iter()
+                .filter(|breakpoint| breakpoint.matches_io_write(addr, old, new))
+                .copied()
+                .collect();
+            for breakpoint in hits {
+                self.hit_breakpoint(breakpoint);
+            }
+        }
+        for (addr, value) in std::mem::take(&mut self.cpu.bus.watched_writes) {
+            self.hooks.write(addr, value);
+        }
+
+        // The CGB boot ROM running on a GBA finishes with bit 0 of B set,
+        // which is how games detect they are on a GBA
+        if boot_rom_was_enabled
+            && !self.cpu.bus.memory.is_boot_rom_enabled()
+            && self.cpu.bus.model == HardwareModel::Agb
+        {
+            self.cpu.b |= 0x01;
+        }
+
+        // Only once something ran, so a halted CPU doesn't stop again and again
+        if stall == 0 && !(was_halted && self.cpu.halted) {
+            if let Some(stop) = self.cpu.bus.debugger.end_instruction(self.cpu.pc, self.cpu.sp) {
+                self.hooks.brk(Break::Debugger(stop), self.cpu.pc);
+                self.emit(EmulatorEvent::DebugStop { stop, pc: self.cpu.pc });
+                self.break_hit = true;
+            }
+        }
+
+        base_cycles
     }
 
-    pub fn run_frame(&mut self) {
-        while !self.cpu.bus.ppu.is_frame_ready() {
+    // Returns None when a breakpoint stopped the machine mid-frame; the next
+    // call resumes where this one left off
+    pub fn run_frame(&mut self) -> Option<Frame<'_>> {
+        let started = self.perf.is_some().then(|| (std::time::Instant::now(), self.cycles));
+        self.begin_movie_frame();
+        while !self.poll_frame() {
             self.step();
+            if std::mem::take(&mut self.break_hit) {
+                return None;
+            }
+        }
+        self.end_movie_frame();
+        if let (Some(perf), Some((start, start_cycles))) = (&mut self.perf, started) {
+            perf.record_frame(self.cycles - start_cycles, start.elapsed(), self.cpu.bus.profile.take());
+        }
+        Some(self.frame())
+    }
+
+    // Times run_frame and the subsystems within it, which costs some speed.
+    // Not for wasm32-unknown-unknown, where std has no clock to read.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.perf = enabled.then(PerfCounters::new);
+        self.cpu.bus.profile.set_enabled(enabled);
+    }
+
+    // Over the last second of frames, once profiling has run that long
+    pub fn perf_stats(&self) -> Option<PerfStats> {
+        self.perf.as_ref().and_then(PerfCounters::stats)
+    }
+
+    // Records the buttons of every frame run_frame runs from now on, starting
+    // from the current state. Stepping the machine some other way, or loading
+    // a state, leaves the recording out of step with the run.
+    pub fn start_recording(&mut self) {
+        self.start_movie(Movie::new(self.rom_hash, self.save_state()), true);
+    }
+
+    // Loads the movie's starting state and takes over the buttons until its
+    // last frame
+    pub fn start_playback(&mut self, movie: Movie) -> Result<()> {
+        if movie.rom_hash != self.rom_hash {
+            return Err(EmulatorError::MovieRom);
+        }
+        self.load_state(&movie.start_state)?;
+        self.start_movie(movie, false);
+        Ok(())
+    }
+
+    // The cartridge clock follows emulated time during movies so host time
+    // can't change the run
+    fn start_movie(&mut self, movie: Movie, recording: bool) {
+        self.set_rtc_mode(RtcMode::CycleSynced);
+        self.movie = Some(MovieSession { movie, recording, start_frame: self.frame_index, desynced: false });
+    }
+
+    // Ends recording or playback, returning the movie
+    pub fn stop_movie(&mut self) -> Option<Movie> {
+        self.movie.take().map(|session| session.movie)
+    }
+
+    pub fn is_movie_active(&self) -> bool {
+        self.movie.is_some()
+    }
+
+    // Called again when run_frame resumes after a breakpoint, so it mustn't
+    // take the frame's input twice
+    fn begin_movie_frame(&mut self) {
+        let Some(session) = &mut self.movie else {
+            return;
+        };
+        let frame = self.frame_index - session.start_frame;
+        if session.recording {
+            if session.movie.inputs.len() as u64 == frame {
+                session.movie.inputs.push(self.cpu.bus.joypad.held());
+            }
+        } else if let Some(&buttons) = session.movie.inputs.get(frame as usize) {
+            self.set_inputs(buttons);
         }
     }
+
+    fn end_movie_frame(&mut self) {
+        let Some(session) = &self.movie else {
+            return;
+        };
+        let frame = self.frame_index - session.start_frame;
+        let ended = !session.recording && frame >= session.movie.inputs.len() as u64;
+        if frame.is_multiple_of(movie::HASH_INTERVAL) {
+            let hash = self.state_hash();
+            let index = (frame / movie::HASH_INTERVAL - 1) as usize;
+            let Some(session) = &mut self.movie else {
+                return;
+            };
+            if session.recording {
+                session.movie.hashes.truncate(index);
+                session.movie.hashes.push(hash);
+            } else if !session.desynced && session.movie.hashes.get(index).is_some_and(|&recorded| recorded != hash) {
+                session.desynced = true;
+                self.emit(EmulatorEvent::MovieDesync { frame });
+            }
+        }
+        if ended {
+            self.movie = None;
+            self.set_inputs(0);
+            self.emit(EmulatorEvent::MovieEnded { frames: frame });
+        }
+    }
+
+    // Returns true once per completed frame, after the end-of-frame bookkeeping
+    pub fn poll_frame(&mut self) -> bool {
+        if !self.cpu.bus.ppu.is_frame_ready() {
+            return false;
+        }
+
+        if let Some(report) = self.watchdog.end_frame(&self.cpu) {
+            self.emit(EmulatorEvent::Hang(report));
+        }
+        self.frame_index += 1;
+        self.frame_cycles = self.cycles;
+        self.frame_dirty = self.cpu.bus.ppu.take_frame_dirty();
+        if !self.hooks.frame.is_empty() {
+            let mut hooks = std::mem::take(&mut self.hooks.frame);
+            let frame = self.frame();
+            for (_, hook) in &mut hooks {
+                hook(&frame);
+            }
+            self.hooks.frame = hooks;
+        }
+        true
+    }
+
+    // Frames completed since the machine was created
+    pub fn frame_count(&self) -> u64 {
+        self.frame_index
+    }
+
+    // The most recently completed frame. While stopped at a breakpoint the
+    // pixels already show the lines drawn since.
+    pub fn frame(&self) -> Frame<'_> {
+        Frame {
+            index: self.frame_index.saturating_sub(1),
+            cycles: self.frame_cycles,
+            pixels: self.framebuffer(),
+            dirty: self.frame_dirty,
+        }
+    }
+
+    // The last frame as a PNG image, each pixel `scale` times as big, in the
+    // palette's colors if one is given
+    pub fn screenshot(&self, scale: u32, palette: Option<&DmgPalette>) -> Vec<u8> {
+        let pixels = self.frame().pixels;
+        let pixels = match palette {
+            Some(palette) => scanout::apply_palette(pixels, palette),
+            None => pixels.to_vec(),
+        };
+        let scale = scale.max(1) as usize;
+        let pixels = png::upscale(&pixels, SCREEN_WIDTH, scale);
+        png::encode_rgb(&pixels, SCREEN_WIDTH * scale, SCREEN_HEIGHT * scale)
+    }
 }
 
 
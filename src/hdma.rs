@@ -0,0 +1,114 @@
+use crate::error::Result;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+
+const HDMA_SOURCE_HIGH: u16 = 0xFF51;
+const HDMA_SOURCE_LOW: u16 = 0xFF52;
+const HDMA_DEST_HIGH: u16 = 0xFF53;
+const HDMA_DEST_LOW: u16 = 0xFF54;
+const HDMA_CONTROL: u16 = 0xFF55;
+
+pub const BLOCK_SIZE: u16 = 0x10;
+
+// A block takes 8 microseconds in either speed mode, which is 8 machine
+// cycles at normal speed and 16 in double speed
+const CYCLES_PER_BLOCK: u32 = 32;
+
+// CGB VRAM DMA (HDMA1-HDMA5). A general purpose transfer copies everything
+// at once; an HBlank transfer copies one 16 byte block per HBlank. The CPU
+// is stopped while a block is copied.
+pub struct Hdma {
+    source: u16,
+    // Offset into VRAM
+    dest: u16,
+    // Blocks left, minus one, as read back from FF55
+    remaining: u8,
+    hblank_active: bool,
+}
+
+impl Default for Hdma {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hdma {
+    pub fn new() -> Self {
+        Hdma {
+            source: 0,
+            dest: 0,
+            remaining: 0x7F,
+            hblank_active: false,
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            // Bit 7 reads 0 while an HBlank transfer is running and 1 once it
+            // finished or was cancelled; 0xFF after a full transfer
+            HDMA_CONTROL => ((!self.hblank_active as u8) << 7) | self.remaining,
+            _ => 0xFF,
+        }
+    }
+
+    // Returns the number of blocks to copy right away when a general purpose
+    // transfer is started
+    pub fn write(&mut self, addr: u16, value: u8) -> Option<u16> {
+        match addr {
+            HDMA_SOURCE_HIGH => self.source = (self.source & 0x00FF) | ((value as u16) << 8),
+            HDMA_SOURCE_LOW => self.source = (self.source & 0xFF00) | (value & 0xF0) as u16,
+            HDMA_DEST_HIGH => self.dest = (self.dest & 0x00FF) | (((value & 0x1F) as u16) << 8),
+            HDMA_DEST_LOW => self.dest = (self.dest & 0xFF00) | (value & 0xF0) as u16,
+            // Clearing bit 7 during an HBlank transfer stops it, keeping the
+            // remaining length readable
+            HDMA_CONTROL if self.hblank_active && value & 0x80 == 0 => self.hblank_active = false,
+            HDMA_CONTROL => {
+                self.remaining = value & 0x7F;
+                self.hblank_active = value & 0x80 != 0;
+                if !self.hblank_active {
+                    return Some(self.remaining as u16 + 1);
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    pub fn is_hblank_active(&self) -> bool {
+        self.hblank_active
+    }
+
+    // Source and VRAM destination of the next block, stepping past it
+    pub fn next_block(&mut self) -> (u16, u16) {
+        let block = (self.source, 0x8000 | self.dest);
+        self.source = self.source.wrapping_add(BLOCK_SIZE);
+        self.dest = (self.dest + BLOCK_SIZE) & 0x1FF0;
+        self.remaining = self.remaining.wrapping_sub(1) & 0x7F;
+        if self.remaining == 0x7F {
+            self.hblank_active = false;
+        }
+        block
+    }
+
+    // Time the CPU is stopped while `blocks` blocks are copied, in CPU cycles
+    pub fn stall_cycles(blocks: u16, double_speed: bool) -> u32 {
+        let cycles = blocks as u32 * CYCLES_PER_BLOCK;
+        if double_speed { cycles * 2 } else { cycles }
+    }
+}
+
+impl Savestate for Hdma {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.source);
+        state.write_u16(self.dest);
+        state.write_u8(self.remaining);
+        state.write_bool(self.hblank_active);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.source = state.read_u16()?;
+        self.dest = state.read_u16()? & 0x1FF0;
+        self.remaining = state.read_u8()? & 0x7F;
+        self.hblank_active = state.read_bool()?;
+        Ok(())
+    }
+}
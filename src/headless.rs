@@ -0,0 +1,143 @@
+use crate::debugger::{self, ConsoleExit, DebugStop};
+use crate::events::{self, EmulatorEvent};
+use crate::gameboy::GameBoy;
+use crate::error::{EmulatorError, Result};
+use crate::remote::RemoteServer;
+
+// When a run without a window ends
+#[derive(Default)]
+pub struct HeadlessOptions {
+    // Stop after this many frames; None runs until another condition hits
+    pub frames: Option<u32>,
+    // Stop once the program has sent this text out of the serial port, as
+    // test ROMs do with "Passed"
+    pub serial_match: Option<String>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StopReason {
+    FrameLimit,
+    SerialMatch,
+    Breakpoint,
+    Hang,
+    // The frames ran out before the serial output matched
+    Timeout,
+    // A movie being played back ran out of input
+    MovieEnded,
+    // The FrameDriver failed, with its error in HeadlessResult::driver_error
+    DriverError,
+}
+
+impl StopReason {
+    // Process exit status for scripts: 0 when the run ended as asked, 2 for
+    // a hang and 3 for a timeout (1 is left for errors)
+    pub fn exit_code(self) -> i32 {
+        match self {
+            StopReason::FrameLimit | StopReason::SerialMatch | StopReason::Breakpoint | StopReason::MovieEnded => 0,
+            StopReason::Hang => 2,
+            StopReason::Timeout => 3,
+            StopReason::DriverError => 1,
+        }
+    }
+}
+
+// Steers the game from frame to frame alongside the remote clients, as
+// Lua scripts do
+pub trait FrameDriver {
+    // Held down during the next frame
+    fn buttons(&self) -> u8;
+    fn after_frame(&mut self, gb: &mut GameBoy) -> Result<()>;
+}
+
+pub struct HeadlessResult {
+    pub reason: StopReason,
+    pub frames: u32,
+    // Every byte the program sent out of the serial port
+    pub serial: Vec<u8>,
+    pub driver_error: Option<EmulatorError>,
+}
+
+// Runs frame by frame until a condition in `options`, a breakpoint or a hang
+// stops it. Events are printed as they come. Debugger stops open the console
+// on stdin and only end the run when it quits.
+pub fn run(
+    gb: &mut GameBoy,
+    options: &HeadlessOptions,
+    mut remote: Option<&mut RemoteServer>,
+    mut driver: Option<&mut dyn FrameDriver>,
+) -> HeadlessResult {
+    let mut result = HeadlessResult { reason: StopReason::FrameLimit, frames: 0, serial: Vec::new(), driver_error: None };
+    let pattern = options.serial_match.as_deref().map(str::as_bytes).filter(|pattern| !pattern.is_empty());
+
+    loop {
+        if options.frames.is_some_and(|frames| result.frames >= frames) {
+            result.reason = if pattern.is_some() { StopReason::Timeout } else { StopReason::FrameLimit };
+            break;
+        }
+        let mut buttons = None;
+        if let Some(server) = remote.as_deref_mut() {
+            server.poll(gb);
+            buttons = Some(server.held_buttons());
+        }
+        if let Some(driver) = driver.as_deref() {
+            buttons = Some(buttons.unwrap_or(0) | driver.buttons());
+        }
+        if let Some(buttons) = buttons {
+            gb.set_inputs(buttons);
+        }
+        let mut stopped = gb.run_frame().is_none();
+        result.frames += 1;
+        if let Some(driver) = driver.as_deref_mut().filter(|_| !stopped) {
+            if let Err(e) = driver.after_frame(gb) {
+                result.reason = StopReason::DriverError;
+                result.driver_error = Some(e);
+                break;
+            }
+        }
+        // Nothing plays it, but a WAV dump may be recording it
+        gb.take_audio();
+
+        let timed = gb.take_events();
+        let mut hang = false;
+        let mut debug_stop = false;
+        let mut stepped = false;
+        let mut movie_ended = false;
+        for event in &timed {
+            match event.event {
+                EmulatorEvent::SerialByte { sent, .. } => result.serial.push(sent),
+                EmulatorEvent::Hang(_) => hang = true,
+                EmulatorEvent::DebugStop { stop, .. } => {
+                    debug_stop = true;
+                    stepped |= stop == DebugStop::Step;
+                }
+                EmulatorEvent::MovieEnded { .. } => movie_ended = true,
+                _ => {}
+            }
+        }
+        // Not after every step in the console, which has a command for it
+        if events::report(timed) && !stepped {
+            events::report_history(&gb.history());
+        }
+        if debug_stop && debugger::console(gb, &mut std::io::stdin().lock(), &mut std::io::stdout()) == ConsoleExit::Resume {
+            stopped = false;
+        }
+
+        if pattern.is_some_and(|pattern| result.serial.windows(pattern.len()).any(|window| window == pattern)) {
+            result.reason = StopReason::SerialMatch;
+            break;
+        }
+        if stopped {
+            result.reason = StopReason::Breakpoint;
+            break;
+        }
+        if hang {
+            result.reason = StopReason::Hang;
+            break;
+        }
+        if movie_ended {
+            result.reason = StopReason::MovieEnded;
+            break;
+        }
+    }
+    result
+}
@@ -0,0 +1,74 @@
+// The last instructions the CPU ran, so a lock-up or a breakpoint can show
+// how the program got there. It copies the registers on every instruction,
+// so it's off until GameBoy::set_history_length asks for some.
+
+use crate::cpu::RegisterSnapshot;
+use crate::disasm;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct HistoryEntry {
+    // As they were before the instruction ran
+    pub registers: RegisterSnapshot,
+    // The ROM bank mapped when PC is in ROM
+    pub bank: Option<usize>,
+    // The instruction as it ran, in case the code has changed since
+    pub bytes: [u8; 3],
+}
+
+impl HistoryEntry {
+    // "01:4A20  FA 00 C0  LD A,($C000)     AF:01B0 BC:0013 DE:00D8 HL:014D SP:FFFE"
+    pub fn describe(&self) -> String {
+        let r = &self.registers;
+        let (text, len) = disasm::decode(|offset| self.bytes.get(offset as usize).copied().unwrap_or(0), r.pc);
+        let bytes: Vec<String> = self.bytes[..(len as usize).min(3)].iter().map(|byte| format!("{:02X}", byte)).collect();
+        let bank = match self.bank {
+            Some(bank) => format!("{:02X}:", bank),
+            None => "   ".to_string(),
+        };
+        format!(
+            "{}{:04X}  {:<8}  {:<16} AF:{:02X}{:02X} BC:{:02X}{:02X} DE:{:02X}{:02X} HL:{:02X}{:02X} SP:{:04X}",
+            bank, r.pc, bytes.join(" "), text, r.a, r.f, r.b, r.c, r.d, r.e, r.h, r.l, r.sp,
+        )
+    }
+}
+
+pub struct ExecutionHistory {
+    entries: Vec<HistoryEntry>,
+    // Where the next entry goes once entries is full
+    next: usize,
+    capacity: usize,
+}
+
+impl ExecutionHistory {
+    pub fn new(capacity: usize) -> Self {
+        ExecutionHistory { entries: Vec::with_capacity(capacity), next: 0, capacity }
+    }
+
+    pub fn record(&mut self, entry: HistoryEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() < self.capacity {
+            self.entries.push(entry);
+        } else {
+            self.entries[self.next] = entry;
+            self.next = (self.next + 1) % self.capacity;
+        }
+    }
+
+    // Oldest first
+    pub fn entries(&self) -> Vec<HistoryEntry> {
+        let (newer, older) = self.entries.split_at(self.next);
+        older.iter().chain(newer).copied().collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.next = 0;
+    }
+}
+
+// One line per entry, oldest first
+pub fn dump(entries: &[HistoryEntry]) -> String {
+    entries.iter().map(HistoryEntry::describe).collect::<Vec<_>>().join("\n")
+}
@@ -0,0 +1,130 @@
+// Callbacks for programs embedding the core, so GUIs, scripts and tests can
+// follow the machine without polling it: frames, VBlank, serial bytes,
+// writes to chosen addresses and breakpoints. Each is called from within
+// GameBoy::step or run_frame right after the instruction that caused it.
+// Several can watch the same thing, and each can be removed by its id.
+
+use crate::breakpoints::EventBreakpoint;
+use crate::bus::MemoryBus;
+use crate::debugger::DebugStop;
+use crate::gameboy::Frame;
+
+pub type FrameHook = Box<dyn FnMut(&Frame)>;
+// Like an HBlankHook, called as LY reaches 144
+pub type VBlankHook = Box<dyn FnMut(&mut MemoryBus)>;
+// With the byte sent and the byte received
+pub type SerialHook = Box<dyn FnMut(u8, u8)>;
+// With the address and the value written
+pub type WriteHook = Box<dyn FnMut(u16, u8)>;
+// With what stopped the machine and the PC it stopped at
+pub type BreakHook = Box<dyn FnMut(Break, u16)>;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct HookId(u64);
+
+// Why a break hook was called
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Break {
+    // One of GameBoy's event breakpoints
+    Event(EventBreakpoint),
+    // A code breakpoint, watchpoint or step of the debugger
+    Debugger(DebugStop),
+}
+
+#[derive(Default)]
+pub struct Hooks {
+    next_id: u64,
+    pub(crate) frame: Vec<(HookId, FrameHook)>,
+    pub(crate) vblank: Vec<(HookId, VBlankHook)>,
+    pub(crate) serial: Vec<(HookId, SerialHook)>,
+    pub(crate) write: Vec<(HookId, u16, WriteHook)>,
+    pub(crate) brk: Vec<(HookId, BreakHook)>,
+}
+
+impl Hooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn next_id(&mut self) -> HookId {
+        self.next_id += 1;
+        HookId(self.next_id)
+    }
+
+    pub fn add_frame(&mut self, hook: FrameHook) -> HookId {
+        let id = self.next_id();
+        self.frame.push((id, hook));
+        id
+    }
+
+    pub fn add_vblank(&mut self, hook: VBlankHook) -> HookId {
+        let id = self.next_id();
+        self.vblank.push((id, hook));
+        id
+    }
+
+    pub fn add_serial(&mut self, hook: SerialHook) -> HookId {
+        let id = self.next_id();
+        self.serial.push((id, hook));
+        id
+    }
+
+    pub fn add_write(&mut self, addr: u16, hook: WriteHook) -> HookId {
+        let id = self.next_id();
+        self.write.push((id, addr, hook));
+        id
+    }
+
+    pub fn add_break(&mut self, hook: BreakHook) -> HookId {
+        let id = self.next_id();
+        self.brk.push((id, hook));
+        id
+    }
+
+    // Returns false when no hook had the id
+    pub fn remove(&mut self, id: HookId) -> bool {
+        let before = self.len();
+        self.frame.retain(|(hook_id, _)| *hook_id != id);
+        self.vblank.retain(|(hook_id, _)| *hook_id != id);
+        self.serial.retain(|(hook_id, _)| *hook_id != id);
+        self.write.retain(|(hook_id, _, _)| *hook_id != id);
+        self.brk.retain(|(hook_id, _)| *hook_id != id);
+        self.len() != before
+    }
+
+    fn len(&self) -> usize {
+        self.frame.len() + self.vblank.len() + self.serial.len() + self.write.len() + self.brk.len()
+    }
+
+    // The addresses write hooks watch, for the bus to look out for
+    pub(crate) fn watched_addresses(&self) -> Vec<u16> {
+        let mut addresses: Vec<u16> = self.write.iter().map(|&(_, addr, _)| addr).collect();
+        addresses.sort_unstable();
+        addresses.dedup();
+        addresses
+    }
+
+    pub(crate) fn vblank(&mut self, bus: &mut MemoryBus) {
+        for (_, hook) in &mut self.vblank {
+            hook(bus);
+        }
+    }
+
+    pub(crate) fn serial(&mut self, sent: u8, received: u8) {
+        for (_, hook) in &mut self.serial {
+            hook(sent, received);
+        }
+    }
+
+    pub(crate) fn write(&mut self, addr: u16, value: u8) {
+        for (_, _, hook) in self.write.iter_mut().filter(|(_, watched, _)| *watched == addr) {
+            hook(addr, value);
+        }
+    }
+
+    pub(crate) fn brk(&mut self, reason: Break, pc: u16) {
+        for (_, hook) in &mut self.brk {
+            hook(reason, pc);
+        }
+    }
+}
@@ -0,0 +1,44 @@
+// Compares frames against reference images, for golden-image tests
+use crate::png::Image;
+
+// Marks differing pixels in the highlight image
+const MISMATCH_COLOR: [u8; 3] = [0xFF, 0x00, 0x00];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageDiff {
+    pub width: usize,
+    pub height: usize,
+    // Pixels with any channel different
+    pub differing: usize,
+    // The first of them in reading order, as (x, y)
+    pub first: Option<(usize, usize)>,
+    // The reference at half brightness with the differing pixels in red,
+    // RGB24
+    pub highlight: Vec<u8>,
+}
+
+impl ImageDiff {
+    pub fn matches(&self) -> bool {
+        self.differing == 0
+    }
+}
+
+pub fn compare(expected: &Image, actual: &Image) -> Result<ImageDiff, String> {
+    if (expected.width, expected.height) != (actual.width, actual.height) {
+        return Err(format!(
+            "image is {}x{}, the reference is {}x{}",
+            actual.width, actual.height, expected.width, expected.height
+        ));
+    }
+    let mut diff = ImageDiff { width: expected.width, height: expected.height, differing: 0, first: None, highlight: Vec::with_capacity(expected.pixels.len()) };
+    for (index, (want, got)) in expected.pixels.chunks_exact(3).zip(actual.pixels.chunks_exact(3)).enumerate() {
+        if want == got {
+            diff.highlight.extend(want.iter().map(|channel| channel / 2));
+        } else {
+            diff.differing += 1;
+            diff.first.get_or_insert((index % expected.width, index / expected.width));
+            diff.highlight.extend_from_slice(&MISMATCH_COLOR);
+        }
+    }
+    Ok(diff)
+}
@@ -0,0 +1,105 @@
+// The CGB's infrared port at RP (FF56): an LED the game switches with bit 0
+// and a sensor it reads in bit 1 once bits 6-7 turn reading on. Light goes
+// to and from another Game Boy through an IrTransport.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::error::Result;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+
+const LED: u8 = 0x01;
+// Reads 0 while light comes in
+const NO_LIGHT: u8 = 0x02;
+const READ_ENABLE: u8 = 0xC0;
+
+// Carries the LED's light to another Game Boy and the other's back
+pub trait IrTransport: Send {
+    // Called each time this side's LED switches
+    fn send(&mut self, led: bool);
+    // Whether light from the other side reaches the sensor
+    fn light(&mut self) -> bool;
+}
+
+// Two instances in one process facing each other, as LinkSession runs them
+pub struct LocalIr {
+    own: Arc<AtomicBool>,
+    other: Arc<AtomicBool>,
+}
+
+impl LocalIr {
+    pub fn pair() -> (LocalIr, LocalIr) {
+        let (first, second) = (Arc::new(AtomicBool::new(false)), Arc::new(AtomicBool::new(false)));
+        (LocalIr { own: first.clone(), other: second.clone() }, LocalIr { own: second, other: first })
+    }
+}
+
+impl IrTransport for LocalIr {
+    fn send(&mut self, led: bool) {
+        self.own.store(led, Ordering::Relaxed);
+    }
+
+    fn light(&mut self) -> bool {
+        self.other.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+pub struct InfraredPort {
+    // The LED and read enable bits as last written
+    control: u8,
+    light: bool,
+    // The LED switched since the transport last heard about it
+    led_changed: bool,
+}
+
+impl InfraredPort {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read(&self) -> u8 {
+        let receiving = self.light && self.is_reading();
+        self.control | if receiving { 0 } else { NO_LIGHT }
+    }
+
+    pub fn write(&mut self, value: u8) {
+        let led = self.led();
+        self.control = value & (LED | READ_ENABLE);
+        self.led_changed |= led != self.led();
+    }
+
+    pub fn led(&self) -> bool {
+        self.control & LED != 0
+    }
+
+    // Whether the game is looking at the sensor, so the light should be
+    // kept up to date
+    pub fn is_reading(&self) -> bool {
+        self.control & READ_ENABLE == READ_ENABLE
+    }
+
+    pub fn set_light(&mut self, light: bool) {
+        self.light = light;
+    }
+
+    // The LED's state if it switched since the last call
+    pub fn take_led_change(&mut self) -> Option<bool> {
+        std::mem::take(&mut self.led_changed).then(|| self.led())
+    }
+}
+
+impl Savestate for InfraredPort {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.control);
+        state.write_bool(self.light);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.control = state.read_u8()? & (LED | READ_ENABLE);
+        self.light = state.read_bool()?;
+        // Tell the other side where the LED is now
+        self.led_changed = true;
+        Ok(())
+    }
+}
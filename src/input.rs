@@ -0,0 +1,111 @@
+use crate::joypad::Button;
+
+pub const MACRO_SLOTS: usize = 4;
+
+// Host-side input for one machine. Frontends report what is held, and once per
+// frame the layer produces the packed button byte that actually reaches the
+// joypad, merging in any macro that is playing back. Input is only sampled
+// there, so the game sees the same thing live as in a recording, whenever
+// during the frame the host events arrived.
+pub struct InputLayer {
+    // Keyboard, game controller buttons and analog stick, kept apart so
+    // releasing a button on one doesn't release it on the others
+    held: u8,
+    pad_held: u8,
+    stick: u8,
+    // Pressed since the last sample; a tap released before the frame started
+    // still shows for one frame instead of being lost
+    tapped: u8,
+    macros: [Vec<u8>; MACRO_SLOTS],
+    recording: Option<usize>,
+    playback: Option<(usize, usize)>,
+}
+
+impl Default for InputLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InputLayer {
+    pub fn new() -> Self {
+        InputLayer {
+            held: 0,
+            pad_held: 0,
+            stick: 0,
+            tapped: 0,
+            macros: Default::default(),
+            recording: None,
+            playback: None,
+        }
+    }
+
+    pub fn set_held(&mut self, button: Button, pressed: bool) {
+        if pressed {
+            self.held |= button.mask();
+            self.tapped |= button.mask();
+        } else {
+            self.held &= !button.mask();
+        }
+    }
+
+    pub fn set_pad_held(&mut self, button: Button, pressed: bool) {
+        if pressed {
+            self.pad_held |= button.mask();
+            self.tapped |= button.mask();
+        } else {
+            self.pad_held &= !button.mask();
+        }
+    }
+
+    // Directions currently pushed on the analog stick, as Button masks
+    pub fn set_stick(&mut self, buttons: u8) {
+        self.stick = buttons;
+        self.tapped |= buttons;
+    }
+
+    // Lets go of every keyboard key, for when input moves to another machine
+    pub fn release_keys(&mut self) {
+        self.held = 0;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    // Starts recording into the slot, or stops if that slot is already recording
+    pub fn toggle_recording(&mut self, slot: usize) {
+        if self.recording == Some(slot) {
+            self.recording = None;
+            return;
+        }
+        self.macros[slot].clear();
+        self.recording = Some(slot);
+        self.playback = None;
+    }
+
+    pub fn play_macro(&mut self, slot: usize) {
+        if self.recording.is_none() && !self.macros[slot].is_empty() {
+            self.playback = Some((slot, 0));
+        }
+    }
+
+    pub fn next_frame(&mut self) -> u8 {
+        let mut buttons = self.held | self.pad_held | self.stick | std::mem::take(&mut self.tapped);
+
+        if let Some((slot, frame)) = self.playback {
+            buttons |= self.macros[slot][frame];
+            self.playback = if frame + 1 < self.macros[slot].len() {
+                Some((slot, frame + 1))
+            } else {
+                None
+            };
+        }
+
+        if let Some(slot) = self.recording {
+            self.macros[slot].push(buttons);
+        }
+
+        buttons
+    }
+}
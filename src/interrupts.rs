@@ -1,3 +1,6 @@
+use crate::error::Result;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+
 const VBLANK: u8 = 0b00000001;
 const LCD_STAT: u8 = 0b00000010;
 const TIMER: u8 = 0b00000100;
@@ -10,6 +13,12 @@ pub struct Interrupts {
     pub ime: bool,
 }
 
+impl Default for Interrupts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Interrupts {
     pub fn new() -> Self {
         Interrupts {
@@ -23,39 +32,35 @@ impl Interrupts {
         self.interrupt_flag |= interrupt as u8;
     }
 
-    pub fn has_pending_interrupt(&self) -> bool {
-        self.ime && (self.interrupt_enable & self.interrupt_flag) != 0
+    // Interrupts both requested and enabled, whether or not IME is set
+    pub fn requested(&self) -> u8 {
+        self.interrupt_enable & self.interrupt_flag & 0x1F
     }
 
-    pub fn get_next_interrupt(&mut self) -> Option<u16> {
-        if !self.ime {
-            return None;
-        }
+    pub fn has_pending_interrupt(&self) -> bool {
+        self.ime && self.requested() != 0
+    }
 
-        let pending = self.interrupt_enable & self.interrupt_flag;
-        
-        if pending & VBLANK != 0 {
-            self.interrupt_flag &= !VBLANK;
-            Some(0x0040)
-        } else if pending & LCD_STAT != 0 {
-            self.interrupt_flag &= !LCD_STAT;
-            Some(0x0048)
-        } else if pending & TIMER != 0 {
-            self.interrupt_flag &= !TIMER;
-            Some(0x0050)
-        } else if pending & SERIAL != 0 {
-            self.interrupt_flag &= !SERIAL;
-            Some(0x0058)
-        } else if pending & JOYPAD != 0 {
-            self.interrupt_flag &= !JOYPAD;
-            Some(0x0060)
-        } else {
-            None
-        }
+    // Picks the highest priority interrupt that is requested and enabled
+    // and clears its flag. The CPU checks IME before it starts dispatching.
+    pub fn acknowledge(&mut self) -> Option<InterruptType> {
+        let pending = self.requested();
+        let interrupt = [
+            (VBLANK, InterruptType::VBlank),
+            (LCD_STAT, InterruptType::LcdStat),
+            (TIMER, InterruptType::Timer),
+            (SERIAL, InterruptType::Serial),
+            (JOYPAD, InterruptType::Joypad),
+        ]
+        .into_iter()
+        .find(|(bit, _)| pending & bit != 0)
+        .map(|(_, interrupt)| interrupt)?;
+        self.interrupt_flag &= !(interrupt as u8);
+        Some(interrupt)
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum InterruptType {
     VBlank = 0b00000001,
     LcdStat = 0b00000010,
@@ -64,3 +69,30 @@ pub enum InterruptType {
     Joypad = 0b00010000,
 }
 
+impl Savestate for Interrupts {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.interrupt_enable);
+        state.write_u8(self.interrupt_flag);
+        state.write_bool(self.ime);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.interrupt_enable = state.read_u8()?;
+        self.interrupt_flag = state.read_u8()?;
+        self.ime = state.read_bool()?;
+        Ok(())
+    }
+}
+
+impl InterruptType {
+    // Where the CPU jumps to service it
+    pub fn vector(self) -> u16 {
+        match self {
+            InterruptType::VBlank => 0x0040,
+            InterruptType::LcdStat => 0x0048,
+            InterruptType::Timer => 0x0050,
+            InterruptType::Serial => 0x0058,
+            InterruptType::Joypad => 0x0060,
+        }
+    }
+}
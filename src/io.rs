@@ -0,0 +1,134 @@
+// The I/O registers at FF00-FF7F, plus IE at FFFF: which part of the machine
+// answers for each, the bits that always read as 1, and which only exist on
+// a CGB. MemoryBus routes every access to these addresses through the map;
+// anything missing from it reads 0xFF and ignores writes.
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Owner {
+    Joypad,
+    Serial,
+    Timer,
+    InterruptFlag,
+    InterruptEnable,
+    Apu,
+    Ppu,
+    // Writes start OAM DMA, reads give back the last value written
+    OamDma,
+    Key1,
+    // The boot ROM switch and SVBK
+    Memory,
+    Hdma,
+    // PCM12 and PCM34, read-only views of the channel outputs
+    Pcm,
+    // RP, the infrared port
+    Infrared,
+    // Keeps what is written without affecting anything
+    Latch,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Availability {
+    Always,
+    // On CGB hardware, whatever mode the game runs in
+    CgbHardware,
+    // Only while a CGB game runs in CGB mode
+    CgbMode,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct IoRegister {
+    pub name: &'static str,
+    pub owner: Owner,
+    // Bits that read as 1 whatever was written, including all of a
+    // write-only register
+    pub unused: u8,
+    pub availability: Availability,
+}
+
+impl IoRegister {
+    pub fn is_present(&self, cgb_hardware: bool, cgb_mode: bool) -> bool {
+        match self.availability {
+            Availability::Always => true,
+            Availability::CgbHardware => cgb_hardware,
+            Availability::CgbMode => cgb_mode,
+        }
+    }
+}
+
+pub fn register(addr: u16) -> Option<IoRegister> {
+    use Availability::*;
+    use Owner::*;
+
+    let (name, owner, unused, availability) = match addr {
+        0xFF00 => ("P1", Joypad, 0xC0, Always),
+        0xFF01 => ("SB", Serial, 0x00, Always),
+        // Bit 1, the CGB fast clock, reads 1 on the DMG
+        0xFF02 => ("SC", Serial, 0x7C, Always),
+        0xFF04 => ("DIV", Timer, 0x00, Always),
+        0xFF05 => ("TIMA", Timer, 0x00, Always),
+        0xFF06 => ("TMA", Timer, 0x00, Always),
+        0xFF07 => ("TAC", Timer, 0xF8, Always),
+        0xFF0F => ("IF", InterruptFlag, 0xE0, Always),
+        0xFF10 => ("NR10", Apu, 0x80, Always),
+        0xFF11 => ("NR11", Apu, 0x3F, Always),
+        0xFF12 => ("NR12", Apu, 0x00, Always),
+        0xFF13 => ("NR13", Apu, 0xFF, Always),
+        0xFF14 => ("NR14", Apu, 0xBF, Always),
+        0xFF16 => ("NR21", Apu, 0x3F, Always),
+        0xFF17 => ("NR22", Apu, 0x00, Always),
+        0xFF18 => ("NR23", Apu, 0xFF, Always),
+        0xFF19 => ("NR24", Apu, 0xBF, Always),
+        0xFF1A => ("NR30", Apu, 0x7F, Always),
+        0xFF1B => ("NR31", Apu, 0xFF, Always),
+        0xFF1C => ("NR32", Apu, 0x9F, Always),
+        0xFF1D => ("NR33", Apu, 0xFF, Always),
+        0xFF1E => ("NR34", Apu, 0xBF, Always),
+        0xFF20 => ("NR41", Apu, 0xFF, Always),
+        0xFF21 => ("NR42", Apu, 0x00, Always),
+        0xFF22 => ("NR43", Apu, 0x00, Always),
+        0xFF23 => ("NR44", Apu, 0xBF, Always),
+        0xFF24 => ("NR50", Apu, 0x00, Always),
+        0xFF25 => ("NR51", Apu, 0x00, Always),
+        0xFF26 => ("NR52", Apu, 0x70, Always),
+        0xFF30..=0xFF3F => ("WAVE", Apu, 0x00, Always),
+        0xFF40 => ("LCDC", Ppu, 0x00, Always),
+        0xFF41 => ("STAT", Ppu, 0x80, Always),
+        0xFF42 => ("SCY", Ppu, 0x00, Always),
+        0xFF43 => ("SCX", Ppu, 0x00, Always),
+        0xFF44 => ("LY", Ppu, 0x00, Always),
+        0xFF45 => ("LYC", Ppu, 0x00, Always),
+        0xFF46 => ("DMA", OamDma, 0x00, Always),
+        0xFF47 => ("BGP", Ppu, 0x00, Always),
+        0xFF48 => ("OBP0", Ppu, 0x00, Always),
+        0xFF49 => ("OBP1", Ppu, 0x00, Always),
+        0xFF4A => ("WY", Ppu, 0x00, Always),
+        0xFF4B => ("WX", Ppu, 0x00, Always),
+        0xFF4D => ("KEY1", Key1, 0x7E, CgbMode),
+        0xFF4F => ("VBK", Ppu, 0xFE, CgbMode),
+        0xFF50 => ("BANK", Memory, 0xFF, Always),
+        0xFF51 => ("HDMA1", Hdma, 0xFF, CgbMode),
+        0xFF52 => ("HDMA2", Hdma, 0xFF, CgbMode),
+        0xFF53 => ("HDMA3", Hdma, 0xFF, CgbMode),
+        0xFF54 => ("HDMA4", Hdma, 0xFF, CgbMode),
+        0xFF55 => ("HDMA5", Hdma, 0x00, CgbMode),
+        0xFF56 => ("RP", Infrared, 0x3C, CgbMode),
+        0xFF68 => ("BCPS", Ppu, 0x40, CgbMode),
+        0xFF69 => ("BCPD", Ppu, 0x00, CgbMode),
+        0xFF6A => ("OCPS", Ppu, 0x40, CgbMode),
+        0xFF6B => ("OCPD", Ppu, 0x00, CgbMode),
+        // Object priority mode, set by the boot ROM. The PPU goes by whether
+        // the game runs in CGB mode instead.
+        0xFF6C => ("OPRI", Latch, 0xFE, CgbHardware),
+        0xFF70 => ("SVBK", Memory, 0xF8, CgbMode),
+        // Undocumented, with no known function
+        0xFF72 => ("FF72", Latch, 0x00, CgbHardware),
+        0xFF73 => ("FF73", Latch, 0x00, CgbHardware),
+        0xFF74 => ("FF74", Latch, 0x00, CgbMode),
+        0xFF75 => ("FF75", Latch, 0x8F, CgbHardware),
+        0xFF76 => ("PCM12", Pcm, 0x00, CgbHardware),
+        0xFF77 => ("PCM34", Pcm, 0x00, CgbHardware),
+        0xFFFF => ("IE", InterruptEnable, 0x00, Always),
+        _ => return None,
+    };
+    Some(IoRegister { name, owner, unused, availability })
+}
@@ -1,3 +1,7 @@
+use crate::error::Result;
+use crate::interrupts::{Interrupts, InterruptType};
+use crate::savestate::{Savestate, StateReader, StateWriter};
+
 pub struct Joypad {
     action_buttons: u8,
     direction_buttons: u8,
@@ -5,6 +9,12 @@ pub struct Joypad {
     select_direction: bool,
 }
 
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Joypad {
     pub fn new() -> Self {
         Joypad {
@@ -25,20 +35,48 @@ impl Joypad {
             result |= 0x10;
         }
 
+        // With both groups selected a line reads low if either button on it is pressed
+        let mut lines = 0x0F;
         if self.select_action {
-            result |= self.action_buttons & 0x0F;
-        } else if self.select_direction {
-            result |= self.direction_buttons & 0x0F;
-        } else {
-            result |= 0x0F;
+            lines &= self.action_buttons;
+        }
+        if self.select_direction {
+            lines &= self.direction_buttons;
         }
 
-        result
+        result | (lines & 0x0F)
     }
 
-    pub fn write(&mut self, value: u8) {
+    // Selecting a group while one of its buttons is held pulls a line low,
+    // which requests the interrupt just like a press does
+    pub fn write(&mut self, value: u8, interrupts: &mut Interrupts) {
+        let before = self.read() & 0x0F;
         self.select_action = (value & 0x20) == 0;
         self.select_direction = (value & 0x10) == 0;
+        let after = self.read() & 0x0F;
+        if before & !after != 0 {
+            interrupts.request_interrupt(InterruptType::Joypad);
+        }
+    }
+
+    // Presses or releases a button, requesting the joypad interrupt when a
+    // currently selected input line goes from high to low
+    pub fn set_button(&mut self, button: Button, pressed: bool, interrupts: &mut Interrupts) {
+        let before = self.read() & 0x0F;
+        if pressed {
+            self.press_button(button);
+        } else {
+            self.release_button(button);
+        }
+        let after = self.read() & 0x0F;
+        if before & !after != 0 {
+            interrupts.request_interrupt(InterruptType::Joypad);
+        }
+    }
+
+    // Buttons held, packed as in Button::mask
+    pub fn held(&self) -> u8 {
+        (!self.action_buttons & 0x0F) | (!self.direction_buttons & 0x0F) << 4
     }
 
     pub fn press_button(&mut self, button: Button) {
@@ -68,7 +106,7 @@ impl Joypad {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Button {
     A,
     B,
@@ -80,3 +118,58 @@ pub enum Button {
     Down,
 }
 
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+        Button::Right,
+        Button::Left,
+        Button::Up,
+        Button::Down,
+    ];
+
+    // Bit of this button in a packed input byte: action buttons in the low
+    // nibble, directions in the high nibble, set while pressed
+    pub fn mask(self) -> u8 {
+        match self {
+            Button::A => 0x01,
+            Button::B => 0x02,
+            Button::Select => 0x04,
+            Button::Start => 0x08,
+            Button::Right => 0x10,
+            Button::Left => 0x20,
+            Button::Up => 0x40,
+            Button::Down => 0x80,
+        }
+    }
+}
+
+impl std::str::FromStr for Button {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Button::ALL
+            .into_iter()
+            .find(|button| format!("{:?}", button).eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unknown button '{}'", s))
+    }
+}
+
+impl Savestate for Joypad {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.action_buttons);
+        state.write_u8(self.direction_buttons);
+        state.write_bool(self.select_action);
+        state.write_bool(self.select_direction);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.action_buttons = state.read_u8()?;
+        self.direction_buttons = state.read_u8()?;
+        self.select_action = state.read_bool()?;
+        self.select_direction = state.read_bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+// The ROM browser the window opens with Ctrl+O: the ROMs in a directory,
+// listed over the dimmed screen in the overlay font, with one selected.
+// Picking one is up to the frontend, which swaps in a new GameBoy for it.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::overlay::{OverlayText, GLYPH_WIDTH, LINE_HEIGHT};
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::sweep;
+
+const TITLE_COLOR: [u8; 3] = [0xFF, 0xFF, 0x00];
+const NAME_COLOR: [u8; 3] = [0xC0, 0xC0, 0xC0];
+const SELECTED_COLOR: [u8; 3] = [0x00, 0xFF, 0x00];
+
+// The list starts a blank line under the title
+const LIST_TOP: i32 = 2 + LINE_HEIGHT * 2;
+// Names shown at once; the list scrolls to keep the selection among them
+pub const VISIBLE_ROMS: usize = ((SCREEN_HEIGHT as i32 - LIST_TOP) / LINE_HEIGHT) as usize;
+// Longer names are cut, leaving room for the marker
+const MAX_NAME_LEN: usize = (SCREEN_WIDTH as i32 / GLYPH_WIDTH) as usize - 3;
+
+pub struct RomBrowser {
+    dir: PathBuf,
+    roms: Vec<PathBuf>,
+    selected: usize,
+    // The first name shown
+    top: usize,
+}
+
+impl RomBrowser {
+    // Lists the .gb, .gbc, .zip and .gz files in dir, sorted by name
+    pub fn open(dir: &Path) -> io::Result<RomBrowser> {
+        let roms = sweep::find_roms(dir)?;
+        Ok(RomBrowser { dir: dir.to_path_buf(), roms, selected: 0, top: 0 })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn roms(&self) -> &[PathBuf] {
+        &self.roms
+    }
+
+    // None when the directory has no ROMs
+    pub fn selected(&self) -> Option<&Path> {
+        self.roms.get(self.selected).map(PathBuf::as_path)
+    }
+
+    // Moves the selection by delta names, stopping at either end
+    pub fn move_selection(&mut self, delta: isize) {
+        let Some(last) = self.roms.len().checked_sub(1) else {
+            return;
+        };
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + VISIBLE_ROMS {
+            self.top = self.selected + 1 - VISIBLE_ROMS;
+        }
+    }
+
+    // Over an RGB24 frame, which is darkened so the names read on any game
+    pub fn draw(&self, pixels: &mut [u8]) {
+        for value in pixels.iter_mut() {
+            *value /= 4;
+        }
+        let title = match self.roms.len() {
+            0 => "NO ROMS FOUND".to_string(),
+            count => format!("OPEN ROM {}/{}", self.selected + 1, count),
+        };
+        OverlayText { x: 2, y: 2, text: title, color: TITLE_COLOR }.draw(pixels);
+        for (row, rom) in self.roms.iter().enumerate().skip(self.top).take(VISIBLE_ROMS) {
+            let (marker, color) = if row == self.selected { ('>', SELECTED_COLOR) } else { (' ', NAME_COLOR) };
+            OverlayText {
+                x: 2,
+                y: LIST_TOP + (row - self.top) as i32 * LINE_HEIGHT,
+                text: format!("{} {}", marker, display_name(rom)),
+                color,
+            }
+            .draw(pixels);
+        }
+    }
+}
+
+fn display_name(path: &Path) -> String {
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    name.chars().take(MAX_NAME_LEN).collect()
+}
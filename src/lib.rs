@@ -0,0 +1,78 @@
+// The emulator core, without any frontend. Embedders drive a GameBoy through
+// its methods (load_rom, run_frame, frame, set_button, take_audio,
+// take_events); the component modules stay public for debuggers and tests.
+pub mod cpu;
+pub mod memory;
+pub mod mbc;
+pub mod bus;
+pub mod interrupts;
+pub mod io;
+pub mod timer;
+pub mod joypad;
+pub mod ppu;
+pub mod apu;
+pub mod gameboy;
+pub mod accuracy;
+pub mod bootrom;
+pub mod rtc;
+pub mod camera;
+pub mod events;
+pub mod hooks;
+pub mod watchdog;
+pub mod dev_warnings;
+pub mod cartridge;
+pub mod model;
+pub mod power_on;
+pub mod serial;
+pub mod infrared;
+pub mod hdma;
+pub mod session;
+pub mod threaded;
+pub mod input;
+pub mod bindings;
+pub mod breakpoints;
+pub mod cheats;
+pub mod ramsearch;
+pub mod error;
+pub mod savestate;
+pub mod savefile;
+pub mod audio_sink;
+pub mod debug_port;
+pub mod debugger;
+#[cfg(feature = "cached-interpreter")]
+pub mod decode_cache;
+pub mod disasm;
+pub mod trace;
+pub mod history;
+pub mod remote;
+pub mod netlink;
+pub mod netplay;
+pub mod scanout;
+pub mod sweep;
+pub mod selftest;
+pub mod pacing;
+pub mod perf;
+pub mod rewind;
+pub mod vram_viewer;
+pub mod launcher;
+pub mod movie;
+pub mod headless;
+pub mod png;
+pub mod inflate;
+pub mod archive;
+pub mod imagediff;
+pub mod overlay;
+pub mod channel_overlay;
+pub mod wav;
+#[cfg(feature = "script")]
+pub mod script;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use accuracy::AccuracyConfig;
+pub use error::{EmulatorError, Result};
+pub use events::{EmulatorEvent, TimedEvent};
+pub use gameboy::{AudioChunk, Frame, GameBoy};
+pub use joypad::Button;
+pub use model::HardwareModel;
+pub use ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
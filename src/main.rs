@@ -1,89 +1,823 @@
-mod cpu;
-mod memory;
-mod mbc;
-mod bus;
-mod interrupts;
-mod timer;
-mod joypad;
-mod ppu;
-mod gameboy;
 mod display;
-mod bootrom;
 
 use std::env;
 use std::fs;
-use std::path::Path;
-use gameboy::GameBoy;
-use display::run_with_display;
+use std::io;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use rust_gbc::{
+    apu, audio_sink, bus, cartridge, cheats, cpu, debugger, events, infrared, interrupts, serial, input, joypad, memory, pacing, power_on, ppu, remote, rtc,
+    headless, png, rewind, savefile, savestate, scanout, selftest, sweep, watchdog,
+};
+use rust_gbc::gameboy::GameBoy;
+use display::{run_session_with_display, run_threaded_with_display, run_with_display, DisplayOptions, RomPaths};
+use rust_gbc::session::LinkSession;
+use rust_gbc::rtc::{ClockOffset, GameRtcModes, OffsetClock, RtcMode};
+use rust_gbc::cartridge::{read_rom_file, CartridgeHeader};
+use rust_gbc::error::EmulatorError;
+use rust_gbc::model::HardwareModel;
+use rust_gbc::accuracy::AccuracyConfig;
+use rust_gbc::power_on::PowerOnPattern;
+use rust_gbc::breakpoints::EventBreakpoint;
+use rust_gbc::savefile::SaveFormat;
+use rust_gbc::audio_sink::FastForwardAudio;
+use rust_gbc::pacing::Speed;
+use rust_gbc::debug_port::DebugPortMode;
+use rust_gbc::remote::RemoteServer;
+use rust_gbc::scanout::{Filter, PaletteList};
+use rust_gbc::camera::StillImage;
+use rust_gbc::bindings::Bindings;
+use rust_gbc::trace::Tracer;
+use rust_gbc::cheats::Cheat;
+use rust_gbc::netlink::NetLink;
+use rust_gbc::netplay::{self, Netplay};
+use rust_gbc::movie::Movie;
+use rust_gbc::headless::FrameDriver;
+use rust_gbc::script::Script;
+use rust_gbc::wav::AudioDump;
+use rust_gbc::threaded::CoreThread;
+use rust_gbc::launcher::RomBrowser;
+use rust_gbc::channel_overlay;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
     if args.len() < 2 {
         println!("Game Boy Color Emulator");
-        println!("\nUsage: {} <rom_file>", args[0]);
+        println!("\nUsage: {} <rom_file> [--rtc=host|cycles] [--rtc-games=<file>] [--hang-frames=N] [--force-dmg]", args[0]);
+        println!("       [--scale=1-6] [--filter=none|grid|scanlines] [--fullscreen]");
+        println!("       [--blend=<percent>]");
+        println!("       [--model=dmg|mgb|cgb|agb] [--power-on=zeros|random] [--seed=N]");
+        println!("       [--link=<second_rom_file>] [--break=vblank|irq:<name>|io:<addr>:<mask>:<value>]");
+        println!("       [--save-format=raw|vba|bgb|sameboy] [--save-backups=N] [--ff-audio=resample|skip]");
+        println!("       [--ff-speed=N|max] [--rewind=<seconds>] [--cheats=<file>]");
+        println!("       [--link-listen=<port>] [--link-connect=<host>:<port>]");
+        println!("       [--netplay-host=<port>] [--netplay-join=<host>:<port>] [--netplay-delay=N] [--netplay-rollback=N]");
+        println!("       [--record-movie=<file>] [--play-movie=<file>] [--script=<file.lua>]");
+        println!("       [--debug-port=serial|addr:<addr>] [--remote=<port>]");
+        println!("       [--palette=<name>] [--palette-file=<file>] [--camera=<file.png>]");
+        println!("       [--headless] [--frames=N] [--until-serial=<text>] [--screenshot=<file.png>]");
+        println!("       [--screenshot-scale=1-6] [--accuracy=fast|balanced|accurate]");
+        println!("       [--boot-rom=<file>] [--skip-boot] [--save-dir=<dir>] [--unrestricted-vram]");
+        println!("       [--clock-offset=<N>[d|h|m|s]] [--keys=<bindings.toml>] [--debug]");
+        println!("       [--trace=<file>|-] [--sample-rate=N] [--dump-wav=<file.wav>] [--wav-stems]");
+        println!("       [--perf] [--threaded] [--dev-warnings] [--history=N] [--rom-dir=<dir>]");
+        println!("       {} sweep <rom_dir> [--frames=N] [--threads=N] [--out=report.csv]", args[0]);
+        println!("       {} selftest [--vectors=<file_or_dir>]", args[0]);
         println!("\nRunning built-in tests...\n");
         run_tests();
         return;
     }
 
+    if args[1] == "sweep" {
+        run_sweep(&args[2..]);
+        return;
+    }
+
+    if args[1] == "selftest" {
+        run_selftest(&args[2..]);
+        return;
+    }
+
     let rom_path = &args[1];
+
+    let mut options = RunOptions {
+        rtc_mode: None,
+        rtc_games: None,
+        hang_frames: watchdog::DEFAULT_HANG_FRAMES,
+        force_dmg: false,
+        unrestricted_vram: false,
+        accuracy: AccuracyConfig::default(),
+        scale: 4,
+        filter: Filter::None,
+        fullscreen: false,
+        frame_blend: 0,
+        model: None,
+        power_on: PowerOnPattern::Zeros,
+        seed: None,
+        link_rom: None,
+        breakpoints: Vec::new(),
+        save_format: SaveFormat::Bgb,
+        save_backups: 3,
+        fast_forward_audio: FastForwardAudio::Resample,
+        fast_forward_speed: Speed::Unthrottled,
+        rewind_seconds: rewind::DEFAULT_REWIND_SECONDS,
+        cheats: None,
+        net_link: None,
+        netplay: None,
+        netplay_delay: netplay::DEFAULT_DELAY,
+        netplay_rollback: 0,
+        movie: None,
+        script: None,
+        debug_port: None,
+        remote_port: None,
+        headless: false,
+        frames: None,
+        serial_match: None,
+        screenshot: None,
+        screenshot_scale: 1,
+        boot_rom: None,
+        skip_boot: false,
+        save_dir: None,
+        rom_dir: None,
+        palette: None,
+        palette_file: None,
+        camera_image: None,
+        clock_offset: None,
+        key_config: None,
+        debug: false,
+        trace: None,
+        sample_rate: apu::SAMPLE_RATE,
+        dump_wav: None,
+        wav_stems: false,
+        perf: false,
+        threaded: false,
+        dev_warnings: false,
+        history: 0,
+    };
+    for arg in &args[2..] {
+        match arg.as_str() {
+            _ if arg.starts_with("--rtc=") => options.rtc_mode = Some(parse_option_value(arg)),
+            _ if arg.starts_with("--rtc-games=") => options.rtc_games = Some(parse_option_value(arg)),
+            "--force-dmg" => options.force_dmg = true,
+            "--unrestricted-vram" => options.unrestricted_vram = true,
+            "--model=dmg" => options.model = Some(HardwareModel::Dmg),
+            "--model=mgb" => options.model = Some(HardwareModel::Mgb),
+            "--model=cgb" => options.model = Some(HardwareModel::Cgb),
+            "--model=agb" => options.model = Some(HardwareModel::Agb),
+            "--power-on=zeros" => options.power_on = PowerOnPattern::Zeros,
+            "--power-on=random" => options.power_on = PowerOnPattern::HardwareLike,
+            "--headless" => options.headless = true,
+            "--skip-boot" => options.skip_boot = true,
+            "--debug" => options.debug = true,
+            "--wav-stems" => options.wav_stems = true,
+            "--perf" => options.perf = true,
+            "--threaded" => options.threaded = true,
+            "--dev-warnings" => options.dev_warnings = true,
+            "--fullscreen" => options.fullscreen = true,
+            _ if arg.starts_with("--frames=") => options.frames = Some(parse_option_value(arg)),
+            _ if arg.starts_with("--until-serial=") => options.serial_match = Some(parse_option_value(arg)),
+            _ if arg.starts_with("--screenshot=") => options.screenshot = Some(parse_option_value(arg)),
+            _ if arg.starts_with("--boot-rom=") => options.boot_rom = Some(parse_option_value(arg)),
+            _ if arg.starts_with("--accuracy=") => options.accuracy = parse_option_value(arg),
+            _ if arg.starts_with("--save-dir=") => options.save_dir = Some(parse_option_value(arg)),
+            _ if arg.starts_with("--rom-dir=") => options.rom_dir = Some(parse_option_value(arg)),
+            _ if arg.starts_with("--blend=") => {
+                options.frame_blend = parse_option_value(arg);
+                if options.frame_blend > scanout::MAX_FRAME_BLEND {
+                    eprintln!("Error: invalid value in '{}'", arg);
+                    std::process::exit(1);
+                }
+            }
+            _ if arg.starts_with("--filter=") => options.filter = parse_option_value(arg),
+            _ if arg.starts_with("--palette=") => options.palette = Some(parse_option_value(arg)),
+            _ if arg.starts_with("--camera=") => options.camera_image = Some(parse_option_value(arg)),
+            _ if arg.starts_with("--palette-file=") => options.palette_file = Some(parse_option_value(arg)),
+            _ if arg.starts_with("--sample-rate=") => {
+                options.sample_rate = parse_option_value(arg);
+                if options.sample_rate == 0 {
+                    eprintln!("Error: invalid value in '{}'", arg);
+                    std::process::exit(1);
+                }
+            }
+            _ if arg.starts_with("--trace=") => options.trace = Some(parse_option_value(arg)),
+            _ if arg.starts_with("--dump-wav=") => options.dump_wav = Some(parse_option_value(arg)),
+            _ if arg.starts_with("--keys=") => options.key_config = Some(parse_option_value(arg)),
+            _ if arg.starts_with("--clock-offset=") => options.clock_offset = Some(parse_option_value(arg)),
+            _ if arg.starts_with("--link=") => options.link_rom = Some(parse_option_value(arg)),
+            _ if arg.starts_with("--link-listen=") => options.net_link = Some(NetLinkTarget::Listen(parse_option_value(arg))),
+            _ if arg.starts_with("--link-connect=") => options.net_link = Some(NetLinkTarget::Connect(parse_option_value(arg))),
+            _ if arg.starts_with("--netplay-host=") => options.netplay = Some(NetplayTarget::Host(parse_option_value(arg))),
+            _ if arg.starts_with("--netplay-join=") => options.netplay = Some(NetplayTarget::Join(parse_option_value(arg))),
+            _ if arg.starts_with("--netplay-delay=") => {
+                options.netplay_delay = parse_option_value(arg);
+                if options.netplay_delay > netplay::MAX_DELAY {
+                    eprintln!("Error: invalid value in '{}'", arg);
+                    std::process::exit(1);
+                }
+            }
+            _ if arg.starts_with("--netplay-rollback=") => {
+                options.netplay_rollback = parse_option_value(arg);
+                if options.netplay_rollback > netplay::MAX_ROLLBACK {
+                    eprintln!("Error: invalid value in '{}'", arg);
+                    std::process::exit(1);
+                }
+            }
+            _ if arg.starts_with("--break=") => options.breakpoints.push(parse_option_value(arg)),
+            _ if arg.starts_with("--save-format=") => options.save_format = parse_option_value(arg),
+            _ if arg.starts_with("--debug-port=") => options.debug_port = Some(parse_option_value(arg)),
+            _ if arg.starts_with("--remote=") => options.remote_port = Some(parse_option_value(arg)),
+            _ if arg.starts_with("--ff-audio=") => options.fast_forward_audio = parse_option_value(arg),
+            _ if arg.starts_with("--ff-speed=") => options.fast_forward_speed = parse_option_value(arg),
+            _ if arg.starts_with("--rewind=") => options.rewind_seconds = parse_option_value(arg),
+            _ if arg.starts_with("--cheats=") => options.cheats = Some(parse_option_value(arg)),
+            _ if arg.starts_with("--record-movie=") => options.movie = Some(MovieMode::Record(parse_option_value(arg))),
+            _ if arg.starts_with("--play-movie=") => options.movie = Some(MovieMode::Play(parse_option_value(arg))),
+            _ if arg.starts_with("--script=") => options.script = Some(parse_option_value(arg)),
+            _ if arg.starts_with("--save-backups=") => options.save_backups = parse_option_value(arg),
+            _ if arg.starts_with("--seed=") => options.seed = Some(parse_option_value(arg)),
+            _ if arg.starts_with("--scale=") => {
+                options.scale = parse_option_value(arg);
+                if !(display::MIN_SCALE..=display::MAX_SCALE).contains(&options.scale) {
+                    eprintln!("Error: invalid value in '{}'", arg);
+                    std::process::exit(1);
+                }
+            }
+            _ if arg.starts_with("--screenshot-scale=") => {
+                options.screenshot_scale = parse_option_value(arg);
+                if !(1..=display::MAX_SCALE).contains(&options.screenshot_scale) {
+                    eprintln!("Error: invalid value in '{}'", arg);
+                    std::process::exit(1);
+                }
+            }
+            _ if arg.starts_with("--history=") => options.history = parse_option_value(arg),
+            _ if arg.starts_with("--hang-frames=") => options.hang_frames = parse_option_value(arg),
+            _ => {
+                eprintln!("Error: unknown option '{}'", arg);
+                std::process::exit(1);
+            }
+        }
+    }
+    if options.netplay.is_some() && (options.headless || options.link_rom.is_some() || options.movie.is_some()) {
+        eprintln!("Error: netplay can't be combined with --headless, --link or movies");
+        std::process::exit(1);
+    }
+    if options.script.is_some() && (options.netplay.is_some() || options.link_rom.is_some()) {
+        eprintln!("Error: --script can't be combined with netplay or --link");
+        std::process::exit(1);
+    }
+    let needs_machine_here = options.headless || options.link_rom.is_some() || options.netplay.is_some()
+        || options.script.is_some() || options.remote_port.is_some() || options.debug;
+    if options.threaded && needs_machine_here {
+        eprintln!("Error: --threaded can't be combined with --headless, --link, netplay, --script, --remote or --debug");
+        std::process::exit(1);
+    }
     
-    if !Path::new(rom_path).exists() {
-        eprintln!("Error: ROM file '{}' not found", rom_path);
+    match run_rom(Path::new(rom_path), &options) {
+        Ok(0) => {}
+        Ok(status) => std::process::exit(status),
+        Err(e) => {
+            eprintln!("\nError: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Runs the built-in component checks, optionally against test vectors
+fn run_selftest(args: &[String]) {
+    let mut vectors = None;
+    for arg in args {
+        match arg.as_str() {
+            _ if arg.starts_with("--vectors=") => vectors = Some(parse_option_value::<PathBuf>(arg)),
+            _ => {
+                eprintln!("Error: unknown option '{}'", arg);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let checks = selftest::run(vectors.as_deref());
+    for check in &checks {
+        println!("{:<10} {:<5} {}", check.component, if check.passed { "ok" } else { "FAIL" }, check.detail);
+    }
+    if !checks.iter().all(|check| check.passed) {
         std::process::exit(1);
     }
+}
 
-    match fs::read(rom_path) {
-        Ok(rom_data) => {
-            println!("Loading ROM: {}", rom_path);
-            println!("ROM size: {} bytes ({} KB)", rom_data.len(), rom_data.len() / 1024);
-            run_rom(rom_data);
+// Runs every ROM in a directory headlessly and writes a CSV compatibility report
+fn run_sweep(args: &[String]) {
+    let Some(dir) = args.first() else {
+        eprintln!("Error: sweep needs a ROM directory");
+        std::process::exit(1);
+    };
+    let mut frames = 600;
+    let mut threads = std::thread::available_parallelism().map_or(1, |count| count.get());
+    let mut out = None;
+    for arg in &args[1..] {
+        match arg.as_str() {
+            _ if arg.starts_with("--frames=") => frames = parse_option_value(arg),
+            _ if arg.starts_with("--threads=") => threads = parse_option_value(arg),
+            _ if arg.starts_with("--out=") => out = Some(parse_option_value::<String>(arg)),
+            _ => {
+                eprintln!("Error: unknown option '{}'", arg);
+                std::process::exit(1);
+            }
         }
+    }
+
+    let roms = match sweep::find_roms(Path::new(dir)) {
+        Ok(roms) => roms,
         Err(e) => {
-            eprintln!("Error reading ROM file: {}", e);
+            eprintln!("Error: failed to read '{}': {}", dir, e);
             std::process::exit(1);
         }
+    };
+    eprintln!("Running {} ROMs for {} frames on {} threads...", roms.len(), frames, threads);
+    // Panics are reported in the CSV, not on stderr from every worker
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let results = sweep::run(&roms, frames, threads);
+    panic::set_hook(default_hook);
+
+    let csv = sweep::to_csv(&results);
+    match out {
+        Some(path) => {
+            if let Err(e) = fs::write(&path, csv) {
+                eprintln!("Error: failed to write '{}': {}", path, e);
+                std::process::exit(1);
+            }
+        }
+        None => print!("{}", csv),
     }
+    let ok = results.iter().filter(|result| result.outcome == sweep::Outcome::Ok).count();
+    eprintln!("{}/{} ROMs ran without crashing or hanging", ok, results.len());
 }
 
-fn run_rom(rom_data: Vec<u8>) {
-    println!("\n=== ROM Info ===");
-    if rom_data.len() > 0x150 {
-        let title_bytes = &rom_data[0x134..0x144];
-        let title = String::from_utf8_lossy(title_bytes)
-            .trim_end_matches('\0')
-            .to_string();
-        println!("Title: {}", title);
-        println!("Cartridge type: 0x{:02X}", rom_data[0x147]);
-        
-        let cart_type_name = match rom_data[0x147] {
-            0x00 => "ROM ONLY",
-            0x01 => "MBC1",
-            0x02 => "MBC1+RAM",
-            0x03 => "MBC1+RAM+BATTERY",
-            0x0F => "MBC3+TIMER+BATTERY",
-            0x10 => "MBC3+TIMER+RAM+BATTERY",
-            0x11 => "MBC3",
-            0x12 => "MBC3+RAM",
-            0x13 => "MBC3+RAM+BATTERY",
-            _ => "Unknown",
-        };
-        println!("Type: {}", cart_type_name);
-        
-        let rom_size = 32 * (1 << rom_data[0x148]);
-        println!("ROM size: {} KB", rom_size);
-        println!("RAM size: 0x{:02X}", rom_data[0x149]);
+// Parses the value of a "--name=value" argument, exiting on malformed input
+fn parse_option_value<T: FromStr>(arg: &str) -> T {
+    let value = arg.split_once('=').map(|(_, value)| value).unwrap_or("");
+    match value.parse() {
+        Ok(value) => value,
+        Err(_) => {
+            eprintln!("Error: invalid value in '{}'", arg);
+            std::process::exit(1);
+        }
     }
-    
+}
+
+#[derive(Clone)]
+struct RunOptions {
+    // Over the one --rtc-games picks for the game, host clock without either
+    rtc_mode: Option<RtcMode>,
+    rtc_games: Option<PathBuf>,
+    hang_frames: u32,
+    force_dmg: bool,
+    // Lets the CPU reach VRAM and OAM whatever mode the PPU is in
+    unrestricted_vram: bool,
+    // Costly timing details to keep, --unrestricted-vram aside
+    accuracy: AccuracyConfig,
+    scale: u32,
+    filter: Filter,
+    fullscreen: bool,
+    // Percent of each frame left in the next, for LCD ghosting
+    frame_blend: u8,
+    // None picks the model the cartridge header asks for
+    model: Option<HardwareModel>,
+    power_on: PowerOnPattern,
+    seed: Option<u64>,
+    link_rom: Option<String>,
+    breakpoints: Vec<EventBreakpoint>,
+    save_format: SaveFormat,
+    // Previous .sav files kept as timestamped backups
+    save_backups: usize,
+    fast_forward_audio: FastForwardAudio,
+    fast_forward_speed: Speed,
+    // Seconds of play kept for rewinding, 0 for none
+    rewind_seconds: u32,
+    // Game Genie and GameShark codes to apply
+    cheats: Option<PathBuf>,
+    // Link cable to another instance over TCP
+    net_link: Option<NetLinkTarget>,
+    // Shared game with another instance over UDP, with the frames a press
+    // waits before it counts and how many frames may run on guesses
+    netplay: Option<NetplayTarget>,
+    netplay_delay: u32,
+    netplay_rollback: u32,
+    movie: Option<MovieMode>,
+    // Lua script run alongside the game
+    script: Option<PathBuf>,
+    debug_port: Option<DebugPortMode>,
+    // Local TCP port for the remote control server
+    remote_port: Option<u16>,
+    // Run without a window, for --frames frames or until the serial output
+    // matches, a hang or a breakpoint
+    headless: bool,
+    frames: Option<u32>,
+    serial_match: Option<String>,
+    // PNG of the last frame, written when a headless run ends
+    screenshot: Option<PathBuf>,
+    // Pixel size of saved screenshots, in both modes
+    screenshot_scale: u32,
+    boot_rom: Option<PathBuf>,
+    // Start at 0x0100 with the registers the boot ROM would leave
+    skip_boot: bool,
+    // Where .sav and .state files go instead of next to the ROM
+    save_dir: Option<PathBuf>,
+    // Where Ctrl+O looks for ROMs, the first ROM's directory when not given
+    rom_dir: Option<PathBuf>,
+    // A built-in palette or one from palette_file, gray when not given
+    palette: Option<String>,
+    palette_file: Option<PathBuf>,
+    // What the Game Boy Camera sees instead of its test pattern
+    camera_image: Option<PathBuf>,
+    // Shifts the cartridge clock away from the host time
+    clock_offset: Option<ClockOffset>,
+    // TOML file with key bindings and gamepad settings
+    key_config: Option<PathBuf>,
+    // Break into the debugger console as soon as the game starts
+    debug: bool,
+    // Instruction log, "-" for stdout
+    trace: Option<PathBuf>,
+    // Audio output rate asked of the sound device
+    sample_rate: u32,
+    // Records the sound to this file, and with wav_stems each channel to its own
+    dump_wav: Option<PathBuf>,
+    wav_stems: bool,
+    // Show speed and where the time goes, in the title or after a headless run
+    perf: bool,
+    // Emulate on a thread of its own while the window shows the frame before
+    threaded: bool,
+    // Report jumps into the wrong memory, invalid opcodes and stack mistakes
+    dev_warnings: bool,
+    // Instructions kept to show what ran before a lock-up or breakpoint
+    history: usize,
+}
+
+// Returns the process exit status, which headless runs use to report how they ended
+fn run_rom(rom_path: &Path, options: &RunOptions) -> Result<i32, EmulatorError> {
+    let rom_data = read_rom_file(rom_path)?;
+    println!("Loading ROM: {}", rom_path.display());
+    println!("ROM size: {} bytes ({} KB)", rom_data.len(), rom_data.len() / 1024);
+    if let Some(dir) = &options.save_dir {
+        fs::create_dir_all(dir).map_err(|source| EmulatorError::SaveWrite { path: dir.clone(), source })?;
+    }
+    let palettes = load_palettes(options)?;
+    let display_options = DisplayOptions {
+        scale: options.scale,
+        fast_forward_audio: options.fast_forward_audio,
+        fast_forward_speed: options.fast_forward_speed,
+        rewind_seconds: options.rewind_seconds,
+        palettes: palettes.clone(),
+        filter: options.filter,
+        fullscreen: options.fullscreen,
+        frame_blend: options.frame_blend,
+        paths: rom_paths(rom_path, options),
+        rom_dir: options.rom_dir.clone().unwrap_or_else(|| rom_dir(rom_path)),
+        screenshot_scale: options.screenshot_scale,
+        bindings: load_bindings(options.key_config.as_deref())?,
+        sample_rate: options.sample_rate,
+    };
+    if options.threaded {
+        run_threaded(rom_data, rom_path, options, &display_options)?;
+        println!("\nEmulation completed successfully!");
+        return Ok(0);
+    }
+    let mut gb = build_gameboy(&rom_data, options)?;
+    load_battery_save(&mut gb, rom_path, options)?;
+
+    match &options.link_rom {
+        Some(link_path) => {
+            let link_path = Path::new(link_path);
+            let link_data = read_rom_file(link_path)?;
+            let mut partner = build_gameboy(&link_data, options)?;
+            load_battery_save(&mut partner, link_path, options)?;
+
+            let mut session = LinkSession::new(gb, partner);
+            let result = run_session_with_display(&mut session, &display_options);
+            let [first, second] = session.games_mut();
+            write_battery_save(first, rom_path, options)?;
+            write_battery_save(second, link_path, options)?;
+            result?;
+        }
+        None => {
+            let remote = options.remote_port
+                .map(|port| {
+                    let server = RemoteServer::bind(port).map_err(|source| EmulatorError::RemoteBind { port, source })?;
+                    println!("Remote control listening on 127.0.0.1:{}", port);
+                    Ok::<_, EmulatorError>(server)
+                })
+                .transpose()?;
+            attach_outputs(&mut gb, options)?;
+            if options.debug {
+                println!("Debugger console on stdin, type help for commands");
+                gb.debugger().step_into();
+            }
+            let mut script = options.script.as_deref().map(|path| Script::load(path, &mut gb)).transpose()?;
+            if options.headless {
+                let status = run_headless(&mut gb, options, &palettes, remote, script.as_mut().map(|script| script as &mut dyn FrameDriver))?;
+                write_battery_save(&mut gb, rom_path, options)?;
+                write_movie(&mut gb, options)?;
+                return Ok(status);
+            }
+            let netplay = options.netplay.as_ref().map(|target| open_netplay(target, &mut gb, options)).transpose()?;
+            // The joining side played on the host's cartridge RAM, which
+            // mustn't replace its own save
+            let mut joined = matches!(options.netplay, Some(NetplayTarget::Join(_)));
+            let mut current_rom = rom_path.to_path_buf();
+            // The trace, WAV dump and link cable stay with the first game
+            // and end when it's closed
+            let mut open_rom = |gb: &mut GameBoy, path: &Path| -> Result<RomPaths, EmulatorError> {
+                let mut next = build_gameboy(&read_rom_file(path)?, options)?;
+                load_battery_save(&mut next, path, options)?;
+                if !joined {
+                    write_battery_save(gb, &current_rom, options)?;
+                }
+                *gb = next;
+                joined = false;
+                current_rom = path.to_path_buf();
+                Ok(rom_paths(path, options))
+            };
+            let result = run_with_display(&mut gb, &display_options, remote, netplay, script, &mut open_rom);
+            if !joined {
+                write_battery_save(&mut gb, &current_rom, options)?;
+            }
+            write_movie(&mut gb, options)?;
+            result?;
+        }
+    }
+
+    println!("\nEmulation completed successfully!");
+    Ok(0)
+}
+
+// Movies, the network link, the trace log and the WAV recording
+fn attach_outputs(gb: &mut GameBoy, options: &RunOptions) -> Result<(), EmulatorError> {
+    match &options.movie {
+        Some(MovieMode::Play(path)) => gb.start_playback(read_movie(path)?)?,
+        Some(MovieMode::Record(_)) => gb.start_recording(),
+        None => {}
+    }
+    if let Some(target) = &options.net_link {
+        gb.set_link(Some(open_net_link(target)?));
+    }
+    if let Some(path) = &options.trace {
+        gb.set_tracer(Some(open_trace(path)?));
+    }
+    if let Some(path) = &options.dump_wav {
+        let dump = AudioDump::create(path, options.wav_stems)
+            .map_err(|(path, source)| EmulatorError::WavCreate { path, source })?;
+        gb.set_audio_dump(Some(dump));
+    }
+    Ok(())
+}
+
+// A GameBoy can't move between threads, so the core thread sets up its own
+// and writes its save and movie before the thread ends
+fn run_threaded(rom_data: Vec<u8>, rom_path: &Path, options: &RunOptions, display_options: &DisplayOptions) -> Result<(), EmulatorError> {
+    let (rom_path, options) = (rom_path.to_path_buf(), options.clone());
+    let (setup_path, setup_options) = (rom_path.clone(), options.clone());
+    let mut core = CoreThread::spawn(move || {
+        let mut gb = build_gameboy(&rom_data, &setup_options)?;
+        load_battery_save(&mut gb, &setup_path, &setup_options)?;
+        attach_outputs(&mut gb, &setup_options)?;
+        Ok(gb)
+    })?;
+    let result = run_threaded_with_display(&mut core, display_options);
+    core.with(move |gb| {
+        write_battery_save(gb, &rom_path, &options)?;
+        write_movie(gb, &options)
+    })??;
+    result
+}
+
+fn run_headless(
+    gb: &mut GameBoy,
+    options: &RunOptions,
+    palettes: &PaletteList,
+    mut remote: Option<RemoteServer>,
+    script: Option<&mut dyn FrameDriver>,
+) -> Result<i32, EmulatorError> {
+    let headless_options = headless::HeadlessOptions {
+        frames: options.frames,
+        serial_match: options.serial_match.clone(),
+    };
+    gb.set_sample_rate(options.sample_rate);
+    let result = headless::run(gb, &headless_options, remote.as_mut(), script);
+    if let Some(error) = &result.driver_error {
+        eprintln!("\nError: {}", error);
+    }
+    let frame = gb.frame();
+    println!("\nHeadless run stopped ({:?}) after {} frames, {} cycles, PC: 0x{:04X}",
+             result.reason, result.frames, frame.cycles, gb.registers().pc);
+    if let Some(stats) = gb.perf_stats() {
+        println!("Performance: {}", stats.summary());
+    }
+
+    if let Some(path) = &options.screenshot {
+        let image = gb.screenshot(options.screenshot_scale, palettes.current().colors.as_ref());
+        fs::write(path, image).map_err(|source| EmulatorError::ScreenshotWrite { path: path.clone(), source })?;
+    }
+    Ok(result.reason.exit_code())
+}
+
+fn build_gameboy(rom_data: &[u8], options: &RunOptions) -> Result<GameBoy, EmulatorError> {
     let mut gb = GameBoy::new();
-    gb.load_rom(&rom_data);
+    if let Some(path) = &options.boot_rom {
+        let boot_rom = fs::read(path).map_err(|source| EmulatorError::BootRomRead { path: path.clone(), source })?;
+        gb.set_boot_rom(&boot_rom)?;
+    }
+    let header = CartridgeHeader::parse(rom_data);
+    let model = options.model
+        .or_else(|| header.as_ref().map(CartridgeHeader::preferred_model))
+        .unwrap_or(HardwareModel::Dmg);
+    gb.set_model(model);
+    gb.set_force_dmg_compat(options.force_dmg);
+    gb.set_skip_boot(options.skip_boot);
+    gb.set_profiling(options.perf);
+    let mut accuracy = options.accuracy;
+    accuracy.vram_blocking &= !options.unrestricted_vram;
+    gb.set_accuracy(accuracy);
+    gb.load_rom(rom_data)?;
+    if let Some(path) = &options.cheats {
+        for cheat in load_cheats(path)? {
+            gb.add_cheat(cheat);
+        }
+    }
+    let game_mode = match (&options.rtc_games, &header) {
+        (Some(path), Some(header)) => load_rtc_games(path)?.mode_for(&header.title),
+        _ => None,
+    };
+    gb.set_rtc_mode(options.rtc_mode.or(game_mode).unwrap_or(RtcMode::HostClock));
+    if let Some(ClockOffset(offset)) = options.clock_offset {
+        gb.set_clock_source(Box::new(OffsetClock { offset }));
+    }
+    if let Some(path) = &options.camera_image {
+        let image = fs::read(path)
+            .map_err(|e| e.to_string())
+            .and_then(|data| png::decode(&data))
+            .map_err(|message| EmulatorError::CameraImage { path: path.clone(), message })?;
+        gb.set_camera_source(Box::new(StillImage::new(&image)));
+    }
+    gb.set_hang_threshold(options.hang_frames);
+    gb.set_dev_warnings(options.dev_warnings);
+    gb.set_history_length(options.history);
+    gb.set_debug_port(options.debug_port);
+    for &breakpoint in &options.breakpoints {
+        gb.add_breakpoint(breakpoint);
+    }
+    gb.set_power_on_pattern(options.power_on, options.seed.unwrap_or_else(host_seed));
     gb.reset();
 
-    match run_with_display(gb) {
-        Ok(_) => println!("\nEmulation completed successfully!"),
-        Err(e) => eprintln!("\nError: {}", e),
+    println!("\n=== ROM Info ===");
+    if let Some(header) = header {
+        println!("Title: {}", header.title);
+        println!("Cartridge type: 0x{:02X}", header.cartridge_type);
+        println!("Type: {}", header.cartridge_type_name());
+        println!("ROM size: {} KB", header.rom_size_kb);
+        println!("RAM size: 0x{:02X}", header.ram_size_code);
+        println!("Mode: {}", gb.compat_decision().describe());
+    }
+
+    Ok(gb)
+}
+
+// Input movie to record or play back
+#[derive(Clone)]
+enum MovieMode {
+    Record(PathBuf),
+    Play(PathBuf),
+}
+
+fn read_movie(path: &Path) -> Result<Movie, EmulatorError> {
+    let data = fs::read(path).map_err(|source| EmulatorError::MovieRead { path: path.to_path_buf(), source })?;
+    Movie::decode(&data).map_err(|message| EmulatorError::Movie { path: path.to_path_buf(), message })
+}
+
+fn write_movie(gb: &mut GameBoy, options: &RunOptions) -> Result<(), EmulatorError> {
+    let (Some(MovieMode::Record(path)), Some(movie)) = (&options.movie, gb.stop_movie()) else {
+        return Ok(());
+    };
+    fs::write(path, movie.encode()).map_err(|source| EmulatorError::MovieWrite { path: path.clone(), source })?;
+    println!("Recorded {} frames to {}", movie.inputs.len(), path.display());
+    Ok(())
+}
+
+#[derive(Clone)]
+enum NetLinkTarget {
+    Listen(u16),
+    Connect(String),
+}
+
+fn open_net_link(target: &NetLinkTarget) -> Result<NetLink, EmulatorError> {
+    let (addr, link) = match target {
+        NetLinkTarget::Listen(port) => {
+            println!("Waiting for the link partner on port {}...", port);
+            (format!("port {}", port), NetLink::listen(*port))
+        }
+        NetLinkTarget::Connect(addr) => (addr.clone(), NetLink::connect(addr.as_str())),
+    };
+    let link = link.map_err(|source| EmulatorError::LinkConnect { addr, source })?;
+    println!("Link cable connected to {}", link.peer().unwrap_or_default());
+    Ok(link)
+}
+
+#[derive(Clone)]
+enum NetplayTarget {
+    Host(u16),
+    Join(String),
+}
+
+fn open_netplay(target: &NetplayTarget, gb: &mut GameBoy, options: &RunOptions) -> Result<Netplay, EmulatorError> {
+    let (delay, rollback) = (options.netplay_delay, options.netplay_rollback);
+    let (addr, netplay) = match target {
+        NetplayTarget::Host(port) => {
+            println!("Waiting for the other player on UDP port {}...", port);
+            (format!("port {}", port), Netplay::host(*port, gb, delay, rollback))
+        }
+        NetplayTarget::Join(addr) => (addr.clone(), Netplay::join(addr.as_str(), gb, delay, rollback)),
+    };
+    let netplay = netplay.map_err(|source| EmulatorError::NetplayConnect { addr, source })?;
+    println!("Netplay with {}, {} frames of input delay", netplay.peer().unwrap_or_default(), delay);
+    Ok(netplay)
+}
+
+fn open_trace(path: &Path) -> Result<Tracer, EmulatorError> {
+    if path == Path::new("-") {
+        return Ok(Tracer::new(Box::new(io::BufWriter::new(io::stdout()))));
+    }
+    let file = fs::File::create(path).map_err(|source| EmulatorError::TraceCreate { path: path.to_path_buf(), source })?;
+    Ok(Tracer::new(Box::new(io::BufWriter::new(file))))
+}
+
+fn load_cheats(path: &Path) -> Result<Vec<Cheat>, EmulatorError> {
+    let text = fs::read_to_string(path).map_err(|source| EmulatorError::CheatRead { path: path.to_path_buf(), source })?;
+    cheats::parse_file(&text).map_err(|message| EmulatorError::CheatFile { path: path.to_path_buf(), message })
+}
+
+fn load_rtc_games(path: &Path) -> Result<GameRtcModes, EmulatorError> {
+    let text = fs::read_to_string(path).map_err(|source| EmulatorError::ConfigRead { path: path.to_path_buf(), source })?;
+    GameRtcModes::parse(&text).map_err(|message| EmulatorError::Config { path: path.to_path_buf(), message })
+}
+
+fn load_bindings(path: Option<&Path>) -> Result<Bindings, EmulatorError> {
+    let Some(path) = path else {
+        return Ok(Bindings::default());
+    };
+    let text = fs::read_to_string(path).map_err(|source| EmulatorError::ConfigRead { path: path.to_path_buf(), source })?;
+    Bindings::parse(&text).map_err(|message| EmulatorError::Config { path: path.to_path_buf(), message })
+}
+
+fn load_palettes(options: &RunOptions) -> Result<PaletteList, EmulatorError> {
+    let mut palettes = PaletteList::default();
+    if let Some(path) = &options.palette_file {
+        let text = fs::read_to_string(path).map_err(|source| EmulatorError::ConfigRead { path: path.clone(), source })?;
+        palettes.add_file(&text).map_err(|message| EmulatorError::Config { path: path.clone(), message })?;
+    }
+    if let Some(name) = &options.palette {
+        palettes.select(name).map_err(EmulatorError::Palette)?;
+    }
+    Ok(palettes)
+}
+
+// Save files are named after the ROM and live next to it, or in --save-dir
+fn save_path(rom_path: &Path, options: &RunOptions, extension: &str) -> PathBuf {
+    // game.gb.gz saves as game.sav, like game.gb would
+    let rom_path = match rom_path.extension() {
+        Some(ext) if ext.eq_ignore_ascii_case("gz") => rom_path.with_extension(""),
+        _ => rom_path.to_path_buf(),
+    };
+    let path = rom_path.with_extension(extension);
+    match (&options.save_dir, path.file_name()) {
+        (Some(dir), Some(name)) => dir.join(name),
+        _ => path,
+    }
+}
+
+fn rom_paths(rom_path: &Path, options: &RunOptions) -> RomPaths {
+    RomPaths {
+        state: save_path(rom_path, options, "state"),
+        screenshot_base: save_path(rom_path, options, "png").with_extension(""),
+    }
+}
+
+// "game.gb" is in the current directory
+fn rom_dir(rom_path: &Path) -> PathBuf {
+    match rom_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+// Battery saves are <rom>.sav
+fn load_battery_save(gb: &mut GameBoy, rom_path: &Path, options: &RunOptions) -> Result<(), EmulatorError> {
+    let path = save_path(rom_path, options, "sav");
+    if !gb.has_battery() || !path.exists() {
+        return Ok(());
     }
+    let data = fs::read(&path).map_err(|source| EmulatorError::SaveRead { path: path.clone(), source })?;
+    gb.load_battery_save(&data);
+    println!("Loaded save file: {}", path.display());
+    Ok(())
+}
+
+fn write_battery_save(gb: &mut GameBoy, rom_path: &Path, options: &RunOptions) -> Result<(), EmulatorError> {
+    let Some(data) = gb.battery_save(options.save_format) else {
+        return Ok(());
+    };
+    let path = save_path(rom_path, options, "sav");
+    savefile::write_with_backups(&path, &data, options.save_backups).map_err(|source| EmulatorError::SaveWrite { path: path.clone(), source })?;
+    println!("Wrote save file: {}", path.display());
+    Ok(())
+}
+
+fn host_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
 }
 
 fn run_tests() {
@@ -106,6 +840,258 @@ fn run_tests() {
     
     println!("\nTest 6: Simple Program");
     test_simple_program();
+
+    println!("\nTest 7: Save State Round Trip");
+    test_savestate_round_trip();
+
+    println!("\nTest 8: Half-Carry Flags");
+    test_half_carry_flags();
+
+    println!("\nTest 9: APU Length Counter");
+    test_apu_length_counter();
+
+    println!("\nTest 10: HBlank Hook");
+    test_hblank_hook();
+
+    println!("\nTest 11: Battery Save Formats");
+    test_battery_save_formats();
+
+    println!("\nTest 12: CGB Double Speed");
+    test_double_speed();
+
+    println!("\nTest 13: Save Backup Rotation");
+    test_save_backup_rotation();
+
+    println!("\nTest 14: CGB Color Palettes");
+    test_cgb_palettes();
+
+    println!("\nTest 15: Fast-Forward Audio");
+    test_fast_forward_audio();
+
+    println!("\nTest 16: Frame Metadata");
+    test_frame_metadata();
+
+    println!("\nTest 17: MBC Bank Wrapping");
+    test_mbc_banking();
+
+    println!("\nTest 18: Debug Port");
+    test_debug_port();
+
+    println!("\nTest 19: Scanout");
+    test_scanout();
+
+    println!("\nTest 20: Remote Control Commands");
+    test_remote_commands();
+
+    println!("\nTest 21: Scanline Scroll Table");
+    test_scroll_table();
+
+    println!("\nTest 22: Cartridge Save State");
+    test_cartridge_savestate();
+
+    println!("\nTest 23: Sprite Overflow Diagnostics");
+    test_sprite_overflow();
+
+    println!("\nTest 24: Custom Boot ROM");
+    test_custom_boot_rom();
+
+    println!("\nTest 25: RTC Clock Source");
+    test_rtc_clock_source();
+
+    println!("\nTest 26: PPU Fuzzing");
+    test_ppu_fuzz();
+
+    println!("\nTest 27: Joypad Register");
+    test_joypad_register();
+
+    println!("\nTest 28: Key Bindings");
+    test_key_bindings();
+
+    println!("\nTest 29: Input Latching");
+    test_input_latching();
+
+    println!("\nTest 30: Event Timestamps");
+    test_event_timestamps();
+
+    println!("\nTest 31: Frame Pacing");
+    test_frame_pacing();
+
+    println!("\nTest 32: VRAM DMA");
+    test_vram_dma();
+
+    println!("\nTest 33: Library API");
+    test_library_api();
+
+    println!("\nTest 34: Self Test");
+    test_selftest();
+
+    println!("\nTest 35: Headless Runs");
+    test_headless_runs();
+
+    println!("\nTest 36: Serial Transfers");
+    test_serial_transfers();
+
+    println!("\nTest 37: HALT With IME Clear");
+    test_halt_without_ime();
+
+    println!("\nTest 38: EI Delay");
+    test_ei_delay();
+
+    println!("\nTest 39: STAT Interrupts");
+    test_stat_interrupts();
+
+    println!("\nTest 40: Pixel FIFO");
+    test_pixel_fifo();
+
+    println!("\nTest 41: VRAM/OAM Access Restrictions");
+    test_vram_access_restrictions();
+
+    println!("\nTest 42: WRAM Banking");
+    test_wram_banking();
+
+    println!("\nTest 43: Boot ROM Mapping");
+    test_boot_rom_mapping();
+
+    println!("\nTest 44: Post-Boot Registers");
+    test_post_boot_registers();
+    
+    println!("\nTest 45: Debugger");
+    test_debugger();
+    
+    println!("\nTest 46: Disassembler");
+    test_disassembler();
+    
+    println!("\nTest 47: Trace Logging");
+    test_trace_logging();
+    
+    println!("\nTest 48: Memory Access Timing");
+    test_memory_access_timing();
+    
+    println!("\nTest 49: Timer Edges");
+    test_timer_edges();
+    
+    println!("\nTest 50: Stereo Mixing");
+    test_stereo_mixing();
+    
+    println!("\nTest 51: Channel Mute/Solo and WAV Export");
+    test_channel_mute_and_wav();
+    
+    println!("\nTest 52: Speed Control");
+    test_speed_control();
+    
+    println!("\nTest 53: Rewind");
+    test_rewind();
+    
+    println!("\nTest 54: Cheat Codes");
+    test_cheat_codes();
+    
+    println!("\nTest 55: Network Link Cable");
+    test_network_link();
+    
+    println!("\nTest 56: Input Movies");
+    test_input_movies();
+    
+    println!("\nTest 57: Screenshots");
+    test_screenshots();
+    
+    println!("\nTest 58: Reference Image Comparison");
+    test_image_comparison();
+    
+    println!("\nTest 59: VRAM Viewer");
+    test_vram_viewer();
+    
+    println!("\nTest 60: OAM Viewer");
+    test_oam_viewer();
+
+    println!("\nTest 61: Memory Viewer");
+    test_memory_viewer();
+
+    println!("\nTest 62: Interrupt Dispatch");
+    test_interrupt_dispatch();
+
+    println!("\nTest 63: STOP and Speed Switch");
+    test_stop_instruction();
+
+    println!("\nTest 64: I/O Register Map");
+    test_io_register_map();
+    
+    println!("\nTest 65: DMG Palettes");
+    test_dmg_palettes();
+    
+    println!("\nTest 66: Scaling and Filters");
+    test_scaling_filters();
+    
+    println!("\nTest 67: Frame Blending");
+    test_frame_blending();
+    
+    println!("\nTest 68: Compressed ROMs");
+    test_compressed_roms();
+    
+    println!("\nTest 69: HuC1 and MBC30");
+    test_huc1_mbc30();
+    
+    println!("\nTest 70: Pocket Camera");
+    test_pocket_camera();
+    
+    println!("\nTest 71: Rumble");
+    test_rumble();
+
+    println!("\nTest 72: Infrared Port");
+    test_infrared();
+
+    println!("\nTest 73: Determinism and State Hashes");
+    test_state_hash();
+
+    println!("\nTest 74: Netplay");
+    test_netplay();
+
+    println!("\nTest 75: Embedding Hooks");
+    test_hooks();
+
+    println!("\nTest 76: Lua Scripts");
+    test_scripts();
+
+    println!("\nTest 77: RAM Search");
+    test_ram_search();
+
+    println!("\nTest 78: Performance Counters");
+    test_perf_counters();
+
+    println!("\nTest 79: Core Thread");
+    test_core_thread();
+
+    println!("\nTest 80: Banked and Self-Modifying Code");
+    test_code_changes();
+
+    println!("\nTest 81: Developer Warnings");
+    test_dev_warnings();
+
+    println!("\nTest 82: Execution History");
+    test_execution_history();
+
+    println!("\nTest 83: ROM Load Errors");
+    test_load_errors();
+
+    println!("\nTest 84: ROM Browser");
+    test_rom_browser();
+
+    println!("\nTest 85: Accuracy Profiles");
+    test_accuracy_profiles();
+
+    println!("\nTest 86: Audio Channel Overlay");
+    test_channel_overlay();
+
+    println!("\nTest 87: RTC Halt and Day Carry");
+    test_rtc_halt_and_carry();
+
+    println!("\nTest 88: Hang Watchdog");
+    test_hang_watchdog();
+
+    println!("\nTest 89: HuC3 and Per-Game RTC Modes");
+    test_huc3_and_game_rtc_modes();
+
+    println!("\nTest 90: I/O Write Breakpoints");
+    test_io_write_breakpoints();
     
     println!("\n=== All tests passed! ===");
 }
@@ -121,7 +1107,7 @@ fn test_arithmetic() {
         0xD6, 0x03,
     ]);
     
-    cpu.bus.load_rom(&program);
+    cpu.bus.load_rom(&program).expect("plain ROM");
     
     cpu.step();
     assert_eq!(cpu.a, 10, "A should be 10");
@@ -145,7 +1131,7 @@ fn test_16bit_ops() {
         0x03,
     ]);
     
-    cpu.bus.load_rom(&program);
+    cpu.bus.load_rom(&program).expect("plain ROM");
     
     cpu.step();
     assert_eq!(cpu.get_bc(), 0x1234, "BC should be 0x1234");
@@ -168,7 +1154,7 @@ fn test_stack() {
         0xC1,
     ]);
     
-    cpu.bus.load_rom(&program);
+    cpu.bus.load_rom(&program).expect("plain ROM");
     
     cpu.step();
     cpu.step();
@@ -196,7 +1182,7 @@ fn test_jumps() {
         0x3E, 0x2A,
     ]);
     
-    cpu.bus.load_rom(&program);
+    cpu.bus.load_rom(&program).expect("plain ROM");
     
     cpu.step();
     assert_eq!(cpu.a, 0, "A should be 0");
@@ -222,7 +1208,7 @@ fn test_bit_ops() {
         0xCB, 0xBF,
     ]);
     
-    cpu.bus.load_rom(&program);
+    cpu.bus.load_rom(&program).expect("plain ROM");
     
     cpu.step();
     assert_eq!(cpu.a, 0xAA, "A should be 0xAA");
@@ -250,7 +1236,7 @@ fn test_simple_program() {
         0x76,
     ]);
     
-    cpu.bus.load_rom(&program);
+    cpu.bus.load_rom(&program).expect("plain ROM");
     
     let max_steps = 100;
     let mut steps = 0;
@@ -267,6 +1253,3504 @@ fn test_simple_program() {
     println!("    Executed in {} steps, {} cycles", steps, cpu.cycles);
 }
 
+fn test_savestate_round_trip() {
+    // Loops forever starting an OAM DMA and a serial transfer, so states get
+    // taken while both are in flight and the PPU is somewhere mid-scanline
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+    rom[0x150..0x15F].copy_from_slice(&[
+        0x3E, 0xC0,
+        0xE0, 0x46,
+        0x3E, 0x81,
+        0xE0, 0x02,
+        0x04,
+        0x78,
+        0xEA, 0x00, 0xC0,
+        0x18, 0xF1,
+    ]);
+
+    let mut original = GameBoy::new();
+    original.load_rom(&rom).expect("test ROM has a header");
+
+    let frames = 30;
+    for frame in 0..frames {
+        original.run_frame();
+        for _ in 0..(frame * 37) % 500 {
+            original.step();
+        }
+
+        let mut restored = GameBoy::new();
+        restored.load_rom(&rom).expect("test ROM has a header");
+        restored.load_state(&original.save_state()).expect("state should load");
+
+        for _ in 0..1000 {
+            original.step();
+            restored.step();
+        }
+        assert_eq!(
+            savestate::state_hash(&original.save_state()),
+            savestate::state_hash(&restored.save_state()),
+            "restored machine diverged after frame {}", frame
+        );
+    }
+
+    let state = original.save_state();
+    assert!(original.load_state(&state[..state.len() - 1]).is_err(), "truncated state should be rejected");
+    assert_eq!(state, original.save_state(), "failed load should leave the machine untouched");
+
+    println!("  [OK] Save states round-trip mid-DMA and mid-transfer");
+    println!("    {} frames compared, state size {} bytes", frames, state.len());
+}
+
+fn test_half_carry_flags() {
+    let mut cpu = cpu::CPU::new();
+
+    let mut program = vec![0; 0x100];
+    program.extend_from_slice(&[
+        0x3E, 0x0F,
+        0xC6, 0x01,
+        0xD6, 0x01,
+        0x31, 0xF8, 0xFF,
+        0xF8, 0x08,
+    ]);
+
+    cpu.bus.load_rom(&program).expect("plain ROM");
+
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.a, 0x10, "A should be 0x10");
+    assert!(cpu.is_half_carry_flag_set(), "ADD 0x0F + 0x01 should half-carry");
+
+    cpu.step();
+    assert_eq!(cpu.a, 0x0F, "A should be 0x0F");
+    assert!(cpu.is_half_carry_flag_set(), "SUB 0x10 - 0x01 should half-borrow");
+
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.get_hl(), 0x0000, "HL should be SP + 8");
+    assert!(cpu.is_half_carry_flag_set(), "LD HL, SP+n should half-carry");
+    assert!(cpu.is_carry_flag_set(), "LD HL, SP+n should carry");
+
+    println!("  [OK] Half-carry flags are computed from the operands");
+    println!("    LD HL, SP+8 = 0x{:04X}", cpu.get_hl());
+}
+
+fn test_apu_length_counter() {
+    let mut apu = apu::APU::new();
+
+    apu.write(0xFF26, 0x80);
+    apu.write(0xFF12, 0xF0);
+    apu.write(0xFF11, 0x3E);
+    apu.write(0xFF14, 0xC0);
+    assert_eq!(apu.read(0xFF26) & 0x01, 0x01, "Channel 1 should be on after trigger");
+
+    // Length 2 expires after two 256 Hz length clocks
+    apu.tick(8192 * 4);
+    assert_eq!(apu.read(0xFF26) & 0x01, 0x00, "Channel 1 should stop when its length runs out");
+    assert!(!apu.take_samples().is_empty(), "APU should produce samples");
+
+    apu.write(0xFF26, 0x00);
+    assert_eq!(apu.read(0xFF12), 0x00, "Powering off should clear the registers");
+
+    println!("  [OK] Length counter silences the channel");
+}
+
+fn test_hblank_hook() {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x102].copy_from_slice(&[0x18, 0xFE]);
+
+    let mut gb = GameBoy::new();
+    gb.load_rom(&rom).expect("test ROM has a header");
+
+    // Writes SCX = LY on every line, the classic wobble raster effect
+    let lines = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    let seen = lines.clone();
+    gb.set_hblank_hook(Some(Box::new(move |ly, bus| {
+        seen.borrow_mut().push(ly);
+        bus.write_byte(0xFF43, ly);
+    })));
+
+    gb.run_frame();
+    gb.run_frame();
+    let lines = lines.borrow();
+    assert!(lines.len() >= 144, "Hook should run once per visible line");
+    assert!(lines.iter().all(|&ly| ly < 144), "Hook should only run for visible lines");
+    assert_eq!(gb.cpu.bus.read_byte(0xFF43), 143, "Hook writes should reach the PPU");
+
+    println!("  [OK] HBlank hook runs for every visible line");
+    println!("    {} lines over two frames", lines.len());
+}
+
+fn test_battery_save_formats() {
+    // MBC3+TIMER+RAM+BATTERY with 32KB of cartridge RAM
+    let mut rom = vec![0; 0x8000];
+    rom[0x147] = 0x10;
+    rom[0x149] = 0x03;
+
+    let cartridge = || {
+        let mut gb = GameBoy::new();
+        gb.load_rom(&rom).expect("test ROM has a header");
+        gb.set_rtc_mode(RtcMode::CycleSynced);
+        gb
+    };
+
+    let mut gb = cartridge();
+    gb.cpu.bus.write_byte(0x0000, 0x0A);
+    gb.cpu.bus.write_byte(0xA000, 0x5A);
+    gb.cpu.bus.write_byte(0x4000, 0x08);
+    gb.cpu.bus.write_byte(0xA000, 42);
+
+    for (format, footer) in [(SaveFormat::Raw, 0), (SaveFormat::Vba, 44), (SaveFormat::Bgb, 48)] {
+        let data = gb.battery_save(format).expect("cartridge has a battery");
+        assert_eq!(data.len(), 0x8000 + footer, "{:?} footer size", format);
+
+        let mut loaded = cartridge();
+        loaded.load_battery_save(&data);
+        let resaved = loaded.battery_save(SaveFormat::SameBoy).unwrap();
+        assert_eq!(resaved[0], 0x5A, "RAM should survive a {:?} save", format);
+        let seconds = if footer == 0 { 0 } else { 42 };
+        assert_eq!(resaved[0x8000], seconds, "RTC seconds after a {:?} save", format);
+    }
+
+    // Saves cut short by other emulators are zero-filled
+    let mut loaded = cartridge();
+    loaded.load_battery_save(&[0x11; 0x2000]);
+    let resaved = loaded.battery_save(SaveFormat::Raw).unwrap();
+    assert_eq!((resaved[0x1FFF], resaved[0x2000]), (0x11, 0x00));
+
+    println!("  [OK] Raw, VBA and BGB saves load back with their RTC footer");
+}
+
+fn test_double_speed() {
+    // CGB-enhanced ROM: LD A,1 / LDH (KEY1),A / STOP / JR -2
+    let mut rom = vec![0; 0x8000];
+    rom[0x143] = 0x80;
+    rom[0x150..0x158].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00, 0x18, 0xFE]);
 
+    // Counts the instructions executed over one frame
+    let run = |switch: bool| {
+        let mut gb = GameBoy::new();
+        gb.set_model(HardwareModel::Cgb);
+        gb.load_rom(&rom).expect("test ROM has a header");
+        assert_eq!(gb.compat_decision(), cartridge::CompatDecision::Cgb);
+        gb.cpu.bus.memory.write_byte(0xFF50, 0x01);
+        gb.cpu.pc = if switch { 0x150 } else { 0x156 };
+        while gb.cpu.pc != 0x156 {
+            gb.step();
+        }
+        assert_eq!(gb.cpu.bus.read_byte(0xFF4D), if switch { 0xFE } else { 0x7E });
+
+        gb.run_frame();
+        let mut steps: u32 = 0;
+        while !gb.poll_frame() {
+            gb.step();
+            steps += 1;
+        }
+        steps
+    };
 
+    let normal = run(false);
+    let double = run(true);
+    assert!(double.abs_diff(normal * 2) <= 2, "Double speed should run twice the instructions per frame");
 
+    println!("  [OK] STOP with KEY1 armed doubles the CPU clock");
+    println!("    {} instructions per frame, {} in double speed", normal, double);
+}
+
+fn test_save_backup_rotation() {
+    let dir = std::env::temp_dir().join(format!("rust-gbc-backups-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("game.sav");
+
+    // Seed old backups so rotation has something to delete
+    for timestamp in [100, 200, 300] {
+        fs::write(dir.join(format!("game.sav.{}.bak", timestamp)), [0]).unwrap();
+    }
+    savefile::write_with_backups(&path, &[1], 2).unwrap();
+    savefile::write_with_backups(&path, &[1], 2).unwrap();
+    savefile::write_with_backups(&path, &[2], 2).unwrap();
+
+    let names = backup_names(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(names.len(), 3, "Two backups and the save itself should remain: {:?}", names);
+    assert!(!names.contains(&"game.sav.200.bak".to_string()), "Oldest backups should be rotated out");
+    assert!(names.contains(&"game.sav".to_string()));
+
+    println!("  [OK] Old saves are kept as rotating backups");
+
+    // Backups from a run with them on survive one with them off, and files
+    // that only look like backups are never touched
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("game.sav.100.bak"), [0]).unwrap();
+    fs::write(dir.join("game.sav.notes.bak"), [0]).unwrap();
+    savefile::write_with_backups(&path, &[1], 0).unwrap();
+    savefile::write_with_backups(&path, &[2], 0).unwrap();
+    let names = backup_names(&dir);
+    assert_eq!(names, ["game.sav", "game.sav.100.bak", "game.sav.notes.bak"], "--save-backups=0 deletes nothing");
+    fs::remove_file(dir.join("game.sav.100.bak")).unwrap();
+
+    // Quick saves all get a backup of their own
+    for value in 3..7 {
+        savefile::write_with_backups(&path, &[value], 10).unwrap();
+    }
+    let names = backup_names(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(names.len(), 6, "four backups, the save and the other file: {:?}", names);
+    println!("  [OK] No backups are made or deleted with 0 kept, and saves within a second don't share one");
+}
+
+fn backup_names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir).unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+fn test_cgb_palettes() {
+    let mut rom = vec![0; 0x8000];
+    rom[0x143] = 0x80;
+    rom[0x100..0x102].copy_from_slice(&[0x18, 0xFE]);
+
+    let mut gb = GameBoy::new();
+    gb.set_model(HardwareModel::Cgb);
+    gb.load_rom(&rom).expect("test ROM has a header");
+    let bus = &mut gb.cpu.bus;
+
+    // BG palette 1, color 0 = pure red, written through the auto-incrementing index
+    bus.write_byte(0xFF68, 0x80 | 0x08);
+    bus.write_byte(0xFF69, 0x1F);
+    bus.write_byte(0xFF69, 0x00);
+    assert_eq!(bus.read_byte(0xFF68), 0x80 | 0x40 | 0x0A, "BCPS should auto-increment");
+
+    // Point the whole BG map at palette 1 through the bank 1 attributes
+    bus.write_byte(0xFF4F, 0x01);
+    for addr in 0x9800..0x9C00 {
+        bus.write_byte(addr, 0x01);
+    }
+    bus.write_byte(0xFF4F, 0x00);
+    assert_eq!(bus.read_byte(0x9800), 0x00, "VBK should switch the CPU back to bank 0");
+
+    gb.run_frame();
+    gb.run_frame();
+    let pixel = &gb.cpu.bus.ppu.framebuffer[..3];
+    assert_eq!(pixel, [0xFF, 0x00, 0x00], "BG should be drawn from CGB palette RAM");
+
+    println!("  [OK] BG pixels take their color from CGB palette RAM");
+}
+
+fn test_fast_forward_audio() {
+    // Four frames' worth of a constant stereo tone, as produced at 4x speed
+    let samples: Vec<f32> = [0.5, -0.25].repeat(2940);
+
+    let resampled = audio_sink::fit_to_speed(&samples, 4, FastForwardAudio::Resample);
+    assert_eq!(resampled.len(), 735 * 2, "Resampling should leave one frame of audio");
+    assert!(resampled.chunks(2).all(|pair| (pair[0] - 0.5).abs() < 1e-6 && (pair[1] + 0.25).abs() < 1e-6),
+            "Resampling shouldn't change the level or swap sides");
+
+    let skipped = audio_sink::fit_to_speed(&samples, 4, FastForwardAudio::Skip);
+    assert_eq!(skipped.len(), 735 * 2, "Skipping should leave one frame of audio");
+    assert_eq!(&skipped[..2], [0.0, 0.0], "The kept stretch should fade in from silence");
+    assert_eq!(&skipped[367 * 2..368 * 2], [0.5, -0.25]);
+    assert!(skipped[734 * 2] < 0.05, "The kept stretch should fade out at the cut");
+
+    assert_eq!(audio_sink::fit_to_speed(&samples, 1, FastForwardAudio::Skip), samples);
+
+    println!("  [OK] Fast-forwarded audio is cut down to real-time length");
+}
+
+fn test_frame_metadata() {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x102].copy_from_slice(&[0x18, 0xFE]);
+
+    let mut gb = GameBoy::new();
+    gb.load_rom(&rom).expect("test ROM has a header");
+    gb.cpu.bus.memory.write_byte(0xFF50, 0x01);
+    gb.cpu.pc = 0x100;
+
+    let (first_index, first_cycles) = {
+        let frame = gb.run_frame().expect("no breakpoints are set");
+        (frame.index, frame.cycles)
+    };
+    let frame = gb.run_frame().expect("no breakpoints are set");
+    assert_eq!(frame.index, first_index + 1);
+    assert_eq!(frame.cycles - first_cycles, 70224, "A frame should take 70224 cycles");
+    assert!(!frame.dirty, "An unchanged screen should not be dirty");
+
+    // Darken the first row of tile 0, which the whole BG map points at
+    gb.cpu.bus.write_byte(0x8000, 0xFF);
+    assert!(gb.run_frame().unwrap().dirty, "A changed screen should be dirty");
+    assert!(!gb.run_frame().unwrap().dirty, "Dirty should clear once the screen settles");
+
+    println!("  [OK] Frames carry their index, timestamp and dirty flag");
+}
+
+fn test_mbc_banking() {
+    // 1MB MBC1 ROM with each bank's number in its first byte
+    let mut rom = vec![0; 0x10_0000];
+    for bank in 0..rom.len() / 0x4000 {
+        rom[bank * 0x4000] = bank as u8;
+    }
+    rom[0x147] = 0x01;
+    let mut memory = memory::Memory::new();
+    memory.load_rom(&rom).expect("supported cartridge");
+
+    memory.write_byte(0x2000, 0x01);
+    memory.write_byte(0x4000, 0x01);
+    assert_eq!(memory.read_byte(0x4000), 0x21, "Upper bank bits should apply in mode 0");
+
+    // A 64KB MBC3 only has 4 banks, so bank 5 wraps to bank 1
+    let mut rom = rom[..0x1_0000].to_vec();
+    rom[0x147] = 0x11;
+    memory.load_rom(&rom).expect("supported cartridge");
+    memory.write_byte(0x2000, 0x05);
+    assert_eq!(memory.read_byte(0x4000), 0x01, "Bank numbers should wrap to the ROM size");
+
+    // MBC5 has a ninth bank bit and, unlike the others, can map bank 0
+    let mut rom = vec![0; 0x80_0000];
+    for bank in 0..rom.len() / 0x4000 {
+        rom[bank * 0x4000] = bank as u8;
+        rom[bank * 0x4000 + 1] = (bank >> 8) as u8;
+    }
+    rom[0x147] = 0x19;
+    memory.load_rom(&rom).expect("supported cartridge");
+    memory.write_byte(0x2000, 0x23);
+    memory.write_byte(0x3000, 0x01);
+    assert_eq!((memory.read_byte(0x4000), memory.read_byte(0x4001)), (0x23, 0x01), "MBC5 bank 0x123");
+    memory.write_byte(0x2000, 0x00);
+    memory.write_byte(0x3000, 0x00);
+    assert_eq!((memory.read_byte(0x4000), memory.read_byte(0x4001)), (0x00, 0x00), "MBC5 bank 0");
+
+    // ROM+RAM cartridges have always-enabled RAM, a 2KB chip repeats across the window
+    rom[0x147] = 0x08;
+    rom[0x149] = 0x01;
+    memory.load_rom(&rom).expect("supported cartridge");
+    memory.write_byte(0xA000, 0x42);
+    assert_eq!(memory.read_byte(0xA800), 0x42, "2KB RAM should be mirrored");
+
+    println!("  [OK] ROM and RAM banks wrap to the cartridge size");
+}
+
+fn test_debug_port() {
+    let print = |gb: &mut GameBoy, text: &str, addr: u16| {
+        for byte in text.bytes() {
+            if addr == 0xFF01 {
+                gb.cpu.bus.write_byte(0xFF01, byte);
+                gb.cpu.bus.write_byte(0xFF02, 0x81);
+            } else {
+                gb.cpu.bus.write_byte(addr, byte);
+            }
+        }
+        gb.step();
+        gb.take_events().into_iter()
+            .filter_map(|timed| match timed.event {
+                events::EmulatorEvent::DebugLog(line) => Some(line),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut gb = GameBoy::new();
+    gb.set_debug_port(Some(DebugPortMode::Serial));
+    assert_eq!(print(&mut gb, "hello\r\nwor", 0xFF01), ["hello"]);
+    assert_eq!(print(&mut gb, "ld\n", 0xFF01), ["world"]);
+
+    // Writes to an address port still land, so watching RAM doesn't change the game
+    gb.set_debug_port(Some("addr:C000".parse().unwrap()));
+    assert_eq!(print(&mut gb, "\u{e9}t\u{e9}\n", 0xC000), ["\u{e9}t\u{e9}"]);
+    assert_eq!(gb.peek(0xC000), b'\n');
+
+    println!("  [OK] Debug port output arrives as UTF-8 lines");
+}
+
+fn test_scanout() {
+    use scanout::Scanout;
+
+    let mut gb = GameBoy::new();
+    let mut sink = scanout::NullScanout::new();
+    sink.set_palette(None);
+    sink.resize(2).unwrap();
+    for _ in 0..3 {
+        let frame = gb.run_frame().expect("no breakpoints are set");
+        sink.present(&frame).unwrap();
+    }
+    assert_eq!(sink.frames, 3);
+
+    // The four DMG shades land exactly on the palette colors
+    let mut palettes = PaletteList::default();
+    palettes.select("green").unwrap();
+    let green = palettes.current().colors.expect("green has colors");
+    let pixels = [0xFF, 0xFF, 0xFF, 0xAA, 0xAA, 0xAA, 0x55, 0x55, 0x55, 0x00, 0x00, 0x00];
+    assert_eq!(scanout::apply_palette(&pixels, &green), green.concat());
+
+    // The browser's RGBA buffer goes through the palette, filter and ghosting
+    use rust_gbc::gameboy::Frame;
+    use ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+    let white = vec![0xFF; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+    let black = vec![0x00; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+    let frame = |pixels, dirty| Frame { index: 0, cycles: 0, pixels, dirty };
+    let mut canvas = scanout::RgbaScanout::new();
+    canvas.set_palette(Some(green));
+    canvas.present(&frame(&white, true)).unwrap();
+    assert_eq!(canvas.size(), (SCREEN_WIDTH, SCREEN_HEIGHT));
+    assert_eq!(canvas.rgba()[..4], [green[0][0], green[0][1], green[0][2], 0xFF]);
+    // A new setting shows on the next frame even if the game drew nothing new
+    canvas.set_filter(Filter::Grid);
+    assert_eq!(canvas.size(), (SCREEN_WIDTH, SCREEN_HEIGHT), "the buffer keeps its size until then");
+    canvas.present(&frame(&white, false)).unwrap();
+    assert_eq!(canvas.size(), (SCREEN_WIDTH * 3, SCREEN_HEIGHT * 3));
+    assert_eq!(canvas.rgba().len(), SCREEN_WIDTH * 3 * SCREEN_HEIGHT * 3 * 4);
+    // The last column of each cell is the grid line
+    assert!(canvas.rgba()[8] < green[0][0] && canvas.rgba()[4] == green[0][0]);
+    canvas.set_filter(Filter::None);
+    canvas.set_frame_blend(50);
+    canvas.present(&frame(&white, false)).unwrap();
+    canvas.present(&frame(&black, true)).unwrap();
+    let lightest = green[0][1] as i32;
+    let darkest = green[3][1] as i32;
+    assert!((canvas.rgba()[1] as i32 - (lightest + darkest) / 2).abs() <= 1, "half of the last frame is left");
+
+    println!("  [OK] Frames reach a scanout, DMG shades map onto a palette, the RGBA buffer is colored, filtered and ghosted");
+}
+
+fn test_remote_commands() {
+    use joypad::Button;
+
+    let mut gb = GameBoy::new();
+    let mut held = 0;
+    let mut run = |line: &str| remote::execute(&mut gb, &mut held, line);
+
+    assert_eq!(run("write C000 12 0x34 ab"), Ok(String::new()));
+    assert_eq!(run("read 0xC000 3"), Ok("12 34 AB".to_string()));
+    assert_eq!(run("read C001"), Ok("34".to_string()));
+    assert!(run("read FFFF 2").is_err());
+    assert!(run("write C000 zz").is_err());
+
+    assert_eq!(run("press start"), Ok(String::new()));
+    assert_eq!(run("press A"), Ok(String::new()));
+    assert_eq!(run("release start"), Ok(String::new()));
+    assert!(run("press turbo").is_err());
+    assert!(run("jump 0100").is_err());
+    assert_eq!(run("frame"), Ok("0 0".to_string()));
+    assert_eq!(held, Button::A.mask());
+
+    println!("  [OK] Memory, button and frame commands reply as expected");
+}
+
+fn test_scroll_table() {
+    let mut gb = GameBoy::new();
+    gb.cpu.bus.write_byte(0xFF42, 0x40);
+    // Shift each following line further right during HBlank, as a parallax effect would
+    gb.set_hblank_hook(Some(Box::new(|ly, bus| bus.write_byte(0xFF43, ly.wrapping_mul(2)))));
+    gb.run_frame().expect("no breakpoints are set");
+    gb.run_frame().expect("no breakpoints are set");
+
+    let table = gb.cpu.bus.ppu.scroll_table();
+    for (line, latch) in table.iter().enumerate().skip(1) {
+        assert_eq!(latch.scx, ((line - 1) * 2) as u8, "line {}", line);
+        assert_eq!(latch.scy, 0x40);
+    }
+    // Line 0 still has the write made after the last line of the previous frame
+    assert_eq!(table[0].scx, 143u8.wrapping_mul(2));
+
+    println!("  [OK] Each line records the scroll values it was drawn with");
+}
+
+fn test_cartridge_savestate() {
+    // 64KB MBC3+RAM+RTC cartridge with each bank's number in its first byte
+    let mut rom = vec![0; 0x1_0000];
+    for bank in 0..rom.len() / 0x4000 {
+        rom[bank * 0x4000] = bank as u8;
+    }
+    rom[0x147] = 0x10;
+    rom[0x149] = 0x03;
+    let mut gb = GameBoy::new();
+    gb.load_rom(&rom).expect("test ROM has a header");
+    gb.set_rtc_mode(RtcMode::CycleSynced);
+
+    let bus = &mut gb.cpu.bus;
+    bus.write_byte(0x0000, 0x0A);
+    bus.write_byte(0x2000, 0x03);
+    bus.write_byte(0x4000, 0x02);
+    bus.write_byte(0xA123, 0x5A);
+    bus.write_byte(0x4000, 0x0A);
+    bus.write_byte(0xA000, 0x17);
+    let state = gb.save_state();
+
+    // Change the banks, RAM and clock, then go back
+    let bus = &mut gb.cpu.bus;
+    bus.write_byte(0xA000, 0x00);
+    bus.write_byte(0x4000, 0x02);
+    bus.write_byte(0xA123, 0x00);
+    bus.write_byte(0x2000, 0x01);
+    bus.write_byte(0x0000, 0x00);
+    gb.load_state(&state).expect("state should load");
+
+    let bus = &mut gb.cpu.bus;
+    assert_eq!(bus.read_byte(0x4000), 0x03, "ROM bank should be restored");
+    bus.write_byte(0x6000, 0x00);
+    bus.write_byte(0x6000, 0x01);
+    assert_eq!(bus.read_byte(0xA000), 0x17, "Selected RTC register should be restored");
+    bus.write_byte(0x4000, 0x02);
+    assert_eq!(bus.read_byte(0xA123), 0x5A, "Cartridge RAM should be restored");
+
+    println!("  [OK] Bank registers, cartridge RAM and the clock survive a state load");
+}
+
+fn test_sprite_overflow() {
+    use ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    let mut gb = GameBoy::new();
+    // Objects on, 12 objects covering lines 40-47 and 3 more on lines 80-87
+    gb.cpu.bus.write_byte(0xFF40, 0x93);
+    for i in 0..15 {
+        let y = if i < 12 { 40 + 16 } else { 80 + 16 };
+        gb.cpu.bus.ppu.oam[i * 4] = y;
+        gb.cpu.bus.ppu.oam[i * 4 + 1] = 8 + i as u8 * 8;
+    }
+    gb.run_frame().expect("no breakpoints are set");
+    gb.run_frame().expect("no breakpoints are set");
+
+    let overflow = gb.cpu.bus.ppu.sprite_overflow();
+    assert_eq!(overflow.len(), 8, "only lines 40-47 have too many objects");
+    assert!(overflow.iter().all(|o| o.requested == 12 && o.dropped == [10, 11]));
+    assert_eq!(overflow.first().map(|o| o.line), Some(40));
+
+    let mut pixels = vec![0xFF; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+    scanout::highlight_lines(&mut pixels, overflow.iter().map(|o| o.line as usize), [0xFF, 0x00, 0x00]);
+    assert_eq!(pixels[40 * SCREEN_WIDTH * 3..][..3], [0xFF, 0x7F, 0x7F]);
+    assert_eq!(pixels[39 * SCREEN_WIDTH * 3..][..3], [0xFF, 0xFF, 0xFF]);
+
+    println!("  [OK] Lines with more than 10 objects report the dropped OAM entries");
+}
+
+fn test_custom_boot_rom() {
+    let mut gb = GameBoy::new();
+    assert!(gb.set_boot_rom(&[0; 0x80]).is_err(), "only 256 and 2304 byte boot ROMs fit");
+
+    // LD A,1; LDH (0x50),A unmaps itself right away
+    let mut boot_rom = [0; 0x100];
+    boot_rom[..4].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+    gb.set_boot_rom(&boot_rom).unwrap();
+    let mut rom = vec![0; 0x8000];
+    rom[0] = 0xAB;
+    gb.load_rom(&rom).expect("test ROM has a header");
+    gb.reset();
+
+    assert_eq!(gb.cpu.bus.read_byte(0x0000), 0x3E);
+    gb.step();
+    gb.step();
+    assert_eq!(gb.cpu.pc, 0x0004);
+    assert_eq!(gb.cpu.bus.read_byte(0x0000), 0xAB, "cartridge should show once the boot ROM unmaps");
+
+    println!("  [OK] A boot ROM file replaces the built-in one");
+}
+
+fn test_rtc_clock_source() {
+    use rtc::{ClockSource, ManualClock};
+
+    // MBC3+TIMER+BATTERY
+    let mut rom = vec![0; 0x8000];
+    rom[0x147] = 0x0F;
+    let mut gb = GameBoy::new();
+    gb.load_rom(&rom).expect("test ROM has a header");
+    let clock = ManualClock::new(1_000_000);
+    gb.set_clock_source(Box::new(clock.clone()));
+
+    // Three days, two hours and five seconds pass without running a frame
+    clock.advance(3 * 86400 + 2 * 3600 + 5);
+    let bus = &mut gb.cpu.bus;
+    bus.write_byte(0x0000, 0x0A);
+    bus.write_byte(0x6000, 0x00);
+    bus.write_byte(0x6000, 0x01);
+    let mut read = |register: u8| {
+        bus.write_byte(0x4000, register);
+        bus.read_byte(0xA000)
+    };
+    assert_eq!([read(0x08), read(0x0A), read(0x0B)], [5, 2, 3]);
+
+    // A battery save taken now and loaded a day later catches up by a day
+    let save = gb.battery_save(SaveFormat::Bgb).expect("cartridge has a battery");
+    let mut later = GameBoy::new();
+    later.load_rom(&rom).expect("test ROM has a header");
+    let later_clock = ManualClock::new(clock.now() + 86400);
+    later.set_clock_source(Box::new(later_clock));
+    later.load_battery_save(&save);
+    let bus = &mut later.cpu.bus;
+    bus.write_byte(0x0000, 0x0A);
+    bus.write_byte(0x6000, 0x00);
+    bus.write_byte(0x6000, 0x01);
+    bus.write_byte(0x4000, 0x0B);
+    assert_eq!(bus.read_byte(0xA000), 4);
+
+    assert_eq!("-2d".parse(), Ok(ClockOffset(-172800)));
+    assert_eq!("90m".parse(), Ok(ClockOffset(5400)));
+    assert!("soon".parse::<ClockOffset>().is_err());
+
+    println!("  [OK] The RTC follows a controllable clock, offsets parse");
+}
+
+fn test_ppu_fuzz() {
+    use power_on::PowerOnMemory;
+
+    // Garbage VRAM, OAM and LCD registers must render without panicking
+    let seeds = 48;
+    for seed in 0..seeds {
+        let mut gb = GameBoy::new();
+        gb.cpu.bus.ppu.set_cgb_mode(seed % 2 == 1);
+        let mut registers = [0u8; 12];
+        let ppu = &mut gb.cpu.bus.ppu;
+        power_on::fill(
+            PowerOnMemory { wram: &mut registers, vram: &mut ppu.vram, oam: &mut ppu.oam },
+            PowerOnPattern::HardwareLike,
+            HardwareModel::Dmg,
+            seed,
+        );
+        for (addr, value) in (0xFF40..=0xFF4B).zip(registers) {
+            // Skip LY and OAM DMA, and keep the LCD on
+            match addr {
+                0xFF44 | 0xFF46 => {}
+                0xFF40 => gb.cpu.bus.ppu.write(addr, value | 0x80),
+                _ => gb.cpu.bus.ppu.write(addr, value),
+            }
+        }
+        for (i, &value) in registers.iter().cycle().take(128).enumerate() {
+            gb.cpu.bus.ppu.write(0xFF68 + (i % 2 * 2) as u16, (i / 2) as u8 | 0x80);
+            gb.cpu.bus.ppu.write(0xFF69 + (i % 2 * 2) as u16, value.rotate_left(i as u32));
+        }
+        gb.run_frame().expect("no breakpoints are set");
+    }
+
+    println!("  [OK] {} frames of random VRAM, OAM and registers rendered", seeds);
+}
+
+fn test_joypad_register() {
+    use joypad::Button;
+
+    let mut gb = GameBoy::new();
+    let joypad_requested = |gb: &mut GameBoy| {
+        let requested = gb.cpu.bus.read_byte(0xFF0F) & 0x10 != 0;
+        gb.cpu.bus.write_byte(0xFF0F, 0x00);
+        requested
+    };
+
+    // Directions selected: pressing Down pulls bit 3 low and interrupts
+    gb.cpu.bus.write_byte(0xFF00, 0x20);
+    gb.set_button(Button::Down, true);
+    assert_eq!(gb.cpu.bus.read_byte(0xFF00), 0xE7);
+    assert!(joypad_requested(&mut gb));
+
+    // Start shares bit 3 but isn't selected
+    gb.set_button(Button::Start, true);
+    gb.set_button(Button::Down, false);
+    assert_eq!(gb.cpu.bus.read_byte(0xFF00), 0xEF);
+    assert!(!joypad_requested(&mut gb));
+
+    // Selecting the action group with Start held is a high-to-low edge too
+    gb.cpu.bus.write_byte(0xFF00, 0x10);
+    assert_eq!(gb.cpu.bus.read_byte(0xFF00), 0xD7);
+    assert!(joypad_requested(&mut gb));
+
+    // Both groups selected reads the two ANDed together
+    gb.set_button(Button::Left, true);
+    gb.cpu.bus.write_byte(0xFF00, 0x00);
+    assert_eq!(gb.cpu.bus.read_byte(0xFF00), 0xC5);
+
+    println!("  [OK] Key presses reach 0xFF00 and request the joypad interrupt");
+}
+
+fn test_key_bindings() {
+    use joypad::Button;
+
+    let bindings = Bindings::parse(
+        "[keys]\n\
+         a = \"K\"\n\
+         start = [\"Return\", \"Space\"]\n\
+         [gamepad]\n\
+         dead_zone = 10000\n",
+    )
+    .expect("valid bindings");
+    let keys_for = |button: Button| -> Vec<&str> {
+        bindings.keys.iter().filter(|&&(_, bound)| bound == button).map(|(key, _)| key.as_str()).collect()
+    };
+    assert_eq!(keys_for(Button::A), ["K"]);
+    assert_eq!(keys_for(Button::Start), ["Return", "Space"]);
+    assert_eq!(keys_for(Button::B), ["X"], "unlisted buttons keep their defaults");
+
+    // Inside the dead zone nothing is pressed, diagonals press two directions
+    assert_eq!(bindings.stick_directions(9000, -9000), 0);
+    assert_eq!(bindings.stick_directions(-20000, 20000), Button::Left.mask() | Button::Down.mask());
+
+    assert!(Bindings::parse("[keys]\nturbo = \"T\"\n").is_err());
+    assert!(Bindings::parse("[gamepad]\ndead_zone = -1\n").is_err());
+    assert!(Bindings::parse("[mouse]\n").is_err());
+
+    println!("  [OK] Bindings files remap keys and set the stick dead zone");
+}
+
+fn test_input_latching() {
+    use input::InputLayer;
+    use joypad::Button;
+
+    let mut input = InputLayer::new();
+    input.toggle_recording(0);
+
+    // A tap that starts and ends between two frames still lands on one frame
+    input.set_held(Button::A, true);
+    input.set_held(Button::A, false);
+    let live = [input.next_frame(), input.next_frame()];
+    assert_eq!(live, [Button::A.mask(), 0]);
+
+    // Keyboard and controller hold the same button independently
+    input.set_held(Button::Start, true);
+    input.set_pad_held(Button::Start, true);
+    input.set_held(Button::Start, false);
+    let held = input.next_frame();
+    assert_eq!(held, Button::Start.mask());
+    input.set_pad_held(Button::Start, false);
+    assert_eq!(input.next_frame(), 0);
+
+    // Playback reproduces exactly what the game saw live
+    input.toggle_recording(0);
+    input.play_macro(0);
+    let replay: Vec<u8> = (0..4).map(|_| input.next_frame()).collect();
+    assert_eq!(replay, [live[0], live[1], held, 0]);
+
+    println!("  [OK] Input is sampled once per frame and taps aren't dropped");
+}
+
+fn test_event_timestamps() {
+    // Sends B over the unconnected serial port forever, counting B up
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+    rom[0x150..0x160].copy_from_slice(&[
+        0x78,
+        0xE0, 0x01,
+        0x3E, 0x81,
+        0xE0, 0x02,
+        0xF0, 0x02,
+        0xE6, 0x80,
+        0x20, 0xFA,
+        0x04,
+        0x18, 0xF0,
+    ]);
+
+    let mut gb = GameBoy::new();
+    gb.load_rom(&rom).expect("test ROM has a header");
+    let state = gb.save_state();
+    let start = gb.take_audio();
+    let end = gb.run_frame().expect("no breakpoints set").cycles;
+
+    let events = gb.take_events();
+    let transfers: Vec<(u64, u8, u8)> = events.iter()
+        .filter_map(|timed| match timed.event {
+            events::EmulatorEvent::SerialByte { sent, received } => Some((timed.cycle, sent, received)),
+            _ => None,
+        })
+        .collect();
+    assert!(transfers.len() > 2, "the loop should finish several transfers in a frame");
+    assert!(events.windows(2).all(|pair| pair[0].cycle <= pair[1].cycle), "stamps should never decrease");
+    assert!(transfers.iter().all(|&(cycle, _, received)| cycle > 0 && cycle <= end && received == 0xFF));
+    assert!(transfers.windows(2).all(|pair| pair[1].1 == pair[0].1.wrapping_add(1)), "bytes should arrive in order");
+
+    let audio = gb.take_audio();
+    assert_eq!((start.cycle, audio.cycle), (0, 0));
+    assert!(!audio.samples.is_empty());
+    assert_eq!(gb.take_audio().cycle, gb.cycles(), "the next chunk starts where this one ended");
+
+    // Loading an earlier state doesn't rewind the counter
+    let before = gb.cycles();
+    gb.load_state(&state).expect("state should load");
+    gb.step();
+    assert!(gb.cycles() > before);
+
+    println!("  [OK] Serial bytes and audio chunks carry the cycle they happened at");
+    println!("    {} transfers in a {} cycle frame", transfers.len(), end);
+}
+
+fn test_frame_pacing() {
+    use std::time::{Duration, Instant};
+
+    let mut pacer = pacing::FramePacer::new();
+    let period = pacer.period();
+    assert_eq!(period.as_nanos(), 16_742_706, "70224 cycles at 4.194304 MHz");
+
+    // A loop that finishes every frame early waits for each deadline in turn
+    let start = Instant::now();
+    let first = pacer.next_deadline(start);
+    assert_eq!(first, start);
+    let mut deadline = first;
+    for _ in 0..597 {
+        deadline = pacer.next_deadline(start);
+    }
+    let elapsed = deadline - first;
+    assert!(elapsed > Duration::from_millis(9990) && elapsed < Duration::from_millis(10005),
+            "597 frames should take ten seconds, took {:?}", elapsed);
+
+    // A short hitch is caught up, a long stall starts a new schedule
+    let late = deadline + period * 3;
+    assert_eq!(pacer.next_deadline(late), deadline + period);
+    let stalled = late + Duration::from_secs(1);
+    assert_eq!(pacer.next_deadline(stalled), stalled);
+
+    let timer = Instant::now();
+    pacer.reset();
+    for _ in 0..4 {
+        pacer.wait();
+    }
+    assert!(timer.elapsed() >= period * 3, "waits should hold the loop to the frame rate");
+
+    println!("  [OK] Frames are paced at 59.73 Hz");
+}
+
+fn test_vram_dma() {
+    let mut gb = GameBoy::new();
+    gb.cpu.bus.cgb_mode = true;
+    let bus = &mut gb.cpu.bus;
+    for i in 0..0x80u16 {
+        bus.write_byte(0xC000 + i, i as u8 ^ 0x5A);
+    }
+    let vram = |bus: &bus::MemoryBus, len: u16| -> Vec<u8> { (0..len).map(|i| bus.read_byte(0x8800 + i)).collect() };
+    let source = |len: u16| -> Vec<u8> { (0..len).map(|i| i as u8 ^ 0x5A).collect() };
+    for (addr, value) in [(0xFF51, 0xC0), (0xFF52, 0x00), (0xFF53, 0x08), (0xFF54, 0x00)] {
+        bus.write_byte(addr, value);
+    }
+
+    // General purpose: both blocks at once, then the CPU sits out 8us per block
+    bus.write_byte(0xFF55, 0x01);
+    assert_eq!(vram(bus, 0x20), source(0x20));
+    assert_eq!(bus.read_byte(0xFF55), 0xFF, "a finished transfer reads 0xFF");
+    let pc = gb.cpu.pc;
+    assert_eq!(gb.step(), 64);
+    assert_eq!(gb.cpu.pc, pc, "the CPU doesn't run during the copy");
+
+    // Double speed takes as long in real time, so twice the CPU cycles
+    let bus = &mut gb.cpu.bus;
+    bus.double_speed = true;
+    bus.write_byte(0xFF55, 0x00);
+    assert_eq!(bus.take_hdma_stall(), 64);
+    bus.double_speed = false;
+
+    // HBlank: a block per HBlank, none while halted, and a cancel keeps the length
+    for (addr, value) in [(0xFF51, 0xC0), (0xFF52, 0x00), (0xFF53, 0x08), (0xFF54, 0x00)] {
+        bus.write_byte(addr, value);
+    }
+    for i in 0..0x40 {
+        bus.write_byte(0x8800 + i, 0);
+    }
+    bus.write_byte(0xFF55, 0x82);
+    assert_eq!(bus.read_byte(0xFF55), 0x02);
+    bus.hblank_dma(false);
+    assert_eq!(bus.read_byte(0xFF55), 0x01);
+    assert_eq!(vram(bus, 0x20), [source(0x10), vec![0; 0x10]].concat());
+    bus.hblank_dma(true);
+    assert_eq!(bus.read_byte(0xFF55), 0x01, "a halted CPU holds the transfer back");
+    bus.write_byte(0xFF55, 0x00);
+    assert_eq!(bus.read_byte(0xFF55), 0x81, "a cancelled transfer keeps its remaining length");
+    bus.hblank_dma(false);
+    assert_eq!(vram(bus, 0x20), [source(0x10), vec![0; 0x10]].concat());
+    assert_eq!(bus.take_hdma_stall(), 32);
+
+    // Restarting continues from where the source and destination were left
+    bus.write_byte(0xFF55, 0x80);
+    bus.hblank_dma(false);
+    assert_eq!(bus.read_byte(0xFF55), 0xFF);
+    assert_eq!(vram(bus, 0x20), source(0x20));
+
+    println!("  [OK] General and HBlank DMA copy, stall the CPU and report their length");
+}
+
+// Only what the crate root exports, as another frontend would see it
+fn test_library_api() {
+    use rust_gbc::{Button, GameBoy, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x102].copy_from_slice(&[0x18, 0xFE]);
+
+    let mut gb = GameBoy::new();
+    gb.load_rom(&rom).expect("test ROM has a header");
+    let frame = gb.run_frame().expect("no breakpoints set");
+    assert_eq!(frame.pixels.len(), SCREEN_WIDTH * SCREEN_HEIGHT * 3);
+    assert_eq!(gb.framebuffer().len(), SCREEN_WIDTH * SCREEN_HEIGHT * 3);
+
+    gb.write_byte(0xFF00, 0x10);
+    gb.set_button(Button::Start, true);
+    assert_eq!(gb.read_byte(0xFF00) & 0x0F, 0x07, "Start reads low in the button group");
+    assert!(!gb.take_audio().samples.is_empty());
+    assert_eq!(gb.registers().pc, gb.cpu.pc);
+
+    println!("  [OK] The core runs through the crate's public API");
+}
+
+fn test_selftest() {
+    let checks = selftest::run(None);
+    for check in &checks {
+        assert!(check.passed, "{} failed: {}", check.component, check.detail);
+    }
+
+    // A wrong expectation fails, and a vector touching I/O is skipped
+    let vectors = r#"[
+      {"name": "04 inc b",
+       "initial": {"pc": 49152, "sp": 0, "a": 0, "b": 1, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0, "ie": 0,
+                   "ram": [[49152, 4]]},
+       "final": {"pc": 49153, "sp": 0, "a": 0, "b": 3, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0, "ie": 0,
+                 "ram": [[49152, 4]]},
+       "cycles": [[49152, 4, "r-m"]]},
+      {"name": "e0 ldh (n),a",
+       "initial": {"pc": 49152, "sp": 0, "a": 7, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0, "ie": 0,
+                   "ram": [[49152, 224], [49153, 64]]},
+       "final": {"pc": 49154, "sp": 0, "a": 7, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0, "ie": 0,
+                 "ram": [[49152, 224], [49153, 64], [65344, 7]]},
+       "cycles": [[49152, 224, "r-m"], [49153, 64, "r-m"], [65344, 7, "-wm"]]}
+    ]"#;
+    let totals = selftest::run_vectors(vectors).expect("vectors parse");
+    assert_eq!((totals.passed, totals.skipped), (0, 1));
+    assert_eq!(totals.failures, ["04 inc b (b 2 != 3)"]);
+    assert!(selftest::run_vectors("{}").is_err());
+
+    println!("  [OK] Self test passes and catches a wrong CPU result");
+}
+
+fn test_headless_runs() {
+    use headless::{HeadlessOptions, StopReason};
+
+    let run = |serial_match: &str| {
+        let mut gb = GameBoy::new();
+        gb.load_rom(&selftest::test_rom()).expect("test ROM has a header");
+        gb.write_byte(0xFF50, 0x01);
+        gb.cpu.pc = 0x0100;
+        let options = HeadlessOptions { frames: Some(5), serial_match: Some(serial_match.to_string()) };
+        headless::run(&mut gb, &options, None, None)
+    };
+
+    let passed = run("Passed");
+    assert_eq!((passed.reason, passed.frames), (StopReason::SerialMatch, 1));
+    assert_eq!(passed.serial, b"Passed\n");
+    assert_eq!(passed.reason.exit_code(), 0);
+    let timeout = run("Failed");
+    assert_eq!((timeout.reason, timeout.frames), (StopReason::Timeout, 5));
+    assert_eq!(timeout.reason.exit_code(), 3);
+
+    // A 2x1 image: signature, IHDR with the size and a stored zlib stream
+    let image = png::encode_rgb(&[0xFF, 0x00, 0x00, 0x00, 0x00, 0xFF], 2, 1);
+    assert_eq!(image[..8], [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]);
+    assert_eq!(image[12..24], [b'I', b'H', b'D', b'R', 0, 0, 0, 2, 0, 0, 0, 1]);
+    let idat = image.windows(4).position(|window| window == b"IDAT").expect("IDAT chunk") + 4;
+    assert_eq!(image[idat..idat + 14], [0x78, 0x01, 0x01, 7, 0, 0xF8, 0xFF, 0, 0xFF, 0, 0, 0, 0, 0xFF]);
+    assert!(image.ends_with(&[b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]), "IEND with its known CRC");
+
+    println!("  [OK] Headless runs stop on serial output or time out, screenshots encode as PNG");
+}
+
+fn test_serial_transfers() {
+    use interrupts::Interrupts;
+    use serial::Serial;
+
+    // A byte takes 4096 cycles with the internal clock, then the interrupt fires
+    let mut serial = Serial::new();
+    let mut interrupts = Interrupts::new();
+    serial.write(0xFF01, 0x42);
+    serial.write(0xFF02, 0x81);
+    serial.tick(4095, &mut interrupts);
+    assert_eq!((serial.read(0xFF02), interrupts.interrupt_flag & 0x08), (0xFF, 0));
+    serial.tick(1, &mut interrupts);
+    assert_eq!((serial.read(0xFF01), serial.read(0xFF02)), (0xFF, 0x7F), "unconnected ports shift in ones");
+    assert_eq!(interrupts.interrupt_flag & 0x08, 0x08);
+    assert_eq!(serial.take_transfers(), [(0x42, 0xFF)]);
+
+    // CGB mode has a 32x faster clock, which DMG mode ignores
+    serial.write(0xFF02, 0x83);
+    serial.tick(128, &mut interrupts);
+    assert_eq!(serial.read(0xFF02), 0xFF, "bit 1 doesn't exist outside CGB mode");
+    serial.set_cgb_mode(true);
+    serial.write(0xFF02, 0x83);
+    serial.tick(128, &mut interrupts);
+    assert_eq!(serial.read(0xFF02), 0x7F);
+    serial.write(0xFF02, 0x02);
+    assert_eq!(serial.read(0xFF02), 0x7E);
+
+    // The external clock waits for a partner that never comes
+    serial.write(0xFF02, 0x80);
+    serial.tick(100_000, &mut interrupts);
+    assert_eq!(serial.read(0xFF02), 0xFC);
+
+    // Test ROM output can be read back as a string
+    let mut gb = GameBoy::new();
+    gb.load_rom(&selftest::test_rom()).expect("test ROM has a header");
+    gb.write_byte(0xFF50, 0x01);
+    gb.cpu.pc = 0x0100;
+    gb.set_serial_capture(true);
+    gb.run_frame();
+    assert_eq!(gb.serial_output(), "Passed\n");
+
+    println!("  [OK] Transfers take 8 bit times, raise the interrupt and can be captured");
+}
+
+fn test_halt_without_ime() {
+    // HALT, INC A, JR @
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x104].copy_from_slice(&[0x76, 0x3C, 0x18, 0xFE]);
+    let boot = |interrupt_flag: u8| {
+        let mut gb = GameBoy::new();
+        gb.load_rom(&rom).expect("test ROM has a header");
+        gb.write_byte(0xFF50, 0x01);
+        gb.cpu.pc = 0x0100;
+        gb.cpu.a = 0;
+        gb.cpu.bus.interrupts.ime = false;
+        gb.cpu.bus.interrupts.interrupt_enable = 0x04;
+        gb.cpu.bus.interrupts.interrupt_flag = interrupt_flag;
+        gb
+    };
+
+    // Stays halted until an enabled interrupt is requested, then carries on
+    // after the HALT instead of jumping to the handler
+    let mut gb = boot(0);
+    gb.step();
+    for _ in 0..3 {
+        gb.step();
+    }
+    assert!(gb.cpu.halted);
+    assert_eq!(gb.cpu.pc, 0x0101);
+    gb.cpu.bus.interrupts.interrupt_flag = 0x04;
+    gb.step();
+    assert!(!gb.cpu.halted);
+    assert_eq!((gb.cpu.pc, gb.cpu.a), (0x0102, 1));
+    assert_eq!(gb.cpu.bus.interrupts.interrupt_flag & 0x04, 0x04, "the interrupt is left pending");
+
+    // Halt bug: with the interrupt already pending, INC A runs twice
+    let mut gb = boot(0x04);
+    gb.step();
+    assert!(!gb.cpu.halted);
+    gb.step();
+    assert_eq!(gb.cpu.pc, 0x0101, "PC doesn't move past the byte after HALT");
+    gb.step();
+    assert_eq!((gb.cpu.pc, gb.cpu.a), (0x0102, 2));
+
+    println!("  [OK] HALT wakes without IME and reproduces the halt bug");
+}
+
+fn test_ei_delay() {
+    // The timer interrupt is already pending; its handler is a RETI
+    let boot = |program: &[u8]| {
+        let mut rom = vec![0; 0x8000];
+        rom[0x50] = 0xD9;
+        rom[0x100..0x100 + program.len()].copy_from_slice(program);
+        let mut gb = GameBoy::new();
+        gb.load_rom(&rom).expect("test ROM has a header");
+        gb.write_byte(0xFF50, 0x01);
+        gb.cpu.pc = 0x0100;
+        gb.cpu.sp = 0xFFFE;
+        gb.cpu.a = 0;
+        gb.cpu.bus.interrupts.ime = false;
+        gb.cpu.bus.interrupts.interrupt_enable = 0x04;
+        gb.cpu.bus.interrupts.interrupt_flag = 0x04;
+        gb
+    };
+    let return_address = |gb: &GameBoy| {
+        u16::from_le_bytes([gb.read_byte(gb.cpu.sp), gb.read_byte(gb.cpu.sp.wrapping_add(1))])
+    };
+
+    // EI, INC A, INC A: the first INC A still runs before the interrupt
+    let mut gb = boot(&[0xFB, 0x3C, 0x3C]);
+    gb.step();
+    assert!(!gb.cpu.bus.interrupts.ime, "IME is not set by EI itself");
+    gb.step();
+    assert!(gb.cpu.bus.interrupts.ime);
+    assert_eq!((gb.cpu.pc, gb.cpu.a), (0x0102, 1));
+    gb.step();
+    assert_eq!(gb.cpu.pc, 0x0050);
+    assert_eq!(return_address(&gb), 0x0102);
+
+    // RETI enables interrupts right away
+    gb.step();
+    assert_eq!(gb.cpu.pc, 0x0102);
+    assert!(gb.cpu.bus.interrupts.ime);
+
+    // EI, DI: the pending enable is cancelled
+    let mut gb = boot(&[0xFB, 0xF3, 0x3C, 0x3C]);
+    for _ in 0..3 {
+        gb.step();
+    }
+    assert!(!gb.cpu.bus.interrupts.ime);
+    assert_eq!((gb.cpu.pc, gb.cpu.a), (0x0103, 1));
+
+    // EI, HALT with an interrupt pending returns to the HALT, which then halts
+    let mut gb = boot(&[0xFB, 0x76, 0x3C]);
+    gb.step();
+    gb.step();
+    gb.step();
+    assert_eq!(gb.cpu.pc, 0x0050);
+    assert_eq!(return_address(&gb), 0x0101);
+    gb.step();
+    gb.step();
+    assert!(gb.cpu.halted);
+    assert_eq!((gb.cpu.pc, gb.cpu.a), (0x0102, 0));
+
+    println!("  [OK] EI takes effect after one instruction, DI cancels it, RETI is immediate");
+}
+
+fn test_stat_interrupts() {
+    // Runs one frame, stopping just short of line 0 of the next, and returns
+    // the lines a STAT interrupt was requested on
+    let frame = |stat: u8, lyc: u8| {
+        let mut ppu = ppu::PPU::new();
+        let mut interrupts = interrupts::Interrupts::new();
+        ppu.write(0xFF41, stat);
+        ppu.write(0xFF45, lyc);
+        let mut lines = Vec::new();
+        for _ in 0..70224 / 4 - 1 {
+            ppu.tick(4, &mut interrupts);
+            if interrupts.interrupt_flag & 0x02 != 0 {
+                interrupts.interrupt_flag &= !0x02;
+                lines.push(ppu.read(0xFF44));
+            }
+        }
+        (lines, ppu)
+    };
+
+    let (lines, _) = frame(0x00, 2);
+    assert!(lines.is_empty(), "no sources enabled");
+
+    let (lines, _) = frame(0x40, 2);
+    assert_eq!(lines, vec![2]);
+
+    let (lines, _) = frame(0x08, 0);
+    assert_eq!(lines, (0..144).collect::<Vec<u8>>(), "one per HBlank");
+
+    let (lines, _) = frame(0x10, 0);
+    assert_eq!(lines, vec![144]);
+
+    // Mode 2 fires on every visible line and once more as VBlank starts
+    let (lines, _) = frame(0x20, 0);
+    assert_eq!(lines.len(), 145);
+
+    // HBlank of line 1 holds the line high into the LYC match on line 2,
+    // which then also keeps line 2's HBlank from raising it again
+    let (lines, _) = frame(0x48, 2);
+    assert_eq!(lines.len(), 143);
+    assert!(!lines.contains(&2));
+
+    // Coincidence flag and the always set bit 7
+    let (_, ppu) = frame(0x00, 153);
+    assert_eq!(ppu.read(0xFF41) & 0x84, 0x84);
+    let (_, ppu) = frame(0x00, 5);
+    assert_eq!(ppu.read(0xFF41) & 0x84, 0x80);
+
+    println!("  [OK] LYC and mode 0/1/2 sources share one edge-triggered STAT line");
+}
+
+fn test_pixel_fifo() {
+    let mut interrupts = interrupts::Interrupts::new();
+    // Dots the first line spends in mode 3 with the given registers
+    let mut mode3_length = |writes: &[(u16, u8)]| {
+        let mut ppu = ppu::PPU::new();
+        for &(addr, value) in writes {
+            match addr {
+                // Locked while the line's OAM search runs
+                0xFE00..=0xFE9F => ppu.oam[(addr - 0xFE00) as usize] = value,
+                _ => ppu.write(addr, value),
+            }
+        }
+        (0..456)
+            .filter(|_| {
+                ppu.tick(1, &mut interrupts);
+                ppu.read(0xFF41) & 0x03 == 3
+            })
+            .count()
+    };
+
+    assert_eq!(mode3_length(&[]), 172);
+    assert_eq!(mode3_length(&[(0xFF43, 3)]), 175, "SCX drops 3 pixels first");
+    assert_eq!(mode3_length(&[(0xFF40, 0xB1), (0xFF4A, 0), (0xFF4B, 87)]), 178, "the window restarts the fetcher");
+    // An object at the left edge, and one further in
+    let objects = [(0xFF40, 0x93), (0xFE00, 16), (0xFE01, 8), (0xFE04, 16), (0xFE05, 60)];
+    let length = mode3_length(&objects);
+    assert!((184..=194).contains(&length), "each object costs 6 to 11 dots, got {}", length);
+
+    // Left half of the BG map is color 0, the right half color 3. SCX jumps
+    // to the right half part way through line 0.
+    let mut ppu = ppu::PPU::new();
+    ppu.vram[0x10..0x20].fill(0xFF);
+    for row in 0..32 {
+        ppu.vram[0x1800 + row * 32 + 16..0x1800 + row * 32 + 32].fill(1);
+    }
+    ppu.tick(80 + 12 + 40, &mut interrupts);
+    ppu.write(0xFF43, 128);
+    ppu.tick(456 * 2, &mut interrupts);
+    let shade = |ppu: &ppu::PPU, x: usize, y: usize| ppu.framebuffer[(y * ppu::SCREEN_WIDTH + x) * 3];
+    assert_eq!(shade(&ppu, 10, 0), 0xFF);
+    assert_eq!(shade(&ppu, 120, 0), 0x00, "the write lands mid-line");
+    assert_eq!(shade(&ppu, 0, 1), 0x00);
+
+    println!("  [OK] Mode 3 grows with SCX, the window and objects; SCX writes apply from the next tile");
+}
+
+fn test_vram_access_restrictions() {
+    let mut interrupts = interrupts::Interrupts::new();
+    // Writes a marker to VRAM and OAM in each mode of a line and reads it back
+    let probe = |ppu: &mut ppu::PPU, value: u8| {
+        ppu.write(0x8000, value);
+        ppu.write(0xFE00, value);
+        (ppu.read(0xFF41) & 0x03, ppu.read(0x8000), ppu.read(0xFE00))
+    };
+
+    let mut ppu = ppu::PPU::new();
+    ppu.vram[0] = 0x11;
+    ppu.oam[0] = 0x11;
+    ppu.tick(1, &mut interrupts);
+    assert_eq!(probe(&mut ppu, 0x22), (2, 0x22, 0xFF), "OAM search locks OAM");
+    ppu.tick(79, &mut interrupts);
+    assert_eq!(probe(&mut ppu, 0x33), (3, 0xFF, 0xFF), "pixel transfer locks both");
+    ppu.tick(200, &mut interrupts);
+    assert_eq!(probe(&mut ppu, 0x44), (0, 0x44, 0x44));
+    assert_eq!(ppu.oam[0], 0x44);
+
+    // Turning the LCD off frees both
+    ppu.tick(176 + 80, &mut interrupts);
+    ppu.write(0xFF40, 0x11);
+    let (_, vram, oam) = probe(&mut ppu, 0x55);
+    assert_eq!((vram, oam), (0x55, 0x55));
+
+    let mut ppu = ppu::PPU::new();
+    ppu.set_access_restrictions(false);
+    ppu.tick(80, &mut interrupts);
+    assert_eq!(probe(&mut ppu, 0x66), (3, 0x66, 0x66));
+
+    println!("  [OK] VRAM is locked in mode 3 and OAM in modes 2 and 3 unless turned off");
+}
+
+fn test_wram_banking() {
+    let mut memory = memory::Memory::new();
+    memory.set_cgb_mode(true);
+    memory.write_byte(0xC000, 0x10);
+    for bank in 1..8 {
+        memory.write_byte(0xFF70, bank);
+        memory.write_byte(0xD000, 0x10 + bank);
+    }
+    for bank in 1..8 {
+        memory.write_byte(0xFF70, bank);
+        assert_eq!(memory.read_byte(0xD000), 0x10 + bank);
+        assert_eq!(memory.read_byte(0xF000), 0x10 + bank, "echo RAM follows the bank");
+        assert_eq!(memory.read_byte(0xC000), 0x10, "bank 0 is fixed");
+    }
+    memory.write_byte(0xFF70, 0xF8);
+    assert_eq!(memory.read_byte(0xFF70), 0xF8);
+    assert_eq!(memory.read_byte(0xD000), 0x11, "bank 0 selects bank 1");
+
+    memory.write_byte(0xE001, 0x22);
+    assert_eq!(memory.read_byte(0xC001), 0x22);
+    memory.write_byte(0xFDFF, 0x33);
+    assert_eq!(memory.read_byte(0xDDFF), 0x33);
+
+    // The DMG has neither the register nor the extra banks
+    memory.set_cgb_mode(false);
+    memory.write_byte(0xFF70, 5);
+    assert_eq!(memory.read_byte(0xFF70), 0xFF);
+    assert_eq!(memory.read_byte(0xD000), 0x11);
+
+    println!("  [OK] SVBK switches D000-DFFF between 7 banks, echo RAM mirrors C000-DDFF");
+}
+
+fn test_boot_rom_mapping() {
+    let mut rom = vec![0; 0x8000];
+    rom[0x0000] = 0xEE;
+    rom[0x0143] = 0x80;
+    rom[0x0150] = 0xCD;
+    rom[0x0300] = 0xAB;
+    let boot = |model: HardwareModel, boot_rom: Option<&[u8]>, skip_boot: bool| {
+        let mut gb = GameBoy::new();
+        gb.set_model(model);
+        if let Some(boot_rom) = boot_rom {
+            gb.set_boot_rom(boot_rom).expect("boot ROM has a valid size");
+        }
+        gb.set_skip_boot(skip_boot);
+        gb.load_rom(&rom).expect("test ROM has a header");
+        gb.reset();
+        gb
+    };
+
+    // A CGB boot ROM leaves a hole for the cartridge header at 0100-01FF
+    let mut cgb_boot_rom = vec![0; 0x900];
+    cgb_boot_rom[0x0000] = 0x31;
+    cgb_boot_rom[0x0300] = 0x77;
+    let mut gb = boot(HardwareModel::Cgb, Some(&cgb_boot_rom), false);
+    assert_eq!(gb.cpu.pc, 0x0000);
+    assert_eq!((gb.read_byte(0x0000), gb.read_byte(0x0150), gb.read_byte(0x0300)), (0x31, 0xCD, 0x77));
+    gb.write_byte(0xFF50, 0x11);
+    assert_eq!((gb.read_byte(0x0000), gb.read_byte(0x0300)), (0xEE, 0xAB), "FF50 unmaps both parts");
+
+    // With only the built-in DMG boot ROM a CGB starts where the CGB boot ROM would leave it
+    let gb = boot(HardwareModel::Cgb, None, false);
+    assert_eq!((gb.cpu.pc, gb.cpu.sp), (0x0100, 0xFFFE));
+    assert_eq!((gb.cpu.a, gb.cpu.f, gb.cpu.d, gb.cpu.e, gb.cpu.l), (0x11, 0x80, 0xFF, 0x56, 0x0D));
+    assert_eq!(gb.read_byte(0x0000), 0xEE);
+    let gb = boot(HardwareModel::Agb, None, false);
+    assert_eq!((gb.cpu.a, gb.cpu.b), (0x11, 0x01), "B bit 0 tells games they run on a GBA");
+
+    // The DMG runs the built-in boot ROM unless told to skip it
+    let gb = boot(HardwareModel::Dmg, None, false);
+    assert_eq!((gb.cpu.pc, gb.read_byte(0x0000)), (0x0000, 0x31));
+    let gb = boot(HardwareModel::Dmg, None, true);
+    assert_eq!((gb.cpu.pc, gb.cpu.a, gb.cpu.f, gb.cpu.c, gb.cpu.e), (0x0100, 0x01, 0xB0, 0x13, 0xD8));
+    assert_eq!(gb.read_byte(0x0000), 0xEE);
+
+    println!("  [OK] CGB boot ROMs map around the header, skipped boots start with post-boot registers");
+}
+
+fn test_post_boot_registers() {
+    let rom = vec![0; 0x8000];
+    let boot = |model: HardwareModel| {
+        let mut gb = GameBoy::new();
+        gb.set_model(model);
+        gb.set_skip_boot(true);
+        gb.load_rom(&rom).expect("test ROM has a header");
+        gb.reset();
+        gb
+    };
+
+    // The Pocket only differs in A
+    let dmg = boot(HardwareModel::Dmg);
+    let mgb = boot(HardwareModel::Mgb);
+    assert_eq!((dmg.cpu.a, mgb.cpu.a), (0x01, 0xFF));
+    let (dmg_registers, mgb_registers) = (dmg.registers(), mgb.registers());
+    assert_eq!((dmg_registers.c, dmg_registers.e), (mgb_registers.c, mgb_registers.e));
+
+    for gb in [dmg, boot(HardwareModel::Cgb)] {
+        assert_eq!(gb.read_byte(0xFF26), 0xF1, "the APU is left powered with channel 1 on");
+        assert_eq!(gb.channel_status()[0].volume, 0, "the boot chime isn't played again");
+        assert_eq!((gb.read_byte(0xFF24), gb.read_byte(0xFF25)), (0x77, 0xF3));
+        assert_eq!((gb.read_byte(0xFF40), gb.read_byte(0xFF47)), (0x91, 0xFC));
+        assert_eq!(gb.read_byte(0xFF0F) & 0x1F, 0x01, "VBlank is pending");
+    }
+    assert_eq!(boot(HardwareModel::Mgb).read_byte(0xFF04), 0xAB);
+
+    println!("  [OK] Skipped boots leave the CPU, APU, PPU and timer as each model's boot ROM does");
+}
+
+fn test_debugger() {
+    use rust_gbc::debugger::{self, ConsoleExit, DebugStop, Watchpoint};
+    use std::io::Cursor;
+
+    let mut rom = vec![0; 0x8000];
+    // CALL 0200; LD (C000),A; JR -2
+    rom[0x0100..0x0108].copy_from_slice(&[0xCD, 0x00, 0x02, 0xEA, 0x00, 0xC0, 0x18, 0xFE]);
+    // LD A,42; RET
+    rom[0x0200..0x0203].copy_from_slice(&[0x3E, 0x42, 0xC9]);
+    let mut gb = GameBoy::new();
+    gb.set_skip_boot(true);
+    gb.load_rom(&rom).expect("test ROM has a header");
+    gb.reset();
+    let run_to_stop = |gb: &mut GameBoy| {
+        assert!(gb.run_frame().is_none(), "the debugger stops the frame");
+        let stops: Vec<DebugStop> = gb.take_events().into_iter()
+            .filter_map(|timed| match timed.event {
+                events::EmulatorEvent::DebugStop { stop, .. } => Some(stop),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(stops.len(), 1);
+        (stops[0], gb.registers().pc)
+    };
+
+    gb.debugger().step_into();
+    assert_eq!(run_to_stop(&mut gb), (DebugStop::Step, 0x0200), "step enters the call");
+    let sp = gb.registers().sp;
+    gb.debugger().step_over(0x0200, sp, 0x3E);
+    assert_eq!(run_to_stop(&mut gb), (DebugStop::Step, 0x0202));
+
+    gb.reset();
+    gb.debugger().step_over(0x0100, 0xFFFE, 0xCD);
+    assert_eq!(run_to_stop(&mut gb), (DebugStop::Step, 0x0103), "next runs the call to its return");
+    assert_eq!(gb.registers().sp, 0xFFFE);
+
+    gb.debugger().add_watchpoint(Watchpoint { addr: 0xC000, on_read: false, on_write: true });
+    let watch = DebugStop::Watch { addr: 0xC000, value: 0x42, write: true };
+    assert_eq!(run_to_stop(&mut gb), (watch, 0x0106), "stops after the watched write");
+
+    let mut output = Vec::new();
+    let exit = debugger::console(&mut gb, &mut Cursor::new("b 106\nx c000 2\nbogus\nc\n"), &mut output);
+    let output = String::from_utf8(output).expect("console output is text");
+    assert_eq!(exit, ConsoleExit::Resume);
+    assert!(output.contains("PC: 0106") && output.contains("breakpoint at 0106") && output.contains("C000: 42"));
+    assert!(output.contains("error: bad command"));
+    assert_eq!(run_to_stop(&mut gb), (DebugStop::Breakpoint, 0x0106));
+
+    assert_eq!(debugger::console(&mut gb, &mut Cursor::new("q\n"), &mut Vec::new()), ConsoleExit::Quit);
+    assert_eq!(debugger::console(&mut gb, &mut Cursor::new(""), &mut Vec::new()), ConsoleExit::Quit);
+
+    println!("  [OK] Breakpoints, watchpoints, step and next stop between instructions");
+}
+
+fn test_disassembler() {
+    use rust_gbc::disasm;
+
+    let decode = |bytes: &[u8], addr: u16| {
+        disasm::decode(|offset| bytes.get(offset as usize).copied().unwrap_or(0), addr)
+    };
+    let cases: [(&[u8], u16, &str, u16); 20] = [
+        (&[0x00], 0x0100, "NOP", 1),
+        (&[0x01, 0x34, 0x12], 0x0100, "LD BC,$1234", 3),
+        (&[0x08, 0x00, 0xC0], 0x0100, "LD ($C000),SP", 3),
+        (&[0x10, 0x00], 0x0100, "STOP", 2),
+        (&[0x18, 0xFE], 0x0150, "JR $0150", 2),
+        (&[0x20, 0x05], 0x0150, "JR NZ,$0157", 2),
+        (&[0x22], 0x0100, "LD (HL+),A", 1),
+        (&[0x3A], 0x0100, "LD A,(HL-)", 1),
+        (&[0x36, 0x7F], 0x0100, "LD (HL),$7F", 2),
+        (&[0x76], 0x0100, "HALT", 1),
+        (&[0x7E], 0x0100, "LD A,(HL)", 1),
+        (&[0x9F], 0x0100, "SBC A,A", 1),
+        (&[0xE0, 0x44], 0x0100, "LDH ($FF44),A", 2),
+        (&[0xE8, 0xFE], 0x0100, "ADD SP,-$02", 2),
+        (&[0xF8, 0x03], 0x0100, "LD HL,SP+$03", 2),
+        (&[0xF1], 0x0100, "POP AF", 1),
+        (&[0xCD, 0x00, 0x40], 0x0100, "CALL $4000", 3),
+        (&[0xFF], 0x0100, "RST $38", 1),
+        (&[0xCB, 0x7C], 0x0100, "BIT 7,H", 2),
+        (&[0xD3], 0x0100, "DB $D3", 1),
+    ];
+    for (bytes, addr, text, len) in cases {
+        assert_eq!(decode(bytes, addr), (text.to_string(), len), "{:02X?}", bytes);
+    }
+    assert_eq!(decode(&[0xCB, 0x36], 0).0, "SWAP (HL)");
+    assert_eq!(decode(&[0xCB, 0xC7], 0).0, "SET 0,A");
+
+    // Every opcode decodes to one to three bytes
+    for opcode in 0..=0xFFu8 {
+        let (text, len) = decode(&[opcode], 0);
+        assert!(!text.is_empty() && (1..=3).contains(&len));
+    }
+
+    let mut gb = GameBoy::new();
+    let mut rom = vec![0; 0x8000];
+    rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+    gb.set_skip_boot(true);
+    gb.load_rom(&rom).expect("test ROM has a header");
+    gb.reset();
+    assert_eq!(gb.disassemble(0x0101), ("JP $0150".to_string(), 3));
+    let mut output = Vec::new();
+    rust_gbc::debugger::console(&mut gb, &mut std::io::Cursor::new("u 100 2\n"), &mut output);
+    let output = String::from_utf8(output).expect("console output is text");
+    assert!(output.contains("=> 0100: 00        NOP") && output.contains("   0101: C3 50 01  JP $0150"));
+
+    println!("  [OK] Every opcode, including CB-prefixed ones, decodes to a mnemonic and length");
+}
+
+fn test_trace_logging() {
+    let path = std::env::temp_dir().join(format!("rust-gbc-trace-{}.log", std::process::id()));
+    let mut rom = vec![0; 0x8000];
+    // NOP; LD A,42; JR -2
+    rom[0x0100..0x0105].copy_from_slice(&[0x00, 0x3E, 0x42, 0x18, 0xFE]);
+    let mut gb = GameBoy::new();
+    gb.set_skip_boot(true);
+    gb.load_rom(&rom).expect("test ROM has a header");
+    gb.reset();
+    let file = fs::File::create(&path).unwrap();
+    gb.set_tracer(Some(rust_gbc::trace::Tracer::new(Box::new(io::BufWriter::new(file)))));
+    for _ in 0..4 {
+        gb.step();
+    }
+    gb.flush_trace();
+    let log = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    // The first line is the one Gameboy Doctor logs start with
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines, [
+        "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,3E,42,18",
+        "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0101 PCMEM:3E,42,18,FE",
+        "A:42 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0103 PCMEM:18,FE,00,00",
+        "A:42 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0103 PCMEM:18,FE,00,00",
+    ]);
+
+    // A failed write ends the trace with a single event instead of the run
+    struct FullDisk;
+    impl io::Write for FullDisk {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::StorageFull, "disk full"))
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    gb.take_events();
+    gb.set_tracer(Some(rust_gbc::trace::Tracer::new(Box::new(FullDisk))));
+    for _ in 0..4 {
+        gb.step();
+    }
+    let stops: Vec<String> = gb.take_events().into_iter()
+        .filter_map(|timed| match timed.event {
+            events::EmulatorEvent::TraceStopped(error) => Some(error),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(stops, ["disk full"]);
+
+    println!("  [OK] Each instruction is logged with its registers and the bytes at PC");
+}
+
+fn test_memory_access_timing() {
+    let mut rom = vec![0; 0x8000];
+    // LD A,(FF05); LDH A,(05); LD (FF05),A
+    rom[0x0100..0x0108].copy_from_slice(&[0xFA, 0x05, 0xFF, 0xF0, 0x05, 0xEA, 0x05, 0xFF]);
+    let mut gb = GameBoy::new();
+    gb.set_skip_boot(true);
+    gb.load_rom(&rom).expect("test ROM has a header");
+    gb.reset();
+    // TIMA counts every 16 cycles from a fresh divider
+    let restart_timer = |gb: &mut GameBoy| {
+        gb.write_byte(0xFF07, 0x05);
+        gb.write_byte(0xFF04, 0x00);
+        gb.write_byte(0xFF05, 0x00);
+    };
+
+    // The read is the fourth machine cycle, the first three already ran the timer
+    restart_timer(&mut gb);
+    gb.step();
+    assert_eq!(gb.registers().a, 1);
+    // Here it is the third
+    restart_timer(&mut gb);
+    gb.step();
+    assert_eq!(gb.registers().a, 0);
+
+    // Writes land on their machine cycle too, after TIMA's first increment
+    gb.cpu.a = 0x80;
+    restart_timer(&mut gb);
+    gb.step();
+    assert_eq!(gb.read_byte(0xFF05), 0x80);
+
+    println!("  [OK] Reads and writes see the machine as of their own machine cycle");
+}
+
+fn test_timer_edges() {
+    use rust_gbc::timer::Timer;
+
+    const TIMER_FLAG: u8 = 0x04;
+    // TIMA on the 262144 Hz input, which follows bit 3 of the counter
+    let timer_at = |divider: u16, counter: u8| {
+        let mut timer = Timer::new();
+        timer.write(0xFF07, 0x05);
+        timer.set_divider(divider);
+        timer.write(0xFF05, counter);
+        timer.write(0xFF06, 0x10);
+        timer
+    };
+    let mut interrupts = interrupts::Interrupts::new();
+
+    // Resetting DIV or switching inputs while the bit is high counts a falling edge
+    let mut timer = timer_at(0x0008, 0x00);
+    timer.write(0xFF04, 0x00);
+    assert_eq!(timer.read(0xFF05), 1);
+    let mut timer = timer_at(0x0008, 0x00);
+    timer.write(0xFF07, 0x04);
+    assert_eq!(timer.read(0xFF05), 1);
+    let mut timer = timer_at(0x0000, 0x00);
+    timer.write(0xFF04, 0x00);
+    assert_eq!((timer.read(0xFF05), timer.read(0xFF07)), (0, 0xFD));
+
+    // An overflow reads 0 for a machine cycle before TMA is loaded
+    let mut timer = timer_at(0x000C, 0xFF);
+    timer.tick(4, &mut interrupts);
+    assert_eq!((timer.read(0xFF05), interrupts.interrupt_flag & TIMER_FLAG), (0x00, 0));
+    timer.tick(4, &mut interrupts);
+    assert_eq!((timer.read(0xFF05), interrupts.interrupt_flag & TIMER_FLAG), (0x10, TIMER_FLAG));
+
+    // Writing TIMA in between cancels the reload and the interrupt
+    interrupts.interrupt_flag = 0;
+    let mut timer = timer_at(0x000C, 0xFF);
+    timer.tick(4, &mut interrupts);
+    timer.write(0xFF05, 0x33);
+    timer.tick(4, &mut interrupts);
+    assert_eq!((timer.read(0xFF05), interrupts.interrupt_flag & TIMER_FLAG), (0x33, 0));
+
+    // In the reload cycle TIMA writes are lost and TMA writes reach TIMA
+    let mut timer = timer_at(0x000C, 0xFF);
+    timer.tick(8, &mut interrupts);
+    timer.write(0xFF05, 0x33);
+    assert_eq!(timer.read(0xFF05), 0x10);
+    timer.write(0xFF06, 0x44);
+    assert_eq!(timer.read(0xFF05), 0x44);
+
+    println!("  [OK] TIMA counts falling edges of the counter, with a delayed, cancellable reload");
+}
+
+fn test_stereo_mixing() {
+    // A second of a square wave on channel 2, in machine cycles as the bus ticks it
+    let run = |nr50: u8, nr51: u8, sample_rate: u32| {
+        let mut apu = apu::APU::new();
+        apu.set_sample_rate(sample_rate);
+        apu.write(0xFF26, 0x80);
+        apu.write(0xFF24, nr50);
+        apu.write(0xFF25, nr51);
+        apu.write(0xFF16, 0x80);
+        apu.write(0xFF17, 0xF0);
+        apu.write(0xFF18, 0x00);
+        apu.write(0xFF19, 0x87);
+        for _ in 0..4_194_304 / 4 {
+            apu.tick(4);
+        }
+        apu.take_samples()
+    };
+    let peak = |samples: &[f32], side: usize| samples.iter().skip(side).step_by(2).fold(0.0f32, |peak, s| peak.max(s.abs()));
+
+    let samples = run(0x77, 0x20, 48_000);
+    assert_eq!(samples.len(), 48_000 * 2, "one left/right pair per sample period");
+    assert!(peak(&samples, 0) > 0.01 && peak(&samples, 1) == 0.0, "NR51 routes channel 2 left only");
+
+    // NR50 scales each side from 1/8 to 8/8
+    let samples = run(0x70, 0x22, 48_000);
+    assert!(samples.chunks(2).all(|pair| (pair[0] - pair[1] * 8.0).abs() < 1e-5));
+
+    // Averaging over each period keeps the wave within its swing at any rate
+    let swing = peak(&run(0x77, 0xFF, 48_000), 0);
+    let samples = run(0x77, 0xFF, 22_050);
+    assert_eq!(samples.len(), 22_050 * 2);
+    assert!(samples.iter().all(|s| s.abs() <= swing + 1e-6));
+
+    println!("  [OK] NR51 pans and NR50 scales each channel, resampled to the chosen rate");
+}
+
+fn test_channel_mute_and_wav() {
+    // A tenth of a second of channel 2's square wave and channel 4's noise
+    let run = |muted: Option<usize>, solo: Option<usize>| {
+        let mut apu = apu::APU::new();
+        apu.set_stems_enabled(true);
+        if let Some(channel) = muted {
+            apu.set_muted(channel, true);
+        }
+        apu.set_solo(solo);
+        apu.write(0xFF26, 0x80);
+        apu.write(0xFF24, 0x77);
+        apu.write(0xFF25, 0xFF);
+        apu.write(0xFF16, 0x80);
+        apu.write(0xFF17, 0xF0);
+        apu.write(0xFF19, 0x87);
+        apu.write(0xFF21, 0xF0);
+        apu.write(0xFF22, 0x00);
+        apu.write(0xFF23, 0x80);
+        for _ in 0..4_194_304 / 40 {
+            apu.tick(4);
+        }
+        (apu.take_samples(), apu.take_stems())
+    };
+    let same = |a: &[f32], b: &[f32]| a.len() == b.len() && a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-5);
+
+    let (mix, stems) = run(None, None);
+    assert!(stems[0].iter().chain(&stems[2]).all(|&s| s == 0.0), "silent channels have silent stems");
+    let summed: Vec<f32> = stems[1].iter().zip(&stems[3]).map(|(a, b)| a + b).collect();
+    assert!(same(&mix, &summed), "the mix is the sum of the stems");
+
+    let (mix, stems) = run(Some(1), None);
+    assert!(same(&mix, &stems[3]), "muting channel 2 leaves the noise");
+    assert!(stems[1].iter().any(|&s| s != 0.0), "stems ignore muting");
+    let (mix, stems) = run(Some(1), Some(1));
+    assert!(same(&mix, &stems[1]), "a solo overrides mutes");
+
+    let path = std::env::temp_dir().join(format!("rust-gbc-wav-{}.wav", std::process::id()));
+    let mut dump = AudioDump::create(&path, true).expect("create WAV files");
+    dump.write(22_050, &mix, &stems).unwrap();
+    dump.finish().unwrap();
+    let wav = fs::read(&path).unwrap();
+    let stem = fs::read(rust_gbc::wav::stem_path(&path, 2)).unwrap();
+    for channel in 1..=4 {
+        let _ = fs::remove_file(rust_gbc::wav::stem_path(&path, channel));
+    }
+    let _ = fs::remove_file(&path);
+    assert_eq!((&wav[0..4], &wav[8..16]), (&b"RIFF"[..], &b"WAVEfmt "[..]));
+    assert_eq!(u16::from_le_bytes([wav[22], wav[23]]), 2, "stereo");
+    assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 22_050);
+    assert_eq!(u32::from_le_bytes(wav[40..44].try_into().unwrap()) as usize, mix.len() * 2);
+    assert_eq!(wav.len(), 44 + mix.len() * 2);
+    assert_eq!(stem.len(), 44 + stems[1].len() * 2);
+
+    println!("  [OK] Muted and soloed channels leave the mix, which is recorded with its stems");
+}
+
+fn test_speed_control() {
+    use std::time::Instant;
+
+    assert_eq!("max".parse(), Ok(Speed::Unthrottled));
+    assert_eq!("4x".parse(), Ok(Speed::Times(4)));
+    assert_eq!("2".parse(), Ok(Speed::Times(2)));
+    assert!("0".parse::<Speed>().is_err() && "fast".parse::<Speed>().is_err());
+    assert_eq!(Speed::Fraction(4).to_string(), "0.25x");
+
+    // Every redraw shows N frames when fast, one every N redraws when slow,
+    // starting with the first so slow motion responds at once
+    let mut pacer = pacing::FramePacer::new();
+    assert_eq!(pacer.frames_due(), 1);
+    pacer.set_speed(Speed::Times(2));
+    assert_eq!(pacer.frames_due(), 2);
+    pacer.set_speed(Speed::Fraction(4));
+    let due: Vec<u32> = (0..8).map(|_| pacer.frames_due()).collect();
+    assert_eq!(due, [1, 0, 0, 0, 1, 0, 0, 0]);
+
+    // Unthrottled runs don't wait at all
+    pacer.set_speed(Speed::Unthrottled);
+    let timer = Instant::now();
+    for _ in 0..10 {
+        pacer.wait();
+    }
+    assert!(timer.elapsed() < pacer.period() * 3);
+
+    // Slow-motion audio is spread over the same stretch of real time
+    let stretched = audio_sink::stretch(&[0.5, -0.25, 0.1, 0.2], 2);
+    assert_eq!(stretched, [0.5, -0.25, 0.5, -0.25, 0.1, 0.2, 0.1, 0.2]);
+
+    println!("  [OK] Fast-forward and slow motion set how many frames each redraw shows");
+}
+
+fn test_rewind() {
+    // Counts up at C000 forever, so every snapshot differs
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x106].copy_from_slice(&[0x21, 0x00, 0xC0, 0x34, 0x18, 0xFD]);
+    let mut gb = GameBoy::new();
+    gb.load_rom(&rom).expect("test ROM loads");
+
+    // Every 4th frame is kept, and only the newest two of those
+    let mut rewind = rewind::RewindBuffer::new(4, 2);
+    let mut snapshots = Vec::new();
+    for frame in 1..=12 {
+        gb.run_frame();
+        rewind.record(&gb);
+        if frame % 4 == 0 {
+            snapshots.push(gb.save_state());
+        }
+    }
+    assert_eq!(rewind.len(), 2);
+    assert!(rewind.memory_used() < snapshots[0].len() * 3 / 2, "older snapshots are stored as deltas");
+
+    gb.run_frame();
+    assert!(rewind.step_back(&mut gb));
+    assert_eq!(gb.save_state(), snapshots[2]);
+    assert!(rewind.step_back(&mut gb));
+    assert_eq!(gb.save_state(), snapshots[1], "the delta restores the older state exactly");
+    assert!(!rewind.step_back(&mut gb) && rewind.is_empty());
+
+    println!("  [OK] Rewinding steps back through delta-compressed snapshots");
+}
+
+fn test_cheat_codes() {
+    use rust_gbc::cheats::{CheatCode, GeniePatch, SharkWrite};
+
+    assert_eq!("00A-17B-C49".parse(), Ok(CheatCode::Genie(GeniePatch { addr: 0x4A17, value: 0x00, compare: Some(0xCD) })));
+    assert_eq!("990-00B".parse(), Ok(CheatCode::Genie(GeniePatch { addr: 0x4000, value: 0x99, compare: None })));
+    assert_eq!("01FF2CD0".parse(), Ok(CheatCode::Shark(SharkWrite { addr: 0xD02C, value: 0xFF, bank: None })));
+    assert_eq!("92FF2CD0".parse(), Ok(CheatCode::Shark(SharkWrite { addr: 0xD02C, value: 0xFF, bank: Some(2) })));
+    assert!("01FF2C00".parse::<CheatCode>().is_err(), "GameShark codes only write RAM");
+    assert!("990-007".parse::<CheatCode>().is_err(), "Game Genie codes only patch ROM");
+    assert!("12G-456".parse::<CheatCode>().is_err());
+
+    let cheats = cheats::parse_file("# Test codes\n\n990-00B Patched byte\n015500C0\n").expect("valid file");
+    assert_eq!(cheats.len(), 2);
+    assert_eq!(cheats[0].describe(0), "0 on  990-00B Patched byte");
+    assert_eq!(cheats::parse_file("990-00B\nnonsense").unwrap_err(), "line 2: invalid cheat code 'nonsense'");
+
+    // A ROM that spins forever, with 12 at 4000
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x102].copy_from_slice(&[0x18, 0xFE]);
+    rom[0x4000] = 0x12;
+    let mut gb = GameBoy::new();
+    gb.load_rom(&rom).expect("test ROM loads");
+    for cheat in cheats {
+        gb.add_cheat(cheat);
+    }
+    gb.add_cheat(Cheat::parse("925500D0", "").unwrap());
+    assert_eq!(gb.read_byte(0x4000), 0x99, "Genie patches apply to ROM reads");
+    assert!(gb.set_cheat_enabled(0, false) && !gb.set_cheat_enabled(3, false));
+    assert_eq!(gb.read_byte(0x4000), 0x12, "disabled patches don't");
+    let code = CheatCode::Genie(GeniePatch { addr: 0x4000, value: 0x99, compare: Some(0x34) });
+    gb.add_cheat(Cheat { code, text: String::new(), name: String::new(), enabled: true });
+    assert_eq!(gb.read_byte(0x4000), 0x12, "a compare value that doesn't match leaves the byte alone");
+
+    // GameShark writes land once the frame's VBlank starts
+    assert_eq!(gb.read_byte(0xC000), 0x00);
+    gb.run_frame();
+    assert_eq!(gb.read_byte(0xC000), 0x55);
+    assert_eq!(gb.cpu.bus.memory.wram_mut()[2 * 0x1000], 0x55, "banked codes write their own WRAM bank");
+
+    println!("  [OK] Game Genie codes patch ROM reads, GameShark codes write RAM every VBlank");
+}
+
+fn test_network_link() {
+    use std::net::{TcpListener, TcpStream};
+
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind a local port");
+    let addr = listener.local_addr().unwrap();
+    let partner = std::thread::spawn(move || {
+        let stream = listener.accept().unwrap().0;
+        let mut link = NetLink::from_stream(stream).expect("handshake");
+        // The slave arms a transfer on the external clock and answers when
+        // the master's byte arrives
+        let mut serial = serial::Serial::new();
+        let mut interrupts = interrupts::Interrupts::new();
+        let mut infrared = infrared::InfraredPort::new();
+        serial.write(0xFF01, 0x99);
+        serial.write(0xFF02, 0x80);
+        let mut transfers = Vec::new();
+        while transfers.is_empty() {
+            link.service(&mut serial, &mut interrupts, &mut infrared, 512).expect("partner stays connected");
+            transfers = serial.take_transfers();
+        }
+        (transfers, interrupts.interrupt_flag & 0x08)
+    });
+
+    let mut link = NetLink::from_stream(TcpStream::connect(addr).unwrap()).expect("handshake");
+    let mut serial = serial::Serial::new();
+    let mut interrupts = interrupts::Interrupts::new();
+    let mut infrared = infrared::InfraredPort::new();
+    serial.set_connected(true);
+    serial.write(0xFF01, 0x42);
+    serial.write(0xFF02, 0x81);
+    serial.tick(4096, &mut interrupts);
+    link.service(&mut serial, &mut interrupts, &mut infrared, 4).expect("the partner answers");
+    assert_eq!(serial.take_transfers(), [(0x42, 0x99)], "the master gets the slave's SB");
+    assert_eq!(interrupts.interrupt_flag & 0x08, 0x08);
+    assert_eq!(partner.join().unwrap(), (vec![(0x99, 0x42)], 0x08), "the slave gets the master's byte");
+
+    // Once the partner is gone the transfer shifts in all ones and the link reports it
+    serial.write(0xFF02, 0x81);
+    serial.tick(4096, &mut interrupts);
+    assert!(link.service(&mut serial, &mut interrupts, &mut infrared, 4).is_err());
+    assert_eq!(serial.take_transfers(), [(0x99, 0xFF)]);
+
+    println!("  [OK] Serial transfers cross a TCP link cable between two instances");
+}
+
+fn test_input_movies() {
+    use events::EmulatorEvent;
+
+    // Adds the joypad lines to C000 forever, so the state follows the input
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x10D].copy_from_slice(&[0x3E, 0x20, 0xE0, 0x00, 0xF0, 0x00, 0x21, 0x00, 0xC0, 0x86, 0x77, 0x18, 0xF3]);
+    let mut gb = GameBoy::new();
+    gb.load_rom(&rom).expect("test ROM loads");
+    gb.run_frame();
+
+    gb.start_recording();
+    for frame in 0..130u32 {
+        gb.set_inputs((frame * 7) as u8);
+        gb.run_frame();
+    }
+    let result = gb.read_byte(0xC000);
+    let movie = gb.stop_movie().expect("a movie was recording");
+    assert!(!gb.is_movie_active());
+    assert_eq!(movie.inputs.len(), 130);
+    assert_eq!(movie.hashes.len(), 2, "a hash every 60 frames");
+    assert_eq!(Movie::decode(&movie.encode()), Ok(movie.clone()));
+    assert!(Movie::decode(&movie.encode()[..20]).is_err());
+
+    // Replaying on a fresh machine takes over the buttons and ends by itself
+    let play = |movie: Movie| {
+        let mut gb = GameBoy::new();
+        gb.load_rom(&rom).expect("test ROM loads");
+        gb.start_playback(movie).expect("same ROM");
+        let mut frames = 0;
+        let mut desyncs = Vec::new();
+        while gb.is_movie_active() {
+            gb.run_frame();
+            frames += 1;
+            for timed in gb.take_events() {
+                if let EmulatorEvent::MovieDesync { frame } = timed.event {
+                    desyncs.push(frame);
+                }
+            }
+        }
+        (frames, gb.read_byte(0xC000), desyncs)
+    };
+    assert_eq!(play(movie.clone()), (130, result, Vec::new()), "the replay matches the recording");
+
+    let mut tampered = movie.clone();
+    tampered.hashes[0] ^= 1;
+    assert_eq!(play(tampered).2, [60], "a mismatched hash is reported once");
+
+    let mut other = rom.clone();
+    other[0x200] = 0xFF;
+    let mut gb = GameBoy::new();
+    gb.load_rom(&other).expect("test ROM loads");
+    assert!(matches!(gb.start_playback(movie), Err(EmulatorError::MovieRom)));
+
+    println!("  [OK] Recorded movies replay in sync and report desyncs");
+}
+
+fn test_screenshots() {
+    use std::time::Duration;
+    use rust_gbc::scanout::GREEN_PALETTE;
+
+    // Each pixel becomes a 2x2 block
+    let pixels = [1, 1, 1, 2, 2, 2, 3, 3, 3, 4, 4, 4];
+    let scaled = png::upscale(&pixels, 2, 2);
+    assert_eq!(scaled.chunks_exact(3).map(|pixel| pixel[0]).collect::<Vec<_>>(), [1, 1, 2, 2, 1, 1, 2, 2, 3, 3, 4, 4, 3, 3, 4, 4]);
+
+    let time = UNIX_EPOCH + Duration::from_millis(1_582_979_696_789);
+    assert_eq!(png::screenshot_path(Path::new("shots/tetris"), time), Path::new("shots/tetris-20200229-123456-789.png"));
+
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x102].copy_from_slice(&[0x18, 0xFE]);
+    let mut gb = GameBoy::new();
+    gb.load_rom(&rom).expect("test ROM loads");
+    gb.run_frame();
+    let image = gb.screenshot(3, Some(&GREEN_PALETTE));
+    assert_eq!(image[16..24], [0, 0, 0x01, 0xE0, 0, 0, 0x01, 0xB0], "480x432 at 3x");
+    // zlib header, stored block header and the first row's filter byte come
+    // before the pixels
+    let idat = image.windows(4).position(|window| window == b"IDAT").expect("IDAT chunk") + 4;
+    let expected = scanout::apply_palette(&gb.frame().pixels[..3], &GREEN_PALETTE);
+    assert_eq!(image[idat + 8..idat + 17], expected.repeat(3)[..], "the palette is applied before scaling");
+    assert_eq!(gb.screenshot(1, None), png::encode_rgb(gb.frame().pixels, ppu::SCREEN_WIDTH, ppu::SCREEN_HEIGHT));
+
+    println!("  [OK] Screenshots encode the last frame as PNG, upscaled and in the chosen palette");
+}
+
+fn test_image_comparison() {
+    use rust_gbc::imagediff;
+
+    // A 2x2 image compressed with fixed Huffman codes, the first row with
+    // the Sub filter and the second with Up
+    let compressed = [
+        0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44, 0x52, 0x00, 0x00, 0x00, 0x02,
+        0x00, 0x00, 0x00, 0x02, 0x08, 0x02, 0x00, 0x00, 0x00, 0xFD, 0xD4, 0x9A, 0x73, 0x00, 0x00, 0x00, 0x14, 0x49, 0x44, 0x41,
+        0x54, 0x78, 0xDA, 0x63, 0xE4, 0x12, 0x91, 0x03, 0x02, 0x26, 0x06, 0x06, 0x06, 0x46, 0x26, 0x66, 0x00, 0x06, 0x1A, 0x00,
+        0xA0, 0x2F, 0x65, 0x89, 0x67, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82,
+    ];
+    let reference = png::decode(&compressed).expect("valid PNG");
+    assert_eq!((reference.width, reference.height), (2, 2));
+    assert_eq!(reference.pixels, [10, 20, 30, 40, 50, 60, 10, 20, 30, 41, 52, 63]);
+
+    // What the emulator writes reads back the same
+    let written = png::encode_rgb(&reference.pixels, 2, 2);
+    assert_eq!(png::decode(&written), Ok(reference.clone()));
+    let mut corrupt = written.clone();
+    corrupt[written.len() - 20] ^= 0xFF;
+    assert!(png::decode(&corrupt).is_err(), "the zlib checksum catches damaged data");
+
+    let mut frame = reference.clone();
+    assert!(imagediff::compare(&reference, &frame).unwrap().matches());
+    frame.pixels[10] = 0;
+    let diff = imagediff::compare(&reference, &frame).unwrap();
+    assert_eq!((diff.differing, diff.first), (1, Some((1, 1))));
+    assert_eq!(diff.highlight[..3], [5, 10, 15], "matching pixels are dimmed");
+    assert_eq!(diff.highlight[9..], [0xFF, 0x00, 0x00], "differing ones are red");
+    let cropped = png::Image { width: 1, height: 2, pixels: vec![0; 6] };
+    assert!(imagediff::compare(&reference, &cropped).is_err());
+
+    println!("  [OK] PNG reference images decode and compare pixel by pixel");
+}
+
+fn test_vram_viewer() {
+    use rust_gbc::vram_viewer::{self, ViewerPage, VIEWER_WIDTH};
+
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x102].copy_from_slice(&[0x18, 0xFE]);
+    let mut gb = GameBoy::new();
+    gb.load_rom(&rom).expect("test ROM loads");
+    gb.set_access_restrictions(false);
+    // Tile 1's top row is all color 1, and the first map entry points at it
+    gb.write_byte(0x8010, 0xFF);
+    gb.write_byte(0x9800, 0x01);
+    gb.write_byte(0xFF40, 0x91);
+    gb.write_byte(0xFF47, 0xE4);
+    gb.write_byte(0xFF42, 8);
+    gb.write_byte(0xFF43, 200);
+    let pixel = |image: &[u8], x: usize, y: usize| image[(y * VIEWER_WIDTH + x) * 3..][..3].to_vec();
+
+    let tiles = vram_viewer::render(&gb, ViewerPage::Tiles);
+    assert_eq!((pixel(&tiles, 8, 0), pixel(&tiles, 8, 1)), (vec![0xAA; 3], vec![0xFF; 3]));
+
+    let map = vram_viewer::render(&gb, ViewerPage::Map9800);
+    assert_eq!(pixel(&map, 0, 0), [0xAA; 3], "BGP colors the map");
+    assert_eq!(pixel(&map, 0, 9), [0xFF; 3]);
+    // 160 pixels from SCX 200 wrap around to 103
+    assert_eq!(pixel(&map, 200, 8), [0xFF, 0x20, 0x20], "the visible area is outlined");
+    assert_eq!(pixel(&map, 103, 100), [0xFF, 0x20, 0x20]);
+    assert_eq!(pixel(&map, 104, 100), [0xFF; 3]);
+    let other = vram_viewer::render(&gb, ViewerPage::Map9C00);
+    assert_eq!(pixel(&other, 200, 8), [0xFF; 3], "only the map the BG uses is outlined");
+
+    let palettes = vram_viewer::render(&gb, ViewerPage::Palettes);
+    assert_eq!(gb.palettes().obj.len(), 2, "OBP0 and OBP1 outside CGB mode");
+    assert_eq!((pixel(&palettes, 8, 8), pixel(&palettes, 36, 8), pixel(&palettes, 92, 8)), (vec![0xFF; 3], vec![0xAA; 3], vec![0x00; 3]));
+    assert_eq!(ViewerPage::Palettes.next(), ViewerPage::Objects);
+
+    println!("  [OK] The VRAM viewer draws tiles, maps with the visible area and palettes");
+}
+
+fn test_oam_viewer() {
+    use rust_gbc::vram_viewer::{self, ViewerPage, VIEWER_WIDTH};
+
+    let mut gb = GameBoy::new();
+    // 12 objects on lines 40-47, the first showing tile 1 flipped, and one off screen
+    gb.cpu.bus.write_byte(0xFF40, 0x93);
+    gb.cpu.bus.write_byte(0xFF48, 0xE4);
+    gb.cpu.bus.ppu.vram[0x10] = 0x0F;
+    for i in 0..12 {
+        gb.cpu.bus.ppu.oam[i * 4] = 40 + 16;
+        gb.cpu.bus.ppu.oam[i * 4 + 1] = 8 + i as u8 * 8;
+    }
+    gb.cpu.bus.ppu.oam[2..4].copy_from_slice(&[0x01, 0x20]);
+    gb.run_frame().expect("no breakpoints are set");
+    gb.run_frame().expect("no breakpoints are set");
+
+    let listing = debugger::oam_listing(&gb);
+    assert_eq!(listing.len(), 40);
+    assert_eq!(listing[0], " 0 at   0, 40 tile 01 flags 20 OBP0 x-flip");
+    assert_eq!(listing[11], "11 at  88, 40 tile 00 flags 00 OBP0 dropped on line 40,41,42,43,44,45,46,47");
+    assert_eq!(listing[12], "12 at  -8,-16 tile 00 flags 00 OBP0");
+
+    let image = vram_viewer::render(&gb, ViewerPage::Objects);
+    let pixel = |x: usize, y: usize| image[(y * VIEWER_WIDTH + x) * 3..][..3].to_vec();
+    // Tile 1's top row is 0F, flipped to light pixels on the left at twice the size
+    assert_eq!((pixel(8, 8), pixel(15, 9), pixel(16, 8)), (vec![0xAA; 3], vec![0xAA; 3], vec![0x60, 0x60, 0x68]));
+    assert_eq!(pixel(3 * 32 + 2, 48 + 2), [0xA0, 0x20, 0x20], "dropped objects are marked");
+    assert_eq!(pixel(4 * 32 + 2, 48 + 2), [0x28, 0x28, 0x2C], "so are ones off screen");
+
+    println!("  [OK] OAM entries are listed and drawn, with dropped objects marked");
+}
+
+fn test_memory_viewer() {
+    use rust_gbc::memory::Region;
+    use std::io::Cursor;
+
+    // MBC1 with four ROM banks and 8 KB of RAM
+    let mut rom = vec![0; 0x10000];
+    rom[0x0147] = 0x03;
+    rom[0x0148] = 0x01;
+    rom[0x0149] = 0x02;
+    rom[0x8010..0x8015].copy_from_slice(b"HELLO");
+    let mut gb = GameBoy::new();
+    gb.set_skip_boot(true);
+    gb.load_rom(&rom).expect("test ROM has a header");
+    gb.reset();
+    let console = |gb: &mut GameBoy, commands: &str| {
+        let mut output = Vec::new();
+        debugger::console(gb, &mut Cursor::new(commands.to_string()), &mut output);
+        String::from_utf8(output).expect("console output is text")
+    };
+
+    let output = console(&mut gb, "x rom:2:4010 8\nfind rom 48 45 4C\nx 4010 1\n");
+    assert!(output.contains("rom:2:4010: 48 45 4C 4C 4F 00 00 00"), "{}", output);
+    assert!(output.contains("HELLO..."), "printable bytes show as text");
+    assert!(output.contains("rom:2:4010\n"), "find reports the bank");
+    assert!(output.contains("4010: 00 "), "bank 1 is mapped");
+
+    // Through the bus the write picks the ROM bank; banked writes go
+    // straight to memory that isn't mapped
+    let output = console(&mut gb, "set 2000 2\nset c000 12 34\nset wram:3:d000 AB\nset rom:1:4000 C9\nx vram:2:8000\nx rom:0:3ff8 10\nfind 12 34\n");
+    assert_eq!(gb.peek(0x4010), b'H');
+    assert_eq!((gb.peek(0xC000), gb.peek(0xC001)), (0x12, 0x34));
+    assert_eq!((gb.region(Region::Wram)[0x3000], gb.region(Region::Rom)[0x4000]), (0xAB, 0xC9));
+    assert!(output.contains("error: no vram bank 2"), "{}", output);
+    assert!(output.contains("error: length runs past the end of the bank"));
+    assert!(output.contains("C000\n"), "find searches the address space");
+    assert_eq!(Region::Wram.address(3, 0x10), 0xD010);
+    assert_eq!(Region::Rom.address(0, 0x10), 0x0010);
+
+    println!("  [OK] Memory is dumped, edited and searched through the bus or by bank");
+}
+
+fn test_interrupt_dispatch() {
+    // With SP at 0000, pushing the high byte of PC writes IE, like Mooneye's
+    // ie_push. Runs LD SP,0000; LD A,ie; LDH (FF),A; LD A,if; LDH (0F),A;
+    // EI; NOP from `base`, then the dispatch.
+    let dispatch = |base: u16, ie: u8, flags: u8| {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0103].copy_from_slice(&[0xC3, base as u8, (base >> 8) as u8]);
+        let code = [0x31, 0x00, 0x00, 0x3E, ie, 0xE0, 0xFF, 0x3E, flags, 0xE0, 0x0F, 0xFB, 0x00];
+        rom[base as usize..base as usize + code.len()].copy_from_slice(&code);
+        let mut gb = GameBoy::new();
+        gb.set_skip_boot(true);
+        gb.load_rom(&rom).expect("test ROM has a header");
+        gb.reset();
+        for _ in 0..8 {
+            gb.step();
+        }
+        assert_eq!(gb.step(), 20, "dispatch takes five machine cycles");
+        (gb.registers().pc, gb.peek(0xFF0F) & 0x1F, gb.peek(0xFFFF))
+    };
+
+    assert_eq!(dispatch(0x0400, 0x04, 0x04), (0x0050, 0x00, 0x04), "the timer interrupt is serviced");
+    assert_eq!(dispatch(0x0200, 0x04, 0x04), (0x0000, 0x04, 0x02), "the push disables it and cancels the dispatch");
+    assert_eq!(dispatch(0x0400, 0x01, 0x05), (0x0050, 0x01, 0x04), "the push enables the timer instead of VBlank");
+
+    println!("  [OK] IE written by the PC push cancels or retargets the interrupt");
+}
+
+fn test_stop_instruction() {
+    use joypad::Button;
+
+    // LD A,p1; LDH (00),A; STOP; LD B,42; JR -2
+    let boot = |p1: u8, cgb: bool| {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x010A].copy_from_slice(&[0x3E, p1, 0xE0, 0x00, 0x10, 0x00, 0x06, 0x42, 0x18, 0xFE]);
+        let mut gb = GameBoy::new();
+        gb.set_skip_boot(true);
+        gb.load_rom(&rom).expect("test ROM has a header");
+        gb.reset();
+        gb.cpu.bus.cgb_mode = cgb;
+        gb
+    };
+
+    // Action buttons selected
+    let mut gb = boot(0x10, false);
+    gb.step();
+    gb.step();
+    gb.cpu.bus.timer.set_divider(0x1234);
+    gb.step();
+    assert!(gb.cpu.bus.stopped && gb.cpu.pc == 0x0106, "STOP skips its second byte");
+    for _ in 0..1000 {
+        gb.step();
+    }
+    assert_eq!((gb.cpu.pc, gb.peek(0xFF04)), (0x0106, 0x00), "DIV is reset and held");
+    gb.set_button(Button::Right, true);
+    gb.step();
+    assert!(gb.cpu.bus.stopped, "a button that isn't selected doesn't wake it");
+    gb.set_button(Button::A, true);
+    gb.step();
+    assert_eq!((gb.cpu.bus.stopped, gb.cpu.pc), (false, 0x0108));
+
+    // A selected button already held keeps STOP from stopping
+    let mut gb = boot(0x10, false);
+    gb.set_button(Button::Start, true);
+    for _ in 0..3 {
+        gb.step();
+    }
+    assert!(!gb.cpu.bus.stopped && gb.cpu.halted);
+
+    // With KEY1 armed it switches speed instead, then carries on
+    let mut gb = boot(0x30, true);
+    gb.step();
+    gb.step();
+    gb.write_byte(0xFF4D, 0x01);
+    // step counts normal-speed cycles, and the CPU now runs at twice that
+    assert_eq!(gb.step(), (4 + 2050 * 4) / 2, "the CPU waits for the clock to settle");
+    assert_eq!((gb.cpu.bus.stopped, gb.peek(0xFF4D)), (false, 0xFE));
+    gb.step();
+    assert_eq!(gb.cpu.b, 0x42);
+
+    println!("  [OK] STOP waits for a selected button, or switches speed when armed");
+}
+
+fn test_io_register_map() {
+    use rust_gbc::io;
+
+    let boot = |model: HardwareModel, cgb_flag: u8| {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0143] = cgb_flag;
+        let mut gb = GameBoy::new();
+        gb.set_model(model);
+        gb.set_skip_boot(true);
+        gb.load_rom(&rom).expect("test ROM has a header");
+        gb.reset();
+        gb
+    };
+    let write_read = |gb: &mut GameBoy, addr: u16, value: u8| {
+        gb.write_byte(addr, value);
+        gb.peek(addr)
+    };
+
+    let mut gb = boot(HardwareModel::Dmg, 0x00);
+    assert_eq!(write_read(&mut gb, 0xFF0F, 0x01), 0xE1, "IF's top bits read as 1");
+    assert_eq!(write_read(&mut gb, 0xFF10, 0x00), 0x80);
+    assert_eq!(write_read(&mut gb, 0xFF46, 0xC0), 0xC0, "DMA reads back the last write");
+    for addr in [0xFF03, 0xFF15, 0xFF27, 0xFF4D, 0xFF50, 0xFF56, 0xFF6C, 0xFF72, 0xFF7F] {
+        assert_eq!(write_read(&mut gb, addr, 0x00), 0xFF, "{:04X} reads 0xFF on the DMG", addr);
+    }
+
+    let mut gb = boot(HardwareModel::Cgb, 0x80);
+    assert_eq!((write_read(&mut gb, 0xFF56, 0x00), write_read(&mut gb, 0xFF56, 0xC1)), (0x3E, 0xFF));
+    assert_eq!(write_read(&mut gb, 0xFF6C, 0x00), 0xFE);
+    assert_eq!((write_read(&mut gb, 0xFF74, 0x5A), write_read(&mut gb, 0xFF75, 0x00)), (0x5A, 0x8F));
+    assert_eq!(write_read(&mut gb, 0xFF4D, 0x01), 0x7F);
+
+    // A DMG game on a CGB only sees the registers every mode has
+    let mut gb = boot(HardwareModel::Cgb, 0x00);
+    assert_eq!((write_read(&mut gb, 0xFF72, 0x5A), write_read(&mut gb, 0xFF74, 0x5A)), (0x5A, 0xFF));
+    assert_eq!(write_read(&mut gb, 0xFF4F, 0x01), 0xFF);
+
+    assert_eq!(io::register(0xFF41).map(|register| register.name), Some("STAT"));
+    assert!(io::register(0xFF7F).is_none());
+
+    println!("  [OK] I/O registers read with their unused bits set, and CGB ones only on a CGB");
+}
+
+fn test_dmg_palettes() {
+    use rust_gbc::scanout::{DmgPalette, POCKET_PALETTE};
+
+    let mut palettes = PaletteList::default();
+    assert_eq!(palettes.names(), ["gray", "green", "pocket", "bgb", "sgb"]);
+    assert_eq!(palettes.current().colors, None, "gray leaves the framebuffer alone");
+    assert_eq!(palettes.cycle().colors, Some(rust_gbc::scanout::GREEN_PALETTE));
+    palettes.select("sgb").unwrap();
+    assert_eq!(palettes.cycle().name, "gray", "cycling wraps around");
+    assert!(palettes.select("purple").unwrap_err().contains("gray, green"));
+
+    let file = r##"
+        [palettes]
+        autumn = ["#FFF6D3", "F9A875", "#eb6b6f", "#7C3F58"]
+        pocket = ["#FFFFFF", "#AAAAAA", "#555555", "#000000"]
+    "##;
+    palettes.add_file(file).unwrap();
+    assert_eq!(palettes.names(), ["gray", "green", "pocket", "bgb", "sgb", "autumn"]);
+    palettes.select("autumn").unwrap();
+    let autumn: DmgPalette = [[0xFF, 0xF6, 0xD3], [0xF9, 0xA8, 0x75], [0xEB, 0x6B, 0x6F], [0x7C, 0x3F, 0x58]];
+    assert_eq!(palettes.current().colors, Some(autumn));
+    palettes.select("pocket").unwrap();
+    assert_ne!(palettes.current().colors, Some(POCKET_PALETTE), "the file replaces a built-in of the same name");
+
+    for bad in [
+        "[palettes]\nshort = [\"#FFFFFF\", \"#000000\"]",
+        "[palettes]\nbad = [\"#FFFFFF\", \"#AAAAAA\", \"#555555\", \"#00000G\"]",
+        "[colors]\nx = 1",
+    ] {
+        assert!(PaletteList::default().add_file(bad).is_err(), "{:?} is rejected", bad);
+    }
+
+    // Shades land on the selected palette's colors
+    let pixels = [0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00];
+    assert_eq!(scanout::apply_palette(&pixels, &autumn), [autumn[0], autumn[3]].concat());
+
+    println!("  [OK] Built-in and file palettes are selected by name and cycle in order");
+}
+
+fn test_scaling_filters() {
+    use rust_gbc::scanout::{integer_fit, FILTER_FACTOR};
+
+    // Whole multiples, centered, with the aspect kept
+    assert_eq!(integer_fit((160, 144), (640, 576)), (0, 0, 640, 576));
+    assert_eq!(integer_fit((160, 144), (1920, 1080)), (400, 36, 1120, 1008));
+    assert_eq!(integer_fit((320, 144), (700, 500)), (30, 106, 640, 288));
+    // Smaller than 1x shrinks to fit instead
+    assert_eq!(integer_fit((160, 144), (80, 100)), (0, 14, 80, 72));
+
+    assert_eq!("scanlines".parse::<Filter>(), Ok(Filter::Scanlines));
+    assert!("blur".parse::<Filter>().is_err());
+    assert_eq!(Filter::Scanlines.next(), Filter::None);
+
+    let pixels = vec![200; ppu::SCREEN_WIDTH * ppu::SCREEN_HEIGHT * 3];
+    assert_eq!(scanout::apply_filter(&pixels, Filter::None), pixels);
+    let row_len = ppu::SCREEN_WIDTH * FILTER_FACTOR * 3;
+    let at = |image: &[u8], x: usize, y: usize| image[y * row_len + x * 3];
+    let grid = scanout::apply_filter(&pixels, Filter::Grid);
+    assert_eq!(grid.len(), pixels.len() * FILTER_FACTOR * FILTER_FACTOR);
+    assert_eq!((at(&grid, 0, 0), at(&grid, 2, 0), at(&grid, 0, 2), at(&grid, 3, 3)), (200, 125, 125, 200));
+    let scanlines = scanout::apply_filter(&pixels, Filter::Scanlines);
+    assert_eq!((at(&scanlines, 2, 0), at(&scanlines, 2, 2), at(&scanlines, 5, 5)), (200, 100, 100));
+
+    println!("  [OK] Screens scale by whole multiples and filters draw the LCD grid or scanlines");
+}
+
+fn test_frame_blending() {
+    use scanout::FrameBlend;
+
+    let (white, black) = ([0xFF; 3], [0x00; 3]);
+    let mut off = FrameBlend::new(0);
+    off.apply(&white);
+    assert_eq!(off.apply(&black), black, "no blending shows each frame as it is");
+    assert!(off.is_settled());
+
+    // A sprite drawn every other frame comes out half way between
+    let mut blend = FrameBlend::new(50);
+    assert_eq!(blend.apply(&white), white, "the first frame has nothing to mix with");
+    assert_eq!(blend.apply(&black), [0x7F; 3]);
+    assert!(!blend.is_settled());
+    assert_eq!(blend.apply(&white), [0xBF; 3]);
+
+    // A still picture settles on the frame itself
+    let mut frames = 0;
+    while !blend.is_settled() {
+        blend.apply(&black);
+        frames += 1;
+        assert!(frames < 16, "the ghost fades out");
+    }
+    assert_eq!(blend.apply(&black), black);
+
+    assert_eq!(FrameBlend::new(100).percent(), scanout::MAX_FRAME_BLEND);
+
+    println!("  [OK] Frames blend with the last one shown and fade to a still picture");
+}
+
+fn test_compressed_roms() {
+    use rust_gbc::archive;
+    use rust_gbc::png::crc32;
+
+    let rom: Vec<u8> = (0..0x8000u32).map(|i| (i * 7 % 251) as u8).collect();
+    // One stored deflate block per 64 KB, enough for a small ROM
+    let deflate = |data: &[u8]| {
+        let len = data.len() as u16;
+        [&[0x01][..], &len.to_le_bytes(), &(!len).to_le_bytes(), data].concat()
+    };
+
+    // With a file name in the header, as gzip writes by default
+    let mut gz = vec![0x1F, 0x8B, 8, 0x08, 0, 0, 0, 0, 0, 0xFF];
+    gz.extend_from_slice(b"game.gb\0");
+    gz.extend(deflate(&rom));
+    gz.extend_from_slice(&crc32(&rom).to_le_bytes());
+    gz.extend_from_slice(&(rom.len() as u32).to_le_bytes());
+    assert_eq!(archive::unpack(gz.clone()).unwrap(), rom);
+    let last = gz.len() - 5;
+    gz[last] ^= 1;
+    assert!(archive::gunzip(&gz).unwrap_err().contains("checksum"));
+
+    // A zip with a readme ahead of the ROM, which is picked by its extension
+    let zip = |entries: &[(&str, &[u8])]| {
+        let (mut out, mut directory) = (Vec::new(), Vec::new());
+        for (name, data) in entries {
+            let packed = deflate(data);
+            let fields = [
+                &8u16.to_le_bytes()[..],
+                &[0; 4],
+                &crc32(data).to_le_bytes(),
+                &(packed.len() as u32).to_le_bytes(),
+                &(data.len() as u32).to_le_bytes(),
+                &(name.len() as u16).to_le_bytes(),
+                &[0; 2],
+            ]
+            .concat();
+            directory.extend([&b"PK\x01\x02"[..], &[20, 0, 20, 0, 0, 0], &fields, &[0; 10], &(out.len() as u32).to_le_bytes(), name.as_bytes()].concat());
+            out.extend([&b"PK\x03\x04"[..], &[20, 0, 0, 0], &fields, name.as_bytes(), &packed].concat());
+        }
+        let count = (entries.len() as u16).to_le_bytes();
+        let end = [&b"PK\x05\x06"[..], &[0; 4], &count, &count, &(directory.len() as u32).to_le_bytes(), &(out.len() as u32).to_le_bytes(), &[0; 2]].concat();
+        [out, directory, end].concat()
+    };
+    let archive = zip(&[("readme.txt", b"hello"), ("__MACOSX/._Game.GBC", b"fork"), ("roms/Game.GBC", &rom)]);
+    assert_eq!(archive::unpack(archive).unwrap(), rom);
+    let error = archive::unpack(zip(&[("readme.txt", b"hello")])).unwrap_err();
+    assert_eq!(error, "zip archive has no .gb or .gbc file, only readme.txt");
+
+    // Anything else is a plain ROM
+    assert_eq!(archive::unpack(rom.clone()).unwrap(), rom);
+
+    println!("  [OK] ROMs load from .gz files and the first .gb/.gbc entry of .zip archives");
+}
+
+fn test_huc1_mbc30() {
+    // Each bank's number in its first byte
+    let banked_rom = |size: usize, cartridge_type: u8, ram_code: u8| {
+        let mut rom = vec![0; size];
+        for bank in 0..size / 0x4000 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        rom[0x147] = cartridge_type;
+        rom[0x149] = ram_code;
+        rom
+    };
+    let mut memory = memory::Memory::new();
+
+    // A 4MB MBC3 is an MBC30, with bank 0x81 out of reach of the MBC3's 7 bits
+    memory.load_rom(&banked_rom(0x40_0000, 0x13, 0x05)).expect("supported cartridge");
+    memory.write_byte(0x2000, 0x81);
+    assert_eq!(memory.read_byte(0x4000), 0x81);
+    // and eight RAM banks
+    memory.write_byte(0x0000, 0x0A);
+    for bank in 0..8 {
+        memory.write_byte(0x4000, bank);
+        memory.write_byte(0xA000, 0x10 + bank);
+    }
+    memory.write_byte(0x4000, 0x07);
+    assert_eq!(memory.read_byte(0xA000), 0x17);
+    assert_eq!(memory.cartridge_ram().iter().step_by(0x2000).copied().collect::<Vec<_>>(), [0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17]);
+
+    // A 2MB one stays an MBC3
+    memory.load_rom(&banked_rom(0x20_0000, 0x13, 0x03)).expect("supported cartridge");
+    memory.write_byte(0x2000, 0x81);
+    assert_eq!(memory.read_byte(0x4000), 0x01);
+
+    // HuC1: 6 bits of ROM bank, RAM without enabling, and the IR port over it
+    memory.load_rom(&banked_rom(0x10_0000, 0xFF, 0x03)).expect("supported cartridge");
+    memory.write_byte(0x2000, 0x3F);
+    assert_eq!(memory.read_byte(0x4000), 0x3F);
+    memory.write_byte(0x4000, 0x02);
+    memory.write_byte(0xA000, 0x42);
+    memory.write_byte(0x0000, 0x0E);
+    assert_eq!(memory.read_byte(0xA000), 0xC0, "no light comes in");
+    memory.write_byte(0xA000, 0x01);
+    memory.write_byte(0x0000, 0x00);
+    assert_eq!(memory.read_byte(0xA000), 0x42, "the LED write didn't reach the RAM");
+    assert_eq!(memory.cartridge_ram()[0x4000], 0x42);
+
+    let header = CartridgeHeader::parse(&banked_rom(0x8000, 0xFF, 0x03)).unwrap();
+    assert_eq!(header.cartridge_type_name(), "HuC1+RAM+BATTERY");
+    assert!(header.has_battery());
+
+    println!("  [OK] MBC30 reaches 256 ROM and 8 RAM banks, HuC1 banks its ROM and RAM and maps its IR port");
+}
+
+fn test_pocket_camera() {
+    use rust_gbc::camera::{StillImage, PICTURE_START};
+
+    let mut rom = vec![0; 0x10_0000];
+    rom[0x147] = 0xFC;
+    rom[0x149] = 0x04;
+    let mut memory = memory::Memory::new();
+    memory.load_rom(&rom).expect("supported cartridge");
+    assert_eq!(memory.cartridge_ram().len(), 0x2_0000);
+    // Black on the left half, white on the right
+    let image = png::Image { width: 2, height: 1, pixels: vec![0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF] };
+    memory.camera_mut().expect("the camera has a sensor").set_source(Box::new(StillImage::new(&image)));
+
+    // Bit 4 of the RAM bank maps the registers; the same thresholds across
+    // the whole matrix
+    memory.write_byte(0x4000, 0x10);
+    for at in (0..16).map(|cell| 0xA006 + cell * 3) {
+        for (offset, threshold) in [0x40, 0x80, 0xC0].into_iter().enumerate() {
+            memory.write_byte(at + offset as u16, threshold);
+        }
+    }
+    assert_eq!(memory.read_byte(0xA006), 0x00, "only register 0 reads back");
+    memory.write_byte(0xA000, 0x01);
+    assert_eq!(memory.read_byte(0xA000) & 0x01, 0x01, "busy while capturing");
+    memory.write_byte(0x4000, 0x00);
+    assert_eq!(memory.read_byte(0xA100), 0x00, "RAM is the sensor's until it's done");
+    for _ in 0..200 {
+        memory.tick(1024);
+    }
+    memory.write_byte(0x4000, 0x10);
+    assert_eq!(memory.read_byte(0xA000) & 0x01, 0x00, "done");
+
+    // Dark pixels come out black, light ones white, in 2bpp tiles
+    memory.write_byte(0x4000, 0x00);
+    assert_eq!((memory.read_byte(0xA100), memory.read_byte(0xA101)), (0xFF, 0xFF));
+    let right = (PICTURE_START + 8 * 16) as u16 + 0xA000;
+    assert_eq!((memory.read_byte(right), memory.read_byte(right + 1)), (0x00, 0x00));
+
+    // RAM writes need enabling, reads don't
+    memory.write_byte(0xA000, 0x12);
+    assert_eq!(memory.read_byte(0xA000), 0x00);
+    memory.write_byte(0x0000, 0x0A);
+    memory.write_byte(0xA000, 0x12);
+    assert_eq!(memory.read_byte(0xA000), 0x12);
+
+    println!("  [OK] The camera maps its registers, takes a while to capture and dithers the picture into RAM");
+}
+
+fn test_rumble() {
+    let mut rom = vec![0; 0x8000];
+    rom[0x147] = 0x1D;
+    rom[0x149] = 0x03;
+    let mut memory = memory::Memory::new();
+    memory.load_rom(&rom).expect("supported cartridge");
+
+    // On for three quarters of the time
+    memory.write_byte(0x4000, 0x08);
+    memory.tick(300);
+    memory.write_byte(0x4000, 0x00);
+    memory.tick(100);
+    assert_eq!(memory.take_rumble(), Some(0.75));
+    assert_eq!(memory.take_rumble(), Some(0.0), "nothing has run since");
+
+    // Bit 3 drives the motor rather than selecting RAM bank 8
+    memory.write_byte(0x0000, 0x0A);
+    memory.write_byte(0x4000, 0x01);
+    memory.write_byte(0xA000, 0x11);
+    memory.write_byte(0x4000, 0x09);
+    assert_eq!(memory.read_byte(0xA000), 0x11);
+    memory.tick(10);
+    assert_eq!(memory.take_rumble(), Some(1.0));
+
+    // Plain MBC5s have no motor, and bit 3 is a bank bit
+    rom[0x147] = 0x1B;
+    memory.load_rom(&rom).expect("supported cartridge");
+    memory.write_byte(0x4000, 0x08);
+    memory.tick(10);
+    assert_eq!(memory.take_rumble(), None);
+
+    // Each switch is an event stamped with its cycle, so a frontend can time
+    // pulses shorter than a frame; writing the same state again isn't one
+    rom[0x147] = 0x1D;
+    // LD A,08; LD (4000),A; NOP x4; LD A,00; LD (4000),A; LD (4000),A; JR -2
+    rom[0x0100..0x0114].copy_from_slice(&[
+        0x3E, 0x08, 0xEA, 0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x3E, 0x00, 0xEA, 0x00, 0x40, 0xEA, 0x00, 0x40, 0x18,
+        0xFE, 0x00,
+    ]);
+    let mut gb = GameBoy::new();
+    gb.set_skip_boot(true);
+    gb.load_rom(&rom).expect("supported cartridge");
+    gb.reset();
+    gb.run_frame();
+    let switches: Vec<(u64, f32)> = gb.take_events().into_iter()
+        .filter_map(|timed| match timed.event {
+            events::EmulatorEvent::Rumble { strength } => Some((timed.cycle, strength)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(switches.iter().map(|&(_, strength)| strength).collect::<Vec<_>>(), [1.0, 0.0]);
+    assert_eq!(switches[1].0 - switches[0].0, 40, "four NOPs, LD A,n and LD (nn),A apart");
+
+    println!("  [OK] Rumble cartridges report how long the motor ran and when it switched");
+}
+
+fn test_infrared() {
+    use std::net::{TcpListener, TcpStream};
+
+    let boot = |code: &[u8]| {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0143] = 0x80;
+        rom[0x0100..0x0100 + code.len()].copy_from_slice(code);
+        let mut gb = GameBoy::new();
+        gb.set_model(HardwareModel::Cgb);
+        gb.set_skip_boot(true);
+        gb.load_rom(&rom).expect("test ROM has a header");
+        gb.reset();
+        gb
+    };
+    // One side turns its LED on, the other reads its sensor into C000
+    let sender = boot(&[0x3E, 0xC1, 0xE0, 0x56, 0x18, 0xFE]);
+    let receiver = boot(&[0x3E, 0xC0, 0xE0, 0x56, 0xF0, 0x56, 0xEA, 0x00, 0xC0, 0x18, 0xF9]);
+    let mut session = LinkSession::new(sender, receiver);
+    session.run_frame();
+    let [sender, receiver] = session.games_mut();
+    assert_eq!(receiver.peek(0xC000), 0xFC, "bit 1 reads 0 with the partner's LED on");
+    assert_eq!(sender.peek(0xFF56), 0xFF, "the dark receiver sends no light back");
+
+    // The sensor only reports light while reading is enabled
+    let mut port = infrared::InfraredPort::new();
+    port.set_light(true);
+    assert_eq!(port.read(), 0x02);
+    port.write(0xC0);
+    assert_eq!(port.read(), 0xC0);
+    assert_eq!(port.take_led_change(), None);
+    port.write(0xC1);
+    assert_eq!((port.take_led_change(), port.take_led_change()), (Some(true), None));
+
+    // Over a TCP link the LED's state crosses to the partner
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind a local port");
+    let addr = listener.local_addr().unwrap();
+    let partner = std::thread::spawn(move || {
+        let mut link = NetLink::from_stream(listener.accept().unwrap().0).expect("handshake");
+        let mut serial = serial::Serial::new();
+        let mut interrupts = interrupts::Interrupts::new();
+        let mut infrared = infrared::InfraredPort::new();
+        infrared.write(0xC0);
+        while infrared.read() & 0x02 != 0 {
+            link.service(&mut serial, &mut interrupts, &mut infrared, 4).expect("partner stays connected");
+        }
+    });
+    let mut link = NetLink::from_stream(TcpStream::connect(addr).unwrap()).expect("handshake");
+    let mut serial = serial::Serial::new();
+    let mut interrupts = interrupts::Interrupts::new();
+    let mut infrared = infrared::InfraredPort::new();
+    infrared.write(0x01);
+    link.service(&mut serial, &mut interrupts, &mut infrared, 4).expect("the partner is there");
+    partner.join().unwrap();
+
+    println!("  [OK] Infrared light crosses between linked instances");
+}
+
+fn test_state_hash() {
+    let boot = |model: HardwareModel, seed: u64| {
+        let mut gb = GameBoy::new();
+        gb.set_model(model);
+        gb.set_skip_boot(true);
+        gb.set_power_on_pattern(PowerOnPattern::HardwareLike, seed);
+        gb.load_rom(&selftest::test_rom()).expect("test ROM has a header");
+        gb.reset();
+        gb
+    };
+
+    for model in [HardwareModel::Dmg, HardwareModel::Agb] {
+        let (mut first, mut second) = (boot(model, 7), boot(model, 7));
+        for frame in 0..30u8 {
+            let buttons = frame.wrapping_mul(37);
+            first.set_inputs(buttons);
+            second.set_inputs(buttons);
+            first.run_frame();
+            second.run_frame();
+            assert_eq!(first.state_hash(), second.state_hash(), "{:?} runs split at frame {}", model, frame);
+        }
+        assert_eq!(first.state_hash(), savestate::state_hash(&first.save_state()));
+
+        // Loading a state reproduces the hash, and the run carries on the same
+        let state = first.save_state();
+        first.run_frame();
+        let expected = first.state_hash();
+        second.load_state(&state).expect("state loads");
+        second.run_frame();
+        assert_eq!(second.state_hash(), expected);
+    }
+    assert_ne!(boot(HardwareModel::Dmg, 7).state_hash(), boot(HardwareModel::Dmg, 8).state_hash(), "the seed is part of the state");
+
+    println!("  [OK] Identical runs hash identically frame by frame");
+}
+
+fn test_netplay() {
+    use std::net::UdpSocket;
+    use std::time::{Duration, Instant};
+
+    // Keeps reading the joypad into C000-C0FF, so the buttons end up in the state
+    let mut rom = vec![0; 0x8000];
+    rom[0x0100..0x010D].copy_from_slice(&[0x21, 0x00, 0xC0, 0x3E, 0x10, 0xE0, 0x00, 0xF0, 0x00, 0x77, 0x2C, 0x18, 0xF6]);
+    let boot = move |rom: &[u8]| {
+        let mut gb = GameBoy::new();
+        gb.set_skip_boot(true);
+        gb.load_rom(rom).expect("test ROM has a header");
+        gb.reset();
+        gb
+    };
+    let free_port = || UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    // Each player's buttons change at its own pace; the joining side stalls
+    // now and then so the host has to guess and roll back
+    let play = |netplay: &mut Netplay, gb: &mut GameBoy, player: u64| {
+        for frame in 0..240u64 {
+            let buttons = if player == 0 { (frame / 5 % 4) as u8 } else { ((frame / 3 % 3) << 4) as u8 };
+            if player == 1 && frame % 40 == 0 {
+                std::thread::sleep(Duration::from_millis(40));
+            }
+            while !netplay.run_frame(gb, buttons).expect("the peer stays connected") {}
+            assert_eq!(netplay.take_desync(), None, "player {} desynced", player);
+        }
+        // Stay around for the peer's last frames
+        let until = Instant::now() + Duration::from_millis(300);
+        while Instant::now() < until && netplay.poll(gb).is_ok() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    };
+
+    for rollback in [0, netplay::MAX_ROLLBACK] {
+        let port = free_port();
+        let host_rom = rom.clone();
+        let host = std::thread::spawn(move || {
+            let mut gb = boot(&host_rom);
+            let mut netplay = Netplay::host(port, &mut gb, 2, rollback).expect("the other player joins");
+            play(&mut netplay, &mut gb, 0);
+            (netplay.frame(), gb.state_hash())
+        });
+        let mut gb = boot(&rom);
+        // The joining side starts from the host's state, whatever its own
+        gb.write_byte(0xD000, 0x55);
+        let mut netplay = Netplay::join(("127.0.0.1", port), &mut gb, 2, rollback).expect("joins the host");
+        assert_eq!(gb.peek(0xD000), 0x00);
+        play(&mut netplay, &mut gb, 1);
+        let joined = (netplay.frame(), gb.state_hash());
+        drop(netplay);
+        let hosted = host.join().unwrap();
+        assert_eq!(hosted, joined, "both sides end in the same state");
+    }
+
+    // A different game is turned away
+    let port = free_port();
+    let host = std::thread::spawn(move || Netplay::host(port, &mut boot(&vec![0; 0x8000]), 2, 0).is_err());
+    let error = Netplay::join(("127.0.0.1", port), &mut boot(&rom), 2, 0).err().expect("the ROMs differ");
+    assert!(error.to_string().contains("different ROM"));
+    assert!(host.join().unwrap());
+
+    println!("  [OK] Two instances share one game over UDP, in lockstep and with rollback");
+}
+
+fn test_hooks() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use rust_gbc::debugger::DebugStop;
+    use rust_gbc::hooks::Break;
+    use ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+    const FRAME_BYTES: usize = SCREEN_WIDTH * SCREEN_HEIGHT * 3;
+
+    // Sends B over the unconnected serial port forever, counting B up
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+    rom[0x150..0x160].copy_from_slice(&[0x78, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0xF0, 0x02, 0xE6, 0x80, 0x20, 0xFA, 0x04, 0x18, 0xF0]);
+    let mut gb = GameBoy::new();
+    gb.set_skip_boot(true);
+    gb.load_rom(&rom).expect("test ROM has a header");
+    gb.reset();
+
+    let frames = Rc::new(RefCell::new(Vec::new()));
+    let vblanks = Rc::new(RefCell::new(0));
+    let serial = Rc::new(RefCell::new(Vec::new()));
+    let writes = Rc::new(RefCell::new(Vec::new()));
+    let breaks = Rc::new(RefCell::new(Vec::new()));
+    let log = frames.clone();
+    let frame_hook = gb.on_frame(move |frame| log.borrow_mut().push((frame.index, frame.pixels.len())));
+    let log = vblanks.clone();
+    gb.on_vblank(move |bus| {
+        *log.borrow_mut() += 1;
+        // VBlank hooks can poke the machine, like a cheat would
+        bus.write_byte(0xC000, 0x42);
+    });
+    let log = serial.clone();
+    gb.on_serial(move |sent, received| log.borrow_mut().push((sent, received)));
+    let log = writes.clone();
+    let write_hook = gb.on_write(0xFF01, move |addr, value| log.borrow_mut().push((addr, value)));
+    let log = breaks.clone();
+    gb.on_break(move |reason, pc| log.borrow_mut().push((reason, pc)));
+
+    for _ in 0..3 {
+        gb.run_frame().expect("no breakpoints set");
+    }
+    assert_eq!(*frames.borrow(), [(0, FRAME_BYTES), (1, FRAME_BYTES), (2, FRAME_BYTES)]);
+    assert_eq!(*vblanks.borrow(), 3);
+    assert_eq!(gb.peek(0xC000), 0x42);
+    let sent: Vec<u8> = serial.borrow().iter().map(|&(sent, _)| sent).collect();
+    assert!(sent.len() > 2 && serial.borrow().iter().all(|&(_, received)| received == 0xFF));
+    // Every byte the loop put in SB, including the one still going out
+    let written: Vec<u8> = writes.borrow().iter().map(|&(addr, value)| { assert_eq!(addr, 0xFF01); value }).collect();
+    assert_eq!(written[..sent.len()], sent[..]);
+    assert!(breaks.borrow().is_empty());
+
+    // Removed hooks stay quiet, and an id only removes once
+    assert!(gb.remove_hook(frame_hook));
+    assert!(gb.remove_hook(write_hook));
+    assert!(!gb.remove_hook(write_hook));
+    let (frame_count, write_count) = (frames.borrow().len(), writes.borrow().len());
+    gb.run_frame();
+    assert_eq!((frames.borrow().len(), writes.borrow().len()), (frame_count, write_count));
+
+    // Event breakpoints and debugger stops both reach break hooks
+    gb.debugger().add_breakpoint(0x0150);
+    assert!(gb.run_frame().is_none());
+    assert_eq!(*breaks.borrow().last().unwrap(), (Break::Debugger(DebugStop::Breakpoint), 0x0150));
+    gb.debugger().remove_breakpoint(0x0150);
+    gb.add_breakpoint(EventBreakpoint::VBlank);
+    while gb.run_frame().is_some() {}
+    assert_eq!(breaks.borrow().last().map(|&(reason, _)| reason), Some(Break::Event(EventBreakpoint::VBlank)));
+    assert_eq!(breaks.borrow().len(), 2);
+
+    println!("  [OK] Frame, VBlank, serial, write and break hooks fire as the machine runs");
+}
+
+fn test_scripts() {
+    use rust_gbc::headless::{HeadlessOptions, StopReason};
+    use rust_gbc::overlay::OverlayText;
+    use rust_gbc::{Button, SCREEN_WIDTH};
+
+    // Keeps reading the joypad into C000-C0FF, so the buttons end up in memory
+    let mut rom = vec![0; 0x8000];
+    rom[0x0100..0x010D].copy_from_slice(&[0x21, 0x00, 0xC0, 0x3E, 0x10, 0xE0, 0x00, 0xF0, 0x00, 0x77, 0x2C, 0x18, 0xF6]);
+    let mut gb = GameBoy::new();
+    gb.set_skip_boot(true);
+    gb.load_rom(&rom).expect("test ROM has a header");
+    gb.reset();
+
+    let source = r#"
+        gb.write(0xD000, 0x34)
+        gb.write(0xD001, 0x12)
+        assert(gb.read16(0xD000) == 0x1234)
+        assert(gb.registers().pc >= 0x100)
+        frames = 0
+        gb.on_frame(function()
+            frames = frames + 1
+            gb.write(0xD002, gb.frame())
+            gb.input{a = frames % 2 == 1, right = true}
+            gb.text(1, 1, "F" .. frames, 0xFF0000)
+        end)
+    "#;
+    let mut script = Script::new("test.lua", source, &mut gb).expect("the script runs");
+    assert_eq!(script.buttons(), 0);
+    for frame in 1..=3u8 {
+        gb.set_inputs(script.buttons());
+        gb.run_frame();
+        script.after_frame(&mut gb).expect("on_frame runs");
+        assert_eq!(gb.peek(0xD002), frame);
+        // What the last call asked for was held during this frame
+        let a_held = frame % 2 == 0;
+        assert_eq!(gb.peek(0xC000) & 0x01, if a_held { 0 } else { 1 });
+        let expected = Button::Right.mask() | if frame % 2 == 1 { Button::A.mask() } else { 0 };
+        assert_eq!(script.buttons(), expected);
+    }
+    // Text is drawn in the given color with a shadow, on top of the frame
+    assert!(script.has_overlay());
+    let mut pixels = gb.frame().pixels.to_vec();
+    script.draw_overlay(&mut pixels);
+    let mut expected = gb.frame().pixels.to_vec();
+    OverlayText { x: 1, y: 1, text: "F3".to_string(), color: [0xFF, 0x00, 0x00] }.draw(&mut expected);
+    assert_eq!(pixels, expected);
+    assert_eq!(pixels[(SCREEN_WIDTH + 1) * 3..][..3], [0xFF, 0x00, 0x00], "F's top left pixel");
+    assert_eq!(pixels[(2 * SCREEN_WIDTH + 2) * 3..][..3], [0x00, 0x00, 0x00], "the shadow under F's top row");
+
+    // Errors name the script, and end a headless run with a failure status
+    let error = Script::new("broken.lua", "gb.input{jump = true}", &mut gb).err().expect("there's no jump button");
+    assert!(error.to_string().contains("broken.lua") && error.to_string().contains("unknown button"));
+    let mut failing = Script::new("failing.lua", "gb.on_frame(function() if gb.frame() > 5 then error('done') end end)", &mut gb)
+        .expect("the script loads");
+    let options = HeadlessOptions { frames: Some(60), ..Default::default() };
+    let result = headless::run(&mut gb, &options, None, Some(&mut failing));
+    assert_eq!((result.reason, result.frames), (StopReason::DriverError, 3));
+    assert!(result.driver_error.is_some_and(|error| error.to_string().contains("done")));
+    assert_eq!(result.reason.exit_code(), 1);
+
+    println!("  [OK] Scripts read and write memory, hold buttons and draw text each frame");
+}
+
+fn test_ram_search() {
+    use rust_gbc::cheats::Cheat;
+    use rust_gbc::memory::Region;
+    use rust_gbc::ramsearch::{self, Comparison};
+    use std::io::Cursor;
+
+    // Loops in place, leaving work RAM to the test
+    let mut rom = vec![0; 0x8000];
+    rom[0x0100..0x0102].copy_from_slice(&[0x18, 0xFE]);
+    let boot = |rom: &[u8], model: HardwareModel| {
+        let mut gb = GameBoy::new();
+        gb.set_model(model);
+        gb.set_skip_boot(true);
+        gb.load_rom(rom).expect("test ROM has a header");
+        gb.reset();
+        gb
+    };
+    let console = |gb: &mut GameBoy, commands: &str| {
+        let mut output = Vec::new();
+        debugger::console(gb, &mut Cursor::new(commands.to_string()), &mut output);
+        String::from_utf8(output).expect("console output is text")
+    };
+
+    // Lives at C100 go from 3 to 2 while a timer at D200 counts up
+    let mut gb = boot(&rom, HardwareModel::Dmg);
+    gb.write_byte(0xC100, 3);
+    gb.write_byte(0xD200, 3);
+    let output = console(&mut gb, "search\n");
+    assert!(output.contains("search started with 8192 candidates"), "{}", output);
+    gb.run_frame();
+    gb.write_byte(0xC100, 2);
+    gb.write_byte(0xD200, 4);
+    gb.run_frame();
+    let output = console(&mut gb, "search dec\nsearch 2\nresults\nsearch zz\n");
+    assert!(output.contains("candidates left: 1\n(debug) candidates left: 1"), "{}", output);
+    assert!(output.contains("wram:0:C100 02"), "{}", output);
+    assert!(output.contains("invalid comparison 'zz'"), "{}", output);
+
+    // Pinned bytes show up as watches, and a code holds them
+    let output = console(&mut gb, "pin c100\npin wram:1:d200\nunpin d200\nunpin d300\nshark c100 63\n");
+    assert!(output.contains("d300 isn't pinned") && output.contains("016300C1"), "{}", output);
+    assert_eq!(gb.ram_search().watch_text(gb.searchable_wram()), "C100 02 2");
+    gb.add_cheat(Cheat::parse("016300C1", "lives").unwrap());
+    gb.run_frame();
+    assert_eq!(gb.peek(0xC100), 0x63);
+
+    // In CGB mode all eight banks are searched, and codes name their bank
+    rom[0x0143] = 0x80;
+    let mut gb = boot(&rom, HardwareModel::Cgb);
+    gb.write_byte(0xFF70, 3);
+    gb.write_byte(0xD010, 7);
+    gb.start_ram_search();
+    assert_eq!(gb.ram_search().candidates().len(), 0x8000);
+    gb.write_byte(0xD010, 9);
+    gb.filter_ram_search(Comparison::Increased);
+    assert_eq!(gb.ram_search().candidates(), [(0x3010, 9)]);
+    assert_eq!(ramsearch::wram_location(0x3010), (3, 0xD010));
+    let code = ramsearch::shark_code(0x3010, 0x30, true);
+    assert_eq!(code, "933010D0");
+    gb.write_byte(0xFF70, 1);
+    gb.add_cheat(Cheat::parse(&code, "").unwrap());
+    gb.run_frame();
+    assert_eq!(gb.region(Region::Wram)[0x3010], 0x30, "the code reaches bank 3 while bank 1 is mapped");
+    gb.ram_search_mut().pin(0x3010);
+    assert_eq!(gb.ram_search().watch_text(gb.searchable_wram()), "D010/3 30 48");
+
+    println!("  [OK] Work RAM narrows down across frames into watches and GameShark codes");
+}
+
+fn test_perf_counters() {
+    use std::time::{Duration, Instant};
+
+    let mut gb = GameBoy::new();
+    gb.set_skip_boot(true);
+    gb.load_rom(&selftest::test_rom()).expect("test ROM has a header");
+    gb.reset();
+    gb.run_frame();
+    assert_eq!(gb.perf_stats(), None, "profiling starts off");
+
+    // Stats come once a second of frames has gone by
+    gb.set_profiling(true);
+    let start = Instant::now();
+    while gb.perf_stats().is_none() {
+        assert!(start.elapsed() < Duration::from_secs(10), "stats after a second of frames");
+        gb.run_frame();
+    }
+    let stats = gb.perf_stats().expect("stats are in");
+    assert!(stats.fps > 0.0 && stats.speed > 0.0);
+    assert!(stats.ppu > Duration::ZERO && stats.apu > Duration::ZERO);
+    assert!(stats.summary().contains(" fps, "), "summary: {}", stats.summary());
+
+    // Profiling can be turned off again, dropping the stats
+    gb.set_profiling(false);
+    gb.run_frame();
+    assert_eq!(gb.perf_stats(), None);
+    println!("  [OK] Speed and PPU/APU time are measured once profiling is on");
+}
+
+fn test_core_thread() {
+    use rust_gbc::joypad::Button;
+    use rust_gbc::threaded::{CoreThread, MAX_FRAMES_AHEAD};
+
+    let boot = || {
+        let mut gb = GameBoy::new();
+        gb.set_skip_boot(true);
+        gb.load_rom(&selftest::test_rom())?;
+        gb.reset();
+        Ok(gb)
+    };
+    let buttons = |frame: u8| if frame.is_multiple_of(3) { Button::A.mask() } else { 0 };
+
+    // Frames come back in order, with one always in flight, and end up where
+    // the same frames run on this thread do
+    let mut core = CoreThread::spawn(boot).expect("the core thread starts");
+    assert!(core.finish_frame().expect("the thread runs").is_none(), "nothing to finish yet");
+    let mut local = boot().expect("test ROM has a header");
+    core.start_frame(buttons(0)).expect("the thread runs");
+    for frame in 0..10 {
+        core.start_frame(buttons(frame + 1)).expect("the thread runs");
+        assert_eq!(core.pending_frames(), MAX_FRAMES_AHEAD);
+        let output = core.finish_frame().expect("the thread runs").expect("a frame was started");
+        local.set_inputs(buttons(frame));
+        let expected = local.run_frame().expect("no breakpoints").pixels.to_vec();
+        assert_eq!(output.index, frame as u64);
+        assert!(output.complete);
+        assert_eq!(output.pixels, expected);
+        assert!(!output.samples.is_empty());
+    }
+    core.finish_frame().expect("the thread runs");
+    local.set_inputs(buttons(10));
+    local.run_frame();
+    let hash = core.with(|gb| gb.state_hash()).expect("the thread runs");
+    assert_eq!(hash, local.state_hash(), "same state as a single-threaded run");
+
+    // Setup errors come back from spawn
+    let error = CoreThread::spawn(|| {
+        let mut gb = GameBoy::new();
+        gb.load_rom(&[0; 16])?;
+        Ok(gb)
+    });
+    assert!(matches!(error, Err(EmulatorError::RomTooSmall(16))));
+    println!("  [OK] The core thread runs frames in order and matches a single-threaded run");
+}
+
+// What the cached interpreter must get right: the same address running
+// different code in different ROM banks, and code in work RAM rewritten
+// after it ran
+fn test_code_changes() {
+    let mut rom = vec![0; 0x10000];
+    rom[0x0147] = 0x01; // MBC1
+    rom[0x0148] = 0x01; // 4 banks
+    rom[0x0100..0x0103].copy_from_slice(&[0xC3, 0x50, 0x01]); // JP 0150
+    #[rustfmt::skip]
+    let program = [
+        0x3E, 0x01, 0xEA, 0x00, 0x20,   // LD A,1; LD (2000),A
+        0xCD, 0x00, 0x40,               // CALL 4000
+        0xEA, 0x00, 0xC0,               // LD (C000),A
+        0x3E, 0x02, 0xEA, 0x00, 0x20,   // LD A,2; LD (2000),A
+        0xCD, 0x00, 0x40,               // CALL 4000
+        0xEA, 0x01, 0xC0,               // LD (C001),A
+        0x21, 0x00, 0xC1,               // LD HL,C100
+        0x36, 0x3E, 0x23,               // LD (HL),3E; INC HL
+        0x36, 0x33, 0x23,               // LD (HL),33; INC HL
+        0x36, 0xC9,                     // LD (HL),C9
+        0xCD, 0x00, 0xC1,               // CALL C100, running LD A,33; RET
+        0xEA, 0x02, 0xC0,               // LD (C002),A
+        0x3E, 0x44, 0xEA, 0x01, 0xC1,   // LD A,44; LD (C101),A
+        0xCD, 0x00, 0xC1,               // CALL C100, now LD A,44; RET
+        0xEA, 0x03, 0xC0,               // LD (C003),A
+        0x18, 0xFE,                     // JR -2
+    ];
+    rom[0x0150..0x0150 + program.len()].copy_from_slice(&program);
+    rom[0x4000..0x4003].copy_from_slice(&[0x3E, 0x11, 0xC9]); // bank 1: LD A,11; RET
+    rom[0x8000..0x8003].copy_from_slice(&[0x3E, 0x22, 0xC9]); // bank 2: LD A,22; RET
+
+    let mut gb = GameBoy::new();
+    gb.set_skip_boot(true);
+    gb.load_rom(&rom).expect("test ROM has a header");
+    gb.reset();
+    // The second time through runs code already seen
+    for pass in 0..2 {
+        for addr in 0xC000..0xC004 {
+            gb.write_byte(addr, 0);
+        }
+        gb.cpu.pc = 0x0150;
+        gb.run_frame();
+        let results: Vec<u8> = (0xC000..0xC004).map(|addr| gb.peek(addr)).collect();
+        assert_eq!(results, [0x11, 0x22, 0x33, 0x44], "pass {}", pass + 1);
+    }
+    println!("  [OK] Switched banks and rewritten RAM run the code now there");
+}
+
+fn test_dev_warnings() {
+    use rust_gbc::dev_warnings::{DevWarning, DevWarningKind};
+
+    fn warnings(program: &[u8], enabled: bool) -> Vec<DevWarning> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0103].copy_from_slice(&[0xC3, 0x50, 0x01]); // JP 0150
+        rom[0x0150..0x0150 + program.len()].copy_from_slice(program);
+        let mut gb = GameBoy::new();
+        gb.set_skip_boot(true);
+        gb.load_rom(&rom).expect("test ROM has a header");
+        gb.reset();
+        gb.set_dev_warnings(enabled);
+        gb.cpu.pc = 0x0150;
+        for _ in 0..100 {
+            gb.step();
+        }
+        gb.take_events()
+            .into_iter()
+            .filter_map(|timed| match timed.event {
+                events::EmulatorEvent::DevWarning(warning) => Some(warning),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[rustfmt::skip]
+    let stack = [
+        0x31, 0x82, 0xFF,   // LD SP,FF82
+        0xC5, 0xC5,         // PUSH BC; PUSH BC, into the I/O registers
+        0x31, 0xFE, 0xFF,   // LD SP,FFFE
+        0xC1,               // POP BC, wrapping to 0000
+        0x31, 0xF0, 0xDF,   // LD SP,DFF0
+        0x31, 0x34, 0x12,   // LD SP,1234
+        0xC1,               // POP BC, reading from ROM
+        0xC5,               // PUSH BC, writing to it
+        0x31, 0xF0, 0xDF,   // LD SP,DFF0
+        0x18, 0xFE,         // JR -2
+    ];
+    let found = warnings(&stack, true);
+    let kinds: Vec<(DevWarningKind, u16, u16)> = found.iter().map(|w| (w.kind, w.pc, w.sp)).collect();
+    assert_eq!(kinds, [
+        (DevWarningKind::StackOverflow, 0x0155, 0xFF7E),
+        (DevWarningKind::StackUnderflow, 0x0159, 0x0000),
+        (DevWarningKind::StackOverflow, 0x0161, 0x1234),
+    ]);
+    // The second push is the one that went too far
+    assert_eq!(found[0].recent, [0x0150, 0x0153, 0x0154]);
+    assert_eq!(found[0].bank, Some(0));
+    assert!(warnings(&stack, false).is_empty(), "off by default");
+    println!("  [OK] Pushes outside RAM and popping past the top reported once each");
+
+    let jump = warnings(&[0xC3, 0x00, 0x80], true); // JP 8000
+    assert_eq!(jump.len(), 1, "running on through VRAM isn't reported again");
+    assert_eq!((jump[0].kind, jump[0].pc, jump[0].bank), (DevWarningKind::BadJump, 0x8000, None));
+    assert_eq!(jump[0].describe(), "executing VRAM at 0x8000");
+    println!("  [OK] A jump into VRAM reported with the recent PCs");
+}
+
+fn test_execution_history() {
+    let mut rom = vec![0; 0x8000];
+    rom[0x0100..0x0103].copy_from_slice(&[0xC3, 0x50, 0x01]); // JP 0150
+    #[rustfmt::skip]
+    let program = [
+        0x3E, 0x42,         // LD A,42
+        0x06, 0x07,         // LD B,07
+        0x04,               // INC B
+        0xEA, 0x00, 0xC0,   // LD (C000),A
+        0xDD,               // locks up
+    ];
+    rom[0x0150..0x0150 + program.len()].copy_from_slice(&program);
+
+    let locked = |history_length: usize| {
+        let mut gb = GameBoy::new();
+        gb.set_skip_boot(true);
+        gb.load_rom(&rom).expect("test ROM has a header");
+        gb.reset();
+        gb.set_history_length(history_length);
+        gb.cpu.pc = 0x0150;
+        for _ in 0..100 {
+            gb.step();
+        }
+        let locks: Vec<_> = gb.take_events()
+            .into_iter()
+            .filter_map(|timed| match timed.event {
+                events::EmulatorEvent::CpuLocked { opcode, pc, history } => Some((opcode, pc, history)),
+                _ => None,
+            })
+            .collect();
+        (gb, locks)
+    };
+
+    let (gb, locks) = locked(4);
+    assert_eq!(locks.len(), 1, "reported once while it stays locked");
+    let (opcode, pc, history) = &locks[0];
+    assert_eq!((*opcode, *pc), (0xDD, 0x0158));
+    let pcs: Vec<u16> = history.iter().map(|entry| entry.registers.pc).collect();
+    assert_eq!(pcs, [0x0152, 0x0154, 0x0155, 0x0158], "the last four, oldest first");
+    let last = history[3];
+    assert_eq!((last.registers.a, last.registers.b, last.bytes[0], last.bank), (0x42, 0x08, 0xDD, Some(0)));
+    assert!(history[2].describe().starts_with("00:0155  EA 00 C0  LD ($C000),A"), "{}", history[2].describe());
+    // The CPU keeps running the locked opcode, which fills the history
+    assert!(gb.history().iter().all(|entry| entry.registers.pc == 0x0158));
+    println!("  [OK] The instructions before an invalid opcode come with its report");
+
+    let (gb, locks) = locked(0);
+    assert_eq!(locks.len(), 1);
+    assert!(locks[0].2.is_empty() && gb.history().is_empty(), "no history unless asked for");
+    println!("  [OK] Lock-ups are reported without history too");
+}
+
+fn test_load_errors() {
+    let mut rom = vec![0; 0x8000];
+    rom[0x0134..0x0138].copy_from_slice(b"GOOD");
+    let mut gb = GameBoy::new();
+    gb.load_rom(&rom).expect("ROM only cartridge");
+    let hash = gb.rom_hash();
+
+    let mut mbc2 = rom.clone();
+    mbc2[0x0147] = 0x05;
+    let error = gb.load_rom(&mbc2).expect_err("no MBC2 here");
+    assert!(matches!(error, EmulatorError::UnsupportedCartridge(0x05)));
+    assert_eq!(error.to_string(), "unsupported cartridge type 0x05");
+    assert_eq!(gb.rom_hash(), hash, "the ROM already loaded stays");
+    println!("  [OK] Unsupported cartridge types are an error, leaving the loaded ROM in place");
+
+    let error = EmulatorError::Frontend { action: "open the audio device", message: "no device".to_string() };
+    assert_eq!(error.to_string(), "failed to open the audio device: no device");
+    println!("  [OK] Frontend errors say what failed");
+}
+
+fn test_rom_browser() {
+    use ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    let dir = std::env::temp_dir().join(format!("rust-gbc-browser-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for index in 0..30 {
+        fs::write(dir.join(format!("game{:02}.gb", index)), [0]).unwrap();
+    }
+    fs::write(dir.join("notes.txt"), [0]).unwrap();
+    let mut browser = RomBrowser::open(&dir).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(browser.roms().len(), 30, "only ROMs are listed");
+    assert_eq!(browser.selected(), Some(dir.join("game00.gb").as_path()));
+    browser.move_selection(-1);
+    assert_eq!(browser.selected(), Some(dir.join("game00.gb").as_path()), "the selection stops at the top");
+    browser.move_selection(25);
+    assert_eq!(browser.selected(), Some(dir.join("game25.gb").as_path()));
+    browser.move_selection(100);
+    assert_eq!(browser.selected(), Some(dir.join("game29.gb").as_path()), "and at the bottom");
+    println!("  [OK] ROMs in the directory are listed in order and the selection stays on the list");
+
+    // The last name has scrolled into the bottom row
+    let mut pixels = vec![0xFF; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+    browser.draw(&mut pixels);
+    assert!(pixels.iter().all(|&value| value == 0x3F || value == 0xFF || value == 0x00 || value == 0xC0));
+    let green_rows: Vec<usize> = pixels
+        .chunks(3)
+        .enumerate()
+        .filter(|(_, pixel)| *pixel == [0x00, 0xFF, 0x00])
+        .map(|(index, _)| index / SCREEN_WIDTH)
+        .collect();
+    assert!(!green_rows.is_empty(), "the selected name is highlighted");
+    assert!(green_rows.iter().all(|&row| (134..SCREEN_HEIGHT).contains(&row)), "{:?}", green_rows);
+    println!("  [OK] The list is drawn over the dimmed screen, scrolled to the selection");
+
+    assert_eq!(rom_dir(Path::new("game.gb")), PathBuf::from("."));
+    assert_eq!(rom_dir(Path::new("roms/game.gb")), PathBuf::from("roms"));
+    println!("  [OK] The browser starts in the first ROM's directory");
+}
+
+fn test_accuracy_profiles() {
+    assert_eq!("fast".parse::<AccuracyConfig>(), Ok(AccuracyConfig::FAST));
+    assert_eq!("balanced".parse::<AccuracyConfig>(), Ok(AccuracyConfig::BALANCED));
+    assert_eq!(AccuracyConfig::default(), AccuracyConfig::ACCURATE);
+    assert!("turbo".parse::<AccuracyConfig>().is_err());
+    println!("  [OK] Profiles are chosen by name, accurate by default");
+
+    // A scrolled BG, the window and overlapping objects from noise, drawn
+    // both ways over a whole frame
+    let mut interrupts = interrupts::Interrupts::new();
+    let draw = |pixel_fifo: bool, cgb: bool, interrupts: &mut interrupts::Interrupts| {
+        let mut ppu = ppu::PPU::new();
+        ppu.set_pixel_fifo(pixel_fifo);
+        ppu.set_cgb_mode(cgb);
+        let mut seed = 0x1234_5678u32;
+        let mut noise = || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (seed >> 16) as u8
+        };
+        ppu.vram.iter_mut().for_each(|byte| *byte = noise());
+        for i in 0..40 {
+            // Objects bunched into the top of the screen so lines overflow
+            ppu.oam[i * 4..i * 4 + 4].copy_from_slice(&[16 + noise() % 64, noise() % 176, noise(), noise()]);
+        }
+        for (addr, value) in [(0xFF40, 0xF7), (0xFF42, 5), (0xFF43, 13), (0xFF47, 0xE4), (0xFF48, 0xD2), (0xFF4A, 40), (0xFF4B, 60)] {
+            ppu.write(addr, value);
+        }
+        for index in 0..64 {
+            ppu.write(0xFF68, index);
+            ppu.write(0xFF69, index.wrapping_mul(37));
+            ppu.write(0xFF6A, index);
+            ppu.write(0xFF6B, index.wrapping_mul(53));
+        }
+        ppu.tick(70224, interrupts);
+        ppu.framebuffer
+    };
+    for cgb in [false, true] {
+        let fifo = draw(true, cgb, &mut interrupts);
+        let scanline = draw(false, cgb, &mut interrupts);
+        assert!(fifo == scanline, "the scanline renderer draws what the FIFO does, CGB {}", cgb);
+    }
+    println!("  [OK] The scanline renderer draws the same frame as the pixel FIFO");
+
+    let mut ppu = ppu::PPU::new();
+    ppu.set_pixel_fifo(false);
+    ppu.write(0xFF43, 3);
+    let mode3 = (0..456)
+        .filter(|_| {
+            ppu.tick(1, &mut interrupts);
+            ppu.read(0xFF41) & 0x03 == 3
+        })
+        .count();
+    assert_eq!(mode3, 172, "scrolling doesn't lengthen it");
+    println!("  [OK] Without the FIFO mode 3 has a fixed length");
+
+    // The machine comes out the same after each instruction whether it ran
+    // through the accesses or caught up at the end
+    let mut rom = vec![0; 0x8000];
+    rom[0x0100..0x0108].copy_from_slice(&[0xF0, 0x44, 0xEA, 0x00, 0xC0, 0x18, 0xF9, 0x00]);
+    let run = |accuracy: AccuracyConfig| {
+        let mut gb = GameBoy::new();
+        gb.set_skip_boot(true);
+        gb.set_accuracy(accuracy);
+        gb.load_rom(&rom).expect("plain ROM");
+        gb.reset();
+        for _ in 0..20_000 {
+            gb.step();
+        }
+        (gb.cycles(), gb.read_byte(0xFF44), gb.read_byte(0xFF41) & 0x03)
+    };
+    assert_eq!(run(AccuracyConfig::ACCURATE), run(AccuracyConfig::FAST));
+    println!("  [OK] Instructions take as long without access timing");
+}
+
+fn test_channel_overlay() {
+    use ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    let mut apu = apu::APU::new();
+    apu.set_scope_enabled(true);
+    apu.write(0xFF26, 0x80);
+    apu.write(0xFF25, 0xFF);
+    // Pulse 1 at 50% duty and 440 Hz, full volume fading every 3 steps
+    apu.write(0xFF11, 0x80);
+    apu.write(0xFF12, 0xF3);
+    apu.write(0xFF13, 0xD6);
+    apu.write(0xFF14, 0x86);
+    // The wave channel at half level, silent noise
+    apu.write(0xFF1A, 0x80);
+    apu.write(0xFF1C, 0x40);
+    apu.write(0xFF1D, 0x00);
+    apu.write(0xFF1E, 0x87);
+    apu.tick(4 * 1024);
+
+    let status = apu.channel_status();
+    assert!(status[0].playing);
+    assert_eq!(status[0].volume, 15);
+    assert_eq!(status[0].envelope, Some(apu::EnvelopeStatus { increase: false, period: 3 }));
+    assert!((status[0].frequency_hz - 440.0).abs() < 1.0, "got {} Hz", status[0].frequency_hz);
+    assert_eq!((status[2].volume, status[2].envelope), (7, None));
+    assert!((status[2].frequency_hz - 256.0).abs() < 0.5, "got {} Hz", status[2].frequency_hz);
+    assert!(!status[1].playing && !status[3].playing);
+    assert_eq!(channel_overlay::label(0, &status[0]), "PULSE 1  440HZ  VOL 15 -3");
+    assert_eq!(channel_overlay::label(1, &status[1]), "PULSE 2  OFF");
+    println!("  [OK] Each channel reports whether it plays, its volume, envelope and frequency");
+
+    apu.tick(apu::SCOPE_LEN as u32 * 64);
+    let scope = apu.scope();
+    assert_eq!(scope[0].len(), apu::SCOPE_LEN);
+    assert!(scope[0].contains(&15) && scope[0].contains(&0), "the square wave swings between off and full");
+    assert!(scope[1].iter().all(|&output| output == 0));
+    println!("  [OK] The scope keeps each channel's recent output");
+
+    let mut pixels = vec![0xFF; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+    channel_overlay::draw(&mut pixels, &status, &scope);
+    let strip_of = |color: [u8; 3]| {
+        pixels.chunks(3).enumerate()
+            .filter(|(_, pixel)| *pixel == color)
+            .map(|(index, _)| index / SCREEN_WIDTH / channel_overlay::STRIP_HEIGHT)
+            .collect::<std::collections::BTreeSet<_>>()
+    };
+    assert_eq!(strip_of([0xFF, 0x60, 0x60]), [0].into(), "pulse 1 is plotted in the top strip");
+    assert_eq!(strip_of([0x60, 0xC0, 0xFF]), [2].into());
+    println!("  [OK] Each channel is plotted in a strip of its own");
+}
+
+fn test_rtc_halt_and_carry() {
+    use rtc::ManualClock;
+
+    // MBC3+TIMER+BATTERY
+    let mut rom = vec![0; 0x8000];
+    rom[0x147] = 0x0F;
+    let mut gb = GameBoy::new();
+    gb.load_rom(&rom).expect("test ROM has a header");
+    let clock = ManualClock::new(1_000_000);
+    gb.set_clock_source(Box::new(clock.clone()));
+    let bus = &mut gb.cpu.bus;
+    bus.write_byte(0x0000, 0x0A);
+    let write = |bus: &mut bus::MemoryBus, register: u8, value: u8| {
+        bus.write_byte(0x4000, register);
+        bus.write_byte(0xA000, value);
+    };
+    // Seconds, minutes, hours, day low and day high, as latched
+    let latch = |bus: &mut bus::MemoryBus| {
+        bus.write_byte(0x6000, 0x00);
+        bus.write_byte(0x6000, 0x01);
+        [0x08, 0x09, 0x0A, 0x0B, 0x0C].map(|register| {
+            bus.write_byte(0x4000, register);
+            bus.read_byte(0xA000)
+        })
+    };
+
+    // Halted, the counters take what's written and hold it however long passes
+    write(bus, 0x0C, 0x40);
+    write(bus, 0x08, 30);
+    write(bus, 0x09, 59);
+    write(bus, 0x0A, 23);
+    clock.advance(3600);
+    assert_eq!(latch(bus), [30, 59, 23, 0, 0x40]);
+    write(bus, 0x08, 59);
+    clock.advance(100);
+    assert_eq!(latch(bus), [59, 59, 23, 0, 0x40], "a write while halted doesn't start the clock");
+
+    // Day 511 rolls over to 0 and sets the carry; time spent halted isn't caught up
+    write(bus, 0x0B, 0xFF);
+    write(bus, 0x0C, 0x01);
+    assert_eq!(latch(bus), [59, 59, 23, 0xFF, 0x01]);
+    clock.advance(1);
+    assert_eq!(latch(bus), [0, 0, 0, 0x00, 0x80]);
+    // It stays set as the days go on, until the game clears it
+    clock.advance(86400);
+    assert_eq!(latch(bus), [0, 0, 0, 0x01, 0x80]);
+    write(bus, 0x0C, 0x00);
+    assert_eq!(latch(bus), [0, 0, 0, 0x01, 0x00]);
+
+    // The same holds when the clock runs on emulated cycles
+    gb.set_rtc_mode(RtcMode::CycleSynced);
+    let bus = &mut gb.cpu.bus;
+    write(bus, 0x0C, 0x40);
+    for _ in 0..3 {
+        bus.memory.tick(4_194_304);
+    }
+    assert_eq!(latch(bus), [0, 0, 0, 0x01, 0x40]);
+    write(bus, 0x0C, 0x00);
+    bus.memory.tick(4_194_304);
+    assert_eq!(latch(bus), [1, 0, 0, 0x01, 0x00]);
+
+    println!("  [OK] Halting freezes the RTC, writes stick and the day carry stays set");
+}
+
+fn test_hang_watchdog() {
+    use watchdog::DEFAULT_HANG_FRAMES;
+
+    let run = |program: &[u8], frames: u32| {
+        let mut rom = vec![0; 0x8000];
+        // RETI for the VBlank interrupt
+        rom[0x40] = 0xD9;
+        rom[0x0100..0x0100 + program.len()].copy_from_slice(program);
+        let mut gb = GameBoy::new();
+        gb.set_skip_boot(true);
+        gb.load_rom(&rom).expect("test ROM has a header");
+        gb.reset();
+        gb.write_byte(0xFFFF, 0x01);
+        let mut hangs = Vec::new();
+        for _ in 0..frames {
+            gb.run_frame();
+            hangs.extend(gb.take_events().into_iter().filter_map(|timed| match timed.event {
+                events::EmulatorEvent::Hang(report) => Some(report),
+                _ => None,
+            }));
+        }
+        hangs
+    };
+
+    // DI; JR -2 spins at 0x0101 with nothing to break it out, reported once
+    let hangs = run(&[0xF3, 0x18, 0xFE], DEFAULT_HANG_FRAMES * 2);
+    assert_eq!(hangs.len(), 1, "one report however long it spins");
+    let report = &hangs[0];
+    assert_eq!(report.frames, DEFAULT_HANG_FRAMES);
+    assert_eq!((report.pc_low, report.pc_high), (0x0101, 0x0101));
+    assert_eq!(report.registers.pc, 0x0101);
+    assert!(!report.halted);
+
+    // EI; HALT; JR -3 waits for VBlank in place, which is how games idle
+    let hangs = run(&[0xFB, 0x76, 0x18, 0xFD], DEFAULT_HANG_FRAMES * 2);
+    assert!(hangs.is_empty(), "halting with interrupts on isn't a hang");
+
+    println!("  [OK] A loop with interrupts off is reported once, a HALT waiting for VBlank isn't");
+}
+
+fn test_huc3_and_game_rtc_modes() {
+    use rtc::ManualClock;
+
+    // HuC3 with 32KB of RAM and eight ROM banks, each filled with its number
+    let mut rom: Vec<u8> = (0..8).flat_map(|bank| vec![bank; 0x4000]).collect();
+    rom[0x0147] = 0xFE;
+    rom[0x0149] = 0x03;
+    let mut gb = GameBoy::new();
+    gb.load_rom(&rom).expect("test ROM has a header");
+    let clock = ManualClock::new(1_000_000);
+    gb.set_clock_source(Box::new(clock.clone()));
+    let bus = &mut gb.cpu.bus;
+    bus.write_byte(0x2000, 0x05);
+    assert_eq!(bus.read_byte(0x4000), 5);
+
+    // Mode 0xA reads and writes RAM, 0x0 only reads it
+    bus.write_byte(0x0000, 0x0A);
+    bus.write_byte(0xA000, 0x42);
+    bus.write_byte(0x0000, 0x00);
+    bus.write_byte(0xA000, 0x99);
+    assert_eq!(bus.read_byte(0xA000), 0x42);
+
+    let command = |bus: &mut bus::MemoryBus, value: u8| {
+        bus.write_byte(0x0000, 0x0B);
+        bus.write_byte(0xA000, value);
+    };
+    let read_time = |bus: &mut bus::MemoryBus| {
+        command(bus, 0x60);
+        command(bus, 0x40);
+        command(bus, 0x50);
+        [0; 7].map(|_| {
+            command(bus, 0x10);
+            bus.write_byte(0x0000, 0x0C);
+            bus.read_byte(0xA000)
+        })
+    };
+    // 23:59 on day 510, as the minute of the day and the day, a nibble at a time
+    command(bus, 0x40);
+    command(bus, 0x50);
+    for nibble in [0xF, 0x9, 0x5, 0xE, 0xF, 0x1, 0x0] {
+        command(bus, 0x30 | nibble);
+    }
+    command(bus, 0x61);
+    assert_eq!(read_time(bus), [0xF, 0x9, 0x5, 0xE, 0xF, 0x1, 0x0]);
+    clock.advance(60);
+    assert_eq!(read_time(bus), [0x0, 0x0, 0x0, 0xF, 0xF, 0x1, 0x0], "midnight on day 511");
+    bus.write_byte(0x0000, 0x0D);
+    assert_eq!(bus.read_byte(0xA000), 0x01, "the clock is ready");
+
+    // It runs on emulated cycles too, and is kept in battery saves
+    gb.set_rtc_mode(RtcMode::CycleSynced);
+    let bus = &mut gb.cpu.bus;
+    bus.memory.tick(90 * 4_194_304);
+    assert_eq!(read_time(bus), [0x1, 0x0, 0x0, 0xF, 0xF, 0x1, 0x0]);
+    let save = gb.battery_save(SaveFormat::Bgb).expect("HuC3 cartridges have a battery");
+    assert_eq!(save.len(), 0x8000 + 48);
+
+    // Games listed by title get their own mode, the rest none
+    let modes = GameRtcModes::parse("\"POKEMON_GLD\" = \"cycles\"\nROBOPON = \"host\"\n").expect("the file parses");
+    assert_eq!(modes.mode_for("POKEMON_GLD"), Some(RtcMode::CycleSynced));
+    assert_eq!(modes.mode_for("ROBOPON"), Some(RtcMode::HostClock));
+    assert_eq!(modes.mode_for("TETRIS"), None);
+    assert!(GameRtcModes::parse("TETRIS = \"sundial\"").is_err());
+    assert!(GameRtcModes::parse("TETRIS = 1").is_err());
+
+    println!("  [OK] HuC3 banks, keeps RAM and runs its clock by command, RTC modes are picked per title");
+}
+
+fn test_io_write_breakpoints() {
+    use rust_gbc::debugger::Watchpoint;
+
+    let mut rom = vec![0; 0x8000];
+    // LD A,42; LDH (SCY),A; JR -2
+    rom[0x0100..0x0106].copy_from_slice(&[0x3E, 0x42, 0xE0, 0x42, 0x18, 0xFE]);
+    let mut gb = GameBoy::new();
+    gb.set_skip_boot(true);
+    gb.load_rom(&rom).expect("test ROM has a header");
+    gb.reset();
+    gb.add_breakpoint("io:FF42:FF:42".parse().expect("the breakpoint parses"));
+    // Checking the breakpoint reads SCY's old value, which mustn't count as the CPU reading it
+    gb.debugger().add_watchpoint(Watchpoint { addr: 0xFF42, on_read: true, on_write: false });
+    gb.run_frame();
+    let events: Vec<_> = gb.take_events().into_iter().map(|timed| timed.event).collect();
+    assert!(matches!(
+        events.as_slice(),
+        [events::EmulatorEvent::Breakpoint { breakpoint: EventBreakpoint::IoWrite { addr: 0xFF42, .. }, .. }]
+    ), "{:?}", events);
+
+    println!("  [OK] A write that sets the watched bits breaks without tripping read watchpoints");
+}
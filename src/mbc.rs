@@ -1,36 +1,138 @@
-pub trait MemoryBankController {
+use crate::camera::CameraSensor;
+use crate::error::{EmulatorError, Result};
+use crate::rtc::{Rtc, RtcMode};
+use crate::savestate::{Savestate, StateReader, StateWriter};
+
+// The ROM is not part of the state, only the RAM and the bank registers
+pub trait MemoryBankController: Savestate {
     fn read_rom(&self, addr: u16) -> u8;
     fn write_rom(&mut self, addr: u16, value: u8);
+    // The bank read_rom reads addr from, before wrapping around the ROM size
+    fn rom_bank(&self, addr: u16) -> usize {
+        (addr >= 0x4000) as usize
+    }
     fn read_ram(&self, addr: u16) -> u8;
     fn write_ram(&mut self, addr: u16, value: u8);
+
+    // The whole ROM, for the debugger to look at and patch
+    fn rom(&self) -> &[u8];
+    fn rom_mut(&mut self) -> &mut [u8];
+
+    fn tick(&mut self, _cycles: u32) {}
+
+    fn set_rtc_mode(&mut self, _mode: RtcMode) {}
+
+    // External cartridge RAM, empty when the cartridge has none
+    fn ram(&self) -> &[u8] {
+        &[]
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+
+    fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        None
+    }
+
+    fn camera_mut(&mut self) -> Option<&mut CameraSensor> {
+        None
+    }
+
+    // How hard the rumble motor ran since the last call, as the share of
+    // time it was on from 0.0 to 1.0. Games vary the strength by switching
+    // it on and off quickly. None on cartridges without one.
+    fn take_rumble(&mut self) -> Option<f32> {
+        None
+    }
+
+    // Whether the last write switched the rumble motor, and to what
+    fn take_motor_switch(&mut self) -> Option<bool> {
+        None
+    }
+}
+
+// External RAM size from the header byte at 0x149
+pub fn ram_size(code: u8) -> usize {
+    match code {
+        0x01 => 2 * 1024,
+        0x02 => 8 * 1024,
+        0x03 => 32 * 1024,
+        0x04 => 128 * 1024,
+        0x05 => 64 * 1024,
+        _ => 0,
+    }
+}
+
+// Bank numbers wrap around the actual ROM size, as only the address lines
+// the chip has are connected
+fn rom_byte(rom: &[u8], bank: usize, offset: usize) -> u8 {
+    let banks = (rom.len() / 0x4000).max(1);
+    rom.get((bank % banks) * 0x4000 + offset).copied().unwrap_or(0xFF)
+}
+
+// The same goes for RAM, including 2KB chips mirrored across the 8KB window
+fn ram_index(ram: &[u8], bank: usize, addr: u16) -> Option<usize> {
+    if ram.is_empty() {
+        return None;
+    }
+    Some((bank * 0x2000 + (addr - 0xA000) as usize) % ram.len())
 }
 
+// ROM only, optionally with up to 8KB of RAM that is always enabled
 pub struct NoMBC {
     rom: Vec<u8>,
+    ram: Vec<u8>,
 }
 
 impl NoMBC {
-    pub fn new(rom: Vec<u8>) -> Self {
-        NoMBC { rom }
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        NoMBC { rom, ram: vec![0; ram_size.min(0x2000)] }
     }
 }
 
 impl MemoryBankController for NoMBC {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn rom_mut(&mut self) -> &mut [u8] {
+        &mut self.rom
+    }
+
     fn read_rom(&self, addr: u16) -> u8 {
-        if (addr as usize) < self.rom.len() {
-            self.rom[addr as usize]
-        } else {
-            0xFF
-        }
+        self.rom.get(addr as usize).copied().unwrap_or(0xFF)
     }
 
     fn write_rom(&mut self, _addr: u16, _value: u8) {}
 
-    fn read_ram(&self, _addr: u16) -> u8 {
-        0xFF
+    fn read_ram(&self, addr: u16) -> u8 {
+        ram_index(&self.ram, 0, addr).map_or(0xFF, |index| self.ram[index])
     }
 
-    fn write_ram(&mut self, _addr: u16, _value: u8) {}
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if let Some(index) = ram_index(&self.ram, 0, addr) {
+            self.ram[index] = value;
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+impl Savestate for NoMBC {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_bytes(&mut self.ram)
+    }
 }
 
 pub struct MBC1 {
@@ -43,8 +145,7 @@ pub struct MBC1 {
 }
 
 impl MBC1 {
-    pub fn new(rom: Vec<u8>) -> Self {
-        let ram_size = 32 * 1024;
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
         MBC1 {
             rom,
             ram: vec![0; ram_size],
@@ -57,38 +158,30 @@ impl MBC1 {
 }
 
 impl MemoryBankController for MBC1 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn rom_mut(&mut self) -> &mut [u8] {
+        &mut self.rom
+    }
+
     fn read_rom(&self, addr: u16) -> u8 {
         match addr {
-            0x0000..=0x3FFF => {
-                let bank = if self.banking_mode == 1 {
-                    (self.ram_bank << 5) % (self.rom.len() / 0x4000)
-                } else {
-                    0
-                };
-                let rom_addr = bank * 0x4000 + (addr as usize);
-                if rom_addr < self.rom.len() {
-                    self.rom[rom_addr]
-                } else {
-                    0xFF
-                }
-            }
-            0x4000..=0x7FFF => {
-                let bank = if self.banking_mode == 1 {
-                    ((self.ram_bank << 5) | self.rom_bank) % (self.rom.len() / 0x4000)
-                } else {
-                    self.rom_bank
-                };
-                let rom_addr = bank * 0x4000 + ((addr - 0x4000) as usize);
-                if rom_addr < self.rom.len() {
-                    self.rom[rom_addr]
-                } else {
-                    0xFF
-                }
-            }
+            0x0000..=0x7FFF => rom_byte(&self.rom, self.rom_bank(addr), (addr & 0x3FFF) as usize),
             _ => 0xFF,
         }
     }
 
+    fn rom_bank(&self, addr: u16) -> usize {
+        match addr {
+            0x0000..=0x3FFF if self.banking_mode == 1 => self.ram_bank << 5,
+            0x0000..=0x3FFF => 0,
+            // The upper bank bits apply in both modes, only bank 0 ignores them in mode 0
+            _ => (self.ram_bank << 5) | self.rom_bank,
+        }
+    }
+
     fn write_rom(&mut self, addr: u16, value: u8) {
         match addr {
             0x0000..=0x1FFF => {
@@ -112,33 +205,46 @@ impl MemoryBankController for MBC1 {
         if !self.ram_enabled {
             return 0xFF;
         }
-        let bank = if self.banking_mode == 1 {
-            self.ram_bank
-        } else {
-            0
-        };
-        let ram_addr = bank * 0x2000 + ((addr - 0xA000) as usize);
-        if ram_addr < self.ram.len() {
-            self.ram[ram_addr]
-        } else {
-            0xFF
-        }
+        let bank = if self.banking_mode == 1 { self.ram_bank } else { 0 };
+        ram_index(&self.ram, bank, addr).map_or(0xFF, |index| self.ram[index])
     }
 
     fn write_ram(&mut self, addr: u16, value: u8) {
         if !self.ram_enabled {
             return;
         }
-        let bank = if self.banking_mode == 1 {
-            self.ram_bank
-        } else {
-            0
-        };
-        let ram_addr = bank * 0x2000 + ((addr - 0xA000) as usize);
-        if ram_addr < self.ram.len() {
-            self.ram[ram_addr] = value;
+        let bank = if self.banking_mode == 1 { self.ram_bank } else { 0 };
+        if let Some(index) = ram_index(&self.ram, bank, addr) {
+            self.ram[index] = value;
         }
     }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+impl Savestate for MBC1 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+        state.write_u8(self.rom_bank as u8);
+        state.write_u8(self.ram_bank as u8);
+        state.write_bool(self.ram_enabled);
+        state.write_u8(self.banking_mode);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_bytes(&mut self.ram)?;
+        self.rom_bank = (state.read_u8()? & 0x1F).max(1) as usize;
+        self.ram_bank = (state.read_u8()? & 0x03) as usize;
+        self.ram_enabled = state.read_bool()?;
+        self.banking_mode = state.read_u8()? & 0x01;
+        Ok(())
+    }
 }
 
 pub struct MBC3 {
@@ -147,107 +253,743 @@ pub struct MBC3 {
     rom_bank: usize,
     ram_bank: usize,
     ram_enabled: bool,
-    rtc_enabled: bool,
+    rtc_register: Option<u8>,
+    rtc: Rtc,
+    // MBC30, as in Pokemon Crystal (J): 8 bits of ROM bank and 3 of RAM bank
+    // instead of 7 and 2
+    is_mbc30: bool,
 }
 
 impl MBC3 {
-    pub fn new(rom: Vec<u8>) -> Self {
-        let ram_size = 32 * 1024;
+    // The header has no type of its own for the MBC30, so it's told apart by
+    // a ROM or RAM bigger than the MBC3 can reach
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        let is_mbc30 = rom.len() > 128 * 0x4000 || ram_size > 4 * 0x2000;
         MBC3 {
             rom,
             ram: vec![0; ram_size],
             rom_bank: 1,
             ram_bank: 0,
             ram_enabled: false,
-            rtc_enabled: false,
+            rtc_register: None,
+            rtc: Rtc::new(RtcMode::HostClock),
+            is_mbc30,
         }
     }
+
+    pub fn is_mbc30(&self) -> bool {
+        self.is_mbc30
+    }
+
+    fn rom_bank_mask(&self) -> u8 {
+        if self.is_mbc30 { 0xFF } else { 0x7F }
+    }
+
+    fn ram_bank_mask(&self) -> u8 {
+        if self.is_mbc30 { 0x07 } else { 0x03 }
+    }
 }
 
 impl MemoryBankController for MBC3 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn rom_mut(&mut self) -> &mut [u8] {
+        &mut self.rom
+    }
+
     fn read_rom(&self, addr: u16) -> u8 {
         match addr {
-            0x0000..=0x3FFF => {
-                if (addr as usize) < self.rom.len() {
-                    self.rom[addr as usize]
-                } else {
-                    0xFF
-                }
-            }
-            0x4000..=0x7FFF => {
-                let rom_addr = (self.rom_bank * 0x4000) + ((addr - 0x4000) as usize);
-                if rom_addr < self.rom.len() {
-                    self.rom[rom_addr]
-                } else {
-                    0xFF
-                }
-            }
+            0x0000..=0x3FFF => rom_byte(&self.rom, 0, addr as usize),
+            0x4000..=0x7FFF => rom_byte(&self.rom, self.rom_bank, (addr - 0x4000) as usize),
             _ => 0xFF,
         }
     }
 
+    fn rom_bank(&self, addr: u16) -> usize {
+        if addr < 0x4000 { 0 } else { self.rom_bank }
+    }
+
     fn write_rom(&mut self, addr: u16, value: u8) {
         match addr {
             0x0000..=0x1FFF => {
                 self.ram_enabled = (value & 0x0F) == 0x0A;
             }
             0x2000..=0x3FFF => {
-                let bank = (value & 0x7F) as usize;
+                let bank = (value & self.rom_bank_mask()) as usize;
                 self.rom_bank = if bank == 0 { 1 } else { bank };
             }
             0x4000..=0x5FFF => {
-                if value <= 0x03 {
+                if value <= self.ram_bank_mask() {
                     self.ram_bank = value as usize;
-                    self.rtc_enabled = false;
-                } else if value >= 0x08 && value <= 0x0C {
-                    self.rtc_enabled = true;
+                    self.rtc_register = None;
+                } else if (0x08..=0x0C).contains(&value) {
+                    self.rtc_register = Some(value);
                 }
             }
             0x6000..=0x7FFF => {
+                self.rtc.write_latch(value);
             }
             _ => {}
         }
     }
 
     fn read_ram(&self, addr: u16) -> u8 {
-        if !self.ram_enabled || self.rtc_enabled {
+        if !self.ram_enabled {
             return 0xFF;
         }
+        if let Some(register) = self.rtc_register {
+            return self.rtc.read(register);
+        }
+
+        ram_index(&self.ram, self.ram_bank, addr).map_or(0xFF, |index| self.ram[index])
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        if let Some(register) = self.rtc_register {
+            self.rtc.write(register, value);
+            return;
+        }
+
+        if let Some(index) = ram_index(&self.ram, self.ram_bank, addr) {
+            self.ram[index] = value;
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.rtc.tick(cycles);
+    }
+
+    fn set_rtc_mode(&mut self, mode: RtcMode) {
+        self.rtc.set_mode(mode);
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        Some(&mut self.rtc)
+    }
+}
+
+impl Savestate for MBC3 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+        state.write_u8(self.rom_bank as u8);
+        state.write_u8(self.ram_bank as u8);
+        state.write_bool(self.ram_enabled);
+        // 0 when RAM is mapped, the RTC registers are 0x08-0x0C
+        state.write_u8(self.rtc_register.unwrap_or(0));
+        self.rtc.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_bytes(&mut self.ram)?;
+        self.rom_bank = (state.read_u8()? & self.rom_bank_mask()).max(1) as usize;
+        self.ram_bank = (state.read_u8()? & self.ram_bank_mask()) as usize;
+        self.ram_enabled = state.read_bool()?;
+        let register = state.read_u8()?;
+        self.rtc_register = (0x08..=0x0C).contains(&register).then_some(register);
+        self.rtc.load_state(state)
+    }
+}
+
+pub struct MBC5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    // 9 bits, written as a low byte and a separate high bit; bank 0 is allowed
+    rom_bank: usize,
+    ram_bank: usize,
+    ram_enabled: bool,
+    // On rumble cartridges bit 3 of the RAM bank register drives the motor
+    has_rumble: bool,
+    motor_on: bool,
+    motor_switched: bool,
+    // Since the last take_rumble
+    motor_cycles: u32,
+    elapsed_cycles: u32,
+}
+
+impl MBC5 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rumble: bool) -> Self {
+        MBC5 {
+            rom,
+            ram: vec![0; ram_size],
+            rom_bank: 1,
+            ram_bank: 0,
+            ram_enabled: false,
+            has_rumble,
+            motor_on: false,
+            motor_switched: false,
+            motor_cycles: 0,
+            elapsed_cycles: 0,
+        }
+    }
+}
+
+impl MemoryBankController for MBC5 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn rom_mut(&mut self) -> &mut [u8] {
+        &mut self.rom
+    }
+
+    fn read_rom(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => rom_byte(&self.rom, 0, addr as usize),
+            0x4000..=0x7FFF => rom_byte(&self.rom, self.rom_bank, (addr - 0x4000) as usize),
+            _ => 0xFF,
+        }
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        if addr < 0x4000 { 0 } else { self.rom_bank }
+    }
 
-        let ram_addr = (self.ram_bank * 0x2000) + ((addr - 0xA000) as usize);
-        if ram_addr < self.ram.len() {
-            self.ram[ram_addr]
-        } else {
-            0xFF
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => {
+                self.ram_enabled = value == 0x0A;
+            }
+            0x2000..=0x2FFF => {
+                self.rom_bank = (self.rom_bank & 0x100) | value as usize;
+            }
+            0x3000..=0x3FFF => {
+                self.rom_bank = (self.rom_bank & 0xFF) | (((value & 0x01) as usize) << 8);
+            }
+            0x4000..=0x5FFF => {
+                let mask = if self.has_rumble { 0x07 } else { 0x0F };
+                self.ram_bank = (value & mask) as usize;
+                let motor_on = self.has_rumble && value & 0x08 != 0;
+                self.motor_switched |= motor_on != self.motor_on;
+                self.motor_on = motor_on;
+            }
+            _ => {}
         }
     }
 
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        ram_index(&self.ram, self.ram_bank, addr).map_or(0xFF, |index| self.ram[index])
+    }
+
     fn write_ram(&mut self, addr: u16, value: u8) {
-        if !self.ram_enabled || self.rtc_enabled {
+        if !self.ram_enabled {
             return;
         }
+        if let Some(index) = ram_index(&self.ram, self.ram_bank, addr) {
+            self.ram[index] = value;
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        if self.has_rumble {
+            self.elapsed_cycles = self.elapsed_cycles.saturating_add(cycles);
+            if self.motor_on {
+                self.motor_cycles = self.motor_cycles.saturating_add(cycles);
+            }
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
 
-        let ram_addr = (self.ram_bank * 0x2000) + ((addr - 0xA000) as usize);
-        if ram_addr < self.ram.len() {
-            self.ram[ram_addr] = value;
+    fn take_rumble(&mut self) -> Option<f32> {
+        if !self.has_rumble {
+            return None;
         }
+        let strength = match self.elapsed_cycles {
+            0 => 0.0,
+            elapsed => self.motor_cycles as f32 / elapsed as f32,
+        };
+        self.motor_cycles = 0;
+        self.elapsed_cycles = 0;
+        Some(strength)
+    }
+
+    fn take_motor_switch(&mut self) -> Option<bool> {
+        std::mem::take(&mut self.motor_switched).then_some(self.motor_on)
     }
 }
 
-pub fn create_mbc(rom: Vec<u8>) -> Box<dyn MemoryBankController> {
-    if rom.len() > 0x147 {
-        let cartridge_type = rom[0x147];
-        match cartridge_type {
-            0x00 => Box::new(NoMBC::new(rom)),
-            0x01 | 0x02 | 0x03 => Box::new(MBC1::new(rom)),
-            0x0F..=0x13 => Box::new(MBC3::new(rom)),
-            _ => {
-                println!("Warning: Unsupported cartridge type 0x{:02X}, using MBC1", cartridge_type);
-                Box::new(MBC1::new(rom))
+impl Savestate for MBC5 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+        state.write_u16(self.rom_bank as u16);
+        state.write_u8(self.ram_bank as u8);
+        state.write_bool(self.ram_enabled);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_bytes(&mut self.ram)?;
+        self.rom_bank = (state.read_u16()? & 0x1FF) as usize;
+        self.ram_bank = (state.read_u8()? & 0x0F) as usize;
+        self.ram_enabled = state.read_bool()?;
+        Ok(())
+    }
+}
+
+// Hudson's HuC1: up to 64 ROM banks and 4 RAM banks, with an infrared LED
+// and sensor that can be mapped over the RAM. Nothing is ever received.
+pub struct HuC1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rom_bank: usize,
+    ram_bank: usize,
+    ir_mode: bool,
+    ir_led: bool,
+}
+
+impl HuC1 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        HuC1 {
+            rom,
+            ram: vec![0; ram_size],
+            rom_bank: 1,
+            ram_bank: 0,
+            ir_mode: false,
+            ir_led: false,
+        }
+    }
+
+    // Whether the game has the infrared LED lit
+    pub fn ir_led(&self) -> bool {
+        self.ir_led
+    }
+}
+
+impl MemoryBankController for HuC1 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn rom_mut(&mut self) -> &mut [u8] {
+        &mut self.rom
+    }
+
+    fn read_rom(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => rom_byte(&self.rom, 0, addr as usize),
+            0x4000..=0x7FFF => rom_byte(&self.rom, self.rom_bank, (addr - 0x4000) as usize),
+            _ => 0xFF,
+        }
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        if addr < 0x4000 { 0 } else { self.rom_bank }
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            // 0x0E maps the infrared port at A000-BFFF, anything else the
+            // RAM, which needs no enabling
+            0x0000..=0x1FFF => self.ir_mode = value & 0x0F == 0x0E,
+            0x2000..=0x3FFF => self.rom_bank = (value & 0x3F) as usize,
+            0x4000..=0x5FFF => self.ram_bank = (value & 0x03) as usize,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if self.ir_mode {
+            // Bit 0 would be set while light is coming in
+            return 0xC0;
+        }
+        ram_index(&self.ram, self.ram_bank, addr).map_or(0xFF, |index| self.ram[index])
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if self.ir_mode {
+            self.ir_led = value & 0x01 != 0;
+            return;
+        }
+        if let Some(index) = ram_index(&self.ram, self.ram_bank, addr) {
+            self.ram[index] = value;
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+impl Savestate for HuC1 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+        state.write_u8(self.rom_bank as u8);
+        state.write_u8(self.ram_bank as u8);
+        state.write_bool(self.ir_mode);
+        state.write_bool(self.ir_led);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_bytes(&mut self.ram)?;
+        self.rom_bank = (state.read_u8()? & 0x3F) as usize;
+        self.ram_bank = (state.read_u8()? & 0x03) as usize;
+        self.ir_mode = state.read_bool()?;
+        self.ir_led = state.read_bool()?;
+        Ok(())
+    }
+}
+
+// Hudson's HuC3: 128 ROM banks, 4 RAM banks, and a clock and infrared port
+// picked by the mode written to 0x0000-0x1FFF. The clock is driven by
+// commands written in mode 0xB, a nibble of data each, through a small
+// memory whose first seven nibbles hold the minute of the day and the day.
+// The MBC3's RTC keeps the time, so both RTC modes and the .sav footers
+// work alike, with the day count wrapping at 512.
+pub struct HuC3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rom_bank: usize,
+    ram_bank: usize,
+    mode: u8,
+    // The memory nibble the next read or write command reaches
+    address: u8,
+    // What the last command returned, read in mode 0xC
+    response: u8,
+    memory: [u8; 256],
+    rtc: Rtc,
+    ir_led: bool,
+}
+
+impl HuC3 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        HuC3 {
+            rom,
+            ram: vec![0; ram_size],
+            rom_bank: 1,
+            ram_bank: 0,
+            mode: 0,
+            address: 0,
+            response: 0,
+            memory: [0; 256],
+            rtc: Rtc::new(RtcMode::HostClock),
+            ir_led: false,
+        }
+    }
+
+    // Whether the game has the infrared LED lit
+    pub fn ir_led(&self) -> bool {
+        self.ir_led
+    }
+
+    fn command(&mut self, value: u8) {
+        let argument = value & 0x0F;
+        match value >> 4 {
+            0x1 => {
+                self.response = self.memory[self.address as usize];
+                self.address = self.address.wrapping_add(1);
             }
+            0x3 => {
+                self.memory[self.address as usize] = argument;
+                self.address = self.address.wrapping_add(1);
+            }
+            0x4 => self.address = (self.address & 0xF0) | argument,
+            0x5 => self.address = (self.address & 0x0F) | argument << 4,
+            0x6 => match argument {
+                0x0 => self.copy_time_out(),
+                0x1 => self.copy_time_in(),
+                // Status, always ready
+                0x2 => self.response = 0x01,
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    // The minute of the day into nibbles 0-2 and the day into 3-6, low first
+    fn copy_time_out(&mut self) {
+        let [_, minutes, hours, day_low, day_high] = self.rtc.registers().live;
+        let minute_of_day = hours as u32 * 60 + minutes as u32;
+        let day = day_low as u32 | ((day_high & 0x01) as u32) << 8;
+        for nibble in 0..3 {
+            self.memory[nibble] = (minute_of_day >> (nibble * 4)) as u8 & 0x0F;
+        }
+        for nibble in 0..4 {
+            self.memory[3 + nibble] = (day >> (nibble * 4)) as u8 & 0x0F;
+        }
+    }
+
+    fn copy_time_in(&mut self) {
+        let read = |nibbles: &[u8]| nibbles.iter().rev().fold(0u32, |value, &nibble| value << 4 | nibble as u32);
+        let minute_of_day = read(&self.memory[0..3]) % (24 * 60);
+        let day = read(&self.memory[3..7]);
+        self.rtc.write(0x08, 0);
+        self.rtc.write(0x09, (minute_of_day % 60) as u8);
+        self.rtc.write(0x0A, (minute_of_day / 60) as u8);
+        self.rtc.write(0x0B, day as u8);
+        self.rtc.write(0x0C, (day >> 8) as u8 & 0x01);
+    }
+}
+
+impl MemoryBankController for HuC3 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn rom_mut(&mut self) -> &mut [u8] {
+        &mut self.rom
+    }
+
+    fn read_rom(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => rom_byte(&self.rom, 0, addr as usize),
+            0x4000..=0x7FFF => rom_byte(&self.rom, self.rom_bank, (addr - 0x4000) as usize),
+            _ => 0xFF,
         }
-    } else {
-        Box::new(NoMBC::new(rom))
     }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        if addr < 0x4000 { 0 } else { self.rom_bank }
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.mode = value & 0x0F,
+            0x2000..=0x3FFF => self.rom_bank = (value & 0x7F) as usize,
+            0x4000..=0x5FFF => self.ram_bank = (value & 0x03) as usize,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        match self.mode {
+            // 0x0 maps the RAM read-only, 0xA for writing too
+            0x0 | 0xA => ram_index(&self.ram, self.ram_bank, addr).map_or(0xFF, |index| self.ram[index]),
+            0xC => self.response,
+            // The clock is always ready for the next command
+            0xD => 0x01,
+            // Bit 0 would be set while light is coming in
+            0xE => 0xC0,
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        match self.mode {
+            0xA => {
+                if let Some(index) = ram_index(&self.ram, self.ram_bank, addr) {
+                    self.ram[index] = value;
+                }
+            }
+            0xB => self.command(value),
+            0xE => self.ir_led = value & 0x01 != 0,
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.rtc.tick(cycles);
+    }
+
+    fn set_rtc_mode(&mut self, mode: RtcMode) {
+        self.rtc.set_mode(mode);
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        Some(&mut self.rtc)
+    }
+}
+
+impl Savestate for HuC3 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+        state.write_u8(self.rom_bank as u8);
+        state.write_u8(self.ram_bank as u8);
+        state.write_u8(self.mode);
+        state.write_u8(self.address);
+        state.write_u8(self.response);
+        state.write_bytes(&self.memory);
+        state.write_bool(self.ir_led);
+        self.rtc.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_bytes(&mut self.ram)?;
+        self.rom_bank = (state.read_u8()? & 0x7F) as usize;
+        self.ram_bank = (state.read_u8()? & 0x03) as usize;
+        self.mode = state.read_u8()? & 0x0F;
+        self.address = state.read_u8()?;
+        self.response = state.read_u8()? & 0x0F;
+        state.read_bytes(&mut self.memory)?;
+        for nibble in &mut self.memory {
+            *nibble &= 0x0F;
+        }
+        self.ir_led = state.read_bool()?;
+        self.rtc.load_state(state)
+    }
+}
+
+// The Game Boy Camera's controller: 64 ROM banks, 16 RAM banks, and the
+// camera sensor's registers in place of RAM when bit 4 of the RAM bank is set
+pub struct PocketCamera {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rom_bank: usize,
+    ram_bank: usize,
+    // Only writes need it, RAM can always be read
+    ram_enabled: bool,
+    registers_mapped: bool,
+    sensor: CameraSensor,
+}
+
+impl PocketCamera {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        PocketCamera {
+            rom,
+            ram: vec![0; ram_size],
+            rom_bank: 1,
+            ram_bank: 0,
+            ram_enabled: false,
+            registers_mapped: false,
+            sensor: CameraSensor::new(),
+        }
+    }
+}
+
+impl MemoryBankController for PocketCamera {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn rom_mut(&mut self) -> &mut [u8] {
+        &mut self.rom
+    }
+
+    fn read_rom(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => rom_byte(&self.rom, 0, addr as usize),
+            0x4000..=0x7FFF => rom_byte(&self.rom, self.rom_bank, (addr - 0x4000) as usize),
+            _ => 0xFF,
+        }
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        if addr < 0x4000 { 0 } else { self.rom_bank }
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = (value & 0x3F) as usize,
+            0x4000..=0x5FFF => {
+                self.registers_mapped = value & 0x10 != 0;
+                if !self.registers_mapped {
+                    self.ram_bank = (value & 0x0F) as usize;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if self.registers_mapped {
+            return self.sensor.read(addr);
+        }
+        // The sensor has the RAM while it's capturing
+        if self.sensor.is_busy() {
+            return 0x00;
+        }
+        ram_index(&self.ram, self.ram_bank, addr).map_or(0xFF, |index| self.ram[index])
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if self.registers_mapped {
+            self.sensor.write(addr, value);
+            return;
+        }
+        if !self.ram_enabled || self.sensor.is_busy() {
+            return;
+        }
+        if let Some(index) = ram_index(&self.ram, self.ram_bank, addr) {
+            self.ram[index] = value;
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.sensor.tick(cycles, &mut self.ram);
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn camera_mut(&mut self) -> Option<&mut CameraSensor> {
+        Some(&mut self.sensor)
+    }
+}
+
+impl Savestate for PocketCamera {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+        state.write_u8(self.rom_bank as u8);
+        state.write_u8(self.ram_bank as u8);
+        state.write_bool(self.ram_enabled);
+        state.write_bool(self.registers_mapped);
+        self.sensor.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_bytes(&mut self.ram)?;
+        self.rom_bank = (state.read_u8()? & 0x3F) as usize;
+        self.ram_bank = (state.read_u8()? & 0x0F) as usize;
+        self.ram_enabled = state.read_bool()?;
+        self.registers_mapped = state.read_bool()?;
+        self.sensor.load_state(state)
+    }
+}
+
+// Fails for cartridge types without an MBC here, like the MBC2 and MMM01
+pub fn create_mbc(rom: Vec<u8>) -> Result<Box<dyn MemoryBankController>> {
+    if rom.len() <= 0x149 {
+        return Ok(Box::new(NoMBC::new(rom, 0)));
+    }
+    let cartridge_type = rom[0x147];
+    let ram_size = ram_size(rom[0x149]);
+    Ok(match cartridge_type {
+        0x00 | 0x08 | 0x09 => Box::new(NoMBC::new(rom, ram_size)),
+        0x01..=0x03 => Box::new(MBC1::new(rom, ram_size)),
+        0x0F..=0x13 => Box::new(MBC3::new(rom, ram_size)),
+        0x19..=0x1E => Box::new(MBC5::new(rom, ram_size, cartridge_type >= 0x1C)),
+        0xFC => Box::new(PocketCamera::new(rom, ram_size)),
+        0xFE => Box::new(HuC3::new(rom, ram_size)),
+        0xFF => Box::new(HuC1::new(rom, ram_size)),
+        _ => return Err(EmulatorError::UnsupportedCartridge(cartridge_type)),
+    })
 }
 
@@ -1,32 +1,128 @@
-use crate::mbc::{MemoryBankController, create_mbc};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use crate::mbc::{MemoryBankController, NoMBC, create_mbc};
+use crate::cheats::{self, GeniePatch};
 use crate::bootrom;
+use crate::camera::CameraSensor;
+use crate::rtc::{Rtc, RtcMode};
+use crate::error::Result;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+#[cfg(feature = "cached-interpreter")]
+use crate::decode_cache::{DecodeCache, Instruction};
+
+const WRAM_BANK_SIZE: usize = 0x1000;
+
+// Memory the game switches in a bank at a time, so tools can reach any bank
+// whatever is mapped at the moment
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Region {
+    Rom,
+    Vram,
+    Wram,
+    Sram,
+}
+
+impl Region {
+    pub fn name(self) -> &'static str {
+        match self {
+            Region::Rom => "rom",
+            Region::Vram => "vram",
+            Region::Wram => "wram",
+            Region::Sram => "sram",
+        }
+    }
+
+    // The addresses the region shows up at, and the size of one bank
+    pub fn window(self) -> (RangeInclusive<u16>, usize) {
+        match self {
+            Region::Rom => (0x0000..=0x7FFF, 0x4000),
+            Region::Vram => (0x8000..=0x9FFF, 0x2000),
+            Region::Sram => (0xA000..=0xBFFF, 0x2000),
+            Region::Wram => (0xC000..=0xDFFF, WRAM_BANK_SIZE),
+        }
+    }
+
+    // Where a byte of a bank appears when that bank is mapped. Bank 0 of ROM
+    // and WRAM has a fixed place in front of the switchable ones.
+    pub fn address(self, bank: usize, offset: usize) -> u16 {
+        let (window, size) = self.window();
+        let slot = match self {
+            Region::Rom | Region::Wram if bank > 0 => size,
+            _ => 0,
+        };
+        window.start() + (slot + offset % size) as u16
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "rom" => Ok(Region::Rom),
+            "vram" => Ok(Region::Vram),
+            "wram" => Ok(Region::Wram),
+            "sram" => Ok(Region::Sram),
+            _ => Err(format!("unknown memory region '{}'", s)),
+        }
+    }
+}
 
 pub struct Memory {
     mbc: Box<dyn MemoryBankController>,
-    wram: [u8; 0x2000],
+    // Eight 4 KB banks; bank 0 is fixed at C000, D000 shows the one SVBK picks
+    wram: [u8; WRAM_BANK_SIZE * 8],
+    svbk: u8,
+    cgb_mode: bool,
     hram: [u8; 0x7F],
+    // DMG_BOOT_ROM_SIZE or CGB_BOOT_ROM_SIZE bytes
+    boot_rom: Vec<u8>,
     boot_rom_enabled: bool,
+    // Game Genie codes, applied to every cartridge ROM read
+    rom_patches: Vec<GeniePatch>,
+    // The rumble motor switching on or off, for the GameBoy to turn into events
+    pub motor_switches: Vec<bool>,
+    #[cfg(feature = "cached-interpreter")]
+    code_cache: DecodeCache,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Memory {
     pub fn new() -> Self {
         Memory {
-            mbc: create_mbc(vec![0; 0x8000]),
-            wram: [0; 0x2000],
+            mbc: Box::new(NoMBC::new(vec![0; 0x8000], 0)),
+            wram: [0; WRAM_BANK_SIZE * 8],
+            svbk: 0,
+            cgb_mode: false,
             hram: [0; 0x7F],
+            boot_rom: bootrom::get_boot_rom().to_vec(),
             boot_rom_enabled: true,
+            rom_patches: Vec::new(),
+            motor_switches: Vec::new(),
+            #[cfg(feature = "cached-interpreter")]
+            code_cache: DecodeCache::new(0x8000, WRAM_BANK_SIZE * 8),
         }
     }
 
     pub fn read_byte(&self, addr: u16) -> u8 {
         match addr {
             0x0000..=0x00FF if self.boot_rom_enabled => {
-                bootrom::get_boot_rom()[addr as usize]
+                self.boot_rom[addr as usize]
+            }
+            0x0200..=0x08FF if self.boot_rom_enabled && self.has_cgb_boot_rom() => {
+                self.boot_rom[addr as usize]
             }
-            0x0000..=0x7FFF => self.mbc.read_rom(addr),
+            0x0000..=0x7FFF if self.rom_patches.is_empty() => self.mbc.read_rom(addr),
+            0x0000..=0x7FFF => cheats::patch_rom(&self.rom_patches, addr, self.mbc.read_rom(addr)),
             0xA000..=0xBFFF => self.mbc.read_ram(addr),
-            0xC000..=0xDFFF => self.wram[(addr - 0xC000) as usize],
-            0xE000..=0xFDFF => self.wram[(addr - 0xE000) as usize],
+            0xC000..=0xFDFF => self.wram[self.wram_offset(addr)],
+            0xFF70 if self.cgb_mode => 0xF8 | self.svbk,
             0xFF80..=0xFFFE => self.hram[(addr - 0xFF80) as usize],
             _ => 0xFF,
         }
@@ -34,10 +130,18 @@ impl Memory {
 
     pub fn write_byte(&mut self, addr: u16, value: u8) {
         match addr {
-            0x0000..=0x7FFF => self.mbc.write_rom(addr, value),
+            0x0000..=0x7FFF => {
+                self.mbc.write_rom(addr, value);
+                self.motor_switches.extend(self.mbc.take_motor_switch());
+            }
             0xA000..=0xBFFF => self.mbc.write_ram(addr, value),
-            0xC000..=0xDFFF => self.wram[(addr - 0xC000) as usize] = value,
-            0xE000..=0xFDFF => self.wram[(addr - 0xE000) as usize] = value,
+            0xC000..=0xFDFF => {
+                let offset = self.wram_offset(addr);
+                self.wram[offset] = value;
+                #[cfg(feature = "cached-interpreter")]
+                self.code_cache.invalidate_wram(offset);
+            }
+            0xFF70 if self.cgb_mode => self.svbk = value & 0x07,
             0xFF50 if value != 0 => {
                 self.boot_rom_enabled = false;
             }
@@ -46,9 +150,159 @@ impl Memory {
         }
     }
 
-    pub fn load_rom(&mut self, rom: &[u8]) {
-        self.mbc = create_mbc(rom.to_vec());
+    // E000-FDFF echoes C000-DDFF, banking included
+    pub fn wram_offset(&self, addr: u16) -> usize {
+        let offset = addr as usize & 0x1FFF;
+        if offset < WRAM_BANK_SIZE {
+            offset
+        } else {
+            self.wram_bank() * WRAM_BANK_SIZE + offset - WRAM_BANK_SIZE
+        }
+    }
+
+    // Selecting bank 0 gives bank 1, and the DMG only has bank 1
+    fn wram_bank(&self) -> usize {
+        if self.cgb_mode { (self.svbk as usize).max(1) } else { 1 }
+    }
+
+    pub fn set_cgb_mode(&mut self, enabled: bool) {
+        self.cgb_mode = enabled;
+        self.svbk = 0;
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<()> {
+        self.mbc = create_mbc(rom.to_vec())?;
+        #[cfg(feature = "cached-interpreter")]
+        {
+            self.code_cache = DecodeCache::new(rom.len(), self.wram.len());
+        }
+        Ok(())
+    }
+
+    // The bank the cartridge maps at addr, in 0000-7FFF
+    pub fn rom_bank(&self, addr: u16) -> usize {
+        self.mbc.rom_bank(addr)
+    }
+
+    // The instruction at addr from the decode cache, or None where the
+    // interpreter reads it itself: while the boot ROM or Game Genie codes
+    // can change what's read, for code outside ROM and work RAM, and for
+    // instructions that could run across a bank boundary
+    #[cfg(feature = "cached-interpreter")]
+    pub fn cached_instruction(&mut self, addr: u16) -> Option<Instruction> {
+        if self.boot_rom_enabled || !self.rom_patches.is_empty() || addr & 0x0FFF > 0x0FFD {
+            return None;
+        }
+        match addr {
+            0x0000..=0x7FFF => {
+                let key = self.code_cache.rom_key(self.mbc.rom_bank(addr), addr)?;
+                let mbc = &self.mbc;
+                self.code_cache.get(key, |index| mbc.read_rom(addr + index as u16))
+            }
+            0xC000..=0xFDFD => {
+                let offset = self.wram_offset(addr);
+                let key = self.code_cache.wram_key(offset);
+                let wram = &self.wram;
+                self.code_cache.get(key, |index| wram[offset + index])
+            }
+            _ => None,
+        }
+    }
+
+    // Replaces the built-in boot ROM; the caller checks the size
+    pub fn set_boot_rom(&mut self, boot_rom: Vec<u8>) {
+        self.boot_rom = boot_rom;
+    }
+
+    pub fn has_cgb_boot_rom(&self) -> bool {
+        self.boot_rom.len() == bootrom::CGB_BOOT_ROM_SIZE
+    }
+
+    pub fn is_boot_rom_enabled(&self) -> bool {
+        self.boot_rom_enabled
+    }
+
+    pub fn set_boot_rom_enabled(&mut self, enabled: bool) {
+        self.boot_rom_enabled = enabled;
+    }
+
+    pub fn set_rom_patches(&mut self, patches: Vec<GeniePatch>) {
+        self.rom_patches = patches;
+    }
+
+    // Writes D000-DFFF in a given bank, whichever one SVBK maps there
+    pub fn write_wram_bank(&mut self, bank: u8, addr: u16, value: u8) {
+        let offset = (bank as usize).max(1) * WRAM_BANK_SIZE + (addr as usize & 0x0FFF);
+        self.wram[offset] = value;
+        #[cfg(feature = "cached-interpreter")]
+        self.code_cache.invalidate_wram(offset);
+    }
+
+    pub fn wram(&self) -> &[u8] {
+        &self.wram
+    }
+
+    pub fn wram_mut(&mut self) -> &mut [u8] {
+        #[cfg(feature = "cached-interpreter")]
+        self.code_cache.clear_wram();
+        &mut self.wram
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        self.mbc.tick(cycles);
+    }
+
+    pub fn set_rtc_mode(&mut self, mode: RtcMode) {
+        self.mbc.set_rtc_mode(mode);
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        self.mbc.rom()
+    }
+
+    pub fn rom_mut(&mut self) -> &mut [u8] {
+        #[cfg(feature = "cached-interpreter")]
+        self.code_cache.clear_rom();
+        self.mbc.rom_mut()
+    }
+
+    pub fn cartridge_ram(&self) -> &[u8] {
+        self.mbc.ram()
+    }
+
+    pub fn cartridge_ram_mut(&mut self) -> &mut [u8] {
+        self.mbc.ram_mut()
+    }
+
+    pub fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        self.mbc.rtc_mut()
+    }
+
+    pub fn camera_mut(&mut self) -> Option<&mut CameraSensor> {
+        self.mbc.camera_mut()
+    }
+
+    pub fn take_rumble(&mut self) -> Option<f32> {
+        self.mbc.take_rumble()
     }
 }
 
+impl Savestate for Memory {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.wram);
+        state.write_u8(self.svbk);
+        state.write_bytes(&self.hram);
+        state.write_bool(self.boot_rom_enabled);
+        self.mbc.save_state(state);
+    }
 
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_bytes(&mut self.wram)?;
+        #[cfg(feature = "cached-interpreter")]
+        self.code_cache.clear_wram();
+        self.svbk = state.read_u8()? & 0x07;
+        state.read_bytes(&mut self.hram)?;
+        self.boot_rom_enabled = state.read_bool()?;
+        self.mbc.load_state(state)
+    }
+}
@@ -0,0 +1,15 @@
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum HardwareModel {
+    Dmg,
+    // Game Boy Pocket and Light, told apart from the DMG only by A=0xFF after boot
+    Mgb,
+    Cgb,
+    // CGB-compatible mode of a Game Boy Advance
+    Agb,
+}
+
+impl HardwareModel {
+    pub fn is_cgb(&self) -> bool {
+        matches!(self, HardwareModel::Cgb | HardwareModel::Agb)
+    }
+}
@@ -0,0 +1,83 @@
+// Input movies: the buttons held in every frame of a run, replayed from the
+// same starting state to reproduce it exactly. Hashes of the machine state
+// taken along the way show where a replay stops matching the recording.
+
+const MAGIC: &[u8; 4] = b"GBCM";
+const MOVIE_VERSION: u16 = 1;
+
+// Frames between state hashes
+pub const HASH_INTERVAL: u64 = 60;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    // Hash of the ROM it was recorded on
+    pub rom_hash: u64,
+    // Save state the recording started from, cartridge RAM and clock included
+    pub start_state: Vec<u8>,
+    // Buttons held in each frame, laid out as in Button::mask
+    pub inputs: Vec<u8>,
+    // State hash at the end of every HASH_INTERVAL-th frame
+    pub hashes: Vec<u64>,
+}
+
+impl Movie {
+    pub fn new(rom_hash: u64, start_state: Vec<u8>) -> Self {
+        Movie { rom_hash, start_state, inputs: Vec::new(), hashes: Vec::new() }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&MOVIE_VERSION.to_le_bytes());
+        data.extend_from_slice(&self.rom_hash.to_le_bytes());
+        data.extend_from_slice(&(self.start_state.len() as u32).to_le_bytes());
+        data.extend_from_slice(&self.start_state);
+        data.extend_from_slice(&(self.inputs.len() as u32).to_le_bytes());
+        data.extend_from_slice(&self.inputs);
+        data.extend_from_slice(&(self.hashes.len() as u32).to_le_bytes());
+        for hash in &self.hashes {
+            data.extend_from_slice(&hash.to_le_bytes());
+        }
+        data
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader { data };
+        if reader.take(4)? != MAGIC {
+            return Err("not a movie file".to_string());
+        }
+        let version = u16::from_le_bytes(reader.array()?);
+        if version != MOVIE_VERSION {
+            return Err(format!("unsupported movie version {}", version));
+        }
+        let rom_hash = u64::from_le_bytes(reader.array()?);
+        let len = u32::from_le_bytes(reader.array()?) as usize;
+        let start_state = reader.take(len)?.to_vec();
+        let len = u32::from_le_bytes(reader.array()?) as usize;
+        let inputs = reader.take(len)?.to_vec();
+        let len = u32::from_le_bytes(reader.array()?) as usize;
+        let hashes = (0..len).map(|_| reader.array().map(u64::from_le_bytes)).collect::<Result<_, _>>()?;
+        if !reader.data.is_empty() {
+            return Err("trailing data after the movie".to_string());
+        }
+        Ok(Movie { rom_hash, start_state, inputs, hashes })
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < len {
+            return Err("movie file is truncated".to_string());
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+}
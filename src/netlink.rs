@@ -0,0 +1,176 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use crate::infrared::InfraredPort;
+use crate::interrupts::Interrupts;
+use crate::serial::Serial;
+
+// Every message is two bytes, a kind and a value
+const HELLO: u8 = b'G';
+const TRANSFER: u8 = b'T';
+const REPLY: u8 = b'R';
+// The sender's infrared LED switched, value 1 for on
+const INFRARED: u8 = b'I';
+// Bumped whenever the messages change, so mismatched builds refuse to link
+const PROTOCOL_VERSION: u8 = 2;
+
+// Cycles between checks for a byte from the partner's clock, about a scanline.
+// The partner waits for the answer, so this only limits how fast bytes go.
+const POLL_CYCLES: u32 = 512;
+// Sleep between reads while waiting on the partner
+const WAIT_STEP: Duration = Duration::from_micros(100);
+// How long a transfer waits for the partner before it shifts in all ones,
+// as if the cable had been pulled
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// A link cable to another running emulator over TCP. Whichever side starts a
+// transfer with its internal clock sends the byte and waits for the one the
+// other side had in SB, so the instance providing the clock decides when
+// each byte moves and the other just answers, as on the real cable.
+// Infrared goes over the same connection as the partner's LED switching on
+// and off. Nothing keeps the two clocks together, so the light arrives as
+// late as the network makes it.
+pub struct NetLink {
+    stream: TcpStream,
+    // Bytes read from the socket but not yet a whole message
+    pending: Vec<u8>,
+    poll_cycles: u32,
+    // The partner's LED as last heard
+    light: bool,
+}
+
+impl NetLink {
+    // Waits for a partner to connect to the port
+    pub fn listen(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        let (stream, _) = listener.accept()?;
+        Self::from_stream(stream)
+    }
+
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Self::from_stream(TcpStream::connect(addr)?)
+    }
+
+    // Checks the other end is a link cable too
+    pub fn from_stream(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        let mut link = NetLink { stream, pending: Vec::new(), poll_cycles: 0, light: false };
+        link.send(HELLO, PROTOCOL_VERSION)?;
+        match link.receive(HANDSHAKE_TIMEOUT)? {
+            Some((HELLO, PROTOCOL_VERSION)) => Ok(link),
+            Some((HELLO, version)) => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("partner speaks link protocol {}, this is {}", version, PROTOCOL_VERSION),
+            )),
+            _ => Err(io::Error::new(ErrorKind::InvalidData, "partner isn't a rust-gbc link cable")),
+        }
+    }
+
+    pub fn peer(&self) -> Option<String> {
+        self.stream.peer_addr().ok().map(|addr| addr.to_string())
+    }
+
+    // Called after every instruction. Sends a byte this side clocked out and
+    // waits for the answer, or answers a byte the partner clocked in, and
+    // passes infrared both ways. An error means the partner is gone; a
+    // transfer in flight gets 0xFF.
+    pub fn service(&mut self, serial: &mut Serial, interrupts: &mut Interrupts, infrared: &mut InfraredPort, cycles: u32) -> io::Result<()> {
+        if let Some(led) = infrared.take_led_change() {
+            self.send(INFRARED, led as u8)?;
+        }
+        if let Some(byte) = serial.take_outgoing() {
+            self.transfer(byte, serial, interrupts)?;
+            infrared.set_light(self.light);
+            return Ok(());
+        }
+        // Infrared signals are pulses far shorter than a scanline, so a game
+        // watching the sensor gets every instruction's worth
+        self.poll_cycles += cycles;
+        if self.poll_cycles < POLL_CYCLES && !infrared.is_reading() {
+            return Ok(());
+        }
+        self.poll_cycles = 0;
+        while let Some((kind, value)) = self.receive(Duration::ZERO)? {
+            match kind {
+                TRANSFER => {
+                    let reply = serial.exchange_as_slave(value, interrupts);
+                    self.send(REPLY, reply)?;
+                }
+                INFRARED => self.light = value != 0,
+                _ => {}
+            }
+        }
+        infrared.set_light(self.light);
+        Ok(())
+    }
+
+    fn transfer(&mut self, byte: u8, serial: &mut Serial, interrupts: &mut Interrupts) -> io::Result<()> {
+        match self.send(TRANSFER, byte).and_then(|_| self.await_reply(byte, serial, interrupts)) {
+            Ok(Some(reply)) => {
+                serial.complete_transfer(byte, reply, interrupts);
+                Ok(())
+            }
+            // Both sides clocked at once and already swapped bytes
+            Ok(None) => Ok(()),
+            // A partner that is paused or stuck stays connected; its late
+            // reply is ignored
+            Err(error) if error.kind() == ErrorKind::TimedOut => {
+                serial.complete_transfer(byte, 0xFF, interrupts);
+                Ok(())
+            }
+            Err(error) => {
+                serial.complete_transfer(byte, 0xFF, interrupts);
+                Err(error)
+            }
+        }
+    }
+
+    fn await_reply(&mut self, byte: u8, serial: &mut Serial, interrupts: &mut Interrupts) -> io::Result<Option<u8>> {
+        let mut crossed = false;
+        loop {
+            match self.receive(REPLY_TIMEOUT)? {
+                Some((REPLY, reply)) => return Ok(if crossed { None } else { Some(reply) }),
+                // With both clocks running each side shifts in the other's
+                // byte; the partner does the same with ours
+                Some((TRANSFER, value)) if !crossed => {
+                    crossed = true;
+                    serial.complete_transfer(byte, value, interrupts);
+                    self.send(REPLY, byte)?;
+                }
+                Some((INFRARED, value)) => self.light = value != 0,
+                Some(_) => {}
+                None => return Err(io::Error::new(ErrorKind::TimedOut, "link partner stopped answering")),
+            }
+        }
+    }
+
+    fn send(&mut self, kind: u8, value: u8) -> io::Result<()> {
+        self.stream.write_all(&[kind, value])
+    }
+
+    // The next message, waiting up to `timeout` for it; zero doesn't wait
+    fn receive(&mut self, timeout: Duration) -> io::Result<Option<(u8, u8)>> {
+        let deadline = Instant::now() + timeout;
+        while self.pending.len() < 2 {
+            let mut buffer = [0; 64];
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "link partner disconnected")),
+                Ok(len) => self.pending.extend_from_slice(&buffer[..len]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => {
+                    if Instant::now() >= deadline {
+                        return Ok(None);
+                    }
+                    std::thread::sleep(WAIT_STEP);
+                }
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+        }
+        let message = (self.pending[0], self.pending[1]);
+        self.pending.drain(..2);
+        Ok(Some(message))
+    }
+}
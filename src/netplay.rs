@@ -0,0 +1,507 @@
+// Netplay for games without a link cable mode, like Tetris's marathon: both
+// sides run the same game and every frame gets the two players' buttons
+// combined, as if they shared one Game Boy on the couch. Only buttons travel,
+// over UDP; the core's determinism (see GameBoy::state_hash) keeps the two
+// machines identical, and a hash compared every CHECK_INTERVAL frames catches
+// it when they aren't.
+//
+// A press on frame n takes effect on frame n + delay on both sides, which
+// hides network latency up to that many frames. Past that a side either waits
+// for its peer's buttons (lockstep), or with rollback guesses the peer still
+// holds what it last sent and runs ahead. When the real buttons differ from
+// the guess it loads the snapshot taken at that frame and runs forward again.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, ErrorKind};
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::gameboy::GameBoy;
+use crate::rtc::RtcMode;
+use crate::savestate::state_hash;
+
+// Every packet starts with the magic and the protocol version, then its kind
+const MAGIC: [u8; 4] = *b"GBNP";
+// Bumped whenever the packets change, so mismatched builds refuse to connect
+const PROTOCOL_VERSION: u8 = 1;
+const HEADER_SIZE: usize = 6;
+// ROM hash and hardware model, so both sides know they run the same game
+const HELLO: u8 = b'H';
+// A piece of the host's save state, which the joining side starts from
+const STATE: u8 = b'S';
+// The first piece of the state the joining side is missing
+const STATE_ACK: u8 = b'A';
+// The joining side has the whole state
+const DONE: u8 = b'D';
+// Buttons for a run of frames, and the sender's latest state hash
+const INPUTS: u8 = b'I';
+const BYE: u8 = b'Q';
+
+pub const DEFAULT_DELAY: u32 = 2;
+pub const MAX_DELAY: u32 = 10;
+pub const MAX_ROLLBACK: u32 = 10;
+// Frames between the state hashes the two sides compare
+const CHECK_INTERVAL: u64 = 60;
+// Hashes kept for comparing with the peer's, which arrive late
+const KEPT_CHECKS: usize = 8;
+// Save state bytes per packet, well under a typical MTU, and how many are
+// sent before waiting to hear which arrived, so they fit the receive buffer
+const CHUNK_SIZE: usize = 1024;
+const CHUNK_WINDOW: usize = 32;
+const MAX_INPUTS_PER_PACKET: usize = 255;
+const MAX_PACKET_SIZE: usize = 2048;
+// How often unanswered packets are sent again
+const RESEND_INTERVAL: Duration = Duration::from_millis(100);
+const WAIT_STEP: Duration = Duration::from_micros(500);
+// How long run_frame waits for the peer before giving the frontend its turn
+const FRAME_WAIT: Duration = Duration::from_millis(20);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const PEER_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Netplay {
+    socket: UdpSocket,
+    delay: u64,
+    // Frames that may run on guessed buttons, 0 for lockstep
+    rollback: u64,
+    // The host answers a repeated hello, in case its first answer was lost
+    hello: Option<Vec<u8>>,
+    // The next frame to run
+    frame: u64,
+    // Frames before this one ran with the peer's real buttons
+    confirmed: u64,
+    // This side's buttons by frame, kept until the peer has them and no
+    // rollback can need them
+    local: BTreeMap<u64, u8>,
+    remote: BTreeMap<u64, u8>,
+    // The peer has sent all its buttons before this frame
+    received: u64,
+    // The peer has all of this side's buttons before this frame
+    peer_received: u64,
+    // For each frame from `confirmed` up to `frame`, the state at its start
+    // and the buttons guessed for the peer
+    snapshots: VecDeque<(Vec<u8>, u8)>,
+    // (frame, hash) of the state at the start of recent check frames
+    checks: VecDeque<(u64, u64)>,
+    peer_check: Option<(u64, u64)>,
+    desynced: bool,
+    new_desync: Option<u64>,
+    last_heard: Instant,
+    last_sent: Instant,
+}
+
+impl Netplay {
+    fn new(socket: UdpSocket, delay: u32, rollback: u32) -> Self {
+        let delay = delay.min(MAX_DELAY) as u64;
+        Netplay {
+            socket,
+            delay,
+            rollback: rollback.min(MAX_ROLLBACK) as u64,
+            hello: None,
+            frame: 0,
+            confirmed: 0,
+            // Nobody has pressed anything in the frames before the delay
+            local: (0..delay).map(|frame| (frame, 0)).collect(),
+            remote: BTreeMap::new(),
+            received: 0,
+            peer_received: 0,
+            snapshots: VecDeque::new(),
+            checks: VecDeque::new(),
+            peer_check: None,
+            desynced: false,
+            new_desync: None,
+            last_heard: Instant::now(),
+            last_sent: Instant::now(),
+        }
+    }
+
+    // Waits for the other player to join, then sends them the machine's
+    // state so both start from the same one
+    pub fn host(port: u16, gb: &mut GameBoy, delay: u32, rollback: u32) -> io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", port))?;
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        let peer = loop {
+            let (len, from) = socket.recv_from(&mut buffer)?;
+            if let Some((HELLO, body)) = parse_packet(&buffer[..len])? {
+                let hello = packet(HELLO, &hello_body(gb));
+                socket.send_to(&hello, from)?;
+                check_hello(body, gb)?;
+                break from;
+            }
+        };
+        socket.connect(peer)?;
+        socket.set_nonblocking(true)?;
+
+        let mut netplay = Self::new(socket, delay, rollback);
+        netplay.hello = Some(packet(HELLO, &hello_body(gb)));
+        gb.set_rtc_mode(RtcMode::CycleSynced);
+        let state = gb.save_state();
+        let chunks: Vec<&[u8]> = state.chunks(CHUNK_SIZE).collect();
+        let started = Instant::now();
+        let mut missing = 0;
+        'window: loop {
+            for (index, chunk) in chunks.iter().enumerate().skip(missing).take(CHUNK_WINDOW) {
+                let mut body = Vec::with_capacity(4 + chunk.len());
+                body.extend_from_slice(&(index as u16).to_le_bytes());
+                body.extend_from_slice(&(chunks.len() as u16).to_le_bytes());
+                body.extend_from_slice(chunk);
+                netplay.send(STATE, &body)?;
+            }
+            let deadline = Instant::now() + RESEND_INTERVAL;
+            while Instant::now() < deadline {
+                match netplay.receive_packet()? {
+                    Some((STATE_ACK, body)) if body.len() >= 2 => {
+                        missing = u16::from_le_bytes([body[0], body[1]]) as usize;
+                        continue 'window;
+                    }
+                    Some((DONE, _)) => return Ok(netplay),
+                    // The peer got the state and started playing
+                    Some((INPUTS, body)) => {
+                        netplay.handle_inputs(&body);
+                        return Ok(netplay);
+                    }
+                    Some((kind, body)) => netplay.handle_packet(kind, &body)?,
+                    None => std::thread::sleep(WAIT_STEP),
+                }
+            }
+            if started.elapsed() > HANDSHAKE_TIMEOUT {
+                return Err(io::Error::new(ErrorKind::TimedOut, "the other player never received the game state"));
+            }
+        }
+    }
+
+    // Joins a hosted game, replacing the machine's state with the host's
+    pub fn join(addr: impl ToSocketAddrs, gb: &mut GameBoy, delay: u32, rollback: u32) -> io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect(addr)?;
+        socket.set_nonblocking(true)?;
+        let mut netplay = Self::new(socket, delay, rollback);
+        gb.set_rtc_mode(RtcMode::CycleSynced);
+
+        let hello = hello_body(gb);
+        let mut answered = false;
+        let mut chunks: Vec<Option<Vec<u8>>> = Vec::new();
+        // Pieces arrived since the host last heard which are missing
+        let mut unacked = false;
+        let started = Instant::now();
+        let mut last_hello = None::<Instant>;
+        loop {
+            if started.elapsed() > HANDSHAKE_TIMEOUT {
+                return Err(io::Error::new(ErrorKind::TimedOut, "no netplay host answered"));
+            }
+            if !answered && last_hello.is_none_or(|sent| sent.elapsed() >= RESEND_INTERVAL) {
+                netplay.send(HELLO, &hello)?;
+                last_hello = Some(Instant::now());
+            }
+            match netplay.receive_packet()? {
+                Some((HELLO, body)) => {
+                    check_hello(&body, gb)?;
+                    answered = true;
+                }
+                Some((STATE, body)) if body.len() >= 4 => {
+                    let index = u16::from_le_bytes([body[0], body[1]]) as usize;
+                    let count = u16::from_le_bytes([body[2], body[3]]) as usize;
+                    chunks.resize(count, None);
+                    if let Some(chunk) = chunks.get_mut(index) {
+                        *chunk = Some(body[4..].to_vec());
+                        unacked = true;
+                    }
+                    if chunks.iter().all(Option::is_some) {
+                        let state: Vec<u8> = chunks.into_iter().flatten().flatten().collect();
+                        gb.load_state(&state).map_err(|error| io::Error::new(ErrorKind::InvalidData, error.to_string()))?;
+                        netplay.send(DONE, &[])?;
+                        return Ok(netplay);
+                    }
+                }
+                Some(_) => {}
+                // The host's window has been read, ask for the next
+                None if unacked => {
+                    let missing = chunks.iter().position(Option::is_none).unwrap_or(0) as u16;
+                    netplay.send(STATE_ACK, &missing.to_le_bytes())?;
+                    unacked = false;
+                }
+                None => std::thread::sleep(WAIT_STEP),
+            }
+        }
+    }
+
+    pub fn peer(&self) -> Option<String> {
+        self.socket.peer_addr().ok().map(|addr| addr.to_string())
+    }
+
+    // The next frame to run, counted from the start of the session
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // The first frame whose state hash differed from the peer's, reported
+    // once. Past it the two games have gone their own ways.
+    pub fn take_desync(&mut self) -> Option<u64> {
+        self.new_desync.take()
+    }
+
+    // Runs the next frame with this side's `buttons`, which take effect
+    // `delay` frames later. Returns false without running anything if the
+    // peer's buttons are still missing after a short wait; call again on the
+    // next frame. An error means the peer is gone.
+    pub fn run_frame(&mut self, gb: &mut GameBoy, buttons: u8) -> io::Result<bool> {
+        // A press made while waiting goes to the frame after, since the
+        // one already sent can't change
+        self.local.entry(self.frame + self.delay).or_insert(buttons);
+        self.send_inputs()?;
+        let started = Instant::now();
+        loop {
+            self.receive()?;
+            self.reconcile(gb)?;
+            let unconfirmed = self.frame - self.confirmed;
+            if self.remote.contains_key(&self.frame) || unconfirmed < self.rollback {
+                break;
+            }
+            if started.elapsed() >= FRAME_WAIT {
+                return Ok(false);
+            }
+            if self.last_sent.elapsed() >= RESEND_INTERVAL {
+                self.send_inputs()?;
+            }
+            std::thread::sleep(WAIT_STEP);
+        }
+        self.step(gb);
+        Ok(true)
+    }
+
+    // Keeps the session alive while the frontend isn't running frames, so
+    // a paused player doesn't look gone to the peer
+    pub fn poll(&mut self, gb: &mut GameBoy) -> io::Result<()> {
+        self.receive()?;
+        self.reconcile(gb)?;
+        if self.last_sent.elapsed() >= RESEND_INTERVAL {
+            self.send_inputs()?;
+        }
+        Ok(())
+    }
+
+    fn step(&mut self, gb: &mut GameBoy) {
+        let frame = self.frame;
+        let local = self.local.get(&frame).copied().unwrap_or(0);
+        let remote = match self.remote.get(&frame) {
+            Some(&buttons) if frame == self.confirmed => {
+                self.confirm(|| gb.state_hash());
+                buttons
+            }
+            Some(&buttons) => {
+                self.snapshots.push_back((gb.save_state(), buttons));
+                buttons
+            }
+            // The peer is most likely still holding what it last sent
+            None => {
+                let guess = self.remote.range(..frame).next_back().map_or(0, |(_, &buttons)| buttons);
+                self.snapshots.push_back((gb.save_state(), guess));
+                guess
+            }
+        };
+        gb.set_inputs(local | remote);
+        gb.run_frame();
+        self.frame += 1;
+    }
+
+    // Confirms the frames whose guesses the peer's buttons have caught up
+    // with, rolling back to the first wrong guess
+    fn reconcile(&mut self, gb: &mut GameBoy) -> io::Result<()> {
+        while let Some(&(_, guess)) = self.snapshots.front() {
+            let Some(&real) = self.remote.get(&self.confirmed) else {
+                return Ok(());
+            };
+            if real != guess {
+                return self.roll_back(gb);
+            }
+            let (state, _) = self.snapshots.pop_front().expect("checked above");
+            self.confirm(|| state_hash(&state));
+        }
+        Ok(())
+    }
+
+    fn roll_back(&mut self, gb: &mut GameBoy) -> io::Result<()> {
+        let (state, _) = self.snapshots.pop_front().expect("only called with snapshots");
+        self.snapshots.clear();
+        gb.load_state(&state).map_err(|error| io::Error::new(ErrorKind::InvalidData, error.to_string()))?;
+        let target = self.frame;
+        self.frame = self.confirmed;
+        while self.frame < target {
+            self.step(gb);
+            // Heard and seen the first time through
+            gb.take_audio();
+            gb.take_events();
+        }
+        Ok(())
+    }
+
+    // Frame `confirmed` ran with real buttons; `hash` gives the state at its
+    // start
+    fn confirm(&mut self, hash: impl FnOnce() -> u64) {
+        let frame = self.confirmed;
+        if frame > 0 && frame.is_multiple_of(CHECK_INTERVAL) {
+            if self.checks.len() == KEPT_CHECKS {
+                self.checks.pop_front();
+            }
+            self.checks.push_back((frame, hash()));
+            self.compare_checks();
+        }
+        self.confirmed += 1;
+        let keep_local = self.confirmed.min(self.peer_received);
+        self.local.retain(|&frame, _| frame >= keep_local);
+        // The last confirmed buttons stay for guessing
+        let keep_remote = self.confirmed - 1;
+        self.remote.retain(|&frame, _| frame >= keep_remote);
+    }
+
+    fn compare_checks(&mut self) {
+        let Some((frame, hash)) = self.peer_check else {
+            return;
+        };
+        let differs = self.checks.iter().any(|&(mine, my_hash)| mine == frame && my_hash != hash);
+        if differs && !self.desynced {
+            self.desynced = true;
+            self.new_desync = Some(frame);
+        }
+    }
+
+    fn send_inputs(&mut self) -> io::Result<()> {
+        let (check_frame, check_hash) = self.checks.back().copied().unwrap_or((0, 0));
+        let inputs: Vec<u8> = self.local
+            .range(self.peer_received..)
+            .take(MAX_INPUTS_PER_PACKET)
+            .map(|(_, &buttons)| buttons)
+            .collect();
+        let mut body = Vec::with_capacity(33 + inputs.len());
+        body.extend_from_slice(&self.received.to_le_bytes());
+        body.extend_from_slice(&check_frame.to_le_bytes());
+        body.extend_from_slice(&check_hash.to_le_bytes());
+        body.extend_from_slice(&self.peer_received.to_le_bytes());
+        body.push(inputs.len() as u8);
+        body.extend_from_slice(&inputs);
+        self.send(INPUTS, &body)
+    }
+
+    fn handle_inputs(&mut self, body: &[u8]) {
+        let field = |at: usize| body.get(at..at + 8).map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()));
+        let (Some(ack), Some(check_frame), Some(check_hash), Some(first), Some(&count)) =
+            (field(0), field(8), field(16), field(24), body.get(32))
+        else {
+            return;
+        };
+        self.peer_received = self.peer_received.max(ack);
+        if check_frame > 0 {
+            self.peer_check = Some((check_frame, check_hash));
+            self.compare_checks();
+        }
+        let inputs = body.get(33..33 + count as usize).unwrap_or_default();
+        for (frame, &buttons) in (first..).zip(inputs) {
+            if frame >= self.confirmed {
+                self.remote.entry(frame).or_insert(buttons);
+            }
+        }
+        while self.remote.contains_key(&self.received) {
+            self.received += 1;
+        }
+    }
+
+    fn handle_packet(&mut self, kind: u8, body: &[u8]) -> io::Result<()> {
+        match kind {
+            INPUTS => self.handle_inputs(body),
+            HELLO => {
+                if let Some(hello) = &self.hello {
+                    self.socket.send(hello)?;
+                }
+            }
+            // The host didn't hear that the state arrived
+            STATE if self.hello.is_none() => self.send(DONE, &[])?,
+            BYE => return Err(io::Error::new(ErrorKind::ConnectionAborted, "the other player left")),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self) -> io::Result<()> {
+        while let Some((kind, body)) = self.receive_packet()? {
+            self.handle_packet(kind, &body)?;
+        }
+        if self.last_heard.elapsed() > PEER_TIMEOUT {
+            return Err(io::Error::new(ErrorKind::TimedOut, "the other player stopped answering"));
+        }
+        Ok(())
+    }
+
+    // The next packet from the peer, without waiting
+    fn receive_packet(&mut self) -> io::Result<Option<(u8, Vec<u8>)>> {
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        loop {
+            match self.socket.recv(&mut buffer) {
+                Ok(len) => {
+                    if let Some((kind, body)) = parse_packet(&buffer[..len])? {
+                        self.last_heard = Instant::now();
+                        return Ok(Some((kind, body.to_vec())));
+                    }
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => return Ok(None),
+                // The peer's port isn't open yet, or has closed; the timeout
+                // decides when that means it's gone
+                Err(error) if error.kind() == ErrorKind::ConnectionRefused => return Ok(None),
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    fn send(&mut self, kind: u8, body: &[u8]) -> io::Result<()> {
+        self.last_sent = Instant::now();
+        match self.socket.send(&packet(kind, body)) {
+            Err(error) if error.kind() == ErrorKind::ConnectionRefused => Ok(()),
+            result => result.map(|_| ()),
+        }
+    }
+}
+
+impl Drop for Netplay {
+    fn drop(&mut self) {
+        let _ = self.send(BYE, &[]);
+    }
+}
+
+fn packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(HEADER_SIZE + body.len());
+    packet.extend_from_slice(&MAGIC);
+    packet.push(PROTOCOL_VERSION);
+    packet.push(kind);
+    packet.extend_from_slice(body);
+    packet
+}
+
+// The kind and body of a netplay packet; anything else on the port is ignored
+fn parse_packet(packet: &[u8]) -> io::Result<Option<(u8, &[u8])>> {
+    if packet.len() < HEADER_SIZE || packet[..4] != MAGIC {
+        return Ok(None);
+    }
+    if packet[4] != PROTOCOL_VERSION {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("the other player's emulator speaks netplay protocol {}, this is {}", packet[4], PROTOCOL_VERSION),
+        ));
+    }
+    Ok(Some((packet[5], &packet[HEADER_SIZE..])))
+}
+
+fn hello_body(gb: &GameBoy) -> Vec<u8> {
+    let mut body = gb.rom_hash().to_le_bytes().to_vec();
+    body.push(gb.model() as u8);
+    body
+}
+
+fn check_hello(body: &[u8], gb: &GameBoy) -> io::Result<()> {
+    let rom_hash = body.get(..8).map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()));
+    if rom_hash != Some(gb.rom_hash()) {
+        return Err(io::Error::new(ErrorKind::InvalidData, "the other player is running a different ROM"));
+    }
+    if body.get(8) != Some(&(gb.model() as u8)) {
+        return Err(io::Error::new(ErrorKind::InvalidData, "the other player emulates a different model, pass the same --model on both sides"));
+    }
+    Ok(())
+}
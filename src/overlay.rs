@@ -0,0 +1,73 @@
+// Text drawn over the screen in a 3x5 pixel font, for scripts to show
+// counters, timers and the like. Each glyph gets a dark shadow so it stays
+// readable on any background.
+
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// Glyphs advance by this many pixels, lines by LINE_HEIGHT
+pub const GLYPH_WIDTH: i32 = 4;
+pub const LINE_HEIGHT: i32 = 6;
+const SHADOW: [u8; 3] = [0x00, 0x00, 0x00];
+
+// ' ' through '_', 15 bits each with the top row in bits 12-14; lowercase
+// letters use the capitals and anything else shows as '?'
+const FONT: [u16; 64] = [
+    0x0000, 0x2482, 0x5A00, 0x5F7D, 0x3C9E, 0x52A5, 0x2AAB, 0x2400,
+    0x1491, 0x4494, 0x0AA8, 0x05D0, 0x0014, 0x01C0, 0x0002, 0x12A4,
+    0x7B6F, 0x2C97, 0x73E7, 0x72CF, 0x5BC9, 0x79CF, 0x79EF, 0x7252,
+    0x7BEF, 0x7BCF, 0x0410, 0x0414, 0x1511, 0x0E38, 0x4454, 0x72C2,
+    0x2BE3, 0x2BED, 0x6BAE, 0x3923, 0x6B6E, 0x79A7, 0x79A4, 0x396B,
+    0x5BED, 0x7497, 0x126A, 0x5BAD, 0x4927, 0x5FED, 0x6B6D, 0x2B6A,
+    0x6BA4, 0x2B73, 0x6BAD, 0x388E, 0x7492, 0x5B6F, 0x5B6A, 0x5BFD,
+    0x5AAD, 0x5A92, 0x72A7, 0x3493, 0x4889, 0x6496, 0x2A00, 0x0007,
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverlayText {
+    // Top left corner in screen pixels; text can run off any edge
+    pub x: i32,
+    pub y: i32,
+    pub text: String,
+    pub color: [u8; 3],
+}
+
+impl OverlayText {
+    // Into an RGB24 frame; '\n' starts a new line under x
+    pub fn draw(&self, pixels: &mut [u8]) {
+        let (mut x, mut y) = (self.x, self.y);
+        for c in self.text.chars() {
+            if c == '\n' {
+                (x, y) = (self.x, y + LINE_HEIGHT);
+                continue;
+            }
+            let bits = glyph(c);
+            for (offset, color) in [(1, SHADOW), (0, self.color)] {
+                for row in 0..5 {
+                    for column in 0..3 {
+                        if bits & (0x4000 >> (row * 3 + column)) != 0 {
+                            put_pixel(pixels, x + column + offset, y + row + offset, color);
+                        }
+                    }
+                }
+            }
+            x += GLYPH_WIDTH;
+        }
+    }
+}
+
+fn glyph(c: char) -> u16 {
+    let index = c.to_ascii_uppercase() as usize;
+    match index.checked_sub(' ' as usize) {
+        Some(index) if index < FONT.len() => FONT[index],
+        _ => FONT[(b'?' - b' ') as usize],
+    }
+}
+
+fn put_pixel(pixels: &mut [u8], x: i32, y: i32, color: [u8; 3]) {
+    if (0..SCREEN_WIDTH as i32).contains(&x) && (0..SCREEN_HEIGHT as i32).contains(&y) {
+        let at = (y as usize * SCREEN_WIDTH + x as usize) * 3;
+        if let Some(pixel) = pixels.get_mut(at..at + 3) {
+            pixel.copy_from_slice(&color);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+const CYCLES_PER_SECOND: u64 = 4_194_304;
+const CYCLES_PER_FRAME: u64 = 70_224;
+
+// Sleeps are only trusted to wake up this close to the deadline; the rest of
+// the wait is spent spinning
+const SPIN_MARGIN: Duration = Duration::from_millis(2);
+
+// Frames more than this far behind are dropped from the schedule instead of
+// being caught up in a burst
+const MAX_LAG_FRAMES: u32 = 4;
+
+// Frames emulated per displayed frame when running unthrottled, so the
+// window isn't redrawn far more often than it can be seen
+const UNTHROTTLED_FRAMES: u32 = 4;
+
+// How fast the emulation runs compared to the Game Boy
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Speed {
+    // N times real time, 1 being normal speed
+    Times(u32),
+    // 1/N of real time, for slow motion
+    Fraction(u32),
+    // As fast as the host allows
+    Unthrottled,
+}
+
+impl FromStr for Speed {
+    type Err = String;
+
+    // "max" or a multiplier such as "2" or "4x"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "max" {
+            return Ok(Speed::Unthrottled);
+        }
+        match s.strip_suffix('x').unwrap_or(s).parse() {
+            Ok(times) if times > 0 => Ok(Speed::Times(times)),
+            _ => Err(format!("unknown speed '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Speed::Times(times) => write!(f, "{}x", times),
+            Speed::Fraction(divisor) => write!(f, "{}x", 1.0 / *divisor as f32),
+            Speed::Unthrottled => write!(f, "unthrottled"),
+        }
+    }
+}
+
+// Holds the loop to the Game Boy's frame rate, about 59.73 per second. The
+// window is always redrawn at that rate; the speed only changes how many
+// emulated frames each redraw shows, so audio can be fitted to match.
+pub struct FramePacer {
+    period: Duration,
+    next: Option<Instant>,
+    speed: Speed,
+    // Displayed frames since the last emulated one, in slow motion
+    slow_count: u32,
+}
+
+impl Default for FramePacer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FramePacer {
+    pub fn new() -> Self {
+        FramePacer {
+            period: Duration::from_nanos(CYCLES_PER_FRAME * 1_000_000_000 / CYCLES_PER_SECOND),
+            next: None,
+            speed: Speed::Times(1),
+            slow_count: 0,
+        }
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) {
+        if speed != self.speed {
+            self.speed = speed;
+            self.slow_count = 0;
+            self.reset();
+        }
+    }
+
+    // How many frames to emulate before the next one is displayed
+    pub fn frames_due(&mut self) -> u32 {
+        match self.speed {
+            Speed::Times(times) => times,
+            Speed::Fraction(divisor) => {
+                let due = self.slow_count == 0;
+                self.slow_count = (self.slow_count + 1) % divisor.max(1);
+                due as u32
+            }
+            Speed::Unthrottled => UNTHROTTLED_FRAMES,
+        }
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    // When the current frame should be shown, given the time is `now`.
+    // Deadlines follow each other a period apart so rounding doesn't add up.
+    pub fn next_deadline(&mut self, now: Instant) -> Instant {
+        let deadline = match self.next {
+            Some(next) if now.saturating_duration_since(next) <= self.period * MAX_LAG_FRAMES => next,
+            _ => now,
+        };
+        self.next = Some(deadline + self.period);
+        deadline
+    }
+
+    // Starts a fresh schedule, after a pause or fast-forward
+    pub fn reset(&mut self) {
+        self.next = None;
+    }
+
+    // Returns at once when unthrottled
+    pub fn wait(&mut self) {
+        if self.speed == Speed::Unthrottled {
+            self.reset();
+            return;
+        }
+        let deadline = self.next_deadline(Instant::now());
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            if remaining > SPIN_MARGIN {
+                std::thread::sleep(remaining - SPIN_MARGIN);
+            } else {
+                std::hint::spin_loop();
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+// Where the time goes while emulating, for tuning and for the window title:
+// frames per second, speed against a real Game Boy, and how long the PPU, the
+// APU and the rest (mostly the CPU) take per frame. Timing the subsystems
+// reads the clock on every bus tick, so it stays off until
+// GameBoy::set_profiling turns it on, and then only one call to each in
+// SAMPLE_EVERY is timed and counted for all of them.
+
+use std::time::{Duration, Instant};
+
+const CYCLES_PER_SECOND: f64 = 4_194_304.0;
+// Stats cover this much wall time and are replaced when it's over
+const WINDOW: Duration = Duration::from_secs(1);
+const SAMPLE_EVERY: u32 = 16;
+// Clock reads measured to find what they add to each timed call
+const CALIBRATION_READS: u32 = 1000;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Subsystem {
+    Ppu,
+    Apu,
+}
+
+// Time spent in each subsystem since the last take
+#[derive(Default)]
+pub struct SubsystemTimer {
+    enabled: bool,
+    // Calls left until each subsystem's next timed one
+    countdown: [u32; 2],
+    totals: [Duration; 2],
+    // What reading the clock adds to a timed call, taken off each one
+    overhead: Duration,
+}
+
+impl SubsystemTimer {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.totals = Default::default();
+        if enabled {
+            let start = Instant::now();
+            for _ in 0..CALIBRATION_READS {
+                std::hint::black_box(Instant::now().elapsed());
+            }
+            self.overhead = start.elapsed() / CALIBRATION_READS;
+        }
+    }
+
+    #[inline]
+    pub fn time<R>(&mut self, subsystem: Subsystem, run: impl FnOnce() -> R) -> R {
+        if !self.enabled {
+            return run();
+        }
+        let countdown = &mut self.countdown[subsystem as usize];
+        if *countdown > 0 {
+            *countdown -= 1;
+            return run();
+        }
+        *countdown = SAMPLE_EVERY - 1;
+        let start = Instant::now();
+        let result = run();
+        self.totals[subsystem as usize] += start.elapsed().saturating_sub(self.overhead) * SAMPLE_EVERY;
+        result
+    }
+
+    pub fn take(&mut self) -> [Duration; 2] {
+        std::mem::take(&mut self.totals)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PerfStats {
+    // Frames run per second of wall time
+    pub fps: f64,
+    // Emulated time over wall time, 1.0 being a real Game Boy's pace
+    pub speed: f64,
+    // Per frame, on average. `cpu` is the rest of run_frame: the CPU along
+    // with the timer, DMA and serial port.
+    pub cpu: Duration,
+    pub ppu: Duration,
+    pub apu: Duration,
+}
+
+impl PerfStats {
+    // "59.7 fps, 100% speed, CPU 1.21 ms, PPU 0.80 ms, APU 0.33 ms"
+    pub fn summary(&self) -> String {
+        let ms = |time: Duration| time.as_secs_f64() * 1000.0;
+        format!(
+            "{:.1} fps, {:.0}% speed, CPU {:.2} ms, PPU {:.2} ms, APU {:.2} ms",
+            self.fps,
+            self.speed * 100.0,
+            ms(self.cpu),
+            ms(self.ppu),
+            ms(self.apu),
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct PerfCounters {
+    window_start: Option<Instant>,
+    frames: u32,
+    cycles: u64,
+    busy: Duration,
+    subsystems: [Duration; 2],
+    latest: Option<PerfStats>,
+}
+
+impl PerfCounters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Called after each frame with the cycles it ran, how long it took and
+    // the subsystems' share of that
+    pub fn record_frame(&mut self, cycles: u64, busy: Duration, subsystems: [Duration; 2]) {
+        let now = Instant::now();
+        let window_start = *self.window_start.get_or_insert(now - busy);
+        self.frames += 1;
+        self.cycles += cycles;
+        self.busy += busy;
+        for (total, time) in self.subsystems.iter_mut().zip(subsystems) {
+            *total += time;
+        }
+
+        let elapsed = now - window_start;
+        if elapsed < WINDOW {
+            return;
+        }
+        let frames = self.frames as f64;
+        let [ppu, apu] = self.subsystems.map(|time| time.div_f64(frames));
+        self.latest = Some(PerfStats {
+            fps: frames / elapsed.as_secs_f64(),
+            speed: self.cycles as f64 / CYCLES_PER_SECOND / elapsed.as_secs_f64(),
+            cpu: self.busy.div_f64(frames).saturating_sub(ppu + apu),
+            ppu,
+            apu,
+        });
+        *self = PerfCounters { window_start: Some(now), latest: self.latest, ..Self::default() };
+    }
+
+    // Over the last full second; None until one has passed
+    pub fn stats(&self) -> Option<PerfStats> {
+        self.latest
+    }
+}
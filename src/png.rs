@@ -0,0 +1,256 @@
+// Minimal PNG writer for RGB24 images. The pixel data is stored without
+// compression, which keeps this short at the cost of bigger files. Reading
+// handles the non-interlaced images other programs write, for comparing
+// frames against reference images.
+
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::inflate::inflate;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+// Largest stored deflate block
+const MAX_BLOCK: usize = 0xFFFF;
+
+pub fn encode_rgb(pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+    assert_eq!(pixels.len(), width * height * 3, "pixel data doesn't match the image size");
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, truecolor, deflate, no filtering, not interlaced
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    // Each row starts with filter type 0
+    let mut raw = Vec::with_capacity(height * (width * 3 + 1));
+    for row in pixels.chunks_exact(width * 3) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+// A decoded image, RGB24 whatever the file stored
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+// Gray, RGB and indexed images with or without alpha, which is dropped.
+// 16-bit channels and interlacing aren't supported.
+pub fn decode(data: &[u8]) -> Result<Image, String> {
+    if !data.starts_with(&SIGNATURE) {
+        return Err("not a PNG file".to_string());
+    }
+    let mut header = None;
+    let mut palette = Vec::new();
+    let mut compressed = Vec::new();
+    let mut rest = &data[SIGNATURE.len()..];
+    while rest.len() >= 12 {
+        let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let kind = &rest[4..8];
+        let chunk = rest.get(8..8 + len).ok_or("PNG chunk is truncated")?;
+        match kind {
+            b"IHDR" if len == 13 => header = Some(chunk.to_vec()),
+            b"PLTE" => palette = chunk.to_vec(),
+            b"IDAT" => compressed.extend_from_slice(chunk),
+            b"IEND" => break,
+            _ => {}
+        }
+        rest = rest.get(len + 12..).ok_or("PNG chunk is truncated")?;
+    }
+    let header = header.ok_or("PNG has no header")?;
+    let width = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+    let height = u32::from_be_bytes(header[4..8].try_into().unwrap()) as usize;
+    let (depth, color_type, interlace) = (header[8] as usize, header[9], header[12]);
+    let channels = match color_type {
+        0 | 3 => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => return Err(format!("unknown PNG color type {}", color_type)),
+    };
+    if depth > 8 || (channels > 1 && depth != 8) || interlace != 0 {
+        return Err("16-bit and interlaced PNGs aren't supported".to_string());
+    }
+
+    let raw = zlib_decompress(&compressed)?;
+    let row_bytes = (width * channels * depth).div_ceil(8);
+    let rows = unfilter(&raw, row_bytes, height, (channels * depth).div_ceil(8))?;
+    let mut pixels = Vec::with_capacity(width * height * 3);
+    for row in rows.chunks_exact(row_bytes) {
+        for x in 0..width {
+            match color_type {
+                0 | 3 => {
+                    let bit = x * depth;
+                    let value = (row[bit / 8] >> (8 - depth - bit % 8)) as usize & ((1 << depth) - 1);
+                    if color_type == 3 {
+                        let color = palette.get(value * 3..value * 3 + 3).ok_or("PNG palette index out of range")?;
+                        pixels.extend_from_slice(color);
+                    } else {
+                        let gray = (value * 255 / ((1 << depth) - 1)) as u8;
+                        pixels.extend_from_slice(&[gray; 3]);
+                    }
+                }
+                4 => pixels.extend_from_slice(&[row[x * 2]; 3]),
+                _ => pixels.extend_from_slice(&row[x * channels..x * channels + 3]),
+            }
+        }
+    }
+    Ok(Image { width, height, pixels })
+}
+
+fn zlib_decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < 6 || data[0] & 0x0F != 8 || !u16::from_be_bytes([data[0], data[1]]).is_multiple_of(31) || data[1] & 0x20 != 0 {
+        return Err("invalid zlib header".to_string());
+    }
+    let out = inflate(&data[2..])?;
+    // Deflate doesn't say where it ends, so the checksum is the last four bytes
+    if adler32(&out).to_be_bytes() != data[data.len() - 4..] {
+        return Err("zlib checksum doesn't match".to_string());
+    }
+    Ok(out)
+}
+
+// Undoes each row's filter, which predicts bytes from the ones to the left
+// (`step` bytes back, a whole pixel) and above
+fn unfilter(raw: &[u8], row_bytes: usize, height: usize, step: usize) -> Result<Vec<u8>, String> {
+    if raw.len() < height * (row_bytes + 1) {
+        return Err("PNG image data is truncated".to_string());
+    }
+    let mut out = vec![0u8; height * row_bytes];
+    for y in 0..height {
+        let filter = raw[y * (row_bytes + 1)];
+        let line = &raw[y * (row_bytes + 1) + 1..(y + 1) * (row_bytes + 1)];
+        for (x, &byte) in line.iter().enumerate() {
+            let at = y * row_bytes + x;
+            let left = if x >= step { out[at - step] } else { 0 };
+            let up = if y > 0 { out[at - row_bytes] } else { 0 };
+            let up_left = if x >= step && y > 0 { out[at - row_bytes - step] } else { 0 };
+            let predicted = match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => return Err(format!("unknown PNG filter {}", filter)),
+            };
+            out[at] = byte.wrapping_add(predicted);
+        }
+    }
+    Ok(out)
+}
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = left as i16 + up as i16 - up_left as i16;
+    let (to_left, to_up, to_up_left) = ((estimate - left as i16).abs(), (estimate - up as i16).abs(), (estimate - up_left as i16).abs());
+    if to_left <= to_up && to_left <= to_up_left {
+        left
+    } else if to_up <= to_up_left {
+        up
+    } else {
+        up_left
+    }
+}
+
+// Repeats every pixel `scale` times across and down
+pub fn upscale(pixels: &[u8], width: usize, scale: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(pixels.len() * scale * scale);
+    for row in pixels.chunks_exact(width * 3) {
+        let start = out.len();
+        for pixel in row.chunks_exact(3) {
+            for _ in 0..scale {
+                out.extend_from_slice(pixel);
+            }
+        }
+        let end = out.len();
+        for _ in 1..scale {
+            out.extend_from_within(start..end);
+        }
+    }
+    out
+}
+
+// <base>-YYYYMMDD-HHMMSS-mmm.png in UTC, so screenshots sort by time and
+// several a second don't overwrite each other
+pub fn screenshot_path(base: &Path, time: SystemTime) -> PathBuf {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_date(seconds / 86400);
+    let name = format!(
+        "{}-{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}.png",
+        base.file_name().unwrap_or_default().to_string_lossy(),
+        year, month, day,
+        seconds / 3600 % 24, seconds / 60 % 60, seconds % 60,
+        since_epoch.subsec_millis()
+    );
+    base.with_file_name(name)
+}
+
+// Year, month and day of a count of days since 1970-01-01
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // Months counted from March, so the leap day comes last
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as u64;
+    (year, month, day)
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(blocks.peek().is_none() as u8);
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
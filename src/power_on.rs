@@ -0,0 +1,69 @@
+use crate::model::HardwareModel;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum PowerOnPattern {
+    Zeros,
+    // Pseudo-random garbage shaped like the chosen model's RAM at power-on
+    HardwareLike,
+}
+
+pub struct PowerOnMemory<'a> {
+    pub wram: &'a mut [u8],
+    pub vram: &'a mut [u8],
+    pub oam: &'a mut [u8],
+}
+
+pub fn fill(memory: PowerOnMemory, pattern: PowerOnPattern, model: HardwareModel, seed: u64) {
+    if pattern == PowerOnPattern::Zeros {
+        memory.wram.fill(0);
+        memory.vram.fill(0);
+        memory.oam.fill(0);
+        return;
+    }
+
+    let mut rng = XorShift::new(seed);
+    match model {
+        HardwareModel::Dmg | HardwareModel::Mgb => {
+            // DMG SRAM powers up noisy with a bias towards set bits
+            for byte in memory.wram.iter_mut() {
+                *byte = rng.next_byte() | rng.next_byte();
+            }
+            for byte in memory.vram.iter_mut() {
+                *byte = rng.next_byte();
+            }
+            for byte in memory.oam.iter_mut() {
+                *byte = rng.next_byte() & rng.next_byte();
+            }
+        }
+        HardwareModel::Cgb | HardwareModel::Agb => {
+            // CGB work RAM settles into alternating 0x00/0xFF runs with a few stray bits
+            for (i, byte) in memory.wram.iter_mut().enumerate() {
+                let base = if (i / 8) % 2 == 0 { 0x00 } else { 0xFF };
+                *byte = base ^ (rng.next_byte() & rng.next_byte() & rng.next_byte());
+            }
+            memory.vram.fill(0);
+            for byte in memory.oam.iter_mut() {
+                *byte = rng.next_byte() & rng.next_byte() & rng.next_byte();
+            }
+        }
+    }
+}
+
+struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // xorshift never leaves the all-zero state, so keep it away from it
+        let state = seed ^ 0x9E37_79B9_7F4A_7C15;
+        XorShift { state: if state == 0 { 0x9E37_79B9_7F4A_7C15 } else { state } }
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 32) as u8
+    }
+}
@@ -1,9 +1,18 @@
 use crate::interrupts::{Interrupts, InterruptType};
+use crate::error::{EmulatorError, Result};
+use crate::savestate::{Savestate, StateReader, StateWriter};
+use std::collections::VecDeque;
 
 pub const SCREEN_WIDTH: usize = 160;
 pub const SCREEN_HEIGHT: usize = 144;
 
 const SCANLINE_CYCLES: u32 = 456;
+const OAM_SEARCH_CYCLES: u32 = 80;
+// Dots to read a tile number and both bytes of a tile row
+const FETCH_DOTS: u8 = 6;
+const OBJ_FETCH_DOTS: u8 = 6;
+// Mode 3 with nothing to lengthen it, which the scanline renderer always takes
+const PIXEL_TRANSFER_DOTS: u32 = 172;
 const VBLANK_START: u8 = 144;
 const VBLANK_END: u8 = 154;
 
@@ -15,9 +24,294 @@ enum Mode {
     PixelTransfer = 3,
 }
 
+impl Mode {
+    fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            0 => Some(Mode::HBlank),
+            1 => Some(Mode::VBlank),
+            2 => Some(Mode::OamSearch),
+            3 => Some(Mode::PixelTransfer),
+            _ => None,
+        }
+    }
+}
+
+// Scroll registers a scanline was drawn with
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct ScrollLatch {
+    pub scx: u8,
+    pub scy: u8,
+}
+
+// A line that had more objects on it than the 10 the hardware draws
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpriteOverflow {
+    pub line: u8,
+    pub requested: u8,
+    // OAM indices of the objects left out, in OAM order
+    pub dropped: Vec<u8>,
+}
+
+// Four colors per palette, as the PPU picks them before the LCD response.
+// CGB mode has eight of each kind; otherwise BG is BGP and OBJ is OBP0 and
+// OBP1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedPalettes {
+    pub bg: Vec<[[u8; 3]; 4]>,
+    pub obj: Vec<[[u8; 3]; 4]>,
+}
+
+// One object as OAM holds it, for debuggers
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct OamEntry {
+    pub index: u8,
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    pub flags: u8,
+}
+
+impl OamEntry {
+    // Top left corner on screen; objects at 0 or less are partly or wholly off it
+    pub fn screen_pos(&self) -> (i16, i16) {
+        (self.x as i16 - 8, self.y as i16 - 16)
+    }
+
+    pub fn x_flip(&self) -> bool {
+        self.flags & 0x20 != 0
+    }
+
+    pub fn y_flip(&self) -> bool {
+        self.flags & 0x40 != 0
+    }
+
+    // "12 at  32, 40 tile 1A flags 20 OBP0 x-flip"
+    pub fn describe(&self, cgb_mode: bool) -> String {
+        let (x, y) = self.screen_pos();
+        let mut text = format!("{:2} at {:3},{:3} tile {:02X} flags {:02X}", self.index, x, y, self.tile, self.flags);
+        if cgb_mode {
+            text += &format!(" bank {} pal {}", (self.flags >> 3) & 1, self.flags & 0x07);
+        } else {
+            text += if self.flags & 0x10 != 0 { " OBP1" } else { " OBP0" };
+        }
+        for (set, name) in [(self.x_flip(), "x-flip"), (self.y_flip(), "y-flip"), (self.flags & 0x80 != 0, "behind-bg")] {
+            if set {
+                text += " ";
+                text += name;
+            }
+        }
+        text
+    }
+}
+
+// Per-tile attributes shared by the BG, window and OBJ fetches
+#[derive(Default, Copy, Clone)]
+struct TileAttributes {
+    bank: usize,
+    x_flip: bool,
+    y_flip: bool,
+    // DMG palette register, or CGB palette number, the color numbers are mapped through
+    palette: u8,
+    // Colors 1-3 of the BG are drawn over objects
+    priority: bool,
+}
+
+impl TileAttributes {
+    fn from_oam(flags: u8, cgb_mode: bool, obp: [u8; 2]) -> Self {
+        TileAttributes {
+            // VRAM bank 1 tiles are only reachable in CGB mode
+            bank: if cgb_mode { ((flags >> 3) & 0x01) as usize } else { 0 },
+            x_flip: flags & 0x20 != 0,
+            y_flip: flags & 0x40 != 0,
+            palette: if cgb_mode { flags & 0x07 } else { obp[((flags >> 4) & 0x01) as usize] },
+            priority: flags & 0x80 != 0,
+        }
+    }
+
+    // CGB BG map attributes, stored in VRAM bank 1 at the tile's map address
+    fn from_bg_map(flags: u8) -> Self {
+        TileAttributes {
+            bank: ((flags >> 3) & 0x01) as usize,
+            x_flip: flags & 0x20 != 0,
+            y_flip: flags & 0x40 != 0,
+            palette: flags & 0x07,
+            priority: flags & 0x80 != 0,
+        }
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        let flags = ((self.bank as u8) << 3)
+            | ((self.x_flip as u8) << 5)
+            | ((self.y_flip as u8) << 6)
+            | ((self.priority as u8) << 7);
+        state.write_u8(flags);
+        state.write_u8(self.palette);
+    }
+
+    fn load(state: &mut StateReader) -> Result<Self> {
+        let attrs = TileAttributes::from_bg_map(state.read_u8()?);
+        Ok(TileAttributes { palette: state.read_u8()?, ..attrs })
+    }
+}
+
+// A pixel waiting in the BG or OBJ FIFO
+#[derive(Default, Copy, Clone)]
+struct FifoPixel {
+    color: u8,
+    attrs: TileAttributes,
+    // Decides between overlapping objects in CGB mode
+    oam_index: u8,
+}
+
+impl FifoPixel {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(self.color);
+        self.attrs.save(state);
+        state.write_u8(self.oam_index);
+    }
+
+    fn load(state: &mut StateReader) -> Result<Self> {
+        Ok(FifoPixel {
+            color: state.read_u8()? & 0x03,
+            attrs: TileAttributes::load(state)?,
+            oam_index: state.read_u8()?,
+        })
+    }
+}
+
+// Mode 3 on the current line. The fetcher reads rows of 8 BG or window
+// pixels into the BG FIFO, which shifts one pixel out per dot; an object
+// stops both while its row is mixed into the OBJ FIFO. Fine scrolling, the
+// window and objects all make the mode longer.
+#[derive(Default, Clone)]
+struct LineFetch {
+    // Dots before the first fetch; the hardware reads one tile it throws away
+    warmup: u8,
+    // Next screen column
+    x: u8,
+    // Pixels still to drop off the FIFO, for fine scrolling
+    discard: u8,
+    // Dots into the current fetch, FETCH_DOTS once the row waits to be pushed
+    fetch_dots: u8,
+    // Tile column of the next fetch, counted from SCX or the window's left edge
+    tile_x: u8,
+    row: [u8; 8],
+    row_attrs: TileAttributes,
+    window: bool,
+    bg_fifo: VecDeque<FifoPixel>,
+    obj_fifo: VecDeque<FifoPixel>,
+    // Objects on the line that the fetcher hasn't reached yet
+    objects: VecDeque<u8>,
+    // Object being fetched and the dots left
+    obj_fetch: Option<(u8, u8)>,
+}
+
+impl Savestate for LineFetch {
+    fn save_state(&self, state: &mut StateWriter) {
+        for value in [self.warmup, self.x, self.discard, self.fetch_dots, self.tile_x] {
+            state.write_u8(value);
+        }
+        state.write_bytes(&self.row);
+        self.row_attrs.save(state);
+        state.write_bool(self.window);
+        for fifo in [&self.bg_fifo, &self.obj_fifo] {
+            state.write_u8(fifo.len() as u8);
+            for pixel in fifo {
+                pixel.save(state);
+            }
+        }
+        state.write_u8(self.objects.len() as u8);
+        for &index in &self.objects {
+            state.write_u8(index);
+        }
+        let (index, dots) = self.obj_fetch.unwrap_or((0, 0));
+        state.write_u8(index);
+        state.write_u8(dots);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        for value in [&mut self.warmup, &mut self.x, &mut self.discard, &mut self.fetch_dots, &mut self.tile_x] {
+            *value = state.read_u8()?;
+        }
+        if self.x as usize > SCREEN_WIDTH || self.fetch_dots > FETCH_DOTS {
+            return Err(EmulatorError::InvalidState);
+        }
+        state.read_bytes(&mut self.row)?;
+        self.row.iter_mut().for_each(|color| *color &= 0x03);
+        self.row_attrs = TileAttributes::load(state)?;
+        self.window = state.read_bool()?;
+        for fifo in [&mut self.bg_fifo, &mut self.obj_fifo] {
+            let len = state.read_u8()?;
+            if len > 8 {
+                return Err(EmulatorError::InvalidState);
+            }
+            fifo.clear();
+            for _ in 0..len {
+                fifo.push_back(FifoPixel::load(state)?);
+            }
+        }
+        let len = state.read_u8()?;
+        if len > 10 {
+            return Err(EmulatorError::InvalidState);
+        }
+        self.objects.clear();
+        for _ in 0..len {
+            self.objects.push_back(state.read_u8()? % 40);
+        }
+        let (index, dots) = (state.read_u8()? % 40, state.read_u8()?);
+        self.obj_fetch = (dots > 0).then_some((index, dots));
+        Ok(())
+    }
+}
+
+// CGB palette memory: 8 palettes of 4 little-endian RGB555 colors, accessed a
+// byte at a time through BCPS/OCPS, which auto-increment on writes when bit 7 is set
+struct PaletteRam {
+    data: [u8; 64],
+    spec: u8,
+}
+
+impl PaletteRam {
+    fn new() -> Self {
+        PaletteRam { data: [0xFF; 64], spec: 0 }
+    }
+
+    fn read_spec(&self) -> u8 {
+        self.spec | 0x40
+    }
+
+    fn write_spec(&mut self, value: u8) {
+        self.spec = value & 0xBF;
+    }
+
+    fn read_data(&self) -> u8 {
+        self.data[(self.spec & 0x3F) as usize]
+    }
+
+    fn write_data(&mut self, value: u8) {
+        self.data[(self.spec & 0x3F) as usize] = value;
+        if self.spec & 0x80 != 0 {
+            self.spec = 0x80 | (self.spec.wrapping_add(1) & 0x3F);
+        }
+    }
+
+    // Expands a color to 8 bits per channel
+    fn rgb(&self, palette: u8, color_num: u8) -> [u8; 3] {
+        let index = (palette as usize * 4 + color_num as usize) * 2;
+        let color = u16::from_le_bytes([self.data[index], self.data[index + 1]]);
+        let channel = |shift: u16| {
+            let value = ((color >> shift) & 0x1F) as u8;
+            (value << 3) | (value >> 2)
+        };
+        [channel(0), channel(5), channel(10)]
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
 pub struct PPU {
     pub framebuffer: [u8; SCREEN_WIDTH * SCREEN_HEIGHT * 3],
-    pub vram: [u8; 0x2000],
+    // Two 8 KB banks, the CPU sees the one selected through VBK
+    pub vram: [u8; 0x4000],
     pub oam: [u8; 0xA0],
     
     lcdc: u8,
@@ -26,6 +320,9 @@ pub struct PPU {
     scx: u8,
     ly: u8,
     lyc: u8,
+    // The enabled STAT sources are ORed into one line, and only its rising
+    // edge requests an interrupt
+    stat_line: bool,
     bgp: u8,
     obp0: u8,
     obp1: u8,
@@ -35,13 +332,45 @@ pub struct PPU {
     mode: Mode,
     cycles: u32,
     frame_ready: bool,
+    // Set when a pixel changes, so unchanged frames can skip the upload
+    frame_dirty: bool,
+    // Line that just entered HBlank, until picked up by take_hblank_line
+    hblank_line: Option<u8>,
+    // Internal window line counter, only advances on lines the window was drawn
+    window_line: u8,
+    // Set once LY matched WY this frame
+    window_y_hit: bool,
+    line: LineFetch,
+    cgb_mode: bool,
+    // VBK: offset of the VRAM bank the CPU sees, only switchable in CGB mode
+    vram_bank: usize,
+    bg_palettes: PaletteRam,
+    obj_palettes: PaletteRam,
+    // Maps output intensities onto the response of the emulated LCD
+    lcd_response: [u8; 256],
+    // Whether the CPU is kept out of OAM in modes 2 and 3 and out of VRAM in mode 3
+    access_restricted: bool,
+    // Draws mode 3 through the FIFO rather than a line at a time
+    pixel_fifo: bool,
+    // Scroll values of the lines drawn so far, and of the last complete frame
+    scroll_lines: [ScrollLatch; SCREEN_HEIGHT],
+    last_scroll_lines: [ScrollLatch; SCREEN_HEIGHT],
+    // Lines with too many objects so far, and in the last complete frame
+    overflow_lines: Vec<SpriteOverflow>,
+    last_overflow_lines: Vec<SpriteOverflow>,
+}
+
+impl Default for PPU {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PPU {
     pub fn new() -> Self {
         PPU {
             framebuffer: [0xFF; SCREEN_WIDTH * SCREEN_HEIGHT * 3],
-            vram: [0; 0x2000],
+            vram: [0; 0x4000],
             oam: [0; 0xA0],
             lcdc: 0x91,
             stat: 0x00,
@@ -49,6 +378,7 @@ impl PPU {
             scx: 0,
             ly: 0,
             lyc: 0,
+            stat_line: false,
             bgp: 0xFC,
             obp0: 0xFF,
             obp1: 0xFF,
@@ -57,39 +387,123 @@ impl PPU {
             mode: Mode::OamSearch,
             cycles: 0,
             frame_ready: false,
+            frame_dirty: true,
+            hblank_line: None,
+            window_line: 0,
+            window_y_hit: false,
+            line: LineFetch::default(),
+            cgb_mode: false,
+            vram_bank: 0,
+            bg_palettes: PaletteRam::new(),
+            obj_palettes: PaletteRam::new(),
+            lcd_response: std::array::from_fn(|i| i as u8),
+            access_restricted: true,
+            pixel_fifo: true,
+            scroll_lines: [ScrollLatch::default(); SCREEN_HEIGHT],
+            last_scroll_lines: [ScrollLatch::default(); SCREEN_HEIGHT],
+            overflow_lines: Vec::new(),
+            last_overflow_lines: Vec::new(),
         }
     }
 
+    // The GBA panel is noticeably darker than the CGB one, which is why
+    // CGB games brighten their palettes when they detect a GBA. The curve is
+    // x^1.5, worked out as sqrt(i^3 / 255) because powf's rounding differs
+    // between platforms and IEEE division and sqrt don't.
+    pub fn set_agb_lcd(&mut self, enabled: bool) {
+        self.lcd_response = std::array::from_fn(|i| {
+            if enabled {
+                ((i * i * i) as f64 / 255.0).sqrt().round() as u8
+            } else {
+                i as u8
+            }
+        });
+    }
+
+    // Lets the CPU reach VRAM and OAM in every mode, for games that only
+    // work with the looser timing
+    pub fn set_access_restrictions(&mut self, enabled: bool) {
+        self.access_restricted = enabled;
+    }
+
+    // Off, each line is drawn at once as a mode 3 of fixed length ends, which
+    // misses register writes made partway through it
+    pub fn set_pixel_fifo(&mut self, enabled: bool) {
+        self.pixel_fifo = enabled;
+    }
+
+    pub fn set_cgb_mode(&mut self, enabled: bool) {
+        self.cgb_mode = enabled;
+        self.vram_bank = 0;
+    }
+
     pub fn tick(&mut self, cycles: u32, interrupts: &mut Interrupts) {
         if !self.is_lcd_enabled() {
             return;
         }
 
-        self.cycles += cycles;
+        let mut remaining = cycles;
+        while remaining > 0 {
+            // Outside mode 3 nothing happens until the dot that ends the
+            // mode, so the ones before it are skipped over together
+            let idle = self.idle_dots().min(remaining);
+            if idle > 0 {
+                self.cycles += idle;
+                remaining -= idle;
+            } else {
+                self.dot(interrupts);
+                remaining -= 1;
+            }
+            self.update_stat(interrupts);
+        }
+    }
+
+    fn idle_dots(&self) -> u32 {
+        let end = match self.mode {
+            Mode::OamSearch => OAM_SEARCH_CYCLES,
+            Mode::HBlank | Mode::VBlank => SCANLINE_CYCLES,
+            Mode::PixelTransfer if !self.pixel_fifo => OAM_SEARCH_CYCLES + PIXEL_TRANSFER_DOTS,
+            Mode::PixelTransfer => return 0,
+        };
+        end.saturating_sub(self.cycles + 1)
+    }
+
+    // `cycles` counts the dots into the current line
+    fn dot(&mut self, interrupts: &mut Interrupts) {
+        self.cycles += 1;
 
         match self.mode {
             Mode::OamSearch => {
-                if self.cycles >= 80 {
-                    self.cycles -= 80;
+                if self.cycles >= OAM_SEARCH_CYCLES {
                     self.mode = Mode::PixelTransfer;
+                    self.start_line();
                 }
             }
             Mode::PixelTransfer => {
-                if self.cycles >= 172 {
-                    self.cycles -= 172;
+                if self.pixel_fifo {
+                    self.transfer_dot();
+                } else if self.cycles >= OAM_SEARCH_CYCLES + PIXEL_TRANSFER_DOTS {
+                    self.draw_line();
+                }
+                if self.line.x as usize == SCREEN_WIDTH {
                     self.mode = Mode::HBlank;
-                    self.render_scanline();
+                    if self.line.window {
+                        self.window_line = self.window_line.wrapping_add(1);
+                    }
+                    self.hblank_line = Some(self.ly);
                 }
             }
             Mode::HBlank => {
-                if self.cycles >= 204 {
-                    self.cycles -= 204;
+                if self.cycles >= SCANLINE_CYCLES {
+                    self.cycles = 0;
                     self.ly += 1;
 
                     if self.ly >= VBLANK_START {
                         self.mode = Mode::VBlank;
                         interrupts.request_interrupt(InterruptType::VBlank);
                         self.frame_ready = true;
+                        self.last_scroll_lines = self.scroll_lines;
+                        self.last_overflow_lines = std::mem::take(&mut self.overflow_lines);
                     } else {
                         self.mode = Mode::OamSearch;
                     }
@@ -97,73 +511,328 @@ impl PPU {
             }
             Mode::VBlank => {
                 if self.cycles >= SCANLINE_CYCLES {
-                    self.cycles -= SCANLINE_CYCLES;
+                    self.cycles = 0;
                     self.ly += 1;
 
                     if self.ly >= VBLANK_END {
                         self.ly = 0;
+                        self.window_line = 0;
+                        self.window_y_hit = false;
                         self.mode = Mode::OamSearch;
                     }
                 }
             }
         }
+    }
 
-        self.update_stat();
+    fn start_line(&mut self) {
+        self.scroll_lines[self.ly as usize] = ScrollLatch { scx: self.scx, scy: self.scy };
+        // The window can only start on lines at or below the first one WY matched this frame
+        self.window_y_hit |= self.ly == self.wy;
+        let objects = self.select_objects();
+        self.line = LineFetch {
+            warmup: FETCH_DOTS,
+            discard: self.scx & 0x07,
+            objects,
+            ..LineFetch::default()
+        };
     }
 
-    fn render_scanline(&mut self) {
-        if !self.is_bg_enabled() {
+    fn transfer_dot(&mut self) {
+        if self.line.warmup > 0 {
+            self.line.warmup -= 1;
+            return;
+        }
+
+        if let Some((index, dots)) = self.line.obj_fetch {
+            if dots > 1 {
+                self.line.obj_fetch = Some((index, dots - 1));
+            } else {
+                self.line.obj_fetch = None;
+                self.merge_object(index);
+            }
+            return;
+        }
+
+        // Reaching WX restarts the fetcher on the window's first tile
+        if !self.line.window && self.is_window_visible() && self.line.x as u16 + 7 >= self.wx as u16 {
+            self.line.window = true;
+            self.line.bg_fifo.clear();
+            self.line.fetch_dots = 0;
+            self.line.tile_x = 0;
+            // Columns left of the screen when WX is below 7
+            self.line.discard = 7u8.saturating_sub(self.wx);
+        }
+
+        // An object lets the fetcher finish its tile, then stops it while the
+        // object's row is read
+        let object_due = self.is_obj_enabled()
+            && self.line.objects.front().is_some_and(|&i| self.oam[i as usize * 4 + 1] as u16 <= self.line.x as u16 + 8);
+        if object_due && self.line.fetch_dots == FETCH_DOTS {
+            if let Some(index) = self.line.objects.pop_front() {
+                self.line.obj_fetch = Some((index, OBJ_FETCH_DOTS - 1));
+            }
+            return;
+        }
+
+        self.fetcher_dot();
+        if object_due {
             return;
         }
 
-        let y = self.ly;
-        let scroll_y = self.scy.wrapping_add(y);
-        let tile_y = (scroll_y / 8) as u16;
+        let Some(bg) = self.line.bg_fifo.pop_front() else {
+            return;
+        };
+        if self.line.discard > 0 {
+            self.line.discard -= 1;
+            return;
+        }
+        let obj = self.line.obj_fifo.pop_front();
+        self.output_pixel(bg, obj);
+        self.line.x += 1;
+    }
+
+    // The scanline renderer's mode 3: the whole line from the registers as
+    // they are now, matching what the FIFO draws when they don't change
+    fn draw_line(&mut self) {
+        let mut bg = [FifoPixel::default(); SCREEN_WIDTH];
+        let fine_x = (self.scx & 0x07) as usize;
+        let map_base = if self.is_bg_tile_map_high() { 0x1C00 } else { 0x1800 };
+        for tile in 0..=SCREEN_WIDTH / 8 {
+            let tile_x = (self.scx / 8).wrapping_add(tile as u8);
+            let (row, attrs) = self.map_row(map_base, tile_x, self.scy.wrapping_add(self.ly));
+            for (offset, &color) in row.iter().enumerate() {
+                if let Some(pixel) = (tile * 8 + offset).checked_sub(fine_x).and_then(|x| bg.get_mut(x)) {
+                    *pixel = FifoPixel { color, attrs, oam_index: 0 };
+                }
+            }
+        }
+
+        // The window covers the BG from WX - 7 to the right edge
+        if self.is_window_visible() && (self.wx as usize) < SCREEN_WIDTH + 7 {
+            self.line.window = true;
+            let left = self.wx as i16 - 7;
+            let map_base = if self.is_window_tile_map_high() { 0x1C00 } else { 0x1800 };
+            for tile in 0..=SCREEN_WIDTH / 8 {
+                let (row, attrs) = self.map_row(map_base, tile as u8, self.window_line);
+                for (offset, &color) in row.iter().enumerate() {
+                    let x = left + (tile * 8 + offset) as i16;
+                    if let Some(pixel) = usize::try_from(x).ok().and_then(|x| bg.get_mut(x)) {
+                        *pixel = FifoPixel { color, attrs, oam_index: 0 };
+                    }
+                }
+            }
+        }
+
+        let mut obj: [Option<FifoPixel>; SCREEN_WIDTH] = [None; SCREEN_WIDTH];
+        if self.is_obj_enabled() {
+            for &index in &self.line.objects {
+                let (pixels, attrs, left) = self.object_row(index);
+                for (offset, &color) in pixels.iter().enumerate() {
+                    let Some(current) = usize::try_from(left + offset as i16).ok().and_then(|x| obj.get_mut(x)) else {
+                        continue;
+                    };
+                    let taken = current.is_some_and(|current| !(self.cgb_mode && index < current.oam_index));
+                    if color != 0 && !taken {
+                        *current = Some(FifoPixel { color, attrs, oam_index: index });
+                    }
+                }
+            }
+        }
 
         for x in 0..SCREEN_WIDTH {
-            let scroll_x = self.scx.wrapping_add(x as u8);
-            let tile_x = (scroll_x / 8) as u16;
-            
-            let tile_map_addr = if self.is_bg_tile_map_high() {
-                0x1C00 + tile_y * 32 + tile_x
-            } else {
-                0x1800 + tile_y * 32 + tile_x
-            };
+            self.line.x = x as u8;
+            self.output_pixel(bg[x], obj[x]);
+        }
+        self.line.x = SCREEN_WIDTH as u8;
+    }
 
-            let tile_index = self.vram[tile_map_addr as usize];
-            
-            let tile_data_addr = if self.is_tile_data_unsigned() {
-                tile_index as u16 * 16
-            } else {
-                if tile_index < 128 {
-                    0x1000 + tile_index as u16 * 16
+    // Reads the next row of 8 BG or window pixels, then waits for the FIFO to
+    // run empty before pushing it
+    fn fetcher_dot(&mut self) {
+        if self.line.fetch_dots < FETCH_DOTS {
+            self.line.fetch_dots += 1;
+            if self.line.fetch_dots == FETCH_DOTS {
+                (self.line.row, self.line.row_attrs) = self.fetch_bg_row();
+            }
+        } else if self.line.bg_fifo.is_empty() {
+            let attrs = self.line.row_attrs;
+            self.line.bg_fifo.extend(self.line.row.iter().map(|&color| FifoPixel { color, attrs, oam_index: 0 }));
+            self.line.tile_x = self.line.tile_x.wrapping_add(1);
+            self.line.fetch_dots = 0;
+        }
+    }
+
+    // SCX and SCY are read at every fetch, so writes during mode 3 take
+    // effect from the next tile on
+    fn fetch_bg_row(&self) -> ([u8; 8], TileAttributes) {
+        if self.line.window {
+            let map_base = if self.is_window_tile_map_high() { 0x1C00 } else { 0x1800 };
+            self.map_row(map_base, self.line.tile_x, self.window_line)
+        } else {
+            let map_base = if self.is_bg_tile_map_high() { 0x1C00 } else { 0x1800 };
+            let tile_x = (self.scx / 8).wrapping_add(self.line.tile_x);
+            self.map_row(map_base, tile_x, self.scy.wrapping_add(self.ly))
+        }
+    }
+
+    fn is_window_visible(&self) -> bool {
+        // On DMG LCDC bit 0 hides the window along with the BG
+        self.is_window_enabled() && (self.is_bg_enabled() || self.cgb_mode) && self.window_y_hit
+    }
+
+    fn output_pixel(&mut self, bg: FifoPixel, obj: Option<FifoPixel>) {
+        // With the BG disabled the DMG shows plain white behind the objects;
+        // in CGB mode LCDC bit 0 doesn't hide the BG, it only takes away its priority
+        let bg_color = if self.is_bg_enabled() || self.cgb_mode { bg.color } else { 0 };
+        let mut rgb = if self.cgb_mode {
+            self.bg_palettes.rgb(bg.attrs.palette, bg_color)
+        } else {
+            self.shade(self.bgp, bg_color)
+        };
+
+        if let Some(obj) = obj.filter(|obj| obj.color != 0 && self.is_obj_enabled()) {
+            // A cleared LCDC bit 0 puts every object on top in CGB mode
+            let bg_wins = !self.cgb_mode || self.is_bg_enabled();
+            if !(bg_wins && (obj.attrs.priority || bg.attrs.priority) && bg_color != 0) {
+                rgb = if self.cgb_mode {
+                    self.obj_palettes.rgb(obj.attrs.palette, obj.color)
                 } else {
-                    0x1000 + ((tile_index as i8) as i16 * 16) as u16
-                }
-            };
+                    self.shade(obj.attrs.palette, obj.color)
+                };
+            }
+        }
+
+        let index = self.ly as usize * SCREEN_WIDTH + self.line.x as usize;
+        self.put_pixel(index, rgb);
+    }
 
-            let line = (scroll_y % 8) as u16;
-            let byte1 = self.vram[(tile_data_addr + line * 2) as usize];
-            let byte2 = self.vram[(tile_data_addr + line * 2 + 1) as usize];
+    // Looks up the tile at column tile_x of a 32x32 tile map, on the tile row
+    // holding map_y, and returns that row of it with the tile's attributes
+    fn map_row(&self, map_base: u16, tile_x: u8, map_y: u8) -> ([u8; 8], TileAttributes) {
+        let tile_x = (tile_x & 0x1F) as u16;
+        let tile_y = (map_y / 8) as u16;
+        let map_addr = (map_base + tile_y * 32 + tile_x) as usize;
+        let tile_index = self.vram[map_addr];
+        let attrs = if self.cgb_mode {
+            TileAttributes::from_bg_map(self.vram[0x2000 + map_addr])
+        } else {
+            TileAttributes::default()
+        };
+
+        // In the signed mode indices 0-127 use 0x9000-0x97FF and 128-255 use 0x8800-0x8FFF
+        let tile_data_addr = if self.is_tile_data_unsigned() {
+            tile_index as u16 * 16
+        } else {
+            (0x1000 + (tile_index as i8) as i16 * 16) as u16
+        };
+
+        (self.fetch_tile_row(tile_data_addr, map_y % 8, attrs), attrs)
+    }
 
-            let pixel_x = 7 - (scroll_x % 8);
-            let color_bit = ((byte2 >> pixel_x) & 1) << 1 | ((byte1 >> pixel_x) & 1);
-            let color = self.get_bg_color(color_bit);
+    // OAM indices of the objects drawn on this line, in the order the
+    // fetcher reaches them
+    fn select_objects(&mut self) -> VecDeque<u8> {
+        let y = self.ly as i16;
+        let height: i16 = if self.is_obj_tall() { 16 } else { 8 };
 
-            let pixel_index = (y as usize * SCREEN_WIDTH + x) * 3;
-            self.framebuffer[pixel_index] = color;
-            self.framebuffer[pixel_index + 1] = color;
-            self.framebuffer[pixel_index + 2] = color;
+        // The hardware picks the first 10 objects in OAM order that overlap the line
+        let mut visible: Vec<u8> = (0..40)
+            .filter(|&i| {
+                let top = self.oam[i as usize * 4] as i16 - 16;
+                y >= top && y < top + height
+            })
+            .collect();
+        if visible.len() > 10 {
+            self.overflow_lines.push(SpriteOverflow {
+                line: self.ly,
+                requested: visible.len() as u8,
+                dropped: visible[10..].to_vec(),
+            });
+            visible.truncate(10);
+        }
+        // Objects with the same X come in OAM order
+        visible.sort_by_key(|&i| self.oam[i as usize * 4 + 1]);
+        visible.into()
+    }
+
+    // Mixes an object's row into the OBJ FIFO, which holds the pixels from
+    // the current column on
+    fn merge_object(&mut self, index: u8) {
+        let (pixels, attrs, left) = self.object_row(index);
+        for (offset, &color) in pixels.iter().enumerate() {
+            let Ok(slot) = usize::try_from(left + offset as i16 - self.line.x as i16) else {
+                continue;
+            };
+            while self.line.obj_fifo.len() <= slot {
+                self.line.obj_fifo.push_back(FifoPixel::default());
+            }
+            // On DMG objects arrive in X order, so the pixel already there wins;
+            // in CGB mode the lower OAM index does
+            let current = &mut self.line.obj_fifo[slot];
+            if color != 0 && (current.color == 0 || (self.cgb_mode && index < current.oam_index)) {
+                *current = FifoPixel { color, attrs, oam_index: index };
+            }
         }
     }
 
-    fn get_bg_color(&self, color_num: u8) -> u8 {
-        match (self.bgp >> (color_num * 2)) & 0x03 {
+    // The row of an object on this line, with its attributes and left edge
+    fn object_row(&self, index: u8) -> ([u8; 8], TileAttributes, i16) {
+        let i = index as usize;
+        let y = self.ly as i16;
+        let height: i16 = if self.is_obj_tall() { 16 } else { 8 };
+        let top = self.oam[i * 4] as i16 - 16;
+        let left = self.oam[i * 4 + 1] as i16 - 8;
+        let mut tile_index = self.oam[i * 4 + 2];
+        let attrs = TileAttributes::from_oam(self.oam[i * 4 + 3], self.cgb_mode, [self.obp0, self.obp1]);
+
+        // Clamped in case OAM changed since the object was selected
+        let mut row = (y - top).clamp(0, height - 1) as u8;
+        if attrs.y_flip {
+            row = height as u8 - 1 - row;
+        }
+        // Tall objects ignore bit 0 of the tile index and continue into the next tile
+        if height == 16 {
+            tile_index &= 0xFE;
+        }
+        let tile_data_addr = tile_index as u16 * 16 + (row as u16 / 8) * 16;
+        // Y flip is already applied across the whole object above
+        let pixels = self.fetch_tile_row(tile_data_addr, row % 8, TileAttributes { y_flip: false, ..attrs });
+        (pixels, attrs, left)
+    }
+
+    // Decodes one 8-pixel row of a tile into color numbers, left to right on screen,
+    // with the bank and flips taken from the attributes
+    fn fetch_tile_row(&self, tile_data_addr: u16, row: u8, attrs: TileAttributes) -> [u8; 8] {
+        let row = if attrs.y_flip { 7 - row } else { row } as usize;
+        // Masked so no tile index or row can reach past the end of the bank
+        let addr = attrs.bank * 0x2000 + ((tile_data_addr as usize + row * 2) & 0x1FFE);
+        let byte1 = self.vram[addr];
+        let byte2 = self.vram[addr + 1];
+
+        std::array::from_fn(|i| {
+            let bit = if attrs.x_flip { i } else { 7 - i };
+            ((byte2 >> bit) & 1) << 1 | ((byte1 >> bit) & 1)
+        })
+    }
+
+    fn shade(&self, palette: u8, color_num: u8) -> [u8; 3] {
+        let shade = match (palette >> (color_num * 2)) & 0x03 {
             0 => 0xFF,
             1 => 0xAA,
             2 => 0x55,
             3 => 0x00,
             _ => unreachable!(),
+        };
+        [shade; 3]
+    }
+
+    fn put_pixel(&mut self, index: usize, rgb: [u8; 3]) {
+        let pixel_index = index * 3;
+        for (channel, value) in rgb.into_iter().enumerate() {
+            let value = self.lcd_response[value as usize];
+            self.frame_dirty |= self.framebuffer[pixel_index + channel] != value;
+            self.framebuffer[pixel_index + channel] = value;
         }
     }
 
@@ -175,6 +844,23 @@ impl PPU {
         self.lcdc & 0x01 != 0
     }
 
+    fn is_obj_enabled(&self) -> bool {
+        self.lcdc & 0x02 != 0
+    }
+
+    // LCDC bit 2, 8x16 objects
+    pub fn is_obj_tall(&self) -> bool {
+        self.lcdc & 0x04 != 0
+    }
+
+    fn is_window_enabled(&self) -> bool {
+        self.lcdc & 0x20 != 0
+    }
+
+    fn is_window_tile_map_high(&self) -> bool {
+        self.lcdc & 0x40 != 0
+    }
+
     fn is_bg_tile_map_high(&self) -> bool {
         self.lcdc & 0x08 != 0
     }
@@ -183,8 +869,67 @@ impl PPU {
         self.lcdc & 0x10 != 0
     }
 
-    fn update_stat(&mut self) {
-        self.stat = (self.stat & 0xFC) | (self.mode as u8);
+    fn update_stat(&mut self, interrupts: &mut Interrupts) {
+        let coincidence = self.ly == self.lyc;
+        self.stat = (self.stat & 0xF8) | ((coincidence as u8) << 2) | (self.mode as u8);
+
+        let line = match self.mode {
+            Mode::HBlank => self.stat & 0x08 != 0,
+            // Line 144 also raises the mode 2 source
+            Mode::VBlank => self.stat & 0x10 != 0 || (self.ly == VBLANK_START && self.stat & 0x20 != 0),
+            Mode::OamSearch => self.stat & 0x20 != 0,
+            Mode::PixelTransfer => false,
+        } || (coincidence && self.stat & 0x40 != 0);
+
+        if line && !self.stat_line {
+            interrupts.request_interrupt(InterruptType::LcdStat);
+        }
+        self.stat_line = line;
+    }
+
+    pub fn is_in_vblank(&self) -> bool {
+        self.mode == Mode::VBlank
+    }
+
+    pub fn take_hblank_line(&mut self) -> Option<u8> {
+        self.hblank_line.take()
+    }
+
+    // SCX/SCY as each line of the last complete frame was drawn with them,
+    // to check that mid-frame scroll writes land on the intended lines
+    pub fn scroll_table(&self) -> &[ScrollLatch; SCREEN_HEIGHT] {
+        &self.last_scroll_lines
+    }
+
+    // Lines of the last complete frame where objects were dropped, which is
+    // what makes games flicker when they rotate their OAM order
+    pub fn sprite_overflow(&self) -> &[SpriteOverflow] {
+        &self.last_overflow_lines
+    }
+
+    pub fn is_cgb_mode(&self) -> bool {
+        self.cgb_mode
+    }
+
+    pub fn oam_entries(&self) -> [OamEntry; 40] {
+        std::array::from_fn(|index| {
+            let [y, x, tile, flags] = self.oam[index * 4..index * 4 + 4].try_into().unwrap();
+            OamEntry { index: index as u8, y, x, tile, flags }
+        })
+    }
+
+    pub fn palettes(&self) -> DecodedPalettes {
+        if self.cgb_mode {
+            let decode = |ram: &PaletteRam| (0..8).map(|palette| std::array::from_fn(|color| ram.rgb(palette, color as u8))).collect();
+            DecodedPalettes { bg: decode(&self.bg_palettes), obj: decode(&self.obj_palettes) }
+        } else {
+            let decode = |palette| std::array::from_fn(|color| self.shade(palette, color as u8));
+            DecodedPalettes { bg: vec![decode(self.bgp)], obj: vec![decode(self.obp0), decode(self.obp1)] }
+        }
+    }
+
+    pub fn take_frame_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.frame_dirty)
     }
 
     pub fn is_frame_ready(&mut self) -> bool {
@@ -193,12 +938,34 @@ impl PPU {
         ready
     }
 
+    // While the PPU is using them the CPU reads 0xFF and its writes are lost
+    fn is_vram_blocked(&self) -> bool {
+        self.access_restricted && self.is_lcd_enabled() && self.mode == Mode::PixelTransfer
+    }
+
+    fn is_oam_blocked(&self) -> bool {
+        self.access_restricted
+            && self.is_lcd_enabled()
+            && matches!(self.mode, Mode::OamSearch | Mode::PixelTransfer)
+    }
+
+    // VRAM in the selected bank as the DMA engines see it, whatever the mode
+    pub fn read_vram(&self, addr: u16) -> u8 {
+        self.vram[self.vram_bank + (addr & 0x1FFF) as usize]
+    }
+
+    pub fn write_vram(&mut self, addr: u16, value: u8) {
+        self.vram[self.vram_bank + (addr & 0x1FFF) as usize] = value;
+    }
+
     pub fn read(&self, addr: u16) -> u8 {
         match addr {
-            0x8000..=0x9FFF => self.vram[(addr - 0x8000) as usize],
+            0x8000..=0x9FFF if self.is_vram_blocked() => 0xFF,
+            0xFE00..=0xFE9F if self.is_oam_blocked() => 0xFF,
+            0x8000..=0x9FFF => self.read_vram(addr),
             0xFE00..=0xFE9F => self.oam[(addr - 0xFE00) as usize],
             0xFF40 => self.lcdc,
-            0xFF41 => self.stat,
+            0xFF41 => 0x80 | self.stat,
             0xFF42 => self.scy,
             0xFF43 => self.scx,
             0xFF44 => self.ly,
@@ -208,13 +975,20 @@ impl PPU {
             0xFF49 => self.obp1,
             0xFF4A => self.wy,
             0xFF4B => self.wx,
+            0xFF4F if self.cgb_mode => 0xFE | (self.vram_bank / 0x2000) as u8,
+            0xFF68 if self.cgb_mode => self.bg_palettes.read_spec(),
+            0xFF69 if self.cgb_mode => self.bg_palettes.read_data(),
+            0xFF6A if self.cgb_mode => self.obj_palettes.read_spec(),
+            0xFF6B if self.cgb_mode => self.obj_palettes.read_data(),
             _ => 0xFF,
         }
     }
 
     pub fn write(&mut self, addr: u16, value: u8) {
         match addr {
-            0x8000..=0x9FFF => self.vram[(addr - 0x8000) as usize] = value,
+            0x8000..=0x9FFF if self.is_vram_blocked() => {}
+            0xFE00..=0xFE9F if self.is_oam_blocked() => {}
+            0x8000..=0x9FFF => self.write_vram(addr, value),
             0xFE00..=0xFE9F => self.oam[(addr - 0xFE00) as usize] = value,
             0xFF40 => self.lcdc = value,
             0xFF41 => self.stat = (self.stat & 0x07) | (value & 0xF8),
@@ -227,8 +1001,66 @@ impl PPU {
             0xFF49 => self.obp1 = value,
             0xFF4A => self.wy = value,
             0xFF4B => self.wx = value,
+            0xFF4F if self.cgb_mode => self.vram_bank = (value as usize & 0x01) * 0x2000,
+            0xFF68 if self.cgb_mode => self.bg_palettes.write_spec(value),
+            0xFF69 if self.cgb_mode => self.bg_palettes.write_data(value),
+            0xFF6A if self.cgb_mode => self.obj_palettes.write_spec(value),
+            0xFF6B if self.cgb_mode => self.obj_palettes.write_data(value),
             _ => {}
         }
     }
 }
 
+// The framebuffer is saved too, so a state taken mid-frame keeps the lines
+// already drawn and the mode, dot counter and window line resume mid-scanline
+impl Savestate for PPU {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.framebuffer);
+        state.write_bytes(&self.vram);
+        state.write_bytes(&self.oam);
+        for register in [
+            self.lcdc, self.stat, self.scy, self.scx, self.ly, self.lyc,
+            self.bgp, self.obp0, self.obp1, self.wy, self.wx,
+        ] {
+            state.write_u8(register);
+        }
+        state.write_u8(self.mode as u8);
+        state.write_bool(self.stat_line);
+        state.write_u32(self.cycles);
+        state.write_bool(self.frame_ready);
+        state.write_u8(self.window_line);
+        state.write_bool(self.window_y_hit);
+        self.line.save_state(state);
+        state.write_bool(self.vram_bank != 0);
+        for palettes in [&self.bg_palettes, &self.obj_palettes] {
+            state.write_bytes(&palettes.data);
+            state.write_u8(palettes.spec);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_bytes(&mut self.framebuffer)?;
+        state.read_bytes(&mut self.vram)?;
+        state.read_bytes(&mut self.oam)?;
+        for register in [
+            &mut self.lcdc, &mut self.stat, &mut self.scy, &mut self.scx, &mut self.ly, &mut self.lyc,
+            &mut self.bgp, &mut self.obp0, &mut self.obp1, &mut self.wy, &mut self.wx,
+        ] {
+            *register = state.read_u8()?;
+        }
+        self.mode = Mode::from_bits(state.read_u8()?).ok_or(EmulatorError::InvalidState)?;
+        self.stat_line = state.read_bool()?;
+        self.cycles = state.read_u32()?;
+        self.frame_ready = state.read_bool()?;
+        self.window_line = state.read_u8()?;
+        self.window_y_hit = state.read_bool()?;
+        self.line.load_state(state)?;
+        self.frame_dirty = true;
+        self.vram_bank = if state.read_bool()? { 0x2000 } else { 0 };
+        for palettes in [&mut self.bg_palettes, &mut self.obj_palettes] {
+            state.read_bytes(&mut palettes.data)?;
+            palettes.spec = state.read_u8()?;
+        }
+        Ok(())
+    }
+}
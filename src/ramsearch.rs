@@ -0,0 +1,136 @@
+// Finding where a game keeps a value, as cheat searches do: every byte of
+// work RAM starts out as a candidate, and each search keeps those that went
+// up, went down, stayed or now hold a given value since the one before.
+// What's left can be pinned as a watch shown over the screen, or turned into
+// a GameShark code that holds it.
+
+use std::str::FromStr;
+
+use crate::memory::Region;
+
+const WRAM_BANK_SIZE: usize = 0x1000;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Comparison {
+    Increased,
+    Decreased,
+    Unchanged,
+    Changed,
+    Equal(u8),
+}
+
+impl Comparison {
+    fn matches(self, previous: u8, value: u8) -> bool {
+        match self {
+            Comparison::Increased => value > previous,
+            Comparison::Decreased => value < previous,
+            Comparison::Unchanged => value == previous,
+            Comparison::Changed => value != previous,
+            Comparison::Equal(expected) => value == expected,
+        }
+    }
+}
+
+impl FromStr for Comparison {
+    type Err = String;
+
+    // inc, dec, same, diff, or a value in hex
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "inc" => Ok(Comparison::Increased),
+            "dec" => Ok(Comparison::Decreased),
+            "same" => Ok(Comparison::Unchanged),
+            "diff" => Ok(Comparison::Changed),
+            _ => u8::from_str_radix(s.trim_start_matches("0x"), 16)
+                .map(Comparison::Equal)
+                .map_err(|_| format!("invalid comparison '{}'", s)),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct RamSearch {
+    // Offsets into work RAM still in the running, with their values at the
+    // last search; None until one starts
+    candidates: Option<Vec<(usize, u8)>>,
+    pinned: Vec<usize>,
+}
+
+impl RamSearch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn start(&mut self, wram: &[u8]) {
+        self.candidates = Some(wram.iter().copied().enumerate().collect());
+    }
+
+    // Returns how many candidates are left. The first search starts from
+    // the values as they are now, so only Equal can narrow it down.
+    pub fn filter(&mut self, wram: &[u8], comparison: Comparison) -> usize {
+        let candidates = self.candidates.get_or_insert_with(|| wram.iter().copied().enumerate().collect());
+        candidates.retain_mut(|(offset, previous)| {
+            let value = wram[*offset];
+            let keep = comparison.matches(*previous, value);
+            *previous = value;
+            keep
+        });
+        candidates.len()
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.candidates.is_some()
+    }
+
+    pub fn candidates(&self) -> &[(usize, u8)] {
+        self.candidates.as_deref().unwrap_or_default()
+    }
+
+    pub fn pin(&mut self, offset: usize) {
+        if !self.pinned.contains(&offset) {
+            self.pinned.push(offset);
+        }
+    }
+
+    // Returns false when the offset wasn't pinned
+    pub fn unpin(&mut self, offset: usize) -> bool {
+        let len = self.pinned.len();
+        self.pinned.retain(|&pinned| pinned != offset);
+        self.pinned.len() != len
+    }
+
+    pub fn pinned(&self) -> &[usize] {
+        &self.pinned
+    }
+
+    // One line per watch, "D123 7F 127", for the overlay
+    pub fn watch_text(&self, wram: &[u8]) -> String {
+        self.pinned
+            .iter()
+            .map(|&offset| {
+                let (bank, addr) = wram_location(offset);
+                let value = wram[offset];
+                match bank {
+                    0 | 1 => format!("{:04X} {:02X} {}", addr, value, value),
+                    _ => format!("{:04X}/{} {:02X} {}", addr, bank, value, value),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+// The bank a work RAM offset is in and where it appears when mapped
+pub fn wram_location(offset: usize) -> (usize, u16) {
+    let bank = offset / WRAM_BANK_SIZE;
+    (bank, Region::Wram.address(bank, offset % WRAM_BANK_SIZE))
+}
+
+// A GameShark code writing value to the offset every frame. On the CGB,
+// D000-DFFF gets a 9x code for its bank so it lands whatever SVBK maps.
+pub fn shark_code(offset: usize, value: u8, cgb: bool) -> String {
+    let (bank, addr) = wram_location(offset);
+    let kind = if cgb && bank > 0 { 0x90 | bank as u8 } else { 0x01 };
+    let [high, low] = addr.to_be_bytes();
+    format!("{:02X}{:02X}{:02X}{:02X}", kind, value, low, high)
+}
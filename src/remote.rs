@@ -0,0 +1,177 @@
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::debugger;
+use crate::gameboy::GameBoy;
+use crate::joypad::Button;
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// Longest line a client may send before it is disconnected
+const MAX_LINE_LEN: usize = 1024;
+// Largest block a single read command returns
+const MAX_READ_LEN: usize = 0x1000;
+
+// A control server on localhost. Clients send one command per line and get
+// one reply line back, starting with "ok" or "err":
+//
+//   read <addr> [len]          hex bytes from the CPU's view of memory
+//   write <addr> <byte>...     writes bytes starting at addr
+//   press <button>             holds a button until released
+//   release <button>
+//   save <path> / load <path>  save state files
+//   screenshot <path>          the last frame as a binary PPM image
+//   frame                      index and cycle count of the last frame
+//   scroll                     SCX,SCY pairs for each line of the last frame
+//   cheats                     loaded cheat codes, separated by ;
+//   cheat <n> on|off           turns a cheat code on or off
+//   oam                        the 40 objects, separated by ;
+//
+// Numbers are hex, with or without 0x. Commands run between frames.
+pub struct RemoteServer {
+    listener: TcpListener,
+    clients: Vec<Client>,
+    // Buttons held by clients, combined with the keyboard
+    held: u8,
+}
+
+struct Client {
+    stream: TcpStream,
+    pending: Vec<u8>,
+}
+
+impl RemoteServer {
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        Ok(RemoteServer { listener, clients: Vec::new(), held: 0 })
+    }
+
+    pub fn held_buttons(&self) -> u8 {
+        self.held
+    }
+
+    // Accepts new clients and runs every complete command that has arrived
+    pub fn poll(&mut self, gb: &mut GameBoy) {
+        while let Ok((stream, _)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_ok() {
+                self.clients.push(Client { stream, pending: Vec::new() });
+            }
+        }
+
+        let mut held = self.held;
+        self.clients.retain_mut(|client| client.serve(gb, &mut held));
+        self.held = held;
+    }
+}
+
+impl Client {
+    // Returns false once the client should be dropped
+    fn serve(&mut self, gb: &mut GameBoy, held: &mut u8) -> bool {
+        let mut buffer = [0u8; 512];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return false,
+                Ok(len) => self.pending.extend_from_slice(&buffer[..len]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => return false,
+            }
+        }
+
+        while let Some(end) = self.pending.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let reply = match execute(gb, held, line.trim()) {
+                Ok(reply) if reply.is_empty() => "ok\n".to_string(),
+                Ok(reply) => format!("ok {}\n", reply),
+                Err(error) => format!("err {}\n", error),
+            };
+            if self.stream.write_all(reply.as_bytes()).is_err() {
+                return false;
+            }
+        }
+        self.pending.len() <= MAX_LINE_LEN
+    }
+}
+
+pub fn execute(gb: &mut GameBoy, held: &mut u8, line: &str) -> Result<String, String> {
+    let mut words = line.split_whitespace();
+    let command = words.next().unwrap_or("");
+    let args: Vec<&str> = words.collect();
+
+    match (command, args.as_slice()) {
+        ("read", [addr, rest @ ..]) if rest.len() <= 1 => {
+            let addr = parse_hex(addr)?;
+            let len = rest.first().map(|len| parse_hex(len)).transpose()?.unwrap_or(1) as usize;
+            if len == 0 || len > MAX_READ_LEN || addr as usize + len > 0x10000 {
+                return Err("length out of range".to_string());
+            }
+            let bytes: Vec<String> = (0..len)
+                .map(|offset| format!("{:02X}", gb.read_byte(addr + offset as u16)))
+                .collect();
+            Ok(bytes.join(" "))
+        }
+        ("write", [addr, values @ ..]) if !values.is_empty() => {
+            let addr = parse_hex(addr)?;
+            if addr as usize + values.len() > 0x10000 {
+                return Err("write past the end of memory".to_string());
+            }
+            let values = values.iter().map(|value| parse_hex(value)).collect::<Result<Vec<_>, _>>()?;
+            for (offset, value) in values.into_iter().enumerate() {
+                gb.write_byte(addr + offset as u16, value as u8);
+            }
+            Ok(String::new())
+        }
+        ("press" | "release", [name]) => {
+            let button: Button = name.parse()?;
+            let pressed = command == "press";
+            if pressed {
+                *held |= button.mask();
+            } else {
+                *held &= !button.mask();
+            }
+            gb.set_button(button, pressed);
+            Ok(String::new())
+        }
+        ("save", [path]) => fs::write(path, gb.save_state()).map(|_| String::new()).map_err(|e| e.to_string()),
+        ("load", [path]) => {
+            let data = fs::read(path).map_err(|e| e.to_string())?;
+            gb.load_state(&data).map(|_| String::new()).map_err(|e| e.to_string())
+        }
+        ("screenshot", [path]) => {
+            let mut image = format!("P6\n{} {}\n255\n", SCREEN_WIDTH, SCREEN_HEIGHT).into_bytes();
+            image.extend_from_slice(gb.frame().pixels);
+            fs::write(path, image).map(|_| String::new()).map_err(|e| e.to_string())
+        }
+        ("frame", []) => {
+            let frame = gb.frame();
+            Ok(format!("{} {}", frame.index, frame.cycles))
+        }
+        ("scroll", []) => {
+            let lines: Vec<String> = gb
+                .scroll_table()
+                .iter()
+                .map(|latch| format!("{:02X},{:02X}", latch.scx, latch.scy))
+                .collect();
+            Ok(lines.join(" "))
+        }
+        ("cheats", []) => {
+            let cheats: Vec<String> = gb.cheats().iter().enumerate().map(|(index, cheat)| cheat.describe(index)).collect();
+            Ok(cheats.join(";"))
+        }
+        ("oam", []) => Ok(debugger::oam_listing(gb).join(";")),
+        ("cheat", [index, state @ ("on" | "off")]) => {
+            let index = index.parse().map_err(|_| format!("invalid cheat number '{}'", index))?;
+            if !gb.set_cheat_enabled(index, *state == "on") {
+                return Err(format!("no cheat {}", index));
+            }
+            Ok(String::new())
+        }
+        _ => Err(format!("bad command '{}'", line)),
+    }
+}
+
+fn parse_hex(text: &str) -> Result<u16, String> {
+    u16::from_str_radix(text.trim_start_matches("0x"), 16).map_err(|_| format!("invalid hex value '{}'", text))
+}
@@ -0,0 +1,143 @@
+use std::collections::VecDeque;
+
+use crate::gameboy::GameBoy;
+
+// Frames between snapshots
+pub const REWIND_INTERVAL: u32 = 4;
+pub const DEFAULT_REWIND_SECONDS: u32 = 30;
+
+// Recent save states to step back through. Only the newest is kept whole;
+// each older one is stored as its difference from the next newer one, which
+// is mostly unchanged bytes between snapshots a few frames apart.
+pub struct RewindBuffer {
+    interval: u32,
+    capacity: usize,
+    frames: u32,
+    newest: Option<Vec<u8>>,
+    // Oldest first
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl RewindBuffer {
+    // Snapshots every `interval` frames, keeping up to `capacity` of them
+    pub fn new(interval: u32, capacity: usize) -> Self {
+        RewindBuffer { interval: interval.max(1), capacity, frames: 0, newest: None, deltas: VecDeque::new() }
+    }
+
+    // Enough snapshots for `seconds` of play at REWIND_INTERVAL
+    pub fn with_seconds(seconds: u32) -> Self {
+        Self::new(REWIND_INTERVAL, (seconds.saturating_mul(60) / REWIND_INTERVAL) as usize)
+    }
+
+    pub fn interval(&self) -> u32 {
+        self.interval
+    }
+
+    pub fn len(&self) -> usize {
+        self.newest.as_ref().map_or(0, |_| self.deltas.len() + 1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    // Bytes held by the snapshots
+    pub fn memory_used(&self) -> usize {
+        self.newest.as_ref().map_or(0, Vec::len) + self.deltas.iter().map(Vec::len).sum::<usize>()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames = 0;
+        self.newest = None;
+        self.deltas.clear();
+    }
+
+    // Call after every emulated frame
+    pub fn record(&mut self, gb: &GameBoy) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.frames += 1;
+        if self.frames < self.interval {
+            return;
+        }
+        self.frames = 0;
+        let state = gb.save_state();
+        if let Some(previous) = self.newest.take() {
+            // States only change size when another game is loaded
+            if previous.len() == state.len() {
+                self.deltas.push_back(encode_delta(&state, &previous));
+            } else {
+                self.deltas.clear();
+            }
+        }
+        self.newest = Some(state);
+        while self.len() > self.capacity {
+            self.deltas.pop_front();
+        }
+    }
+
+    // Loads the newest snapshot and forgets it, so the next call goes further
+    // back. Returns false once there is nothing left.
+    pub fn step_back(&mut self, gb: &mut GameBoy) -> bool {
+        let Some(state) = self.newest.take() else {
+            return false;
+        };
+        self.newest = self.deltas.pop_back().map(|delta| apply_delta(&delta, &state));
+        self.frames = 0;
+        gb.load_state(&state).is_ok()
+    }
+}
+
+// The XOR of two equal-length states as alternating runs: a count of
+// unchanged bytes, a count of changed ones and their XOR, counts as LEB128
+fn encode_delta(newer: &[u8], older: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    let mut at = 0;
+    while at < newer.len() {
+        let same = newer[at..].iter().zip(&older[at..]).take_while(|(a, b)| a == b).count();
+        at += same;
+        let changed = newer[at..].iter().zip(&older[at..]).take_while(|(a, b)| a != b).count();
+        write_count(&mut delta, same);
+        write_count(&mut delta, changed);
+        delta.extend(newer[at..at + changed].iter().zip(&older[at..]).map(|(a, b)| a ^ b));
+        at += changed;
+    }
+    delta
+}
+
+fn apply_delta(delta: &[u8], newer: &[u8]) -> Vec<u8> {
+    let mut older = newer.to_vec();
+    let mut reader = delta.iter().copied();
+    let mut at = 0;
+    while let Some(same) = read_count(&mut reader) {
+        at += same;
+        let changed = read_count(&mut reader).unwrap_or(0);
+        for (byte, xor) in older[at..at + changed].iter_mut().zip(&mut reader) {
+            *byte ^= xor;
+        }
+        at += changed;
+    }
+    older
+}
+
+fn write_count(out: &mut Vec<u8>, mut count: usize) {
+    while count >= 0x80 {
+        out.push(count as u8 | 0x80);
+        count >>= 7;
+    }
+    out.push(count as u8);
+}
+
+fn read_count(reader: &mut impl Iterator<Item = u8>) -> Option<usize> {
+    let mut count = 0;
+    let mut shift = 0;
+    loop {
+        let byte = reader.next()?;
+        count |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Some(count);
+        }
+        shift += 7;
+    }
+}
@@ -0,0 +1,415 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::str::FromStr;
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::Result;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+
+const CYCLES_PER_SECOND: u32 = 4_194_304;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum RtcMode {
+    // Advance with the host wall clock, so time passes while the emulator is closed
+    HostClock,
+    // Advance strictly with emulated cycles, so runs are reproducible
+    CycleSynced,
+}
+
+impl FromStr for RtcMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "host" => Ok(RtcMode::HostClock),
+            "cycles" => Ok(RtcMode::CycleSynced),
+            _ => Err(format!("unknown RTC mode '{}'", s)),
+        }
+    }
+}
+
+// RTC modes picked per game by the title in the cartridge header, read from
+// a TOML file like
+//
+//   "POKEMON_GLD" = "cycles"
+//   "ROBOPON" = "host"
+#[derive(Default)]
+pub struct GameRtcModes {
+    games: Vec<(String, RtcMode)>,
+}
+
+impl GameRtcModes {
+    pub fn parse(text: &str) -> std::result::Result<Self, String> {
+        let table: toml::Table = text.parse().map_err(|e: toml::de::Error| e.message().to_string())?;
+        let mut games = Vec::new();
+        for (title, mode) in &table {
+            let mode = mode.as_str().ok_or_else(|| format!("the mode for '{}' must be a string", title))?;
+            games.push((title.clone(), mode.parse()?));
+        }
+        Ok(GameRtcModes { games })
+    }
+
+    pub fn mode_for(&self, title: &str) -> Option<RtcMode> {
+        self.games.iter().find(|(game, _)| game == title).map(|&(_, mode)| mode)
+    }
+}
+
+// Register values as stored in save-file footers: seconds, minutes, hours,
+// day low and day high, for both the live counters and the latched copy
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RtcRegisters {
+    pub live: [u8; 5],
+    pub latched: [u8; 5],
+}
+
+// Where the host clock mode gets the current time from, in Unix seconds
+pub trait ClockSource: Send {
+    fn now(&self) -> u64;
+}
+
+// The host's wall clock
+pub struct SystemClock;
+
+impl ClockSource for SystemClock {
+    fn now(&self) -> u64 {
+        host_seconds()
+    }
+}
+
+// The wall clock shifted by a fixed number of seconds, to set the in-game
+// time without waiting for it
+pub struct OffsetClock {
+    pub offset: i64,
+}
+
+impl ClockSource for OffsetClock {
+    fn now(&self) -> u64 {
+        host_seconds().saturating_add_signed(self.offset)
+    }
+}
+
+// A clock that only moves when told to. Clones share the same time, so a test
+// can keep one and advance the one handed to the RTC.
+#[derive(Clone)]
+pub struct ManualClock {
+    seconds: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    pub fn new(seconds: u64) -> Self {
+        ManualClock { seconds: Arc::new(AtomicU64::new(seconds)) }
+    }
+
+    pub fn advance(&self, seconds: u64) {
+        self.seconds.fetch_add(seconds, Ordering::Relaxed);
+    }
+}
+
+impl ClockSource for ManualClock {
+    fn now(&self) -> u64 {
+        self.seconds.load(Ordering::Relaxed)
+    }
+}
+
+// A signed number of seconds, or of days, hours or minutes with a d, h or m suffix
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ClockOffset(pub i64);
+
+impl FromStr for ClockOffset {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (number, unit) = match s.char_indices().last() {
+            Some((index, 'd')) => (&s[..index], 86400),
+            Some((index, 'h')) => (&s[..index], 3600),
+            Some((index, 'm')) => (&s[..index], 60),
+            Some((index, 's')) => (&s[..index], 1),
+            _ => (s, 1),
+        };
+        number
+            .parse::<i64>()
+            .ok()
+            .and_then(|number| number.checked_mul(unit))
+            .map(ClockOffset)
+            .ok_or_else(|| format!("invalid clock offset '{}'", s))
+    }
+}
+
+pub struct Rtc {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,
+    halted: bool,
+    day_carry: bool,
+    latched: [u8; 5],
+    latch_armed: bool,
+    mode: RtcMode,
+    cycle_accumulator: u32,
+    last_host_sync: u64,
+    clock: Box<dyn ClockSource>,
+}
+
+impl Rtc {
+    pub fn new(mode: RtcMode) -> Self {
+        Rtc {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halted: false,
+            day_carry: false,
+            latched: [0; 5],
+            latch_armed: false,
+            mode,
+            cycle_accumulator: 0,
+            last_host_sync: host_seconds(),
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    // Time already passed on the old clock is kept, the new one takes over from now
+    pub fn set_clock(&mut self, clock: Box<dyn ClockSource>) {
+        self.sync_host_clock();
+        self.clock = clock;
+        self.last_host_sync = self.clock.now();
+    }
+
+    pub fn now(&self) -> u64 {
+        self.clock.now()
+    }
+
+    pub fn set_mode(&mut self, mode: RtcMode) {
+        if self.mode == RtcMode::HostClock {
+            self.sync_host_clock();
+        }
+        self.mode = mode;
+        self.cycle_accumulator = 0;
+        self.last_host_sync = self.clock.now();
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        if self.mode != RtcMode::CycleSynced || self.halted {
+            return;
+        }
+
+        self.cycle_accumulator += cycles;
+        while self.cycle_accumulator >= CYCLES_PER_SECOND {
+            self.cycle_accumulator -= CYCLES_PER_SECOND;
+            self.advance_seconds(1);
+        }
+    }
+
+    // Writing 0x00 then 0x01 to 0x6000-0x7FFF copies the live counters into the latch
+    pub fn write_latch(&mut self, value: u8) {
+        if self.latch_armed && value == 0x01 {
+            self.sync_host_clock();
+            self.latched = [
+                self.seconds,
+                self.minutes,
+                self.hours,
+                (self.days & 0xFF) as u8,
+                self.day_high(),
+            ];
+        }
+        self.latch_armed = value == 0x00;
+    }
+
+    pub fn read(&self, register: u8) -> u8 {
+        match register {
+            0x08..=0x0C => self.latched[(register - 0x08) as usize],
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, register: u8, value: u8) {
+        self.sync_host_clock();
+        match register {
+            0x08 => {
+                self.seconds = value & 0x3F;
+                self.cycle_accumulator = 0;
+            }
+            0x09 => self.minutes = value & 0x3F,
+            0x0A => self.hours = value & 0x1F,
+            0x0B => self.days = (self.days & 0x100) | value as u16,
+            0x0C => {
+                self.days = (self.days & 0xFF) | (((value & 0x01) as u16) << 8);
+                self.halted = value & 0x40 != 0;
+                // The carry stays set until the game explicitly clears it
+                self.day_carry = value & 0x80 != 0;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn registers(&mut self) -> RtcRegisters {
+        self.sync_host_clock();
+        RtcRegisters {
+            live: [
+                self.seconds,
+                self.minutes,
+                self.hours,
+                (self.days & 0xFF) as u8,
+                self.day_high(),
+            ],
+            latched: self.latched,
+        }
+    }
+
+    // Restores counters saved at saved_at (Unix seconds). With the host clock
+    // the time that passed since then is applied, as if the battery kept it running.
+    pub fn restore(&mut self, registers: RtcRegisters, saved_at: u64) {
+        let [seconds, minutes, hours, day_low, day_high] = registers.live;
+        self.seconds = seconds & 0x3F;
+        self.minutes = minutes & 0x3F;
+        self.hours = hours & 0x1F;
+        self.days = day_low as u16 | (((day_high & 0x01) as u16) << 8);
+        self.halted = day_high & 0x40 != 0;
+        self.day_carry = day_high & 0x80 != 0;
+        self.latched = registers.latched;
+        self.cycle_accumulator = 0;
+
+        let now = self.clock.now();
+        if self.mode == RtcMode::HostClock && !self.halted && now > saved_at {
+            self.advance_seconds(now - saved_at);
+        }
+        self.last_host_sync = now;
+    }
+
+    // DH register: bit 0 is day bit 8, bit 6 the halt flag, bit 7 the day-counter carry
+    fn day_high(&self) -> u8 {
+        let mut value = ((self.days >> 8) & 0x01) as u8;
+        if self.halted {
+            value |= 0x40;
+        }
+        if self.day_carry {
+            value |= 0x80;
+        }
+        value
+    }
+
+    fn sync_host_clock(&mut self) {
+        if self.mode != RtcMode::HostClock {
+            return;
+        }
+
+        // Time spent halted is discarded rather than applied on resume
+        let now = self.clock.now();
+        if now > self.last_host_sync && !self.halted {
+            self.advance_seconds(now - self.last_host_sync);
+        }
+        self.last_host_sync = now;
+    }
+
+    fn advance_seconds(&mut self, elapsed: u64) {
+        let mut remaining = elapsed;
+
+        // Out-of-range values written by the game have to be walked one second at a
+        // time, since the hardware only wraps them at the width of each register
+        while remaining > 0 && (self.seconds >= 60 || self.minutes >= 60 || self.hours >= 24) {
+            self.step_second();
+            remaining -= 1;
+        }
+        if remaining == 0 {
+            return;
+        }
+
+        let total = self.seconds as u64
+            + self.minutes as u64 * 60
+            + self.hours as u64 * 3600
+            + remaining;
+        self.seconds = (total % 60) as u8;
+        self.minutes = ((total / 60) % 60) as u8;
+        self.hours = ((total / 3600) % 24) as u8;
+        let days = self.days as u64 + total / 86400;
+        if days > 0x1FF {
+            self.day_carry = true;
+        }
+        self.days = (days & 0x1FF) as u16;
+    }
+
+    fn step_second(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+
+        self.days = (self.days + 1) & 0x1FF;
+        if self.days == 0 {
+            self.day_carry = true;
+        }
+    }
+}
+
+// The mode isn't saved, it comes from the command line. The last host sync is,
+// so with the host clock the time since the state was saved still passes.
+impl Savestate for Rtc {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.seconds);
+        state.write_u8(self.minutes);
+        state.write_u8(self.hours);
+        state.write_u16(self.days);
+        state.write_bool(self.halted);
+        state.write_bool(self.day_carry);
+        state.write_bytes(&self.latched);
+        state.write_bool(self.latch_armed);
+        state.write_u32(self.cycle_accumulator);
+        state.write_u64(self.last_host_sync);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.seconds = state.read_u8()? & 0x3F;
+        self.minutes = state.read_u8()? & 0x3F;
+        self.hours = state.read_u8()? & 0x1F;
+        self.days = state.read_u16()? & 0x1FF;
+        self.halted = state.read_bool()?;
+        self.day_carry = state.read_bool()?;
+        state.read_bytes(&mut self.latched)?;
+        self.latch_armed = state.read_bool()?;
+        self.cycle_accumulator = state.read_u32()? % CYCLES_PER_SECOND;
+        self.last_host_sync = state.read_u64()?;
+        Ok(())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn host_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn host_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+// std can't read the time in a browser and panics trying; the wasm frontend
+// hands the RTC a clock that asks JavaScript instead
+#[cfg(target_arch = "wasm32")]
+pub fn host_seconds() -> u64 {
+    0
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn host_millis() -> u64 {
+    0
+}
@@ -0,0 +1,177 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::rtc::{host_millis, RtcRegisters};
+
+// Legacy VBA footer: ten 32-bit register words and a 32-bit timestamp
+const FOOTER_LEGACY_LEN: usize = 44;
+// VBA-M, BGB and SameBoy footer: the same words with a 64-bit timestamp
+const FOOTER_LEN: usize = 48;
+
+// Layout used when writing a .sav file. Reading detects the layout from the
+// file size, so a save from any of these emulators loads whatever is selected.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SaveFormat {
+    // Cartridge RAM only, the RTC is not stored
+    Raw,
+    // VisualBoyAdvance 1.x: 44-byte RTC footer
+    Vba,
+    // BGB and SameBoy: 48-byte RTC footer
+    Bgb,
+    SameBoy,
+}
+
+impl SaveFormat {
+    fn footer_len(self) -> usize {
+        match self {
+            SaveFormat::Raw => 0,
+            SaveFormat::Vba => FOOTER_LEGACY_LEN,
+            SaveFormat::Bgb | SaveFormat::SameBoy => FOOTER_LEN,
+        }
+    }
+}
+
+impl FromStr for SaveFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(SaveFormat::Raw),
+            "vba" => Ok(SaveFormat::Vba),
+            "bgb" => Ok(SaveFormat::Bgb),
+            "sameboy" => Ok(SaveFormat::SameBoy),
+            _ => Err(format!("unknown save format '{}'", s)),
+        }
+    }
+}
+
+pub struct DecodedSave {
+    pub ram: Vec<u8>,
+    // Saved registers and the Unix time they were saved at
+    pub rtc: Option<(RtcRegisters, u64)>,
+}
+
+// The RTC comes with the time it was read at, in Unix seconds
+pub fn encode(ram: &[u8], rtc: Option<(RtcRegisters, u64)>, format: SaveFormat) -> Vec<u8> {
+    let mut data = ram.to_vec();
+    let Some((registers, now)) = rtc else {
+        return data;
+    };
+    if format == SaveFormat::Raw {
+        return data;
+    }
+
+    for value in registers.live.iter().chain(registers.latched.iter()) {
+        data.extend_from_slice(&(*value as u32).to_le_bytes());
+    }
+    if format.footer_len() == FOOTER_LEGACY_LEN {
+        data.extend_from_slice(&(now as u32).to_le_bytes());
+    } else {
+        data.extend_from_slice(&now.to_le_bytes());
+    }
+    data
+}
+
+// Emulators disagree on padding: some write fewer bytes than the cartridge
+// has, others round the file up. Short RAM is zero-filled, extra bytes that
+// don't form a known footer are ignored.
+pub fn decode(data: &[u8], ram_len: usize, has_rtc: bool) -> DecodedSave {
+    let footer_len = match data.len().checked_sub(ram_len) {
+        Some(FOOTER_LEN) if has_rtc => FOOTER_LEN,
+        Some(FOOTER_LEGACY_LEN) if has_rtc => FOOTER_LEGACY_LEN,
+        _ => 0,
+    };
+
+    let mut ram = data[..data.len().min(ram_len)].to_vec();
+    ram.resize(ram_len, 0);
+
+    let rtc = if footer_len == 0 {
+        None
+    } else {
+        let footer = &data[ram_len..];
+        let word = |index: usize| footer[index * 4];
+        let registers = RtcRegisters {
+            live: std::array::from_fn(word),
+            latched: std::array::from_fn(|i| word(i + 5)),
+        };
+        let timestamp = if footer_len == FOOTER_LEN {
+            u64::from_le_bytes(footer[40..48].try_into().unwrap())
+        } else {
+            u32::from_le_bytes(footer[40..44].try_into().unwrap()) as u64
+        };
+        Some((registers, timestamp))
+    };
+
+    DecodedSave { ram, rtc }
+}
+
+// Writes a save file, first copying the previous one to <name>.<unix ms>.bak
+// and deleting all but the newest `keep` backups. Nothing rotates when the
+// contents haven't changed, so quitting without saving in-game keeps history.
+// With `keep` at 0 no backups are made and the ones already there stay.
+pub fn write_with_backups(path: &Path, data: &[u8], keep: usize) -> io::Result<()> {
+    match fs::read(path) {
+        Ok(previous) if previous == data => return Ok(()),
+        Ok(_) if keep > 0 => {
+            fs::copy(path, unused_backup_path(path))?;
+        }
+        Ok(_) => {}
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+        Err(error) => return Err(error),
+    }
+    fs::write(path, data)?;
+    if keep == 0 {
+        return Ok(());
+    }
+
+    let mut backups = list_backups(path)?;
+    backups.sort();
+    let excess = backups.len().saturating_sub(keep);
+    for (_, backup) in backups.into_iter().take(excess) {
+        fs::remove_file(backup)?;
+    }
+    Ok(())
+}
+
+// Saves within the same millisecond take the next free one, so names stay
+// unique and still sort by age
+fn unused_backup_path(path: &Path) -> PathBuf {
+    let mut timestamp = host_millis();
+    loop {
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(format!(".{}.bak", timestamp));
+        let backup = PathBuf::from(backup);
+        if !backup.exists() {
+            return backup;
+        }
+        timestamp += 1;
+    }
+}
+
+// Backups of `path` the rotation made, with the timestamps taken from their
+// names; other files that start with the name are left alone
+fn list_backups(path: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return Ok(Vec::new());
+    };
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry_path = entry?.path();
+        let timestamp = entry_path.file_name()
+            .and_then(|file| file.to_str())
+            .and_then(|file| file.strip_prefix(name)?.strip_prefix('.')?.strip_suffix(".bak"))
+            .filter(|digits| !digits.is_empty() && digits.bytes().all(|byte| byte.is_ascii_digit()))
+            .and_then(|digits| digits.parse().ok());
+        if let Some(timestamp) = timestamp {
+            backups.push((timestamp, entry_path));
+        }
+    }
+    Ok(backups)
+}
@@ -0,0 +1,428 @@
+use std::str::FromStr;
+
+use crate::error::Result;
+use crate::gameboy::Frame;
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// Colors shown for the four DMG shades, lightest first
+pub type DmgPalette = [[u8; 3]; 4];
+
+// The original DMG's yellow-green LCD
+pub const GREEN_PALETTE: DmgPalette = [[0x9B, 0xBC, 0x0F], [0x8B, 0xAC, 0x0F], [0x30, 0x62, 0x30], [0x0F, 0x38, 0x0F]];
+
+// The Game Boy Pocket's grayer screen
+pub const POCKET_PALETTE: DmgPalette = [[0xC4, 0xCF, 0xA1], [0x8B, 0x95, 0x6D], [0x4D, 0x53, 0x3C], [0x1F, 0x1F, 0x1F]];
+
+// BGB's default greens
+pub const BGB_PALETTE: DmgPalette = [[0xE0, 0xF8, 0xD0], [0x88, 0xC0, 0x70], [0x34, 0x68, 0x56], [0x08, 0x18, 0x20]];
+
+// The Super Game Boy's first built-in palette, 1-A
+pub const SGB_PALETTE: DmgPalette = [[0xF8, 0xE8, 0xC8], [0xD8, 0x90, 0x48], [0xA8, 0x28, 0x20], [0x30, 0x18, 0x50]];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamedPalette {
+    pub name: String,
+    // None shows the framebuffer's own grays
+    pub colors: Option<DmgPalette>,
+}
+
+// The palettes --palette picks from and Alt+P cycles through: the built-in
+// ones, then any from a palette file
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaletteList {
+    palettes: Vec<NamedPalette>,
+    current: usize,
+}
+
+impl Default for PaletteList {
+    fn default() -> Self {
+        let builtin = [
+            ("gray", None),
+            ("green", Some(GREEN_PALETTE)),
+            ("pocket", Some(POCKET_PALETTE)),
+            ("bgb", Some(BGB_PALETTE)),
+            ("sgb", Some(SGB_PALETTE)),
+        ];
+        PaletteList {
+            palettes: builtin.into_iter().map(|(name, colors)| NamedPalette { name: name.to_string(), colors }).collect(),
+            current: 0,
+        }
+    }
+}
+
+impl PaletteList {
+    // Adds the palettes of a TOML file like
+    //
+    //   [palettes]
+    //   autumn = ["#FFF6D3", "#F9A875", "#EB6B6F", "#7C3F58"]
+    //
+    // with the four colors lightest first. A palette with the name of one
+    // already in the list replaces it.
+    pub fn add_file(&mut self, text: &str) -> std::result::Result<(), String> {
+        let table: toml::Table = text.parse().map_err(|e: toml::de::Error| e.message().to_string())?;
+        for (section, value) in &table {
+            if section != "palettes" {
+                return Err(format!("unknown section [{}]", section));
+            }
+            let entries = value.as_table().ok_or_else(|| format!("[{}] must be a table", section))?;
+            for (name, colors) in entries {
+                let colors = colors
+                    .as_array()
+                    .and_then(|colors| colors.iter().map(|color| color.as_str().and_then(parse_color)).collect::<Option<Vec<_>>>())
+                    .and_then(|colors| DmgPalette::try_from(colors).ok())
+                    .ok_or_else(|| format!("palette '{}' must be a list of four \"RRGGBB\" colors", name))?;
+                self.add(NamedPalette { name: name.clone(), colors: Some(colors) });
+            }
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, palette: NamedPalette) {
+        match self.palettes.iter_mut().find(|existing| existing.name == palette.name) {
+            Some(existing) => *existing = palette,
+            None => self.palettes.push(palette),
+        }
+    }
+
+    pub fn select(&mut self, name: &str) -> std::result::Result<(), String> {
+        self.current = self
+            .palettes
+            .iter()
+            .position(|palette| palette.name == name)
+            .ok_or_else(|| format!("unknown palette '{}', have {}", name, self.names().join(", ")))?;
+        Ok(())
+    }
+
+    pub fn current(&self) -> &NamedPalette {
+        &self.palettes[self.current]
+    }
+
+    // Moves on to the next palette, back to the first after the last
+    pub fn cycle(&mut self) -> &NamedPalette {
+        self.current = (self.current + 1) % self.palettes.len();
+        self.current()
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.palettes.iter().map(|palette| palette.name.as_str()).collect()
+    }
+}
+
+// "RRGGBB", with or without a leading #
+fn parse_color(text: &str) -> Option<[u8; 3]> {
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |at: usize| u8::from_str_radix(&hex[at..at + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+// Where finished frames go. Frontends implement this once, so anything that
+// works on frames (palettes, filters, recording) sits in front of every one.
+pub trait Scanout {
+    fn present(&mut self, frame: &Frame) -> Result<()>;
+
+    // None shows the framebuffer colors unchanged
+    fn set_palette(&mut self, palette: Option<DmgPalette>);
+
+    fn set_filter(&mut self, filter: Filter);
+
+    // Percent of the previous frame kept in each new one, 0 for none
+    fn set_frame_blend(&mut self, percent: u8);
+
+    fn resize(&mut self, scale: u32) -> Result<()>;
+}
+
+// How many output pixels across and down each Game Boy pixel becomes when a
+// filter is on, enough room to draw the gaps between LCD cells
+pub const FILTER_FACTOR: usize = 3;
+// Brightness kept by the darkened rows and columns, out of 256
+const GRID_SHADE: u32 = 160;
+const SCANLINE_SHADE: u32 = 128;
+
+// Software filters drawn into the frame before the window scales it up
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Filter {
+    None,
+    // The gaps between the pixels of the LCD matrix
+    Grid,
+    // Dark lines between rows, like a CRT
+    Scanlines,
+}
+
+impl Filter {
+    pub fn factor(self) -> usize {
+        match self {
+            Filter::None => 1,
+            Filter::Grid | Filter::Scanlines => FILTER_FACTOR,
+        }
+    }
+
+    // For a hotkey that steps through them
+    pub fn next(self) -> Self {
+        match self {
+            Filter::None => Filter::Grid,
+            Filter::Grid => Filter::Scanlines,
+            Filter::Scanlines => Filter::None,
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Filter::None),
+            "grid" => Ok(Filter::Grid),
+            "scanlines" => Ok(Filter::Scanlines),
+            _ => Err(format!("unknown filter '{}'", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Filter::None => "none",
+            Filter::Grid => "grid",
+            Filter::Scanlines => "scanlines",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Draws a full-screen RGB24 frame at filter.factor() times the size. The last
+// row of each cell is darkened for both filters, and the grid also darkens
+// the last column.
+pub fn apply_filter(pixels: &[u8], filter: Filter) -> Vec<u8> {
+    let factor = filter.factor();
+    if factor == 1 {
+        return pixels.to_vec();
+    }
+    let mut out = Vec::with_capacity(pixels.len() * factor * factor);
+    for row in pixels.chunks_exact(SCREEN_WIDTH * 3) {
+        for y in 0..factor {
+            for pixel in row.chunks_exact(3) {
+                for x in 0..factor {
+                    let shade = match filter {
+                        Filter::Grid if x == factor - 1 || y == factor - 1 => GRID_SHADE,
+                        Filter::Scanlines if y == factor - 1 => SCANLINE_SHADE,
+                        _ => 256,
+                    };
+                    out.extend(pixel.iter().map(|&channel| (channel as u32 * shade / 256) as u8));
+                }
+            }
+        }
+    }
+    out
+}
+
+// What Alt+G turns on when --blend didn't pick an amount
+pub const DEFAULT_FRAME_BLEND: u8 = 50;
+// The highest --blend, past which frames would barely change
+pub const MAX_FRAME_BLEND: u8 = 90;
+
+// LCD ghosting: each frame shown is the new one mixed with the last one
+// shown, so what's drawn every other frame (flickering sprites, fake
+// transparency) comes out see-through the way it does on the slow DMG LCD,
+// and fades over a few frames rather than at once.
+pub struct FrameBlend {
+    percent: u8,
+    shown: Vec<u8>,
+    // Whether the last frame shown had no ghost left in it
+    settled: bool,
+}
+
+impl FrameBlend {
+    pub fn new(percent: u8) -> Self {
+        FrameBlend { percent: percent.min(MAX_FRAME_BLEND), shown: Vec::new(), settled: true }
+    }
+
+    pub fn percent(&self) -> u8 {
+        self.percent
+    }
+
+    pub fn apply(&mut self, pixels: &[u8]) -> &[u8] {
+        if self.percent == 0 || self.shown.len() != pixels.len() {
+            self.shown = pixels.to_vec();
+            self.settled = true;
+            return &self.shown;
+        }
+        let kept = self.percent as i32;
+        for (shown, &new) in self.shown.iter_mut().zip(pixels) {
+            // Rounds toward the new frame, so a still picture settles on it
+            *shown = (new as i32 + (*shown as i32 - new as i32) * kept / 100) as u8;
+        }
+        self.settled = self.shown == pixels;
+        &self.shown
+    }
+
+    // Until it is, an unchanged frame still has to be shown again for the
+    // ghost to fade
+    pub fn is_settled(&self) -> bool {
+        self.settled
+    }
+}
+
+// Where an image of the given size goes in a window: the largest whole
+// multiple that fits, centered, or the largest size with the same aspect if
+// even 1x doesn't fit. Returns x, y, width and height.
+pub fn integer_fit(image: (u32, u32), window: (u32, u32)) -> (i32, i32, u32, u32) {
+    let multiple = (window.0 / image.0).min(window.1 / image.1);
+    let (width, height) = if multiple > 0 {
+        (image.0 * multiple, image.1 * multiple)
+    } else if window.0 * image.1 < window.1 * image.0 {
+        (window.0, (window.0 * image.1 / image.0).max(1))
+    } else {
+        ((window.1 * image.0 / image.1).max(1), window.1)
+    };
+    (((window.0 - width) / 2) as i32, ((window.1 - height) / 2) as i32, width, height)
+}
+
+// Recolors a frame through a DMG palette. DMG frames only contain grays, so
+// the red channel gives the shade; levels between the four shades (as the
+// AGB LCD response produces) are blended between neighbouring colors.
+pub fn apply_palette(pixels: &[u8], palette: &DmgPalette) -> Vec<u8> {
+    let mut out = Vec::with_capacity(pixels.len());
+    for pixel in pixels.chunks_exact(3) {
+        let darkness = (255 - pixel[0] as u32) * 3;
+        let index = (darkness / 255).min(2) as usize;
+        let blend = darkness - index as u32 * 255;
+        for (&light, &dark) in palette[index].iter().zip(&palette[index + 1]) {
+            out.push(((light as u32 * (255 - blend) + dark as u32 * blend) / 255) as u8);
+        }
+    }
+    out
+}
+
+// Tints whole screen lines toward a color, for debug overlays
+pub fn highlight_lines(pixels: &mut [u8], lines: impl IntoIterator<Item = usize>, color: [u8; 3]) {
+    let row_len = SCREEN_WIDTH * 3;
+    for line in lines {
+        let Some(row) = pixels.get_mut(line * row_len..(line + 1) * row_len) else {
+            continue;
+        };
+        for pixel in row.chunks_exact_mut(3) {
+            for (channel, &tint) in pixel.iter_mut().zip(&color) {
+                *channel = ((*channel as u16 + tint as u16) / 2) as u8;
+            }
+        }
+    }
+}
+
+// Discards frames, for running without a display
+pub struct NullScanout {
+    pub frames: u64,
+}
+
+impl Default for NullScanout {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NullScanout {
+    pub fn new() -> Self {
+        NullScanout { frames: 0 }
+    }
+}
+
+impl Scanout for NullScanout {
+    fn present(&mut self, _frame: &Frame) -> Result<()> {
+        self.frames += 1;
+        Ok(())
+    }
+
+    fn set_palette(&mut self, _palette: Option<DmgPalette>) {}
+
+    fn set_filter(&mut self, _filter: Filter) {}
+
+    fn set_frame_blend(&mut self, _percent: u8) {}
+
+    fn resize(&mut self, _scale: u32) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Keeps the last frame as RGBA for targets that take a whole buffer, like a
+// browser canvas, with the palette, ghosting and filter already drawn in.
+// The target scales it up itself, so resizing does nothing.
+pub struct RgbaScanout {
+    palette: Option<DmgPalette>,
+    filter: Filter,
+    blend: FrameBlend,
+    rgba: Vec<u8>,
+    // Of rgba, which follows the filter from the next frame presented on
+    width: usize,
+    height: usize,
+    // A setting changed since the last frame, so even an unchanged one is redrawn
+    stale: bool,
+}
+
+impl Default for RgbaScanout {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RgbaScanout {
+    // White until the first frame
+    pub fn new() -> Self {
+        RgbaScanout {
+            palette: None,
+            filter: Filter::None,
+            blend: FrameBlend::new(0),
+            rgba: vec![0xFF; SCREEN_WIDTH * SCREEN_HEIGHT * 4],
+            width: SCREEN_WIDTH,
+            height: SCREEN_HEIGHT,
+            stale: false,
+        }
+    }
+
+    pub fn rgba(&self) -> &[u8] {
+        &self.rgba
+    }
+
+    // Width and height of rgba in pixels
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+}
+
+impl Scanout for RgbaScanout {
+    fn present(&mut self, frame: &Frame) -> Result<()> {
+        if !frame.dirty && !self.stale && self.blend.is_settled() {
+            return Ok(());
+        }
+        let colored = self.palette.as_ref().map(|palette| apply_palette(frame.pixels, palette));
+        let pixels = self.blend.apply(colored.as_deref().unwrap_or(frame.pixels));
+        let filtered = apply_filter(pixels, self.filter);
+        self.rgba.clear();
+        self.rgba.extend(filtered.chunks_exact(3).flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 0xFF]));
+        self.width = SCREEN_WIDTH * self.filter.factor();
+        self.height = SCREEN_HEIGHT * self.filter.factor();
+        self.stale = false;
+        Ok(())
+    }
+
+    fn set_palette(&mut self, palette: Option<DmgPalette>) {
+        self.palette = palette;
+        self.stale = true;
+    }
+
+    fn set_filter(&mut self, filter: Filter) {
+        self.filter = filter;
+        self.stale = true;
+    }
+
+    fn set_frame_blend(&mut self, percent: u8) {
+        self.blend = FrameBlend::new(percent);
+        self.stale = true;
+    }
+
+    fn resize(&mut self, _scale: u32) -> Result<()> {
+        Ok(())
+    }
+}
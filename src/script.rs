@@ -0,0 +1,141 @@
+// Lua scripts that watch and steer a running game, for TAS tools, randomizer
+// checks and auto-splitters. A script's top level runs once when it loads
+// and registers functions with gb.on_frame, which run after every frame.
+// Through the global `gb` table they can:
+//
+//   gb.read(addr), gb.read16(addr), gb.write(addr, value)
+//   gb.registers()             -- {a=, f=, b=, c=, d=, e=, h=, l=, sp=, pc=}
+//   gb.frame()                 -- frames completed so far
+//   gb.input{a=true, up=true}  -- buttons held from the next frame on, {} lets go
+//   gb.text(x, y, text [, 0xRRGGBB])  -- drawn over the screen until the next frame
+//   gb.on_frame(function)
+//
+// The machine can only be reached while the script is running, so functions
+// kept around and called later from a coroutine fail instead.
+
+use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+
+use mlua::{Function, Lua, Table};
+
+use crate::error::{EmulatorError, Result};
+use crate::gameboy::GameBoy;
+use crate::headless::FrameDriver;
+use crate::joypad::Button;
+use crate::overlay::OverlayText;
+
+// Registry key of the Lua table holding the on_frame functions in order
+const FRAME_CALLBACKS: &str = "rust_gbc_frame_callbacks";
+const DEFAULT_TEXT_COLOR: u32 = 0xFFFFFF;
+
+#[derive(Default)]
+struct ScriptOutput {
+    buttons: u8,
+    texts: Vec<OverlayText>,
+}
+
+pub struct Script {
+    lua: Lua,
+    name: String,
+    output: RefCell<ScriptOutput>,
+}
+
+impl Script {
+    pub fn load(path: &Path, gb: &mut GameBoy) -> Result<Script> {
+        let source = fs::read_to_string(path).map_err(|source| EmulatorError::ScriptRead { path: path.to_path_buf(), source })?;
+        Script::new(&path.display().to_string(), &source, gb)
+    }
+
+    // Runs the top level of the script; name appears in its error messages
+    pub fn new(name: &str, source: &str, gb: &mut GameBoy) -> Result<Script> {
+        let script = Script { lua: Lua::new(), name: name.to_string(), output: RefCell::default() };
+        let callbacks = script.lua.create_table().and_then(|table| script.lua.set_named_registry_value(FRAME_CALLBACKS, table));
+        callbacks.map_err(|e| script.error(e))?;
+        script.with_machine(gb, |lua| lua.load(source).set_name(name).exec())?;
+        Ok(script)
+    }
+
+    pub fn has_overlay(&self) -> bool {
+        !self.output.borrow().texts.is_empty()
+    }
+
+    pub fn draw_overlay(&self, pixels: &mut [u8]) {
+        for text in &self.output.borrow().texts {
+            text.draw(pixels);
+        }
+    }
+
+    // Sets up the gb table for as long as run takes
+    fn with_machine<R>(&self, gb: &mut GameBoy, run: impl FnOnce(&Lua) -> mlua::Result<R>) -> Result<R> {
+        let gb = RefCell::new(gb);
+        let output = &self.output;
+        let lua = &self.lua;
+        lua.scope(|scope| {
+            let api = lua.create_table()?;
+            api.set("read", scope.create_function(|_, addr: u16| Ok(gb.borrow().peek(addr)))?)?;
+            api.set("read16", scope.create_function(|_, addr: u16| {
+                let gb = gb.borrow();
+                Ok(u16::from_le_bytes([gb.peek(addr), gb.peek(addr.wrapping_add(1))]))
+            })?)?;
+            api.set("write", scope.create_function(|_, (addr, value): (u16, u8)| {
+                gb.borrow_mut().write_byte(addr, value);
+                Ok(())
+            })?)?;
+            api.set("registers", scope.create_function(|lua, ()| {
+                let r = gb.borrow().registers();
+                lua.create_table_from([
+                    ("a", r.a as u16), ("f", r.f as u16), ("b", r.b as u16), ("c", r.c as u16),
+                    ("d", r.d as u16), ("e", r.e as u16), ("h", r.h as u16), ("l", r.l as u16),
+                    ("sp", r.sp), ("pc", r.pc),
+                ])
+            })?)?;
+            api.set("frame", scope.create_function(|_, ()| Ok(gb.borrow().frame_count()))?)?;
+            api.set("input", scope.create_function(|_, held: Option<Table>| {
+                let mut buttons = 0;
+                for pair in held.into_iter().flat_map(Table::pairs::<String, bool>) {
+                    let (name, pressed) = pair?;
+                    let button: Button = name.parse().map_err(mlua::Error::RuntimeError)?;
+                    if pressed {
+                        buttons |= button.mask();
+                    }
+                }
+                output.borrow_mut().buttons = buttons;
+                Ok(())
+            })?)?;
+            api.set("text", scope.create_function(|_, (x, y, text, color): (i32, i32, String, Option<u32>)| {
+                let [_, r, g, b] = color.unwrap_or(DEFAULT_TEXT_COLOR).to_be_bytes();
+                output.borrow_mut().texts.push(OverlayText { x, y, text, color: [r, g, b] });
+                Ok(())
+            })?)?;
+            api.set("on_frame", lua.create_function(|lua, callback: Function| {
+                lua.named_registry_value::<Table>(FRAME_CALLBACKS)?.push(callback)
+            })?)?;
+            lua.globals().set("gb", api)?;
+            run(lua)
+        })
+        .map_err(|e| self.error(e))
+    }
+
+    fn error(&self, error: mlua::Error) -> EmulatorError {
+        EmulatorError::Script { name: self.name.clone(), message: error.to_string() }
+    }
+}
+
+impl FrameDriver for Script {
+    fn buttons(&self) -> u8 {
+        self.output.borrow().buttons
+    }
+
+    // Calls the on_frame functions; the text they draw replaces the last frame's
+    fn after_frame(&mut self, gb: &mut GameBoy) -> Result<()> {
+        self.output.borrow_mut().texts.clear();
+        self.with_machine(gb, |lua| {
+            let callbacks: Table = lua.named_registry_value(FRAME_CALLBACKS)?;
+            for callback in callbacks.sequence_values::<Function>() {
+                callback?.call::<_, ()>(())?;
+            }
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,367 @@
+use std::fs;
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::cpu::CPU;
+use crate::debug_port::DebugPortMode;
+use crate::events::EmulatorEvent;
+use crate::gameboy::GameBoy;
+use crate::savestate;
+
+// Frames the test ROM gets to print its verdict
+const TEST_ROM_FRAMES: u32 = 10;
+
+// A few vectors in the SingleStepTests sm83 format, so the CPU check runs
+// even without the full set on disk
+const BUILTIN_VECTORS: &str = r#"[
+  {"name": "00 nop",
+   "initial": {"pc": 49152, "sp": 0, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0, "ie": 0,
+               "ram": [[49152, 0]]},
+   "final": {"pc": 49153, "sp": 0, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0, "ie": 0,
+             "ram": [[49152, 0]]},
+   "cycles": [[49152, 0, "r-m"]]},
+  {"name": "3C inc a",
+   "initial": {"pc": 49152, "sp": 0, "a": 15, "b": 0, "c": 0, "d": 0, "e": 0, "f": 16, "h": 0, "l": 0, "ime": 0, "ie": 0,
+               "ram": [[49152, 60]]},
+   "final": {"pc": 49153, "sp": 0, "a": 16, "b": 0, "c": 0, "d": 0, "e": 0, "f": 48, "h": 0, "l": 0, "ime": 0, "ie": 0,
+             "ram": [[49152, 60]]},
+   "cycles": [[49152, 60, "r-m"]]},
+  {"name": "80 add a,b",
+   "initial": {"pc": 49152, "sp": 0, "a": 58, "b": 198, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0, "ie": 0,
+               "ram": [[49152, 128]]},
+   "final": {"pc": 49153, "sp": 0, "a": 0, "b": 198, "c": 0, "d": 0, "e": 0, "f": 176, "h": 0, "l": 0, "ime": 0, "ie": 0,
+             "ram": [[49152, 128]]},
+   "cycles": [[49152, 128, "r-m"]]},
+  {"name": "77 ld (hl),a",
+   "initial": {"pc": 49152, "sp": 0, "a": 66, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 193, "l": 0, "ime": 0, "ie": 0,
+               "ram": [[49152, 119], [49408, 0]]},
+   "final": {"pc": 49153, "sp": 0, "a": 66, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 193, "l": 0, "ime": 0, "ie": 0,
+             "ram": [[49152, 119], [49408, 66]]},
+   "cycles": [[49152, 119, "r-m"], [49408, 66, "-wm"]]},
+  {"name": "c5 push bc",
+   "initial": {"pc": 49152, "sp": 53248, "a": 0, "b": 18, "c": 52, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0, "ie": 0,
+               "ram": [[49152, 197], [53246, 0], [53247, 0]]},
+   "final": {"pc": 49153, "sp": 53246, "a": 0, "b": 18, "c": 52, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0, "ie": 0,
+             "ram": [[49152, 197], [53246, 52], [53247, 18]]},
+   "cycles": [[49152, 197, "r-m"], [null, null, "---"], [53247, 18, "-wm"], [53246, 52, "-wm"]]},
+  {"name": "cb 37 swap a",
+   "initial": {"pc": 49152, "sp": 0, "a": 240, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0, "ie": 0,
+               "ram": [[49152, 203], [49153, 55]]},
+   "final": {"pc": 49154, "sp": 0, "a": 15, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0, "ie": 0,
+             "ram": [[49152, 203], [49153, 55]]},
+   "cycles": [[49152, 203, "r-m"], [49153, 55, "r-m"]]},
+  {"name": "20 jr nz taken",
+   "initial": {"pc": 49152, "sp": 0, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0, "ie": 0,
+               "ram": [[49152, 32], [49153, 5]]},
+   "final": {"pc": 49159, "sp": 0, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0, "ie": 0,
+             "ram": [[49152, 32], [49153, 5]]},
+   "cycles": [[49152, 32, "r-m"], [49153, 5, "r-m"], [null, null, "---"]]},
+  {"name": "cd call",
+   "initial": {"pc": 49152, "sp": 53248, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0, "ie": 0,
+               "ram": [[49152, 205], [49153, 0], [49154, 194], [53246, 0], [53247, 0]]},
+   "final": {"pc": 49664, "sp": 53246, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0, "ie": 0,
+             "ram": [[49152, 205], [49153, 0], [49154, 194], [53246, 3], [53247, 192]]},
+   "cycles": [[49152, 205, "r-m"], [49153, 0, "r-m"], [49154, 194, "r-m"], [null, null, "---"],
+              [53247, 192, "-wm"], [53246, 3, "-wm"]]}
+]"#;
+
+// A small homebrew ROM that checks some arithmetic, memory and stack
+// operations, then prints "Passed" or "Failed" over the serial port
+const TEST_PROGRAM: [u8; 0x51] = [
+    0x31, 0xFE, 0xDF,       // 0150 ld sp,$DFFE
+    0x3E, 0x12,             // 0153 ld a,$12
+    0xC6, 0x34,             // 0155 add a,$34
+    0xFE, 0x46,             // 0157 cp $46
+    0x20, 0x1C,             // 0159 jr nz,fail
+    0x21, 0x00, 0xC0,       // 015B ld hl,$C000
+    0x36, 0x5A,             // 015E ld (hl),$5A
+    0x7E,                   // 0160 ld a,(hl)
+    0xFE, 0x5A,             // 0161 cp $5A
+    0x20, 0x12,             // 0163 jr nz,fail
+    0x01, 0x34, 0x12,       // 0165 ld bc,$1234
+    0xC5,                   // 0168 push bc
+    0xD1,                   // 0169 pop de
+    0x7B,                   // 016A ld a,e
+    0xFE, 0x34,             // 016B cp $34
+    0x20, 0x08,             // 016D jr nz,fail
+    0x21, 0x80, 0x01,       // 016F ld hl,passed
+    0xCD, 0x90, 0x01,       // 0172 call print
+    0x18, 0xFE,             // 0175 jr @
+    0x21, 0x88, 0x01,       // 0177 fail: ld hl,failed
+    0xCD, 0x90, 0x01,       // 017A call print
+    0x18, 0xFE,             // 017D jr @
+    0x00,
+    b'P', b'a', b's', b's', b'e', b'd', b'\n', 0x00, // 0180
+    b'F', b'a', b'i', b'l', b'e', b'd', b'\n', 0x00, // 0188
+    0x2A,                   // 0190 print: ld a,(hl+)
+    0xB7,                   // 0191 or a
+    0xC8,                   // 0192 ret z
+    0xE0, 0x01,             // 0193 ldh ($01),a
+    0x3E, 0x81,             // 0195 ld a,$81
+    0xE0, 0x02,             // 0197 ldh ($02),a
+    0xF0, 0x02,             // 0199 wait: ldh a,($02)
+    0xE6, 0x80,             // 019B and $80
+    0x20, 0xFA,             // 019D jr nz,wait
+    0x18, 0xEF,             // 019F jr print
+];
+
+pub struct Check {
+    pub component: &'static str,
+    pub passed: bool,
+    pub detail: String,
+}
+
+// Runs every check; `vectors` is a SingleStepTests file or a directory of
+// them to use instead of the built-in CPU vectors
+pub fn run(vectors: Option<&Path>) -> Vec<Check> {
+    vec![check_cpu(vectors), check_test_rom(), check_savestate()]
+}
+
+pub fn test_rom() -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+    rom[0x150..0x150 + TEST_PROGRAM.len()].copy_from_slice(&TEST_PROGRAM);
+    rom
+}
+
+fn check_cpu(vectors: Option<&Path>) -> Check {
+    let result = match vectors {
+        Some(path) => vector_files(path).and_then(|files| {
+            let mut totals = VectorTotals::default();
+            for file in files {
+                let json = fs::read_to_string(&file).map_err(|e| format!("{}: {}", file.display(), e))?;
+                totals.add(run_vectors(&json).map_err(|e| format!("{}: {}", file.display(), e))?);
+            }
+            Ok(totals)
+        }),
+        None => run_vectors(BUILTIN_VECTORS),
+    };
+
+    match result {
+        Ok(totals) => Check {
+            component: "cpu",
+            passed: totals.failures.is_empty() && totals.passed > 0,
+            detail: match totals.failures.first() {
+                Some(first) => format!("{} of {} vectors failed, first: {}", totals.failures.len(),
+                                       totals.passed + totals.failures.len(), first),
+                None => format!("{} vectors passed, {} skipped", totals.passed, totals.skipped),
+            },
+        },
+        Err(error) => Check { component: "cpu", passed: false, detail: error },
+    }
+}
+
+fn vector_files(path: &Path) -> Result<Vec<std::path::PathBuf>, String> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(path).map_err(|e| format!("{}: {}", path.display(), e))? {
+        let file = entry.map_err(|e| e.to_string())?.path();
+        if file.extension().is_some_and(|ext| ext == "json") {
+            files.push(file);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[derive(Default)]
+pub struct VectorTotals {
+    pub passed: usize,
+    // Vectors that touch memory this machine doesn't treat as plain RAM
+    pub skipped: usize,
+    pub failures: Vec<String>,
+}
+
+impl VectorTotals {
+    fn add(&mut self, other: VectorTotals) {
+        self.passed += other.passed;
+        self.skipped += other.skipped;
+        self.failures.extend(other.failures);
+    }
+}
+
+pub fn run_vectors(json: &str) -> Result<VectorTotals, String> {
+    let vectors: Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    let vectors = vectors.as_array().ok_or("expected a list of test vectors")?;
+    let mut totals = VectorTotals::default();
+    for vector in vectors {
+        match run_vector(vector)? {
+            Some(Ok(())) => totals.passed += 1,
+            Some(Err(failure)) => totals.failures.push(failure),
+            None => totals.skipped += 1,
+        }
+    }
+    Ok(totals)
+}
+
+// Vectors treat all 64 KB as RAM. ROM bytes are loaded as the cartridge and
+// may only be read; I/O, OAM and cartridge RAM would behave differently.
+fn is_plain_memory(addr: u16) -> bool {
+    matches!(addr, 0x8000..=0x9FFF | 0xC000..=0xDFFF | 0xFF80..=0xFFFE)
+}
+
+// None when the vector can't run on this memory map
+fn run_vector(vector: &Value) -> Result<Option<std::result::Result<(), String>>, String> {
+    let name = vector["name"].as_str().unwrap_or("?");
+    let initial = &vector["initial"];
+    let expected = &vector["final"];
+    let initial_ram = ram_entries(initial)?;
+    let final_ram = ram_entries(expected)?;
+
+    let mut rom = vec![0; 0x8000];
+    for &(addr, value) in &initial_ram {
+        if addr < 0x8000 {
+            rom[addr as usize] = value;
+            if final_ram.iter().any(|&(final_addr, final_value)| final_addr == addr && final_value != value) {
+                return Ok(None);
+            }
+        } else if !is_plain_memory(addr) {
+            return Ok(None);
+        }
+    }
+    if final_ram.iter().any(|&(addr, _)| addr >= 0x8000 && !is_plain_memory(addr)) {
+        return Ok(None);
+    }
+
+    let mut cpu = CPU::new();
+    // A cartridge type byte with no MBC here
+    if cpu.bus.load_rom(&rom).is_err() {
+        return Ok(None);
+    }
+    cpu.bus.write_byte(0xFF50, 0x01);
+    for &(addr, value) in initial_ram.iter().filter(|&&(addr, _)| addr >= 0x8000) {
+        cpu.bus.write_byte(addr, value);
+    }
+    cpu.a = register(initial, "a")? as u8;
+    cpu.f = register(initial, "f")? as u8;
+    cpu.b = register(initial, "b")? as u8;
+    cpu.c = register(initial, "c")? as u8;
+    cpu.d = register(initial, "d")? as u8;
+    cpu.e = register(initial, "e")? as u8;
+    cpu.h = register(initial, "h")? as u8;
+    cpu.l = register(initial, "l")? as u8;
+    cpu.sp = register(initial, "sp")?;
+    cpu.pc = register(initial, "pc")?;
+    cpu.bus.interrupts.ime = register(initial, "ime")? != 0;
+
+    let cycles = cpu.step();
+
+    let mut mismatches = Vec::new();
+    let actual = cpu.snapshot();
+    let registers = [
+        ("a", actual.a as u16), ("f", actual.f as u16), ("b", actual.b as u16), ("c", actual.c as u16),
+        ("d", actual.d as u16), ("e", actual.e as u16), ("h", actual.h as u16), ("l", actual.l as u16),
+        ("sp", actual.sp), ("pc", actual.pc),
+    ];
+    for (name, value) in registers {
+        let want = register(expected, name)?;
+        if value != want {
+            mismatches.push(format!("{} {:X} != {:X}", name, value, want));
+        }
+    }
+    for &(addr, want) in &final_ram {
+        let value = cpu.bus.read_byte(addr);
+        if value != want {
+            mismatches.push(format!("[{:04X}] {:02X} != {:02X}", addr, value, want));
+        }
+    }
+    if let Some(m_cycles) = vector["cycles"].as_array() {
+        if cycles as usize != m_cycles.len() * 4 {
+            mismatches.push(format!("cycles {} != {}", cycles, m_cycles.len() * 4));
+        }
+    }
+
+    if mismatches.is_empty() {
+        Ok(Some(Ok(())))
+    } else {
+        Ok(Some(Err(format!("{} ({})", name, mismatches.join(", ")))))
+    }
+}
+
+fn register(state: &Value, name: &str) -> Result<u16, String> {
+    state[name]
+        .as_u64()
+        .and_then(|value| u16::try_from(value).ok())
+        .ok_or_else(|| format!("missing or invalid '{}'", name))
+}
+
+fn ram_entries(state: &Value) -> Result<Vec<(u16, u8)>, String> {
+    let entries = state["ram"].as_array().ok_or("missing 'ram'")?;
+    entries
+        .iter()
+        .map(|entry| {
+            let addr = entry[0].as_u64().and_then(|addr| u16::try_from(addr).ok());
+            let value = entry[1].as_u64().and_then(|value| u8::try_from(value).ok());
+            addr.zip(value).ok_or_else(|| "invalid 'ram' entry".to_string())
+        })
+        .collect()
+}
+
+fn check_test_rom() -> Check {
+    let mut gb = GameBoy::new();
+    let output = gb.load_rom(&test_rom()).map(|_| {
+        gb.set_debug_port(Some(DebugPortMode::Serial));
+        gb.write_byte(0xFF50, 0x01);
+        gb.cpu.pc = 0x0100;
+        let mut lines = Vec::new();
+        for _ in 0..TEST_ROM_FRAMES {
+            gb.run_frame();
+            lines.extend(gb.take_events().into_iter().filter_map(|timed| match timed.event {
+                EmulatorEvent::DebugLog(line) => Some(line),
+                _ => None,
+            }));
+            if !lines.is_empty() {
+                break;
+            }
+        }
+        lines
+    });
+
+    match output {
+        Ok(lines) => Check {
+            component: "test rom",
+            passed: lines.first().is_some_and(|line| line == "Passed"),
+            detail: match lines.first() {
+                Some(line) => format!("printed \"{}\"", line),
+                None => format!("printed nothing in {} frames", TEST_ROM_FRAMES),
+            },
+        },
+        Err(error) => Check { component: "test rom", passed: false, detail: error.to_string() },
+    }
+}
+
+// Restores a state into a second machine and runs both side by side
+fn check_savestate() -> Check {
+    let rom = test_rom();
+    let mut original = GameBoy::new();
+    let mut restored = GameBoy::new();
+    let loaded = original.load_rom(&rom).and_then(|_| restored.load_rom(&rom)).and_then(|_| {
+        original.run_frame();
+        for _ in 0..1234 {
+            original.step();
+        }
+        restored.load_state(&original.save_state())
+    });
+    if let Err(error) = loaded {
+        return Check { component: "savestate", passed: false, detail: error.to_string() };
+    }
+
+    for _ in 0..10_000 {
+        original.step();
+        restored.step();
+    }
+    let state = original.save_state();
+    let passed = savestate::state_hash(&state) == savestate::state_hash(&restored.save_state());
+    Check {
+        component: "savestate",
+        passed,
+        detail: if passed {
+            format!("{} byte state restores exactly", state.len())
+        } else {
+            "restored machine diverged".to_string()
+        },
+    }
+}
@@ -0,0 +1,163 @@
+use crate::interrupts::{Interrupts, InterruptType};
+use crate::error::Result;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+
+const SERIAL_DATA: u16 = 0xFF01;
+const SERIAL_CONTROL: u16 = 0xFF02;
+
+const TRANSFER_START: u8 = 0x80;
+// CGB only: shift at 262144 Hz instead of 8192 Hz
+const FAST_CLOCK: u8 = 0x02;
+const INTERNAL_CLOCK: u8 = 0x01;
+
+// CPU cycles to shift out a whole byte with the internal clock; they scale
+// with double speed like the CPU
+const CYCLES_PER_BYTE: u32 = 4096;
+const FAST_CYCLES_PER_BYTE: u32 = 128;
+
+pub struct Serial {
+    data: u8,
+    control: u8,
+    // Set while a cable connects this port to another instance
+    connected: bool,
+    // The fast clock bit only exists in CGB mode
+    cgb_mode: bool,
+    // Byte being shifted out with the internal clock and the cycles left
+    shifting: Option<(u8, u32)>,
+    // Byte this port finished sending as clock master, for the link partner
+    outgoing: Option<u8>,
+    // (sent, received) for each finished transfer, until the GameBoy takes them
+    transfers: Vec<(u8, u8)>,
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Serial {
+            data: 0,
+            control: 0,
+            connected: false,
+            cgb_mode: false,
+            shifting: None,
+            outgoing: None,
+            transfers: Vec::new(),
+        }
+    }
+
+    pub fn set_connected(&mut self, connected: bool) {
+        self.connected = connected;
+    }
+
+    pub fn set_cgb_mode(&mut self, enabled: bool) {
+        self.cgb_mode = enabled;
+    }
+
+    pub fn tick(&mut self, cycles: u32, interrupts: &mut Interrupts) {
+        let Some((byte, remaining)) = self.shifting else {
+            return;
+        };
+        if remaining > cycles {
+            self.shifting = Some((byte, remaining - cycles));
+            return;
+        }
+
+        self.shifting = None;
+        if self.connected {
+            self.outgoing = Some(byte);
+        } else {
+            // With nothing plugged in the master shifts in all ones
+            self.complete_transfer(byte, 0xFF, interrupts);
+        }
+    }
+
+    // Byte sent by this port acting as clock master, waiting for the link partner
+    pub fn take_outgoing(&mut self) -> Option<u8> {
+        if self.connected {
+            self.outgoing.take()
+        } else {
+            None
+        }
+    }
+
+    pub fn complete_transfer(&mut self, sent: u8, incoming: u8, interrupts: &mut Interrupts) {
+        self.transfers.push((sent, incoming));
+        self.data = incoming;
+        self.control &= !TRANSFER_START;
+        interrupts.request_interrupt(InterruptType::Serial);
+    }
+
+    // Shifts a byte in from the clock master; the slave only completes a
+    // transfer (and raises its interrupt) when it has one armed
+    pub fn exchange_as_slave(&mut self, incoming: u8, interrupts: &mut Interrupts) -> u8 {
+        let outgoing = self.data;
+        self.data = incoming;
+        if self.control & TRANSFER_START != 0 {
+            self.transfers.push((outgoing, incoming));
+            self.control &= !TRANSFER_START;
+            interrupts.request_interrupt(InterruptType::Serial);
+        }
+        outgoing
+    }
+
+    pub fn take_transfers(&mut self) -> Vec<(u8, u8)> {
+        std::mem::take(&mut self.transfers)
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            SERIAL_DATA => self.data,
+            SERIAL_CONTROL if self.cgb_mode => self.control | 0x7C,
+            SERIAL_CONTROL => self.control | 0x7E,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            SERIAL_DATA => self.data = value,
+            SERIAL_CONTROL => {
+                let fast = if self.cgb_mode { FAST_CLOCK } else { 0 };
+                self.control = value & (TRANSFER_START | fast | INTERNAL_CLOCK);
+                // With the external clock the transfer waits for the partner
+                self.shifting = None;
+                if self.control & (TRANSFER_START | INTERNAL_CLOCK) == TRANSFER_START | INTERNAL_CLOCK {
+                    let cycles = if self.control & FAST_CLOCK != 0 { FAST_CYCLES_PER_BYTE } else { CYCLES_PER_BYTE };
+                    self.shifting = Some((self.data, cycles));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// The cable connection belongs to the session, not to the saved machine
+impl Savestate for Serial {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.data);
+        state.write_u8(self.control);
+        state.write_bool(self.outgoing.is_some());
+        state.write_u8(self.outgoing.unwrap_or(0));
+        let (shifting, remaining) = self.shifting.unwrap_or((0, 0));
+        state.write_bool(self.shifting.is_some());
+        state.write_u8(shifting);
+        state.write_u32(remaining);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.data = state.read_u8()?;
+        self.control = state.read_u8()?;
+        let pending = state.read_bool()?;
+        let byte = state.read_u8()?;
+        self.outgoing = if pending { Some(byte) } else { None };
+        let active = state.read_bool()?;
+        let shifting = state.read_u8()?;
+        let remaining = state.read_u32()?;
+        self.shifting = if active { Some((shifting, remaining)) } else { None };
+        Ok(())
+    }
+}
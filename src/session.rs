@@ -0,0 +1,72 @@
+use crate::gameboy::GameBoy;
+use crate::infrared::LocalIr;
+
+// Two instances with their serial ports cabled together and their infrared
+// ports facing each other, stepped in lockstep
+pub struct LinkSession {
+    games: [GameBoy; 2],
+    focus: usize,
+}
+
+impl LinkSession {
+    pub fn new(mut first: GameBoy, mut second: GameBoy) -> Self {
+        first.cpu.bus.serial.set_connected(true);
+        second.cpu.bus.serial.set_connected(true);
+        let (first_ir, second_ir) = LocalIr::pair();
+        first.set_infrared(Some(Box::new(first_ir)));
+        second.set_infrared(Some(Box::new(second_ir)));
+        LinkSession {
+            games: [first, second],
+            focus: 0,
+        }
+    }
+
+    pub fn games_mut(&mut self) -> &mut [GameBoy; 2] {
+        &mut self.games
+    }
+
+    pub fn focus(&self) -> usize {
+        self.focus
+    }
+
+    pub fn toggle_focus(&mut self) {
+        self.focus = 1 - self.focus;
+    }
+
+    // Runs both machines until each has produced a frame, always advancing the one
+    // that is behind so serial exchanges happen at nearly the same emulated time
+    pub fn run_frame(&mut self) {
+        let mut done = [false; 2];
+        let mut elapsed = [0u64; 2];
+
+        while !(done[0] && done[1]) {
+            let index = if done[0] {
+                1
+            } else if done[1] || elapsed[0] <= elapsed[1] {
+                0
+            } else {
+                1
+            };
+
+            elapsed[index] += self.games[index].step() as u64;
+            self.exchange_serial(index);
+
+            if self.games[index].poll_frame() {
+                done[index] = true;
+            }
+        }
+    }
+
+    fn exchange_serial(&mut self, master: usize) {
+        let [first, second] = &mut self.games;
+        let (master_gb, slave_gb) = if master == 0 { (first, second) } else { (second, first) };
+
+        if let Some(byte) = master_gb.cpu.bus.serial.take_outgoing() {
+            let slave_bus = &mut slave_gb.cpu.bus;
+            let reply = slave_bus.serial.exchange_as_slave(byte, &mut slave_bus.interrupts);
+
+            let master_bus = &mut master_gb.cpu.bus;
+            master_bus.serial.complete_transfer(byte, reply, &mut master_bus.interrupts);
+        }
+    }
+}
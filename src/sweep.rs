@@ -0,0 +1,181 @@
+use std::fs;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::cartridge::{read_rom_file, CartridgeHeader};
+use crate::error::EmulatorError;
+use crate::events::EmulatorEvent;
+use crate::gameboy::GameBoy;
+use crate::model::HardwareModel;
+
+const ROM_EXTENSIONS: [&str; 4] = ["gb", "gbc", "zip", "gz"];
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    // Ran every frame without a hang report
+    Ok,
+    // The watchdog reported the CPU stuck in a loop, or an invalid opcode
+    // locked it up
+    Hang,
+    // The emulator panicked
+    Crash,
+    // The ROM couldn't be read or loaded
+    LoadError,
+}
+
+impl Outcome {
+    fn label(&self) -> &'static str {
+        match self {
+            Outcome::Ok => "ok",
+            Outcome::Hang => "hang",
+            Outcome::Crash => "crash",
+            Outcome::LoadError => "load-error",
+        }
+    }
+}
+
+pub struct SweepResult {
+    pub path: PathBuf,
+    pub title: String,
+    pub outcome: Outcome,
+    pub frames_run: u32,
+    // Frames with more than one color on screen
+    pub non_blank_frames: u32,
+    pub detail: String,
+}
+
+// Every .gb/.gbc/.zip/.gz file directly inside dir, sorted so reports diff cleanly
+pub fn find_roms(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut roms = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let extension = path.extension().and_then(|ext| ext.to_str()).map(str::to_ascii_lowercase);
+        if path.is_file() && extension.is_some_and(|ext| ROM_EXTENSIONS.contains(&ext.as_str())) {
+            roms.push(path);
+        }
+    }
+    roms.sort();
+    Ok(roms)
+}
+
+// Runs each ROM for `frames` frames on `threads` workers; results come back
+// in the order of `roms`. A panicking ROM is reported as a crash, though the
+// panic hook still prints it unless the caller has quietened it.
+pub fn run(roms: &[PathBuf], frames: u32, threads: usize) -> Vec<SweepResult> {
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<SweepResult>>> = Mutex::new(roms.iter().map(|_| None).collect());
+
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = roms.get(index) else {
+                    break;
+                };
+                let result = run_rom(path, frames);
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+
+    results.into_inner().unwrap().into_iter().flatten().collect()
+}
+
+fn run_rom(path: &Path, frames: u32) -> SweepResult {
+    let mut result = SweepResult {
+        path: path.to_path_buf(),
+        title: String::new(),
+        outcome: Outcome::Ok,
+        frames_run: 0,
+        non_blank_frames: 0,
+        detail: String::new(),
+    };
+
+    let rom = match read_rom_file(path) {
+        Ok(rom) => rom,
+        Err(error) => {
+            result.outcome = Outcome::LoadError;
+            result.detail = error.to_string();
+            return result;
+        }
+    };
+    let header = CartridgeHeader::parse(&rom);
+    let model = header.as_ref().map_or(HardwareModel::Dmg, CartridgeHeader::preferred_model);
+    result.title = header.map(|header| header.title).unwrap_or_default();
+
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut gb = GameBoy::new();
+        gb.set_model(model);
+        gb.load_rom(&rom)?;
+        gb.reset();
+        for _ in 0..frames {
+            if let Some(frame) = gb.run_frame() {
+                let first = &frame.pixels[..3];
+                if frame.pixels.chunks_exact(3).any(|pixel| pixel != first) {
+                    result.non_blank_frames += 1;
+                }
+            }
+            result.frames_run += 1;
+
+            let hang = gb.take_events().into_iter().find_map(|timed| match timed.event {
+                EmulatorEvent::Hang(report) => Some(format!("PC 0x{:04X}-0x{:04X}", report.pc_low, report.pc_high)),
+                EmulatorEvent::CpuLocked { opcode, pc, .. } => {
+                    Some(format!("invalid opcode 0x{:02X} at 0x{:04X}", opcode, pc))
+                }
+                _ => None,
+            });
+            if let Some(detail) = hang {
+                result.outcome = Outcome::Hang;
+                result.detail = detail;
+                break;
+            }
+        }
+        Ok::<(), EmulatorError>(())
+    }));
+
+    match outcome {
+        Ok(Ok(())) => {}
+        Ok(Err(error)) => {
+            result.outcome = Outcome::LoadError;
+            result.detail = error.to_string();
+        }
+        Err(payload) => {
+            result.outcome = Outcome::Crash;
+            result.detail = payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "panic".to_string());
+        }
+    }
+    result
+}
+
+pub fn to_csv(results: &[SweepResult]) -> String {
+    let mut csv = String::from("rom,title,status,frames,non_blank_frames,detail\n");
+    for result in results {
+        let file = result.path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+        csv.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            csv_field(&file),
+            csv_field(&result.title),
+            result.outcome.label(),
+            result.frames_run,
+            result.non_blank_frames,
+            csv_field(&result.detail),
+        ));
+    }
+    csv
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
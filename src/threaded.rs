@@ -0,0 +1,162 @@
+// The core on a thread of its own, so a frontend can show one frame and
+// queue its sound while the next is already being emulated. The CPU, PPU
+// and APU stay together on the core thread since they run in lockstep; what
+// moves off it is everything done with a frame once it's finished.
+//
+// A GameBoy can't be moved between threads, so the core thread builds its
+// own and keeps it. Frames are asked for with the buttons to hold and come
+// back in order; anything else is done with `with`, which runs between
+// frames and waits for the result. Running a GameBoy directly on one thread
+// is still the simpler way, and what frontends fall back to.
+
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+use crate::error::{EmulatorError, Result};
+use crate::events::TimedEvent;
+use crate::gameboy::{Frame, GameBoy};
+use crate::history::HistoryEntry;
+use crate::perf::PerfStats;
+
+// Frames that can be started before the oldest is finished
+pub const MAX_FRAMES_AHEAD: usize = 2;
+
+type Job = Box<dyn FnOnce(&mut GameBoy) + Send>;
+
+enum Command {
+    Frame(u8),
+    Run(Job),
+}
+
+// A finished frame with what the frontend needs to show and play it
+pub struct FrameOutput {
+    pub index: u64,
+    pub cycles: u64,
+    // RGB24, SCREEN_WIDTH x SCREEN_HEIGHT
+    pub pixels: Vec<u8>,
+    pub dirty: bool,
+    // False when a breakpoint stopped the machine partway through
+    pub complete: bool,
+    pub samples: Vec<f32>,
+    pub events: Vec<TimedEvent>,
+    pub rumble: Option<f32>,
+    pub perf: Option<PerfStats>,
+    // The instructions up to where a breakpoint stopped it, if history is kept
+    pub history: Vec<HistoryEntry>,
+}
+
+impl FrameOutput {
+    pub fn frame(&self) -> Frame<'_> {
+        Frame { index: self.index, cycles: self.cycles, pixels: &self.pixels, dirty: self.dirty }
+    }
+}
+
+pub struct CoreThread {
+    // None once dropping, which ends the thread's loop
+    commands: Option<Sender<Command>>,
+    frames: Receiver<FrameOutput>,
+    // Frames started and not yet finished
+    pending: usize,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl CoreThread {
+    // Returns build's error if the machine couldn't be set up
+    pub fn spawn(build: impl FnOnce() -> Result<GameBoy> + Send + 'static) -> Result<CoreThread> {
+        let (commands, command_rx) = mpsc::channel();
+        // Never full, as at most MAX_FRAMES_AHEAD frames are pending
+        let (frame_tx, frames) = mpsc::sync_channel(MAX_FRAMES_AHEAD);
+        let (ready_tx, ready) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("core".to_string())
+            .spawn(move || {
+                let mut gb = match build() {
+                    Ok(gb) => gb,
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+                let _ = ready_tx.send(Ok(()));
+                for command in command_rx {
+                    match command {
+                        Command::Frame(buttons) => {
+                            if frame_tx.send(run_frame(&mut gb, buttons)).is_err() {
+                                break;
+                            }
+                        }
+                        Command::Run(job) => job(&mut gb),
+                    }
+                }
+            })
+            .map_err(|source| EmulatorError::CoreThreadSpawn { source })?;
+        ready.recv().map_err(|_| EmulatorError::CoreThreadStopped)??;
+        Ok(CoreThread { commands: Some(commands), frames, pending: 0, thread: Some(thread) })
+    }
+
+    // Starts a frame with these buttons held, after the ones already started.
+    // Panics if MAX_FRAMES_AHEAD are waiting to be finished.
+    pub fn start_frame(&mut self, buttons: u8) -> Result<()> {
+        assert!(self.pending < MAX_FRAMES_AHEAD, "too many frames started");
+        self.send(Command::Frame(buttons))?;
+        self.pending += 1;
+        Ok(())
+    }
+
+    // Waits for the oldest frame started; None when there's none
+    pub fn finish_frame(&mut self) -> Result<Option<FrameOutput>> {
+        if self.pending == 0 {
+            return Ok(None);
+        }
+        let frame = self.frames.recv().map_err(|_| EmulatorError::CoreThreadStopped)?;
+        self.pending -= 1;
+        Ok(Some(frame))
+    }
+
+    pub fn pending_frames(&self) -> usize {
+        self.pending
+    }
+
+    // Runs job on the core thread once the frames started so far are done
+    pub fn with<R: Send + 'static>(&self, job: impl FnOnce(&mut GameBoy) -> R + Send + 'static) -> Result<R> {
+        let (result_tx, result) = mpsc::channel();
+        self.send(Command::Run(Box::new(move |gb| {
+            let _ = result_tx.send(job(gb));
+        })))?;
+        result.recv().map_err(|_| EmulatorError::CoreThreadStopped)
+    }
+
+    fn send(&self, command: Command) -> Result<()> {
+        let commands = self.commands.as_ref().ok_or(EmulatorError::CoreThreadStopped)?;
+        commands.send(command).map_err(|_| EmulatorError::CoreThreadStopped)
+    }
+}
+
+impl Drop for CoreThread {
+    fn drop(&mut self) {
+        self.commands = None;
+        if let Some(thread) = self.thread.take() {
+            // A panic there has been reported already
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run_frame(gb: &mut GameBoy, buttons: u8) -> FrameOutput {
+    gb.set_inputs(buttons);
+    let complete = gb.run_frame().is_some();
+    let frame = gb.frame();
+    let (index, cycles, pixels, dirty) = (frame.index, frame.cycles, frame.pixels.to_vec(), frame.dirty);
+    FrameOutput {
+        index,
+        cycles,
+        pixels,
+        dirty,
+        complete,
+        samples: gb.take_audio().samples,
+        events: gb.take_events(),
+        rumble: gb.take_rumble(),
+        perf: gb.perf_stats(),
+        history: if complete { Vec::new() } else { gb.history() },
+    }
+}
@@ -1,16 +1,37 @@
 use crate::interrupts::{Interrupts, InterruptType};
+use crate::error::Result;
+use crate::savestate::{Savestate, StateReader, StateWriter};
 
 const DIVIDER_REGISTER: u16 = 0xFF04;
 const TIMER_COUNTER: u16 = 0xFF05;
 const TIMER_MODULO: u16 = 0xFF06;
 const TIMER_CONTROL: u16 = 0xFF07;
 
+// Bit of the internal counter each TAC clock select follows: 4096, 262144,
+// 65536 and 16384 Hz
+const TAC_BITS: [u16; 4] = [9, 3, 5, 7];
+
+// DIV is the top byte of a 16-bit counter running at the CPU clock. TIMA
+// counts falling edges of one of its bits, ANDed with the TAC enable bit, so
+// resetting DIV or changing TAC can count an edge of its own.
 pub struct Timer {
     divider: u16,
     counter: u8,
     modulo: u8,
     control: u8,
-    counter_cycles: u32,
+    // TIMA overflowed during the last machine cycle and reads 0; TMA is
+    // loaded and the interrupt requested on the next one unless a TIMA
+    // write cancels it
+    overflow_pending: bool,
+    // The machine cycle TMA was loaded in, when TIMA writes are ignored and
+    // TMA writes go through to TIMA as well
+    reloading: bool,
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Timer {
@@ -20,43 +41,44 @@ impl Timer {
             counter: 0,
             modulo: 0,
             control: 0,
-            counter_cycles: 0,
+            overflow_pending: false,
+            reloading: false,
         }
     }
 
+    // `cycles` is a multiple of a machine cycle, 4 CPU cycles
     pub fn tick(&mut self, cycles: u32, interrupts: &mut Interrupts) {
-        self.divider = self.divider.wrapping_add(cycles as u16);
-
-        if !self.is_enabled() {
-            return;
-        }
-
-        self.counter_cycles += cycles;
-        let threshold = self.get_frequency_threshold();
-
-        while self.counter_cycles >= threshold {
-            self.counter_cycles -= threshold;
-            self.counter = self.counter.wrapping_add(1);
-
-            if self.counter == 0 {
+        for _ in 0..cycles / 4 {
+            self.reloading = false;
+            if std::mem::take(&mut self.overflow_pending) {
                 self.counter = self.modulo;
+                self.reloading = true;
                 interrupts.request_interrupt(InterruptType::Timer);
             }
+
+            let was_high = self.timer_signal();
+            self.divider = self.divider.wrapping_add(4);
+            if was_high && !self.timer_signal() {
+                self.increment();
+            }
         }
     }
 
-    fn is_enabled(&self) -> bool {
-        self.control & 0b00000100 != 0
+    // The multiplexed counter bit TIMA counts falling edges of
+    fn timer_signal(&self) -> bool {
+        let bit = TAC_BITS[(self.control & 0b00000011) as usize];
+        self.control & 0b00000100 != 0 && self.divider & (1 << bit) != 0
     }
 
-    fn get_frequency_threshold(&self) -> u32 {
-        match self.control & 0b00000011 {
-            0 => 1024,
-            1 => 16,
-            2 => 64,
-            3 => 256,
-            _ => unreachable!(),
-        }
+    fn increment(&mut self) {
+        let (counter, overflow) = self.counter.overflowing_add(1);
+        self.counter = counter;
+        self.overflow_pending |= overflow;
+    }
+
+    // Sets the whole internal counter, which a DIV write can only clear
+    pub fn set_divider(&mut self, divider: u16) {
+        self.divider = divider;
     }
 
     pub fn read(&self, addr: u16) -> u8 {
@@ -64,19 +86,52 @@ impl Timer {
             DIVIDER_REGISTER => (self.divider >> 8) as u8,
             TIMER_COUNTER => self.counter,
             TIMER_MODULO => self.modulo,
-            TIMER_CONTROL => self.control,
+            TIMER_CONTROL => 0xF8 | self.control,
             _ => 0xFF,
         }
     }
 
     pub fn write(&mut self, addr: u16, value: u8) {
+        let was_high = self.timer_signal();
         match addr {
             DIVIDER_REGISTER => self.divider = 0,
-            TIMER_COUNTER => self.counter = value,
-            TIMER_MODULO => self.modulo = value,
+            TIMER_COUNTER if self.reloading => {}
+            TIMER_COUNTER => {
+                self.counter = value;
+                self.overflow_pending = false;
+            }
+            TIMER_MODULO => {
+                self.modulo = value;
+                if self.reloading {
+                    self.counter = value;
+                }
+            }
             TIMER_CONTROL => self.control = value & 0b00000111,
             _ => {}
         }
+        if was_high && !self.timer_signal() {
+            self.increment();
+        }
     }
 }
 
+impl Savestate for Timer {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.divider);
+        state.write_u8(self.counter);
+        state.write_u8(self.modulo);
+        state.write_u8(self.control);
+        state.write_bool(self.overflow_pending);
+        state.write_bool(self.reloading);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.divider = state.read_u16()?;
+        self.counter = state.read_u8()?;
+        self.modulo = state.read_u8()?;
+        self.control = state.read_u8()? & 0b00000111;
+        self.overflow_pending = state.read_bool()?;
+        self.reloading = state.read_bool()?;
+        Ok(())
+    }
+}
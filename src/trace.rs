@@ -0,0 +1,34 @@
+use std::io::{self, Write};
+
+use crate::cpu::RegisterSnapshot;
+
+// Logs each instruction before it runs, one line per instruction, in the
+// format Gameboy Doctor and several other emulators use so the logs can be
+// diffed line by line:
+// A:00 F:11 B:22 C:33 D:44 E:55 H:66 L:77 SP:8888 PC:9999 PCMEM:AA,BB,CC,DD
+pub struct Tracer {
+    out: Box<dyn Write>,
+}
+
+impl Tracer {
+    // The writer should be buffered, this writes a line per instruction
+    pub fn new(out: Box<dyn Write>) -> Self {
+        Tracer { out }
+    }
+
+    pub fn log(&mut self, registers: &RegisterSnapshot, pcmem: [u8; 4]) -> io::Result<()> {
+        writeln!(self.out, "{}", format_line(registers, pcmem))
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// `pcmem` holds the four bytes starting at PC
+pub fn format_line(r: &RegisterSnapshot, pcmem: [u8; 4]) -> String {
+    format!(
+        "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+        r.a, r.f, r.b, r.c, r.d, r.e, r.h, r.l, r.sp, r.pc, pcmem[0], pcmem[1], pcmem[2], pcmem[3],
+    )
+}
@@ -0,0 +1,219 @@
+// Pictures of VRAM for debugging, like BGB's VRAM viewer: the tile data in
+// both banks, the two background maps with the visible area marked, the
+// palettes and the 40 objects in OAM. Each page is an RGB24 image of
+// VIEWER_WIDTH x VIEWER_HEIGHT for a frontend to show in a window of its own.
+
+use crate::gameboy::GameBoy;
+
+pub const VIEWER_WIDTH: usize = 256;
+pub const VIEWER_HEIGHT: usize = 256;
+
+// Fills whatever a page doesn't cover
+const BACKDROP: [u8; 3] = [0x40, 0x40, 0x48];
+// Tile data has no palette of its own, so it's shown in grays
+const TILE_GRAYS: [[u8; 3]; 4] = [[0xFF; 3], [0xAA; 3], [0x55; 3], [0x00; 3]];
+// Outlines of the area SCX/SCY show and of the window
+const VIEWPORT_COLOR: [u8; 3] = [0xFF, 0x20, 0x20];
+const WINDOW_COLOR: [u8; 3] = [0x20, 0x60, 0xFF];
+const SWATCH_SIZE: usize = 24;
+// Object cells, 8 across and 5 down, behind a thumbnail at twice the size
+const OBJECT_CELL: (usize, usize) = (32, 48);
+const OBJECT_SCALE: usize = 2;
+const CELL_COLOR: [u8; 3] = [0x60, 0x60, 0x68];
+// Cells of objects left out of a line by the 10-per-line limit last frame,
+// and of objects placed off screen
+const DROPPED_COLOR: [u8; 3] = [0xA0, 0x20, 0x20];
+const HIDDEN_COLOR: [u8; 3] = [0x28, 0x28, 0x2C];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ViewerPage {
+    Tiles,
+    Map9800,
+    Map9C00,
+    Palettes,
+    Objects,
+}
+
+impl ViewerPage {
+    pub fn next(self) -> Self {
+        match self {
+            ViewerPage::Tiles => ViewerPage::Map9800,
+            ViewerPage::Map9800 => ViewerPage::Map9C00,
+            ViewerPage::Map9C00 => ViewerPage::Palettes,
+            ViewerPage::Palettes => ViewerPage::Objects,
+            ViewerPage::Objects => ViewerPage::Tiles,
+        }
+    }
+
+    pub fn title(self) -> &'static str {
+        match self {
+            ViewerPage::Tiles => "Tiles (bank 0 | bank 1)",
+            ViewerPage::Map9800 => "BG map 9800",
+            ViewerPage::Map9C00 => "BG map 9C00",
+            ViewerPage::Palettes => "Palettes (BG | OBJ)",
+            ViewerPage::Objects => "OAM (red: dropped, dark: off screen)",
+        }
+    }
+}
+
+pub fn render(gb: &GameBoy, page: ViewerPage) -> Vec<u8> {
+    let mut image = Canvas::new();
+    match page {
+        ViewerPage::Tiles => draw_tiles(gb, &mut image),
+        ViewerPage::Map9800 => draw_map(gb, &mut image, false),
+        ViewerPage::Map9C00 => draw_map(gb, &mut image, true),
+        ViewerPage::Palettes => draw_palettes(gb, &mut image),
+        ViewerPage::Objects => draw_objects(gb, &mut image),
+    }
+    image.pixels
+}
+
+struct Canvas {
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new() -> Self {
+        Canvas { pixels: BACKDROP.repeat(VIEWER_WIDTH * VIEWER_HEIGHT) }
+    }
+
+    fn put(&mut self, x: usize, y: usize, rgb: [u8; 3]) {
+        let at = (y * VIEWER_WIDTH + x) * 3;
+        self.pixels[at..at + 3].copy_from_slice(&rgb);
+    }
+
+    // An outline that wraps around the edges like the map does
+    fn outline(&mut self, x: usize, y: usize, width: usize, height: usize, rgb: [u8; 3]) {
+        for dx in 0..width {
+            self.put((x + dx) % VIEWER_WIDTH, y % VIEWER_HEIGHT, rgb);
+            self.put((x + dx) % VIEWER_WIDTH, (y + height - 1) % VIEWER_HEIGHT, rgb);
+        }
+        for dy in 0..height {
+            self.put(x % VIEWER_WIDTH, (y + dy) % VIEWER_HEIGHT, rgb);
+            self.put((x + width - 1) % VIEWER_WIDTH, (y + dy) % VIEWER_HEIGHT, rgb);
+        }
+    }
+}
+
+// The color numbers of one row of a tile, left to right
+fn tile_row(vram: &[u8], addr: usize) -> [u8; 8] {
+    let (low, high) = (vram[addr], vram[addr + 1]);
+    std::array::from_fn(|x| ((high >> (7 - x)) & 1) << 1 | ((low >> (7 - x)) & 1))
+}
+
+// 384 tiles per bank in 16 columns, bank 1 to the right of bank 0
+fn draw_tiles(gb: &GameBoy, image: &mut Canvas) {
+    let vram = gb.vram();
+    for bank in 0..2 {
+        for tile in 0..384 {
+            let (left, top) = (bank * 128 + tile % 16 * 8, tile / 16 * 8);
+            for row in 0..8 {
+                let colors = tile_row(vram, bank * 0x2000 + tile * 16 + row * 2);
+                for (x, &color) in colors.iter().enumerate() {
+                    image.put(left + x, top + row, TILE_GRAYS[color as usize]);
+                }
+            }
+        }
+    }
+}
+
+// The whole 32x32 map as the PPU would draw it, with the area SCX/SCY show
+// outlined if the BG uses this map, and the window's if it does
+fn draw_map(gb: &GameBoy, image: &mut Canvas, high: bool) {
+    let vram = gb.vram();
+    let palettes = gb.palettes();
+    let cgb = gb.is_cgb_mode();
+    let lcdc = gb.peek(0xFF40);
+    let map_base = if high { 0x1C00 } else { 0x1800 };
+    for index in 0..32 * 32 {
+        let tile = vram[map_base + index];
+        // CGB attributes sit at the same place in bank 1
+        let attrs = if cgb { vram[0x2000 + map_base + index] } else { 0 };
+        let data = if lcdc & 0x10 != 0 { tile as usize * 16 } else { (0x1000 + (tile as i8 as isize) * 16) as usize };
+        let bank = if attrs & 0x08 != 0 { 0x2000 } else { 0 };
+        let palette = &palettes.bg[(attrs & 0x07) as usize % palettes.bg.len()];
+        for row in 0..8 {
+            let source = if attrs & 0x40 != 0 { 7 - row } else { row };
+            let colors = tile_row(vram, bank + data + source * 2);
+            for x in 0..8 {
+                let color = colors[if attrs & 0x20 != 0 { 7 - x } else { x }];
+                image.put(index % 32 * 8 + x, index / 32 * 8 + row, palette[color as usize]);
+            }
+        }
+    }
+
+    let uses_map = |bit: u8| (lcdc & bit != 0) == high;
+    let (wx, wy) = (gb.peek(0xFF4B) as usize, gb.peek(0xFF4A) as usize);
+    if lcdc & 0x20 != 0 && uses_map(0x40) && wx <= 166 && wy < 144 {
+        // The window always starts at the map's top left corner
+        let left = wx.saturating_sub(7);
+        image.outline(0, 0, 160 - left, 144 - wy, WINDOW_COLOR);
+    }
+    if uses_map(0x08) {
+        image.outline(gb.peek(0xFF43) as usize, gb.peek(0xFF42) as usize, 160, 144, VIEWPORT_COLOR);
+    }
+}
+
+// One row per palette, four swatches each: BG on the left, OBJ on the right
+fn draw_palettes(gb: &GameBoy, image: &mut Canvas) {
+    let palettes = gb.palettes();
+    for (column, kind) in [&palettes.bg, &palettes.obj].into_iter().enumerate() {
+        for (row, palette) in kind.iter().enumerate() {
+            for (index, &rgb) in palette.iter().enumerate() {
+                let (left, top) = (8 + column * 128 + index * (SWATCH_SIZE + 4), 8 + row * (SWATCH_SIZE + 4));
+                for y in 0..SWATCH_SIZE {
+                    for x in 0..SWATCH_SIZE {
+                        image.put(left + x, top + y, rgb);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Every OAM entry in index order, drawn with its own flips and palette
+fn draw_objects(gb: &GameBoy, image: &mut Canvas) {
+    let vram = gb.vram();
+    let palettes = gb.palettes();
+    let cgb = gb.is_cgb_mode();
+    let height = if gb.has_tall_objects() { 16 } else { 8 };
+    let dropped: Vec<u8> = gb.sprite_overflow().iter().flat_map(|line| line.dropped.iter().copied()).collect();
+    for entry in gb.oam_entries() {
+        let (cell_x, cell_y) = (entry.index as usize % 8 * OBJECT_CELL.0, entry.index as usize / 8 * OBJECT_CELL.1);
+        let (x, y) = entry.screen_pos();
+        let background = if dropped.contains(&entry.index) {
+            DROPPED_COLOR
+        } else if x <= -8 || x >= 160 || y <= -height || y >= 144 {
+            HIDDEN_COLOR
+        } else {
+            CELL_COLOR
+        };
+        // A one pixel gap between cells
+        for dy in 1..OBJECT_CELL.1 - 1 {
+            for dx in 1..OBJECT_CELL.0 - 1 {
+                image.put(cell_x + dx, cell_y + dy, background);
+            }
+        }
+
+        let (bank, palette) = if cgb {
+            ((entry.flags as usize >> 3 & 1) * 0x2000, &palettes.obj[(entry.flags & 0x07) as usize])
+        } else {
+            (0, &palettes.obj[(entry.flags >> 4 & 1) as usize])
+        };
+        let tile = if height == 16 { entry.tile & 0xFE } else { entry.tile } as usize;
+        for row in 0..height as usize {
+            let source = if entry.y_flip() { height as usize - 1 - row } else { row };
+            let colors = tile_row(vram, bank + tile * 16 + source * 2);
+            for column in 0..8 {
+                let color = colors[if entry.x_flip() { 7 - column } else { column }];
+                // Color 0 is transparent
+                if color == 0 {
+                    continue;
+                }
+                for (sx, sy) in (0..OBJECT_SCALE).flat_map(|sx| (0..OBJECT_SCALE).map(move |sy| (sx, sy))) {
+                    image.put(cell_x + 8 + column * OBJECT_SCALE + sx, cell_y + 8 + row * OBJECT_SCALE + sy, palette[color as usize]);
+                }
+            }
+        }
+    }
+}
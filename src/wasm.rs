@@ -0,0 +1,190 @@
+// JavaScript bindings for running in a browser. Build the library for
+// wasm32-unknown-unknown with the wasm feature and run wasm-bindgen over it;
+// web/index.html drives the result with a canvas and Web Audio.
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::Clamped;
+
+use crate::accuracy::AccuracyConfig;
+use crate::cartridge::CartridgeHeader;
+use crate::gameboy::GameBoy;
+use crate::joypad::Button;
+use crate::model::HardwareModel;
+use crate::rtc::ClockSource;
+use crate::savefile::SaveFormat;
+use crate::scanout::{Filter, PaletteList, RgbaScanout, Scanout};
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = Date, js_name = now)]
+    fn date_now() -> f64;
+}
+
+// The browser's wall clock for the cartridge RTC
+struct BrowserClock;
+
+impl ClockSource for BrowserClock {
+    fn now(&self) -> u64 {
+        (date_now() / 1000.0) as u64
+    }
+}
+
+// The default desktop keys, by KeyboardEvent.code
+fn button_for_code(code: &str) -> Option<Button> {
+    match code {
+        "ArrowRight" => Some(Button::Right),
+        "ArrowLeft" => Some(Button::Left),
+        "ArrowUp" => Some(Button::Up),
+        "ArrowDown" => Some(Button::Down),
+        "KeyZ" => Some(Button::A),
+        "KeyX" => Some(Button::B),
+        "Enter" => Some(Button::Start),
+        "Backspace" => Some(Button::Select),
+        _ => None,
+    }
+}
+
+#[wasm_bindgen]
+pub struct Emulator {
+    gb: GameBoy,
+    scanout: RgbaScanout,
+    palettes: PaletteList,
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[wasm_bindgen]
+impl Emulator {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Emulator {
+        Emulator { gb: GameBoy::new(), scanout: RgbaScanout::new(), palettes: PaletteList::default() }
+    }
+
+    // Starts the game on the model its header asks for
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), JsError> {
+        let mut gb = GameBoy::new();
+        let model = CartridgeHeader::parse(rom).map_or(HardwareModel::Dmg, |header| header.preferred_model());
+        gb.set_model(model);
+        gb.set_sample_rate(self.gb.sample_rate());
+        gb.load_rom(rom).map_err(|error| JsError::new(&error.to_string()))?;
+        gb.set_clock_source(Box::new(BrowserClock));
+        gb.reset();
+        self.gb = gb;
+        Ok(())
+    }
+
+    pub fn run_frame(&mut self) {
+        if let Some(frame) = self.gb.run_frame() {
+            // Drawing into memory can't fail
+            let _ = self.scanout.present(&frame);
+        }
+        // Nothing here reports them, so they'd only pile up
+        self.gb.take_events();
+    }
+
+    // Of the framebuffer, which a filter makes three times larger
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> usize {
+        self.scanout.size().0
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> usize {
+        self.scanout.size().1
+    }
+
+    // The last frame as RGBA, ready for an ImageData
+    pub fn framebuffer(&self) -> Clamped<Vec<u8>> {
+        Clamped(self.scanout.rgba().to_vec())
+    }
+
+    // gray, the default, green, pocket, bgb, sgb or one added from a file
+    pub fn set_palette(&mut self, name: &str) -> Result<(), JsError> {
+        self.palettes.select(name).map_err(|error| JsError::new(&error))?;
+        self.scanout.set_palette(self.palettes.current().colors);
+        self.redraw();
+        Ok(())
+    }
+
+    // The [palettes] table of a palette file, as --palette-file reads it
+    pub fn add_palettes(&mut self, toml: &str) -> Result<(), JsError> {
+        self.palettes.add_file(toml).map_err(|error| JsError::new(&error))
+    }
+
+    pub fn palette_names(&self) -> Vec<String> {
+        self.palettes.names().into_iter().map(str::to_string).collect()
+    }
+
+    // none, grid or scanlines
+    pub fn set_filter(&mut self, name: &str) -> Result<(), JsError> {
+        let filter: Filter = name.parse().map_err(|error: String| JsError::new(&error))?;
+        self.scanout.set_filter(filter);
+        self.redraw();
+        Ok(())
+    }
+
+    // Percent of each frame left in the next, 0 to 90
+    pub fn set_frame_blend(&mut self, percent: u8) {
+        self.scanout.set_frame_blend(percent);
+        self.redraw();
+    }
+
+    // Returns whether the key is a Game Boy button, so the page knows to
+    // stop the browser acting on it
+    pub fn key_event(&mut self, code: &str, pressed: bool) -> bool {
+        let Some(button) = button_for_code(code) else {
+            return false;
+        };
+        self.gb.set_button(button, pressed);
+        true
+    }
+
+    // By name: a, b, select, start, right, left, up or down
+    pub fn set_button(&mut self, name: &str, pressed: bool) -> Result<(), JsError> {
+        let button: Button = name.parse().map_err(|error: String| JsError::new(&error))?;
+        self.gb.set_button(button, pressed);
+        Ok(())
+    }
+
+    // fast, balanced or accurate, the default; fast suits slow phones
+    pub fn set_accuracy(&mut self, profile: &str) -> Result<(), JsError> {
+        let accuracy: AccuracyConfig = profile.parse().map_err(|error: String| JsError::new(&error))?;
+        self.gb.set_accuracy(accuracy);
+        Ok(())
+    }
+
+    // Should match the AudioContext's rate
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        self.gb.set_sample_rate(rate);
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn sample_rate(&self) -> u32 {
+        self.gb.sample_rate()
+    }
+
+    // Interleaved left/right samples produced since the last call
+    pub fn take_audio(&mut self) -> Vec<f32> {
+        self.gb.take_audio().samples
+    }
+
+    // Cartridge RAM and clock for the page to keep, if the game has a battery
+    pub fn battery_save(&mut self) -> Option<Vec<u8>> {
+        self.gb.battery_save(SaveFormat::Bgb)
+    }
+
+    pub fn load_battery_save(&mut self, data: &[u8]) {
+        self.gb.load_battery_save(data);
+    }
+}
+
+impl Emulator {
+    // Shows a new setting on the current frame, even while paused
+    fn redraw(&mut self) {
+        let _ = self.scanout.present(&self.gb.frame());
+    }
+}
@@ -0,0 +1,76 @@
+use crate::cpu::CPU;
+use crate::events::HangReport;
+
+pub const DEFAULT_HANG_FRAMES: u32 = 120;
+
+// A loop whose body spans more bytes than this is treated as real work
+const LOOP_WINDOW: u16 = 8;
+
+pub struct Watchdog {
+    threshold_frames: u32,
+    stuck_frames: u32,
+    reported: bool,
+    pc_low: u16,
+    pc_high: u16,
+    ime_seen: bool,
+    last_window: Option<(u16, u16)>,
+}
+
+impl Watchdog {
+    pub fn new(threshold_frames: u32) -> Self {
+        Watchdog {
+            threshold_frames,
+            stuck_frames: 0,
+            reported: false,
+            pc_low: u16::MAX,
+            pc_high: 0,
+            ime_seen: false,
+            last_window: None,
+        }
+    }
+
+    pub fn set_threshold(&mut self, frames: u32) {
+        self.threshold_frames = frames;
+        self.stuck_frames = 0;
+        self.reported = false;
+    }
+
+    pub fn observe(&mut self, pc: u16, ime: bool) {
+        self.pc_low = self.pc_low.min(pc);
+        self.pc_high = self.pc_high.max(pc);
+        self.ime_seen |= ime;
+    }
+
+    // Called once per frame; returns a report the first time the threshold is crossed
+    pub fn end_frame(&mut self, cpu: &CPU) -> Option<HangReport> {
+        let window = (self.pc_low, self.pc_high);
+        let spinning = !self.ime_seen
+            && self.pc_high.wrapping_sub(self.pc_low) <= LOOP_WINDOW
+            && self.last_window.is_none_or(|last| last == window);
+
+        self.last_window = Some(window);
+        self.pc_low = u16::MAX;
+        self.pc_high = 0;
+        self.ime_seen = false;
+
+        if !spinning {
+            self.stuck_frames = 0;
+            self.reported = false;
+            return None;
+        }
+
+        self.stuck_frames += 1;
+        if self.threshold_frames == 0 || self.reported || self.stuck_frames < self.threshold_frames {
+            return None;
+        }
+
+        self.reported = true;
+        Some(HangReport {
+            frames: self.stuck_frames,
+            pc_low: window.0,
+            pc_high: window.1,
+            halted: cpu.halted,
+            registers: cpu.snapshot(),
+        })
+    }
+}
@@ -0,0 +1,128 @@
+// Records audio to 16-bit PCM WAV files as it's produced. The header's sizes
+// are only known at the end, so it's written with zeros first and filled in
+// by finish.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const HEADER_LEN: u32 = 44;
+
+pub struct WavWriter {
+    out: BufWriter<File>,
+    channels: u16,
+    sample_rate: u32,
+    data_len: u32,
+    finished: bool,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path, channels: u16) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(&[0; HEADER_LEN as usize])?;
+        Ok(WavWriter { out, channels, sample_rate: crate::apu::SAMPLE_RATE, data_len: 0, finished: false })
+    }
+
+    // The rate written into the header; the last one set before finish wins
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        self.sample_rate = rate;
+    }
+
+    // Interleaved samples from -1.0 to 1.0; louder ones are clipped
+    pub fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        for &sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.out.write_all(&value.to_le_bytes())?;
+        }
+        self.data_len = self.data_len.saturating_add(samples.len() as u32 * 2);
+        Ok(())
+    }
+
+    // Writes the header; dropping the writer does this too but can't report
+    // errors
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        let block_align = self.channels * 2;
+        let mut header = Vec::with_capacity(HEADER_LEN as usize);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(HEADER_LEN - 8 + self.data_len).to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        // PCM
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&self.channels.to_le_bytes());
+        header.extend_from_slice(&self.sample_rate.to_le_bytes());
+        header.extend_from_slice(&(self.sample_rate * block_align as u32).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&16u16.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&self.data_len.to_le_bytes());
+
+        self.out.seek(SeekFrom::Start(0))?;
+        self.out.write_all(&header)?;
+        self.out.flush()
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+// The mixed output, and optionally each channel on its own in files named
+// after it: song.wav gets song.ch1.wav to song.ch4.wav
+pub struct AudioDump {
+    mix: WavWriter,
+    stems: Option<[WavWriter; 4]>,
+}
+
+impl AudioDump {
+    // Returns the path that failed along with the error
+    pub fn create(path: &Path, stems: bool) -> Result<Self, (PathBuf, io::Error)> {
+        let open = |path: &Path| WavWriter::create(path, 2).map_err(|error| (path.to_path_buf(), error));
+        let mix = open(path)?;
+        let stems = if stems {
+            let mut writers = Vec::with_capacity(4);
+            for channel in 1..=4 {
+                writers.push(open(&stem_path(path, channel))?);
+            }
+            writers.try_into().ok()
+        } else {
+            None
+        };
+        Ok(AudioDump { mix, stems })
+    }
+
+    pub fn has_stems(&self) -> bool {
+        self.stems.is_some()
+    }
+
+    pub fn write(&mut self, sample_rate: u32, mix: &[f32], stems: &[Vec<f32>; 4]) -> io::Result<()> {
+        self.mix.set_sample_rate(sample_rate);
+        self.mix.write(mix)?;
+        if let Some(writers) = &mut self.stems {
+            for (writer, samples) in writers.iter_mut().zip(stems) {
+                writer.set_sample_rate(sample_rate);
+                writer.write(samples)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.mix.finish()?;
+        for writer in self.stems.iter_mut().flatten() {
+            writer.finish()?;
+        }
+        Ok(())
+    }
+}
+
+pub fn stem_path(path: &Path, channel: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.ch{}.wav", stem, channel))
+}
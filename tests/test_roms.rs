@@ -0,0 +1,194 @@
+// Runs Blargg's and Mooneye's test ROMs headlessly. The ROMs aren't in the
+// repository: put them under tests/roms/ (or point GB_TEST_ROMS elsewhere) as
+//
+//   blargg/cpu_instrs/cpu_instrs.gb, blargg/cpu_instrs/individual/*.gb
+//   blargg/instr_timing/instr_timing.gb
+//   mooneye/acceptance/**/*.gb
+//   acid2/dmg-acid2.gb and acid2/cgb-acid2.gbc, each with the reference
+//   image from its release next to it as dmg-acid2.png and cgb-acid2.png
+//
+// Suites whose ROMs are missing are skipped with a note. The emulator is slow
+// unoptimized, so run them with `cargo test --release --no-default-features`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rust_gbc::gameboy::GameBoy;
+use rust_gbc::headless::{self, HeadlessOptions, StopReason};
+use rust_gbc::imagediff;
+use rust_gbc::model::HardwareModel;
+use rust_gbc::png;
+use rust_gbc::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// Emulated time each kind of ROM gets before it counts as failed
+const BLARGG_FRAMES: u32 = 60 * 60;
+const MOONEYE_FRAMES: u32 = 60 * 10;
+// The acid2 ROMs draw their face once and then stop
+const ACID2_FRAMES: u32 = 60;
+
+// Mooneye tests load these into B, C, D, E, H and L when they pass, and 0x42
+// into all of them when they fail
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAIL: [u8; 6] = [0x42; 6];
+
+fn rom_root() -> PathBuf {
+    match std::env::var_os("GB_TEST_ROMS") {
+        Some(dir) => PathBuf::from(dir),
+        None => Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/roms"),
+    }
+}
+
+// Test ROMs expect the state the boot ROM leaves, and there is no boot ROM
+// for every model to run
+fn boot(path: &Path, model: HardwareModel) -> GameBoy {
+    let rom = fs::read(path).unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+    let mut gb = GameBoy::new();
+    gb.set_model(model);
+    gb.set_skip_boot(true);
+    gb.load_rom(&rom).unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+    gb.reset();
+    gb
+}
+
+// .gb files under dir, sorted so failures are listed in a stable order
+fn find_roms(dir: &Path) -> Vec<PathBuf> {
+    let mut roms = Vec::new();
+    let Ok(entries) = fs::read_dir(dir) else {
+        return roms;
+    };
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.is_dir() {
+            roms.extend(find_roms(&path));
+        } else if path.extension().is_some_and(|extension| extension == "gb") {
+            roms.push(path);
+        }
+    }
+    roms.sort();
+    roms
+}
+
+// Blargg's ROMs print their results over the serial port and end with
+// "Passed" or "Failed"
+fn run_blargg(path: &Path) -> Result<(), String> {
+    let mut gb = boot(path, HardwareModel::Dmg);
+    let options = HeadlessOptions { frames: Some(BLARGG_FRAMES), serial_match: Some("Passed".to_string()) };
+    let result = headless::run(&mut gb, &options, None, None);
+    match result.reason {
+        StopReason::SerialMatch => Ok(()),
+        reason => Err(format!("{:?}, serial output:\n{}", reason, String::from_utf8_lossy(&result.serial))),
+    }
+}
+
+// The model a Mooneye ROM is meant for, from its name suffix: "-dmgABC..."
+// for the DMG, "-mgb" for the Pocket, "-cgb..." or "-C" for the CGB and "-A"
+// for the GBA. Tests without one run on any model. None for the SGB and the
+// early DMG0 revision, which aren't emulated.
+fn mooneye_model(path: &Path) -> Option<HardwareModel> {
+    let name = path.file_stem()?.to_str()?;
+    let suffix = name.rsplit_once('-').map_or("", |(_, suffix)| suffix);
+    Some(match suffix {
+        "dmg0" | "S" | "sgb" | "sgb2" => return None,
+        "mgb" => HardwareModel::Mgb,
+        "C" => HardwareModel::Cgb,
+        "A" => HardwareModel::Agb,
+        _ if suffix.starts_with("cgb") => HardwareModel::Cgb,
+        _ => HardwareModel::Dmg,
+    })
+}
+
+// Mooneye's ROMs stop in a loop once the registers hold the pass or fail
+// signature
+fn run_mooneye(path: &Path, model: HardwareModel) -> Result<(), String> {
+    let mut gb = boot(path, model);
+    for _ in 0..MOONEYE_FRAMES {
+        gb.run_frame();
+        gb.take_events();
+        let r = gb.registers();
+        match [r.b, r.c, r.d, r.e, r.h, r.l] {
+            MOONEYE_PASS => return Ok(()),
+            MOONEYE_FAIL => return Err("failed".to_string()),
+            _ => {}
+        }
+    }
+    Err("timed out".to_string())
+}
+
+// Runs the ROM to a fixed frame and compares the screen with the reference
+// image next to it. A mismatch leaves the frame and a diff showing where it
+// went wrong in the target directory.
+fn run_golden(path: &Path, model: HardwareModel) -> Result<(), String> {
+    let reference = path.with_extension("png");
+    let reference = fs::read(&reference)
+        .map_err(|error| error.to_string())
+        .and_then(|data| png::decode(&data))
+        .map_err(|error| format!("reference image {}: {}", reference.display(), error))?;
+
+    let mut gb = boot(path, model);
+    let options = HeadlessOptions { frames: Some(ACID2_FRAMES), serial_match: None };
+    let result = headless::run(&mut gb, &options, None, None);
+    if !matches!(result.reason, StopReason::FrameLimit | StopReason::Hang) {
+        return Err(format!("stopped early: {:?}", result.reason));
+    }
+    let frame = png::Image { width: SCREEN_WIDTH, height: SCREEN_HEIGHT, pixels: gb.frame().pixels.to_vec() };
+    let diff = imagediff::compare(&reference, &frame)?;
+    let Some((x, y)) = diff.first else {
+        return Ok(());
+    };
+    let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join(path.file_stem().unwrap_or_default());
+    let _ = fs::write(out.with_extension("actual.png"), png::encode_rgb(&frame.pixels, frame.width, frame.height));
+    let _ = fs::write(out.with_extension("diff.png"), png::encode_rgb(&diff.highlight, diff.width, diff.height));
+    Err(format!("{} pixels differ, the first at {},{}; see {}.diff.png", diff.differing, x, y, out.display()))
+}
+
+// Runs every ROM, then panics with the list of failures
+fn check_all(roms: &[PathBuf], run: impl Fn(&Path) -> Option<Result<(), String>>) {
+    let failures: Vec<String> = roms
+        .iter()
+        .filter_map(|rom| match run(rom) {
+            Some(Err(message)) => Some(format!("{}: {}", rom.display(), message)),
+            _ => None,
+        })
+        .collect();
+    assert!(failures.is_empty(), "{} of {} ROMs failed:\n{}", failures.len(), roms.len(), failures.join("\n"));
+}
+
+fn suite(relative: &str) -> Option<Vec<PathBuf>> {
+    let path = rom_root().join(relative);
+    let roms = if path.is_dir() { find_roms(&path) } else if path.is_file() { vec![path.clone()] } else { Vec::new() };
+    if roms.is_empty() {
+        eprintln!("skipping, no test ROMs at {}", path.display());
+        return None;
+    }
+    Some(roms)
+}
+
+#[test]
+fn blargg_cpu_instrs() {
+    if let Some(roms) = suite("blargg/cpu_instrs") {
+        check_all(&roms, |rom| Some(run_blargg(rom)));
+    }
+}
+
+#[test]
+fn blargg_instr_timing() {
+    if let Some(roms) = suite("blargg/instr_timing") {
+        check_all(&roms, |rom| Some(run_blargg(rom)));
+    }
+}
+
+#[test]
+fn mooneye_acceptance() {
+    if let Some(roms) = suite("mooneye/acceptance") {
+        check_all(&roms, |rom| mooneye_model(rom).map(|model| run_mooneye(rom, model)));
+    }
+}
+
+#[test]
+fn acid2_golden_images() {
+    let roms = [("acid2/dmg-acid2.gb", HardwareModel::Dmg), ("acid2/cgb-acid2.gbc", HardwareModel::Cgb)];
+    for (relative, model) in roms {
+        if let Some(rom) = suite(relative) {
+            check_all(&rom, |rom| Some(run_golden(rom, model)));
+        }
+    }
+}